@@ -0,0 +1,484 @@
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use super::options::MAX_DNS_SERVERS;
+use super::packet::{
+    MessageType, Packet, PacketWriter, BROADCAST_FLAG, OPT_CLIENT_ID, OPT_DNS, OPT_HOSTNAME,
+    OPT_LEASE_TIME, OPT_PARAMETER_LIST, OPT_REBINDING_TIME, OPT_RENEWAL_TIME, OPT_REQUESTED_IP,
+    OPT_ROUTER, OPT_SERVER_ID, OPT_SUBNET_MASK, OPT_VENDOR_CLASS,
+};
+
+// Retransmissions back off from 4 s to 64 s (RFC 2131 section 4.1)
+const FIRST_RETRY_SECS: u64 = 4;
+const MAX_RETRY_SECS: u64 = 64;
+/// REQUESTs sent for an offer before starting over with a DISCOVER.
+const MAX_REQUESTS: u8 = 4;
+/// REQUESTs for the previous address before falling back to a DISCOVER.
+const MAX_REBOOT_REQUESTS: u8 = 2;
+/// Shortest wait between retransmissions while renewing or rebinding.
+const MIN_RENEW_RETRY_SECS: u64 = 60;
+const PARAMETERS: [u8; 6] = [
+    OPT_SUBNET_MASK,
+    OPT_ROUTER,
+    OPT_DNS,
+    OPT_LEASE_TIME,
+    OPT_RENEWAL_TIME,
+    OPT_REBINDING_TIME,
+];
+
+/// What the station's DHCP client asks for.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// Sent as option 12 so upstream routers can show a name.
+    pub hostname: Option<String<32>>,
+    /// Sent as option 61 instead of the MAC address.
+    pub client_id: Option<String<32>>,
+    /// Sent as option 60, identifies the kind of device to the server.
+    pub vendor_class: Option<String<32>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr, MAX_DNS_SERVERS>,
+    pub server: Ipv4Addr,
+    pub lease_secs: u32,
+    pub renew_secs: u32,
+    pub rebind_secs: u32,
+    pub acquired_at: u64,
+}
+
+impl Lease {
+    fn from_ack(ack: &Packet<'_>, server: Ipv4Addr, now: u64) -> Self {
+        // An ACK without a lease time is broken, but one hour is a common
+        // default among servers
+        let lease_secs = ack.u32_option(OPT_LEASE_TIME).unwrap_or(3600);
+        let renew_secs = ack
+            .u32_option(OPT_RENEWAL_TIME)
+            .unwrap_or(lease_secs / 2)
+            .min(lease_secs);
+        let rebind_secs = ack
+            .u32_option(OPT_REBINDING_TIME)
+            .unwrap_or((lease_secs as u64 * 7 / 8) as u32)
+            .clamp(renew_secs, lease_secs);
+        let prefix_len = ack
+            .ip_list(OPT_SUBNET_MASK)
+            .next()
+            .map_or(24, |mask| u32::from(mask).leading_ones() as u8);
+        Self {
+            address: ack.yiaddr,
+            prefix_len,
+            gateway: ack.ip_list(OPT_ROUTER).next(),
+            dns_servers: ack.ip_list(OPT_DNS).take(MAX_DNS_SERVERS).collect(),
+            server,
+            lease_secs,
+            renew_secs,
+            rebind_secs,
+            acquired_at: now,
+        }
+    }
+
+    pub fn renew_at(&self) -> u64 {
+        self.at(self.renew_secs)
+    }
+
+    pub fn rebind_at(&self) -> u64 {
+        self.at(self.rebind_secs)
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.at(self.lease_secs)
+    }
+
+    fn at(&self, secs: u32) -> u64 {
+        // Infinite leases never need renewing
+        if self.lease_secs == u32::MAX {
+            return u64::MAX;
+        }
+        self.acquired_at + secs as u64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Init,
+    /// Asking to keep the address from an earlier lease (INIT-REBOOT).
+    Rebooting {
+        requested: Ipv4Addr,
+    },
+    Selecting,
+    Requesting {
+        offered: Ipv4Addr,
+        server: Ipv4Addr,
+    },
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+/// A message for the runtime to send from `src` to `dst`, port 68 to 67.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Outgoing {
+    pub len: usize,
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Send(Outgoing),
+    /// A lease was granted or renewed, see [`Client::lease`].
+    Bound,
+    /// The lease expired or the server took it back.
+    Lost,
+}
+
+/// DHCP client state machine for the station interface (RFC 2131). Time is
+/// passed in as seconds, messages are built into and parsed from buffers
+/// supplied by the caller.
+pub struct Client {
+    mac: [u8; 6],
+    options: ClientOptions,
+    xid: u32,
+    state: State,
+    lease: Option<Lease>,
+    next_at: u64,
+    attempts: u8,
+}
+
+impl Client {
+    /// With a `previous` address the client first asks to keep it, which
+    /// skips the DISCOVER/OFFER round trip when the server still agrees.
+    pub fn new(mac: [u8; 6], options: ClientOptions, xid: u32, previous: Option<Ipv4Addr>) -> Self {
+        let state = match previous {
+            Some(requested) => State::Rebooting { requested },
+            None => State::Init,
+        };
+        Self {
+            mac,
+            options,
+            xid,
+            state,
+            lease: None,
+            next_at: 0,
+            attempts: 0,
+        }
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// When [`Client::poll`] has something to do next.
+    pub fn next_at(&self) -> u64 {
+        self.next_at
+    }
+
+    /// Runs the timers: (re)transmits, renews and expires the lease.
+    pub fn poll(&mut self, now: u64, buf: &mut [u8]) -> Option<Event> {
+        if now < self.next_at {
+            return None;
+        }
+        match self.state {
+            State::Init => {
+                self.xid = self.xid.wrapping_add(1);
+                self.state = State::Selecting;
+                self.attempts = 0;
+                self.retransmit(now, buf, MessageType::Discover, None, None)
+            }
+            State::Rebooting { requested } => {
+                if self.attempts >= MAX_REBOOT_REQUESTS {
+                    self.restart(now);
+                    return None;
+                }
+                self.retransmit(now, buf, MessageType::Request, Some(requested), None)
+            }
+            State::Selecting => self.retransmit(now, buf, MessageType::Discover, None, None),
+            State::Requesting { offered, server } => {
+                if self.attempts >= MAX_REQUESTS {
+                    self.restart(now);
+                    return None;
+                }
+                self.retransmit(now, buf, MessageType::Request, Some(offered), Some(server))
+            }
+            State::Bound | State::Renewing | State::Rebinding => {
+                let lease = self.lease.clone()?;
+                if now >= lease.expires_at() {
+                    self.restart(now);
+                    return Some(Event::Lost);
+                }
+                let (dst, until) = if now >= lease.rebind_at() {
+                    self.state = State::Rebinding;
+                    (Ipv4Addr::BROADCAST, lease.expires_at())
+                } else {
+                    self.state = State::Renewing;
+                    (lease.server, lease.rebind_at())
+                };
+                let len = self.message(buf, MessageType::Request, lease.address, None, None)?;
+                // Half the time left, but not too often (RFC 2131 section 4.4.5)
+                self.next_at = until.min(now + ((until - now) / 2).max(MIN_RENEW_RETRY_SECS));
+                Some(Event::Send(Outgoing {
+                    len,
+                    src: lease.address,
+                    dst,
+                }))
+            }
+        }
+    }
+
+    /// Handles a message from a server.
+    pub fn handle(&mut self, raw: &[u8], now: u64) -> Option<Event> {
+        let reply = Packet::decode(raw).ok()?;
+        if reply.is_request() || reply.xid != self.xid || reply.mac() != self.mac {
+            return None;
+        }
+        match (self.state, reply.message_type()?) {
+            (State::Selecting, MessageType::Offer) => {
+                let server = reply.server_id()?;
+                if reply.yiaddr.is_unspecified() {
+                    return None;
+                }
+                self.state = State::Requesting {
+                    offered: reply.yiaddr,
+                    server,
+                };
+                self.attempts = 0;
+                self.next_at = now;
+                None
+            }
+            (State::Requesting { offered, server }, MessageType::Ack) => {
+                if reply.yiaddr != offered {
+                    return None;
+                }
+                self.bind(&reply, server, now)
+            }
+            (State::Rebooting { requested }, MessageType::Ack) => {
+                if reply.yiaddr != requested {
+                    return None;
+                }
+                self.bind(&reply, reply.server_id()?, now)
+            }
+            (State::Renewing | State::Rebinding, MessageType::Ack) => {
+                let lease = self.lease.as_ref()?;
+                if reply.yiaddr != lease.address {
+                    return None;
+                }
+                let server = reply.server_id().unwrap_or(lease.server);
+                self.bind(&reply, server, now)
+            }
+            (
+                State::Rebooting { .. }
+                | State::Requesting { .. }
+                | State::Renewing
+                | State::Rebinding,
+                MessageType::Nak,
+            ) => {
+                let had_lease = self.lease.is_some();
+                self.restart(now);
+                had_lease.then_some(Event::Lost)
+            }
+            _ => None,
+        }
+    }
+
+    fn bind(&mut self, ack: &Packet<'_>, server: Ipv4Addr, now: u64) -> Option<Event> {
+        let lease = Lease::from_ack(ack, server, now);
+        self.next_at = lease.renew_at();
+        self.lease = Some(lease);
+        self.state = State::Bound;
+        Some(Event::Bound)
+    }
+
+    fn restart(&mut self, now: u64) {
+        self.lease = None;
+        self.state = State::Init;
+        self.next_at = now;
+    }
+
+    fn retransmit(
+        &mut self,
+        now: u64,
+        buf: &mut [u8],
+        message_type: MessageType,
+        requested: Option<Ipv4Addr>,
+        server: Option<Ipv4Addr>,
+    ) -> Option<Event> {
+        let len = self.message(buf, message_type, Ipv4Addr::UNSPECIFIED, requested, server)?;
+        let delay = (FIRST_RETRY_SECS << self.attempts.min(4)).min(MAX_RETRY_SECS);
+        self.attempts = self.attempts.saturating_add(1);
+        self.next_at = now + delay;
+        Some(Event::Send(Outgoing {
+            len,
+            src: Ipv4Addr::UNSPECIFIED,
+            dst: Ipv4Addr::BROADCAST,
+        }))
+    }
+
+    fn message(
+        &self,
+        buf: &mut [u8],
+        message_type: MessageType,
+        ciaddr: Ipv4Addr,
+        requested: Option<Ipv4Addr>,
+        server: Option<Ipv4Addr>,
+    ) -> Option<usize> {
+        // Without an address we can't receive unicast replies yet
+        let flags = if ciaddr.is_unspecified() {
+            BROADCAST_FLAG
+        } else {
+            0
+        };
+        let mut writer = PacketWriter::request(buf, self.xid, flags, ciaddr, &self.mac).ok()?;
+        writer.message_type(message_type).ok()?;
+
+        // Type 0 marks a free-form identifier, type 1 an Ethernet address
+        let mut client_id = Vec::<u8, 33>::new();
+        match &self.options.client_id {
+            Some(id) => {
+                _ = client_id.push(0);
+                _ = client_id.extend_from_slice(id.as_bytes());
+            }
+            None => {
+                _ = client_id.push(1);
+                _ = client_id.extend_from_slice(&self.mac);
+            }
+        }
+        writer.option(OPT_CLIENT_ID, &client_id).ok()?;
+        if let Some(requested) = requested {
+            writer.ip_option(OPT_REQUESTED_IP, &[requested]).ok()?;
+        }
+        if let Some(server) = server {
+            writer.ip_option(OPT_SERVER_ID, &[server]).ok()?;
+        }
+        if let Some(hostname) = &self.options.hostname {
+            writer.option(OPT_HOSTNAME, hostname.as_bytes()).ok()?;
+        }
+        if let Some(vendor_class) = &self.options.vendor_class {
+            writer
+                .option(OPT_VENDOR_CLASS, vendor_class.as_bytes())
+                .ok()?;
+        }
+        writer.option(OPT_PARAMETER_LIST, &PARAMETERS).ok()?;
+        writer.finish().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const PREVIOUS: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+
+    fn options() -> ClientOptions {
+        ClientOptions {
+            hostname: Some("ap-1".try_into().unwrap()),
+            client_id: None,
+            vendor_class: Some("esp32-ap".try_into().unwrap()),
+        }
+    }
+
+    fn send(client: &mut Client, now: u64, buf: &mut [u8]) -> Outgoing {
+        match client.poll(now, buf) {
+            Some(Event::Send(outgoing)) => outgoing,
+            other => panic!("expected a message, got {other:?}"),
+        }
+    }
+
+    fn reply(request: &[u8], message_type: MessageType, yiaddr: Ipv4Addr) -> [u8; 600] {
+        let request = Packet::decode(request).unwrap();
+        let mut buf = [0u8; 600];
+        let mut writer = PacketWriter::reply_to(&mut buf, &request, yiaddr, SERVER).unwrap();
+        writer.message_type(message_type).unwrap();
+        writer.ip_option(OPT_SERVER_ID, &[SERVER]).unwrap();
+        writer.u32_option(OPT_LEASE_TIME, 3600).unwrap();
+        writer
+            .ip_option(OPT_SUBNET_MASK, &[Ipv4Addr::new(255, 255, 255, 0)])
+            .unwrap();
+        writer.ip_option(OPT_ROUTER, &[SERVER]).unwrap();
+        writer.finish().unwrap();
+        buf
+    }
+
+    #[test]
+    fn asks_for_the_previous_address_first() {
+        let mut client = Client::new(MAC, options(), 1, Some(PREVIOUS));
+        let mut buf = [0u8; 600];
+        let outgoing = send(&mut client, 0, &mut buf);
+        assert_eq!(outgoing.dst, Ipv4Addr::BROADCAST);
+
+        let request = Packet::decode(&buf[..outgoing.len]).unwrap();
+        assert_eq!(request.message_type(), Some(MessageType::Request));
+        assert_eq!(request.requested_ip(), Some(PREVIOUS));
+        // INIT-REBOOT names no server
+        assert_eq!(request.server_id(), None);
+        assert_eq!(request.hostname(), Some("ap-1"));
+        assert_eq!(request.vendor_class(), Some(b"esp32-ap".as_slice()));
+        assert_eq!(
+            request.option(OPT_CLIENT_ID),
+            Some([1, 2, 0, 0, 0, 0, 1].as_slice())
+        );
+
+        let ack = reply(&buf[..outgoing.len], MessageType::Ack, PREVIOUS);
+        assert_eq!(client.handle(&ack, 1), Some(Event::Bound));
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.address, PREVIOUS);
+        assert_eq!(lease.server, SERVER);
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(lease.gateway, Some(SERVER));
+        assert_eq!(lease.renew_at(), 1 + 1800);
+        assert_eq!(lease.rebind_at(), 1 + 3150);
+    }
+
+    #[test]
+    fn discovers_when_the_previous_address_is_refused() {
+        let mut client = Client::new(MAC, options(), 1, Some(PREVIOUS));
+        let mut buf = [0u8; 600];
+        let outgoing = send(&mut client, 0, &mut buf);
+        let nak = reply(
+            &buf[..outgoing.len],
+            MessageType::Nak,
+            Ipv4Addr::UNSPECIFIED,
+        );
+        // Nothing was bound, so nothing was lost
+        assert_eq!(client.handle(&nak, 1), None);
+
+        let outgoing = send(&mut client, 1, &mut buf);
+        let discover = Packet::decode(&buf[..outgoing.len]).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_eq!(discover.requested_ip(), None);
+    }
+
+    #[test]
+    fn gives_up_on_the_previous_address_without_an_answer() {
+        let mut client = Client::new(MAC, ClientOptions::default(), 1, Some(PREVIOUS));
+        let mut buf = [0u8; 600];
+        send(&mut client, 0, &mut buf);
+        let retry_at = client.next_at();
+        send(&mut client, retry_at, &mut buf);
+        let give_up_at = client.next_at();
+        assert_eq!(client.poll(give_up_at, &mut buf), None);
+
+        let outgoing = send(&mut client, give_up_at, &mut buf);
+        let discover = Packet::decode(&buf[..outgoing.len]).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_eq!(discover.hostname(), None);
+    }
+
+    #[test]
+    fn ignores_acks_for_another_address() {
+        let mut client = Client::new(MAC, options(), 1, Some(PREVIOUS));
+        let mut buf = [0u8; 600];
+        let outgoing = send(&mut client, 0, &mut buf);
+        let ack = reply(
+            &buf[..outgoing.len],
+            MessageType::Ack,
+            Ipv4Addr::new(192, 168, 1, 21),
+        );
+        assert_eq!(client.handle(&ack, 1), None);
+        assert_eq!(client.lease(), None);
+    }
+}
@@ -0,0 +1,112 @@
+use core::net::Ipv4Addr;
+use heapless::Vec;
+
+// A successful probe is trusted for this long before the address is probed again
+const PROBE_VALID_SECS: u64 = 60;
+const MAX_VERIFIED: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeclineReason {
+    /// Something answered our probe before the address was offered.
+    Probe,
+    /// A client sent DHCPDECLINE after finding the address in use.
+    Client,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Declined {
+    pub ip: Ipv4Addr,
+    pub until: u64,
+    pub reason: DeclineReason,
+    pub mac: Option<[u8; 6]>,
+}
+
+/// Probes whether an address is already used on the AP network, e.g. with an
+/// ICMP echo. Implemented by the firmware on top of the AP stack and by a
+/// simulated network on the host.
+#[allow(async_fn_in_trait)]
+pub trait AddressProbe {
+    async fn in_use(&mut self, ip: Ipv4Addr) -> bool;
+}
+
+/// Addresses that must not be offered for a while because they were found in
+/// use, plus a short cache of addresses that were recently probed free.
+pub struct ConflictTable<const N: usize> {
+    declined: Vec<Declined, N>,
+    verified: Vec<(Ipv4Addr, u64), MAX_VERIFIED>,
+    cooldown_secs: u64,
+}
+
+impl<const N: usize> ConflictTable<N> {
+    pub const fn new(cooldown_secs: u64) -> Self {
+        Self {
+            declined: Vec::new(),
+            verified: Vec::new(),
+            cooldown_secs,
+        }
+    }
+
+    pub fn declined(&self) -> &[Declined] {
+        &self.declined
+    }
+
+    pub fn is_declined(&self, ip: Ipv4Addr) -> bool {
+        self.declined.iter().any(|declined| declined.ip == ip)
+    }
+
+    pub fn needs_probe(&self, ip: Ipv4Addr) -> bool {
+        !self.verified.iter().any(|(verified, _)| *verified == ip)
+    }
+
+    pub fn expire(&mut self, now: u64) {
+        self.declined.retain(|declined| declined.until > now);
+        self.verified.retain(|(_, until)| *until > now);
+    }
+
+    pub fn record_probe(&mut self, ip: Ipv4Addr, in_use: bool, now: u64) {
+        self.verified.retain(|(verified, _)| *verified != ip);
+        if in_use {
+            self.decline(ip, None, DeclineReason::Probe, now);
+        } else {
+            if self.verified.is_full() {
+                self.verified.remove(0);
+            }
+            _ = self.verified.push((ip, now + PROBE_VALID_SECS));
+        }
+    }
+
+    pub fn decline(&mut self, ip: Ipv4Addr, mac: Option<[u8; 6]>, reason: DeclineReason, now: u64) {
+        self.verified.retain(|(verified, _)| *verified != ip);
+        self.declined.retain(|declined| declined.ip != ip);
+        if self.declined.is_full() {
+            // Forget the entry closest to the end of its cooldown
+            if let Some(index) = self
+                .declined
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, declined)| declined.until)
+                .map(|(index, _)| index)
+            {
+                self.declined.swap_remove(index);
+            }
+        }
+        _ = self.declined.push(Declined {
+            ip,
+            until: now + self.cooldown_secs,
+            reason,
+            mac,
+        });
+    }
+
+    /// Lifts the quarantine of one address, returning whether it was declined.
+    pub fn forget(&mut self, ip: Ipv4Addr) -> bool {
+        let before = self.declined.len();
+        self.declined.retain(|declined| declined.ip != ip);
+        self.declined.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.declined.clear();
+        self.verified.clear();
+    }
+}
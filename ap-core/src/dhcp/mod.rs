@@ -0,0 +1,7 @@
+pub mod conflict;
+pub mod options;
+pub mod packet;
+pub mod server;
+pub mod stats;
+pub mod relay;
+pub mod client;
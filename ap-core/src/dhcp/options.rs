@@ -0,0 +1,878 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use super::packet::{
+    BufferTooSmall, PacketWriter, HEADER_LEN, MAGIC_COOKIE, MAX_MESSAGE_LEN, OPT_CAPTIVE_PORTAL,
+    OPT_CLASSLESS_ROUTES, OPT_DNS, OPT_DOMAIN_NAME, OPT_DOMAIN_SEARCH, OPT_MTU, OPT_NTP,
+};
+use crate::mac::{self, MacDisplay};
+use crate::wifi::ap_network::netmask_bits;
+
+pub const MAX_DNS_SERVERS: usize = 3;
+pub const MAX_NTP_SERVERS: usize = 2;
+pub const MAX_SEARCH_DOMAINS: usize = 3;
+pub const MAX_ROUTES: usize = 4;
+pub const MAX_CUSTOM_OPTIONS: usize = 3;
+pub const MAX_OVERRIDES: usize = 4;
+
+pub type DomainName = String<64>;
+
+// What a reply takes besides the configured options: the header, message
+// type, server id, three lease times, mask, router and the end option
+const REPLY_BASE_LEN: usize = HEADER_LEN + MAGIC_COOKIE.len() + 3 + 6 + 3 * 6 + 6 + 6 + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaticRoute {
+    pub destination: Ipv4Addr,
+    pub prefix_len: u8,
+    pub router: Ipv4Addr,
+}
+
+impl StaticRoute {
+    /// Parses `10.0.0.0/8 via 192.168.2.1`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (destination, router) = s.trim().split_once(" via ")?;
+        let (destination, prefix_len) = destination.trim().split_once('/')?;
+        Some(Self {
+            destination: destination.parse().ok()?,
+            prefix_len: prefix_len.parse().ok()?,
+            router: router.trim().parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for StaticRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} via {}",
+            self.destination, self.prefix_len, self.router
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomOption {
+    pub code: u8,
+    pub data: Vec<u8, 64>,
+}
+
+impl CustomOption {
+    /// Parses `code:hexdata`, e.g. `43:0104c0a80201`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (code, hex) = s.trim().split_once(':')?;
+        let hex = hex.as_bytes();
+        if hex.len() % 2 != 0 {
+            return None;
+        }
+        let mut data = Vec::new();
+        for pair in hex.chunks(2) {
+            let pair = core::str::from_utf8(pair).ok()?;
+            data.push(u8::from_str_radix(pair, 16).ok()?).ok()?;
+        }
+        Some(Self {
+            code: code.parse().ok()?,
+            data,
+        })
+    }
+}
+
+impl fmt::Display for CustomOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.code)?;
+        self.data
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionsError {
+    InvalidDomainName,
+    InvalidRoute,
+    InvalidCaptivePortal,
+    InvalidMtu,
+    ReservedOptionCode,
+    TooLarge,
+}
+
+impl OptionsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidDomainName => "invalid domain name",
+            Self::InvalidRoute => "static route has host bits set or a bad prefix",
+            Self::InvalidCaptivePortal => "captive portal must be an http(s) URI",
+            Self::InvalidMtu => "MTU must be between 68 and 1500",
+            Self::ReservedOptionCode => "custom option code is managed by the server",
+            Self::TooLarge => "options don't fit in a DHCP reply",
+        }
+    }
+}
+
+/// Options handed out on top of the address, mask and router. Empty fields
+/// are not sent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DhcpOptions {
+    pub dns: Vec<Ipv4Addr, MAX_DNS_SERVERS>,
+    pub domain_name: Option<DomainName>,
+    pub ntp: Vec<Ipv4Addr, MAX_NTP_SERVERS>,
+    pub domain_search: Vec<DomainName, MAX_SEARCH_DOMAINS>,
+    pub routes: Vec<StaticRoute, MAX_ROUTES>,
+    pub captive_portal: Option<String<128>>,
+    pub mtu: Option<u16>,
+    pub custom: Vec<CustomOption, MAX_CUSTOM_OPTIONS>,
+}
+
+impl DhcpOptions {
+    pub const fn new() -> Self {
+        Self {
+            dns: Vec::new(),
+            domain_name: None,
+            ntp: Vec::new(),
+            domain_search: Vec::new(),
+            routes: Vec::new(),
+            captive_portal: None,
+            mtu: None,
+            custom: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        let mut names = self.domain_name.iter().chain(self.domain_search.iter());
+        if names.any(|name| !is_valid_domain(name)) {
+            return Err(OptionsError::InvalidDomainName);
+        }
+        for route in &self.routes {
+            if route.prefix_len > 32
+                || u32::from(route.destination) & !netmask_bits(route.prefix_len) != 0
+            {
+                return Err(OptionsError::InvalidRoute);
+            }
+        }
+        if let Some(uri) = &self.captive_portal {
+            if !(uri.starts_with("http://") || uri.starts_with("https://")) {
+                return Err(OptionsError::InvalidCaptivePortal);
+            }
+        }
+        if let Some(mtu) = self.mtu {
+            if !(68..=1500).contains(&mtu) {
+                return Err(OptionsError::InvalidMtu);
+            }
+        }
+        if self.custom.iter().any(|option| is_reserved(option.code)) {
+            return Err(OptionsError::ReservedOptionCode);
+        }
+        if REPLY_BASE_LEN + self.encoded_len() > MAX_MESSAGE_LEN {
+            return Err(OptionsError::TooLarge);
+        }
+        Ok(())
+    }
+
+    /// How many bytes the options take in a reply when a client asks for
+    /// all of them.
+    pub fn encoded_len(&self) -> usize {
+        // Code and length, repeated for every 255 bytes of data
+        let option = |data_len: usize| 2 * data_len.div_ceil(255).max(1) + data_len;
+        let mut len = 0;
+        if !self.dns.is_empty() {
+            len += option(4 * self.dns.len());
+        }
+        if let Some(name) = &self.domain_name {
+            len += option(name.len());
+        }
+        if !self.ntp.is_empty() {
+            len += option(4 * self.ntp.len());
+        }
+        if self.mtu.is_some() {
+            len += option(2);
+        }
+        if !self.domain_search.is_empty() {
+            // Each label gets a length byte, which takes the place of its
+            // dot, plus one for the first label and the terminating zero
+            let names = self.domain_search.iter();
+            len += option(names.map(|name| name.trim_end_matches('.').len() + 2).sum());
+        }
+        if !self.routes.is_empty() {
+            let has_default = self.routes.iter().any(|route| route.prefix_len == 0);
+            let routes = self.routes.iter();
+            let routes_len: usize = routes
+                .map(|route| 1 + (route.prefix_len as usize).div_ceil(8) + 4)
+                .sum();
+            len += option(routes_len + if has_default { 0 } else { 5 });
+        }
+        if let Some(uri) = &self.captive_portal {
+            len += option(uri.len());
+        }
+        for custom in &self.custom {
+            len += option(custom.data.len());
+        }
+        len
+    }
+
+    /// Fields set in `other` replace the ones in `self`.
+    pub fn overlay(&mut self, other: &DhcpOptions) {
+        if !other.dns.is_empty() {
+            self.dns = other.dns.clone();
+        }
+        if other.domain_name.is_some() {
+            self.domain_name = other.domain_name.clone();
+        }
+        if !other.ntp.is_empty() {
+            self.ntp = other.ntp.clone();
+        }
+        if !other.domain_search.is_empty() {
+            self.domain_search = other.domain_search.clone();
+        }
+        if !other.routes.is_empty() {
+            self.routes = other.routes.clone();
+        }
+        if other.captive_portal.is_some() {
+            self.captive_portal = other.captive_portal.clone();
+        }
+        if other.mtu.is_some() {
+            self.mtu = other.mtu;
+        }
+        for option in &other.custom {
+            self.custom.retain(|custom| custom.code != option.code);
+            _ = self.custom.push(option.clone());
+        }
+    }
+
+    /// The options as `key=value` lines, one per list entry.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        for ip in &self.dns {
+            writeln!(out, "dns={ip}")?;
+        }
+        if let Some(name) = &self.domain_name {
+            writeln!(out, "domain_name={name}")?;
+        }
+        for ip in &self.ntp {
+            writeln!(out, "ntp={ip}")?;
+        }
+        for name in &self.domain_search {
+            writeln!(out, "domain_search={name}")?;
+        }
+        for route in &self.routes {
+            writeln!(out, "route={route}")?;
+        }
+        if let Some(uri) = &self.captive_portal {
+            writeln!(out, "captive_portal={uri}")?;
+        }
+        if let Some(mtu) = self.mtu {
+            writeln!(out, "mtu={mtu}")?;
+        }
+        for option in &self.custom {
+            writeln!(out, "custom={option}")?;
+        }
+        Ok(())
+    }
+
+    /// Applies one line written by [`DhcpOptions::write_to`]. Unknown keys
+    /// are skipped.
+    fn parse_line(&mut self, key: &str, value: &str) -> Option<()> {
+        match key {
+            "dns" => self.dns.push(value.parse().ok()?).ok()?,
+            "domain_name" => self.domain_name = Some(value.try_into().ok()?),
+            "ntp" => self.ntp.push(value.parse().ok()?).ok()?,
+            "domain_search" => self.domain_search.push(value.try_into().ok()?).ok()?,
+            "route" => self.routes.push(StaticRoute::parse(value)?).ok()?,
+            "captive_portal" => self.captive_portal = Some(value.try_into().ok()?),
+            "mtu" => self.mtu = Some(value.parse().ok()?),
+            "custom" => self.custom.push(CustomOption::parse(value)?).ok()?,
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// Appends the configured options to a reply. `requested` is the client's
+    /// parameter request list; options that clients only understand when they
+    /// ask for them (121, 114 and custom ones) are left out otherwise. So is
+    /// an option that doesn't fit in what is left of `writer`, rather than
+    /// losing the whole reply.
+    pub fn encode(&self, writer: &mut PacketWriter<'_>, requested: &[u8], gateway: Ipv4Addr) {
+        let wants = |code: u8| requested.is_empty() || requested.contains(&code);
+
+        if !self.dns.is_empty() && wants(OPT_DNS) {
+            _ = writer.ip_option(OPT_DNS, &self.dns);
+        }
+        if let Some(name) = self.domain_name.as_ref().filter(|_| wants(OPT_DOMAIN_NAME)) {
+            _ = writer.option(OPT_DOMAIN_NAME, name.as_bytes());
+        }
+        if !self.ntp.is_empty() && wants(OPT_NTP) {
+            _ = writer.ip_option(OPT_NTP, &self.ntp);
+        }
+        if let Some(mtu) = self.mtu.filter(|_| wants(OPT_MTU)) {
+            _ = writer.option(OPT_MTU, &mtu.to_be_bytes());
+        }
+        if !self.domain_search.is_empty() && wants(OPT_DOMAIN_SEARCH) {
+            let mut data = [0u8; 256];
+            if let Ok(len) = encode_domain_search(&self.domain_search, &mut data) {
+                _ = writer.option(OPT_DOMAIN_SEARCH, &data[..len]);
+            }
+        }
+        if !self.routes.is_empty() && requested.contains(&OPT_CLASSLESS_ROUTES) {
+            let mut data = [0u8; 64];
+            if let Ok(len) = encode_classless_routes(&self.routes, gateway, &mut data) {
+                _ = writer.option(OPT_CLASSLESS_ROUTES, &data[..len]);
+            }
+        }
+        if let Some(uri) = &self.captive_portal {
+            if requested.contains(&OPT_CAPTIVE_PORTAL) {
+                _ = writer.option(OPT_CAPTIVE_PORTAL, uri.as_bytes());
+            }
+        }
+        for option in &self.custom {
+            if requested.contains(&option.code) {
+                _ = writer.option(option.code, &option.data);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientMatch {
+    Mac([u8; 6]),
+    /// Matches clients whose vendor class identifier (option 60) starts
+    /// with this prefix, e.g. `MSFT` or `android-dhcp`.
+    VendorClass(String<32>),
+}
+
+impl ClientMatch {
+    /// Parses `mac aa:bb:cc:dd:ee:ff` or `vendor_class MSFT`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.split_once(' ')? {
+            ("mac", value) => mac::parse(value).map(Self::Mac),
+            ("vendor_class", value) if !value.is_empty() => {
+                value.try_into().ok().map(Self::VendorClass)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, mac: &[u8; 6], vendor_class: Option<&[u8]>) -> bool {
+        match self {
+            Self::Mac(expected) => expected == mac,
+            Self::VendorClass(prefix) => {
+                vendor_class.is_some_and(|class| class.starts_with(prefix.as_bytes()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ClientMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mac(mac) => write!(f, "mac {}", MacDisplay(mac)),
+            Self::VendorClass(prefix) => write!(f, "vendor_class {prefix}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientOverride {
+    pub client: ClientMatch,
+    pub options: DhcpOptions,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OptionsConfig {
+    pub global: DhcpOptions,
+    pub overrides: Vec<ClientOverride, MAX_OVERRIDES>,
+}
+
+impl OptionsConfig {
+    pub const fn new() -> Self {
+        Self {
+            global: DhcpOptions::new(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Resolves the options for one client: vendor class overrides are
+    /// applied first so that a MAC override always wins.
+    pub fn for_client(&self, mac: &[u8; 6], vendor_class: Option<&[u8]>) -> DhcpOptions {
+        let mut options = self.global.clone();
+        for by_mac in [false, true] {
+            let matching = self.overrides.iter().filter(|client_override| {
+                matches!(client_override.client, ClientMatch::Mac(_)) == by_mac
+                    && client_override.client.matches(mac, vendor_class)
+            });
+            for client_override in matching {
+                options.overlay(&client_override.options);
+            }
+        }
+        options
+    }
+
+    /// Overrides are also checked on top of the global options, as clients
+    /// get them.
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        self.global.validate()?;
+        self.overrides.iter().try_for_each(|client_override| {
+            client_override.options.validate()?;
+            let mut options = self.global.clone();
+            options.overlay(&client_override.options);
+            options.validate()
+        })
+    }
+
+    /// The global options followed by each override, which starts at its
+    /// `client=` line.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        self.global.write_to(out)?;
+        for client_override in &self.overrides {
+            writeln!(out, "client={}", client_override.client)?;
+            client_override.options.write_to(out)?;
+        }
+        Ok(())
+    }
+
+    /// Reverse of [`OptionsConfig::write_to`].
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            if key == "client" {
+                let client_override = ClientOverride {
+                    client: ClientMatch::parse(value)?,
+                    options: DhcpOptions::new(),
+                };
+                config.overrides.push(client_override).ok()?;
+                continue;
+            }
+            let options = match config.overrides.last_mut() {
+                Some(client_override) => &mut client_override.options,
+                None => &mut config.global,
+            };
+            options.parse_line(key, value)?;
+        }
+        Some(config)
+    }
+}
+
+/// Encodes a list of domains as option 119 data (RFC 3397), without name
+/// compression.
+pub fn encode_domain_search(names: &[DomainName], out: &mut [u8]) -> Result<usize, BufferTooSmall> {
+    let mut len = 0;
+    for name in names {
+        for label in name.trim_end_matches('.').split('.') {
+            let end = len + 1 + label.len();
+            if end > out.len() {
+                return Err(BufferTooSmall);
+            }
+            out[len] = label.len() as u8;
+            out[len + 1..end].copy_from_slice(label.as_bytes());
+            len = end;
+        }
+        *out.get_mut(len).ok_or(BufferTooSmall)? = 0;
+        len += 1;
+    }
+    Ok(len)
+}
+
+/// Encodes option 121 data (RFC 3442). Clients that accept option 121
+/// ignore the router option, so the default route through `gateway` is
+/// added unless one is configured explicitly.
+pub fn encode_classless_routes(
+    routes: &[StaticRoute],
+    gateway: Ipv4Addr,
+    out: &mut [u8],
+) -> Result<usize, BufferTooSmall> {
+    let default_route = StaticRoute {
+        destination: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+        router: gateway,
+    };
+    let has_default = routes.iter().any(|route| route.prefix_len == 0);
+
+    let mut len = 0;
+    for route in routes
+        .iter()
+        .chain(core::iter::once(&default_route).filter(|_| !has_default))
+    {
+        let significant = (route.prefix_len as usize).div_ceil(8);
+        let end = len + 1 + significant + 4;
+        if end > out.len() {
+            return Err(BufferTooSmall);
+        }
+        out[len] = route.prefix_len;
+        out[len + 1..len + 1 + significant]
+            .copy_from_slice(&route.destination.octets()[..significant]);
+        out[len + 1 + significant..end].copy_from_slice(&route.router.octets());
+        len = end;
+    }
+    Ok(len)
+}
+
+pub fn is_valid_domain(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        })
+}
+
+// Codes the server fills in itself or that carry protocol state
+fn is_reserved(code: u8) -> bool {
+    matches!(code, 0 | 1 | 3 | 50..=61 | 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::packet::{
+        MessageType, Packet, OPT_CLIENT_ID, OPT_MESSAGE_TYPE, OPT_PARAMETER_LIST,
+    };
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+    // A DISCOVER and the REQUEST that follows it as ISC dhclient 4.4 sends
+    // them with Debian's stock dhclient.conf and `send
+    // dhcp-client-identifier = hardware;`: the hostname, a client id built
+    // from the MAC, Debian's parameter request list and padding to the 300
+    // bytes of a BOOTP message.
+    const DHCLIENT_DISCOVER: &[u8] = &[
+        0x01, 0x01, 0x06, 0x00, 0x5e, 0x1b, 0x3c, 0x92, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00,
+        0x27, 0x4e, 0x8c, 0x1d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x63, 0x82, 0x53, 0x63,
+        0x35, 0x01, 0x01, 0x3d, 0x07, 0x01, 0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d, 0x0c, 0x06, 0x64,
+        0x65, 0x62, 0x69, 0x61, 0x6e, 0x37, 0x0d, 0x01, 0x1c, 0x02, 0x03, 0x0f, 0x06, 0x77, 0x0c,
+        0x2c, 0x2f, 0x1a, 0x79, 0x2a, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    const DHCLIENT_REQUEST: &[u8] = &[
+        0x01, 0x01, 0x06, 0x00, 0x5e, 0x1b, 0x3c, 0x92, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00,
+        0x27, 0x4e, 0x8c, 0x1d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x63, 0x82, 0x53, 0x63,
+        0x35, 0x01, 0x03, 0x3d, 0x07, 0x01, 0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d, 0x36, 0x04, 0xc0,
+        0xa8, 0x04, 0x01, 0x32, 0x04, 0xc0, 0xa8, 0x04, 0x0a, 0x0c, 0x06, 0x64, 0x65, 0x62, 0x69,
+        0x61, 0x6e, 0x37, 0x0d, 0x01, 0x1c, 0x02, 0x03, 0x0f, 0x06, 0x77, 0x0c, 0x2c, 0x2f, 0x1a,
+        0x79, 0x2a, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn name(s: &str) -> DomainName {
+        DomainName::try_from(s).unwrap()
+    }
+
+    /// Encodes `options` into a reply to a discover asking for `requested`,
+    /// and returns the reply.
+    fn reply<'a>(options: &DhcpOptions, requested: &[u8], out: &'a mut [u8]) -> Packet<'a> {
+        let mut request = [0u8; MAX_MESSAGE_LEN];
+        let mut writer =
+            PacketWriter::request(&mut request, 1, 0, Ipv4Addr::UNSPECIFIED, &[2; 6]).unwrap();
+        writer.message_type(MessageType::Discover).unwrap();
+        writer.option(OPT_PARAMETER_LIST, requested).unwrap();
+        let len = writer.finish().unwrap();
+        let request = Packet::decode(&request[..len]).unwrap();
+
+        let mut writer = PacketWriter::reply_to(out, &request, GATEWAY, GATEWAY).unwrap();
+        writer.message_type(MessageType::Offer).unwrap();
+        options.encode(&mut writer, request.parameter_list(), GATEWAY);
+        let len = writer.finish().unwrap();
+        Packet::decode(&out[..len]).unwrap()
+    }
+
+    /// Answers a captured client message the way the server does, with the
+    /// options for that client.
+    fn answer<'a>(
+        config: &OptionsConfig,
+        captured: &[u8],
+        message_type: MessageType,
+        out: &'a mut [u8],
+    ) -> Packet<'a> {
+        let request = Packet::decode(captured).unwrap();
+        let options = config.for_client(&request.mac(), request.vendor_class());
+        let mut writer = PacketWriter::reply_to(out, &request, GATEWAY, GATEWAY).unwrap();
+        writer.message_type(message_type).unwrap();
+        options.encode(&mut writer, request.parameter_list(), GATEWAY);
+        let len = writer.finish().unwrap();
+        Packet::decode(&out[..len]).unwrap()
+    }
+
+    #[test]
+    fn encodes_requested_options() {
+        let mut options = DhcpOptions::new();
+        options.dns.push(Ipv4Addr::new(1, 1, 1, 1)).unwrap();
+        options.domain_name = Some(name("lan"));
+        options.mtu = Some(1400);
+        options
+            .routes
+            .push(StaticRoute::parse("10.0.0.0/8 via 192.168.4.2").unwrap())
+            .unwrap();
+
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        let reply = reply(
+            &options,
+            &[OPT_DNS, OPT_MTU, OPT_CLASSLESS_ROUTES],
+            &mut out,
+        );
+        assert_eq!(reply.option(OPT_DNS), Some(&[1, 1, 1, 1][..]));
+        assert_eq!(reply.option(OPT_MTU), Some(&1400u16.to_be_bytes()[..]));
+        assert_eq!(reply.option(OPT_DOMAIN_NAME), None);
+        // The default route through the gateway is added
+        assert_eq!(
+            reply.option(OPT_CLASSLESS_ROUTES),
+            Some(&[8, 10, 192, 168, 4, 2, 0, 192, 168, 4, 1][..])
+        );
+    }
+
+    #[test]
+    fn sends_custom_options_only_when_requested() {
+        let mut options = DhcpOptions::new();
+        options
+            .custom
+            .push(CustomOption::parse("43:0104c0a80201").unwrap())
+            .unwrap();
+
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        assert_eq!(reply(&options, &[OPT_DNS], &mut out).option(43), None);
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        assert_eq!(
+            reply(&options, &[OPT_DNS, 43], &mut out).option(43),
+            Some(&[1, 4, 192, 168, 2, 1][..])
+        );
+    }
+
+    #[test]
+    fn encodes_domain_search() {
+        let mut out = [0u8; 32];
+        let len = encode_domain_search(&[name("example.com."), name("lan")], &mut out).unwrap();
+        assert_eq!(&out[..len], b"\x07example\x03com\x00\x03lan\x00");
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        let mut options = DhcpOptions::new();
+        options.dns.push(Ipv4Addr::new(1, 1, 1, 1)).unwrap();
+        options.ntp.push(Ipv4Addr::new(192, 168, 4, 1)).unwrap();
+        options.domain_search.push(name("example.com")).unwrap();
+        options
+            .routes
+            .push(StaticRoute::parse("10.1.0.0/16 via 192.168.4.2").unwrap())
+            .unwrap();
+        options.captive_portal = Some(String::try_from("http://192.168.4.1/login").unwrap());
+        options
+            .custom
+            .push(CustomOption::parse("224:00ff").unwrap())
+            .unwrap();
+
+        let requested = [
+            OPT_DNS,
+            OPT_NTP,
+            OPT_DOMAIN_SEARCH,
+            OPT_CLASSLESS_ROUTES,
+            114,
+            224,
+        ];
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        let reply = reply(&options, &requested, &mut out);
+        assert_eq!(reply.options_iter().count(), 7);
+        let len: usize = reply.options_iter().map(|(_, data)| 2 + data.len()).sum();
+        // Everything but the message type
+        assert_eq!(len - 3, options.encoded_len());
+    }
+
+    #[test]
+    fn rejects_options_too_large_for_a_reply() {
+        let mut options = DhcpOptions::new();
+        options.captive_portal = Some(String::try_from("http://192.168.4.1/").unwrap());
+        for code in [224, 225, 226] {
+            let mut custom = CustomOption {
+                code,
+                data: Vec::new(),
+            };
+            custom.data.resize(64, 0).unwrap();
+            options.custom.push(custom).unwrap();
+        }
+        assert_eq!(options.validate(), Ok(()));
+
+        for domain in ["a.example.com", "b.example.com", "c.example.com"] {
+            let mut long = String::<64>::new();
+            long.push_str(&"x".repeat(63 - domain.len())).unwrap();
+            long.push('.').unwrap();
+            long.push_str(domain).unwrap();
+            options.domain_search.push(long).unwrap();
+        }
+        assert_eq!(options.validate(), Err(OptionsError::TooLarge));
+
+        // Nor on top of the global options
+        let mut config = OptionsConfig::new();
+        config.global = options.clone();
+        config.global.custom.clear();
+        assert_eq!(config.validate(), Ok(()));
+        let mut custom = DhcpOptions::new();
+        custom.custom = options.custom.clone();
+        config
+            .overrides
+            .push(ClientOverride {
+                client: ClientMatch::Mac([2; 6]),
+                options: custom,
+            })
+            .unwrap();
+        assert_eq!(config.validate(), Err(OptionsError::TooLarge));
+    }
+
+    #[test]
+    fn leaves_out_options_that_dont_fit() {
+        let mut options = DhcpOptions::new();
+        options.dns.push(Ipv4Addr::new(1, 1, 1, 1)).unwrap();
+        options.domain_name = Some(name(&"x".repeat(60)));
+
+        let mut out = [0u8; 300];
+        let reply = reply(&options, &[OPT_DNS, OPT_DOMAIN_NAME], &mut out[..260]);
+        assert_eq!(reply.option(OPT_DNS), Some(&[1, 1, 1, 1][..]));
+        assert_eq!(reply.option(OPT_DOMAIN_NAME), None);
+    }
+
+    fn captured_config() -> OptionsConfig {
+        let mut config = OptionsConfig::new();
+        let global = &mut config.global;
+        global.dns.push(Ipv4Addr::new(1, 1, 1, 1)).unwrap();
+        global.domain_name = Some(name("lan"));
+        global.domain_search.push(name("lan")).unwrap();
+        global.ntp.push(GATEWAY).unwrap();
+        global.mtu = Some(1400);
+        global
+            .routes
+            .push(StaticRoute::parse("10.0.0.0/8 via 192.168.4.2").unwrap())
+            .unwrap();
+        // dhclient asks for neither of these
+        global.captive_portal = Some(String::try_from("http://192.168.4.1/").unwrap());
+        global
+            .custom
+            .push(CustomOption::parse("43:0104c0a80201").unwrap())
+            .unwrap();
+        config
+    }
+
+    #[test]
+    fn reads_dhclient_messages() {
+        let discover = Packet::decode(DHCLIENT_DISCOVER).unwrap();
+        assert_eq!(discover.message_type(), Some(MessageType::Discover));
+        assert_eq!(discover.mac(), [0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d]);
+        assert_eq!(
+            discover.option(OPT_CLIENT_ID),
+            Some(&[1, 0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d][..])
+        );
+        assert_eq!(discover.hostname(), Some("debian"));
+        assert_eq!(
+            discover.parameter_list(),
+            &[1, 28, 2, 3, 15, 6, 119, 12, 44, 47, 26, 121, 42]
+        );
+
+        let request = Packet::decode(DHCLIENT_REQUEST).unwrap();
+        assert_eq!(request.message_type(), Some(MessageType::Request));
+        assert_eq!(request.requested_ip(), Some(Ipv4Addr::new(192, 168, 4, 10)));
+        assert_eq!(request.server_id(), Some(GATEWAY));
+        assert_eq!(request.parameter_list(), discover.parameter_list());
+    }
+
+    #[test]
+    fn answers_dhclient_with_the_options_it_asked_for() {
+        let expected: [(u8, &[u8]); 7] = [
+            (OPT_MESSAGE_TYPE, &[MessageType::Offer as u8]),
+            (OPT_DNS, &[1, 1, 1, 1]),
+            (OPT_DOMAIN_NAME, b"lan"),
+            (OPT_NTP, &[192, 168, 4, 1]),
+            (OPT_MTU, &[0x05, 0x78]),
+            (OPT_DOMAIN_SEARCH, b"\x03lan\x00"),
+            (
+                OPT_CLASSLESS_ROUTES,
+                &[8, 10, 192, 168, 4, 2, 0, 192, 168, 4, 1],
+            ),
+        ];
+        let config = captured_config();
+
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        let offer = answer(&config, DHCLIENT_DISCOVER, MessageType::Offer, &mut out);
+        assert!(offer.options_iter().eq(expected));
+
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        let ack = answer(&config, DHCLIENT_REQUEST, MessageType::Ack, &mut out);
+        assert_eq!(ack.message_type(), Some(MessageType::Ack));
+        assert!(ack.options_iter().skip(1).eq(expected[1..].iter().copied()));
+    }
+
+    #[test]
+    fn applies_a_mac_override_to_dhclient() {
+        let mut config = captured_config();
+        let mut options = DhcpOptions::new();
+        options.dns.push(Ipv4Addr::new(9, 9, 9, 9)).unwrap();
+        config
+            .overrides
+            .push(ClientOverride {
+                client: ClientMatch::Mac([0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d]),
+                options,
+            })
+            .unwrap();
+
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        let ack = answer(&config, DHCLIENT_REQUEST, MessageType::Ack, &mut out);
+        assert_eq!(ack.option(OPT_DNS), Some(&[9, 9, 9, 9][..]));
+        assert_eq!(ack.option(OPT_DOMAIN_NAME), Some(&b"lan"[..]));
+    }
+
+    #[test]
+    fn stores_the_config() {
+        let mut config = captured_config();
+        let mut options = DhcpOptions::new();
+        options.dns.push(Ipv4Addr::new(9, 9, 9, 9)).unwrap();
+        options.mtu = Some(1280);
+        for client in [
+            ClientMatch::Mac([0x08, 0x00, 0x27, 0x4e, 0x8c, 0x1d]),
+            ClientMatch::VendorClass(String::try_from("android-dhcp").unwrap()),
+        ] {
+            config
+                .overrides
+                .push(ClientOverride {
+                    client,
+                    options: options.clone(),
+                })
+                .unwrap();
+        }
+
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert!(text.contains("route=10.0.0.0/8 via 192.168.4.2\n"));
+        assert!(text.contains("custom=43:0104c0a80201\n"));
+        assert!(text.contains("client=mac 08:00:27:4e:8c:1d\n"));
+        assert_eq!(OptionsConfig::parse(&text), Some(config));
+        assert_eq!(OptionsConfig::parse(""), Some(OptionsConfig::new()));
+        assert_eq!(OptionsConfig::parse("client=ip 192.168.4.2"), None);
+    }
+}
@@ -0,0 +1,404 @@
+use core::net::Ipv4Addr;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+/// Size of the buffers messages are received into and built in.
+pub const MAX_MESSAGE_LEN: usize = 600;
+
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+pub const HEADER_LEN: usize = 236;
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+pub const BROADCAST_FLAG: u16 = 0x8000;
+
+pub const OPT_PAD: u8 = 0;
+pub const OPT_SUBNET_MASK: u8 = 1;
+pub const OPT_ROUTER: u8 = 3;
+pub const OPT_DNS: u8 = 6;
+pub const OPT_HOSTNAME: u8 = 12;
+pub const OPT_DOMAIN_NAME: u8 = 15;
+pub const OPT_MTU: u8 = 26;
+pub const OPT_NTP: u8 = 42;
+pub const OPT_REQUESTED_IP: u8 = 50;
+pub const OPT_LEASE_TIME: u8 = 51;
+pub const OPT_MESSAGE_TYPE: u8 = 53;
+pub const OPT_SERVER_ID: u8 = 54;
+pub const OPT_PARAMETER_LIST: u8 = 55;
+pub const OPT_MESSAGE: u8 = 56;
+pub const OPT_RENEWAL_TIME: u8 = 58;
+pub const OPT_REBINDING_TIME: u8 = 59;
+pub const OPT_VENDOR_CLASS: u8 = 60;
+pub const OPT_CLIENT_ID: u8 = 61;
+pub const OPT_RELAY_AGENT_INFO: u8 = 82;
+pub const OPT_CAPTIVE_PORTAL: u8 = 114;
+pub const OPT_DOMAIN_SEARCH: u8 = 119;
+pub const OPT_CLASSLESS_ROUTES: u8 = 121;
+pub const OPT_END: u8 = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    NotEthernet,
+    BadMagicCookie,
+    BadOption,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferTooSmall;
+
+/// A decoded BOOTP/DHCP message. Options are kept in their wire format and
+/// looked up on demand.
+#[derive(Clone, Copy, Debug)]
+pub struct Packet<'a> {
+    pub op: u8,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub options: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        if buf.len() < HEADER_LEN + MAGIC_COOKIE.len() {
+            return Err(DecodeError::Truncated);
+        }
+        // Only Ethernet (htype 1, hlen 6) hardware addresses are supported
+        if buf[1] != 1 || buf[2] != 6 {
+            return Err(DecodeError::NotEthernet);
+        }
+        if buf[HEADER_LEN..HEADER_LEN + 4] != MAGIC_COOKIE {
+            return Err(DecodeError::BadMagicCookie);
+        }
+
+        let packet = Self {
+            op: buf[0],
+            hops: buf[3],
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            secs: u16::from_be_bytes([buf[8], buf[9]]),
+            flags: u16::from_be_bytes([buf[10], buf[11]]),
+            ciaddr: read_ip(&buf[12..]),
+            yiaddr: read_ip(&buf[16..]),
+            siaddr: read_ip(&buf[20..]),
+            giaddr: read_ip(&buf[24..]),
+            chaddr: buf[28..44].try_into().unwrap(),
+            options: &buf[HEADER_LEN + 4..],
+        };
+
+        // Walk the options once so later lookups can't run off the end
+        let mut options = packet.options_iter();
+        for _ in options.by_ref() {}
+        if options.malformed {
+            return Err(DecodeError::BadOption);
+        }
+
+        Ok(packet)
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.op == BOOTREQUEST
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & BROADCAST_FLAG != 0
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.chaddr[..6].try_into().unwrap()
+    }
+
+    pub fn options_iter(&self) -> OptionsIter<'a> {
+        OptionsIter {
+            buf: self.options,
+            malformed: false,
+        }
+    }
+
+    pub fn option(&self, code: u8) -> Option<&'a [u8]> {
+        self.options_iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| data)
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        match self.option(OPT_MESSAGE_TYPE)? {
+            [value] => MessageType::from_u8(*value),
+            _ => None,
+        }
+    }
+
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.option(OPT_REQUESTED_IP).and_then(ip_option)
+    }
+
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.option(OPT_SERVER_ID).and_then(ip_option)
+    }
+
+    pub fn u32_option(&self, code: u8) -> Option<u32> {
+        self.option(code)
+            .and_then(|data| <[u8; 4]>::try_from(data).ok())
+            .map(u32::from_be_bytes)
+    }
+
+    /// Addresses from an option holding a list of them (routers, DNS...).
+    pub fn ip_list(&self, code: u8) -> impl Iterator<Item = Ipv4Addr> + 'a {
+        self.option(code)
+            .unwrap_or(&[])
+            .chunks_exact(4)
+            .map(read_ip)
+    }
+
+    pub fn hostname(&self) -> Option<&'a str> {
+        self.option(OPT_HOSTNAME)
+            .and_then(|data| core::str::from_utf8(data).ok())
+    }
+
+    pub fn vendor_class(&self) -> Option<&'a [u8]> {
+        self.option(OPT_VENDOR_CLASS)
+    }
+
+    pub fn parameter_list(&self) -> &'a [u8] {
+        self.option(OPT_PARAMETER_LIST).unwrap_or(&[])
+    }
+}
+
+pub struct OptionsIter<'a> {
+    buf: &'a [u8],
+    malformed: bool,
+}
+
+impl<'a> Iterator for OptionsIter<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&code, rest) = self.buf.split_first()?;
+            match code {
+                OPT_PAD => self.buf = rest,
+                OPT_END => {
+                    self.buf = &[];
+                    return None;
+                }
+                _ => {
+                    let Some((&len, rest)) = rest.split_first() else {
+                        self.malformed = true;
+                        self.buf = &[];
+                        return None;
+                    };
+                    if rest.len() < len as usize {
+                        self.malformed = true;
+                        self.buf = &[];
+                        return None;
+                    }
+                    let (data, rest) = rest.split_at(len as usize);
+                    self.buf = rest;
+                    return Some((code, data));
+                }
+            }
+        }
+    }
+}
+
+/// Serializes a message into a caller-provided buffer. The fixed header is
+/// written by [`PacketWriter::reply_to`] or [`PacketWriter::request`], options are appended with
+/// [`PacketWriter::option`] and the message is closed with
+/// [`PacketWriter::finish`].
+pub struct PacketWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> PacketWriter<'a> {
+    pub fn reply_to(
+        buf: &'a mut [u8],
+        request: &Packet<'_>,
+        yiaddr: Ipv4Addr,
+        siaddr: Ipv4Addr,
+    ) -> Result<Self, BufferTooSmall> {
+        Self::new(
+            buf,
+            BOOTREPLY,
+            request.xid,
+            request.flags,
+            request.ciaddr,
+            yiaddr,
+            siaddr,
+            request.giaddr,
+            &request.chaddr,
+        )
+    }
+
+    /// Starts a client message from the station side.
+    pub fn request(
+        buf: &'a mut [u8],
+        xid: u32,
+        flags: u16,
+        ciaddr: Ipv4Addr,
+        mac: &[u8; 6],
+    ) -> Result<Self, BufferTooSmall> {
+        let mut chaddr = [0u8; 16];
+        chaddr[..6].copy_from_slice(mac);
+        Self::new(
+            buf,
+            BOOTREQUEST,
+            xid,
+            flags,
+            ciaddr,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &chaddr,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        buf: &'a mut [u8],
+        op: u8,
+        xid: u32,
+        flags: u16,
+        ciaddr: Ipv4Addr,
+        yiaddr: Ipv4Addr,
+        siaddr: Ipv4Addr,
+        giaddr: Ipv4Addr,
+        chaddr: &[u8; 16],
+    ) -> Result<Self, BufferTooSmall> {
+        if buf.len() < HEADER_LEN + MAGIC_COOKIE.len() + 1 {
+            return Err(BufferTooSmall);
+        }
+        buf[..HEADER_LEN].fill(0);
+        buf[0] = op;
+        buf[1] = 1;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&xid.to_be_bytes());
+        buf[10..12].copy_from_slice(&flags.to_be_bytes());
+        buf[12..16].copy_from_slice(&ciaddr.octets());
+        buf[16..20].copy_from_slice(&yiaddr.octets());
+        buf[20..24].copy_from_slice(&siaddr.octets());
+        buf[24..28].copy_from_slice(&giaddr.octets());
+        buf[28..44].copy_from_slice(chaddr);
+        buf[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&MAGIC_COOKIE);
+
+        Ok(Self {
+            buf,
+            len: HEADER_LEN + MAGIC_COOKIE.len(),
+        })
+    }
+
+    /// Starts a verbatim copy of the fixed header of `raw`, as a relay agent
+    /// sends it on, with the hop count and gateway address replaced.
+    pub fn relayed(
+        buf: &'a mut [u8],
+        raw: &[u8],
+        hops: u8,
+        giaddr: Ipv4Addr,
+    ) -> Result<Self, BufferTooSmall> {
+        let len = HEADER_LEN + MAGIC_COOKIE.len();
+        if buf.len() < len + 1 || raw.len() < len {
+            return Err(BufferTooSmall);
+        }
+        buf[..len].copy_from_slice(&raw[..len]);
+        buf[3] = hops;
+        buf[24..28].copy_from_slice(&giaddr.octets());
+        Ok(Self { buf, len })
+    }
+
+    pub fn option(&mut self, code: u8, data: &[u8]) -> Result<&mut Self, BufferTooSmall> {
+        // Options longer than 255 bytes are split into several instances of
+        // the same code (RFC 3396)
+        let mut chunks = data.chunks(255).peekable();
+        if chunks.peek().is_none() {
+            return self.raw_option(code, &[]);
+        }
+        for chunk in chunks {
+            self.raw_option(code, chunk)?;
+        }
+        Ok(self)
+    }
+
+    pub fn ip_option(&mut self, code: u8, ips: &[Ipv4Addr]) -> Result<&mut Self, BufferTooSmall> {
+        let mut data = [0u8; 252];
+        let count = ips.len().min(data.len() / 4);
+        for (chunk, ip) in data.chunks_exact_mut(4).zip(&ips[..count]) {
+            chunk.copy_from_slice(&ip.octets());
+        }
+        self.option(code, &data[..count * 4])
+    }
+
+    pub fn u32_option(&mut self, code: u8, value: u32) -> Result<&mut Self, BufferTooSmall> {
+        self.option(code, &value.to_be_bytes())
+    }
+
+    pub fn message_type(&mut self, message_type: MessageType) -> Result<&mut Self, BufferTooSmall> {
+        self.option(OPT_MESSAGE_TYPE, &[message_type as u8])
+    }
+
+    /// Appends the END option and returns the length of the encoded message.
+    pub fn finish(self) -> Result<usize, BufferTooSmall> {
+        if self.len >= self.buf.len() {
+            return Err(BufferTooSmall);
+        }
+        self.buf[self.len] = OPT_END;
+        // Pad to the minimum BOOTP message size some clients still insist on
+        let len = (self.len + 1).max(300).min(self.buf.len());
+        self.buf[self.len + 1..len].fill(OPT_PAD);
+        Ok(len)
+    }
+
+    fn raw_option(&mut self, code: u8, data: &[u8]) -> Result<&mut Self, BufferTooSmall> {
+        // Always keep room for the END option
+        if self.len + 2 + data.len() + 1 > self.buf.len() {
+            return Err(BufferTooSmall);
+        }
+        self.buf[self.len] = code;
+        self.buf[self.len + 1] = data.len() as u8;
+        self.buf[self.len + 2..self.len + 2 + data.len()].copy_from_slice(data);
+        self.len += 2 + data.len();
+        Ok(self)
+    }
+}
+
+fn read_ip(buf: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3])
+}
+
+fn ip_option(data: &[u8]) -> Option<Ipv4Addr> {
+    match data {
+        [a, b, c, d] => Some(Ipv4Addr::new(*a, *b, *c, *d)),
+        _ => None,
+    }
+}
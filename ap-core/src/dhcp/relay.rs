@@ -0,0 +1,456 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use super::packet::{MessageType, Packet, PacketWriter, OPT_RELAY_AGENT_INFO};
+
+/// Requests that went through more relays than this are dropped (RFC 1542).
+const MAX_HOPS: u8 = 16;
+const SUBOPT_CIRCUIT_ID: u8 = 1;
+const SUBOPT_REMOTE_ID: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    NoServer,
+    InvalidAgentId,
+}
+
+impl ConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoServer => "an upstream DHCP server is required",
+            Self::InvalidAgentId => "circuit and remote IDs must be printable",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayConfig {
+    pub enabled: bool,
+    pub server: Ipv4Addr,
+    /// Relay agent information (option 82) sub-options, left out when empty.
+    pub circuit_id: String<32>,
+    pub remote_id: String<32>,
+}
+
+impl RelayConfig {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            server: Ipv4Addr::UNSPECIFIED,
+            circuit_id: String::new(),
+            remote_id: String::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.enabled && (self.server.is_unspecified() || self.server.is_broadcast()) {
+            return Err(ConfigError::NoServer);
+        }
+        if self.circuit_id.chars().any(char::is_control)
+            || self.remote_id.chars().any(char::is_control)
+        {
+            return Err(ConfigError::InvalidAgentId);
+        }
+        Ok(())
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "enabled={}", self.enabled as u8)?;
+        writeln!(out, "server={}", self.server)?;
+        writeln!(out, "circuit_id={}", self.circuit_id)?;
+        writeln!(out, "remote_id={}", self.remote_id)
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "enabled" => config.enabled = value == "1",
+                "server" => config.server = value.parse().ok()?,
+                "circuit_id" => config.circuit_id = value.try_into().ok()?,
+                "remote_id" => config.remote_id = value.try_into().ok()?,
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+
+    fn agent_info(&self) -> Vec<u8, 68> {
+        let mut info = Vec::new();
+        for (code, id) in [
+            (SUBOPT_CIRCUIT_ID, &self.circuit_id),
+            (SUBOPT_REMOTE_ID, &self.remote_id),
+        ] {
+            if !id.is_empty() {
+                _ = info.push(code);
+                _ = info.push(id.len() as u8);
+                _ = info.extend_from_slice(id.as_bytes());
+            }
+        }
+        info
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelayError {
+    Malformed,
+    /// A reply from an AP client or a request from upstream.
+    WrongDirection,
+    TooManyHops,
+    /// A client request that already carries relay agent information, which
+    /// a client has no business sending (RFC 3046).
+    UntrustedAgentInfo,
+    /// A reply for another relay agent.
+    NotOurs,
+    BufferTooSmall,
+}
+
+impl RelayError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed DHCP message",
+            Self::WrongDirection => "message sent the wrong way",
+            Self::TooManyHops => "too many relay hops",
+            Self::UntrustedAgentInfo => "client sent relay agent information",
+            Self::NotOurs => "reply is for another relay agent",
+            Self::BufferTooSmall => "message too large to relay",
+        }
+    }
+}
+
+/// Rewrites a request from an AP client for the upstream server: `giaddr`
+/// tells the server which subnet to pick an address from and where to send
+/// the reply. Returns the length written to `out`.
+pub fn relay_request(
+    raw: &[u8],
+    giaddr: Ipv4Addr,
+    config: &RelayConfig,
+    out: &mut [u8],
+) -> Result<usize, RelayError> {
+    let request = Packet::decode(raw).map_err(|_| RelayError::Malformed)?;
+    if !request.is_request() {
+        return Err(RelayError::WrongDirection);
+    }
+    if request.hops >= MAX_HOPS {
+        return Err(RelayError::TooManyHops);
+    }
+    // Another relay on the AP network already did the job, just pass it on
+    let first_hop = request.giaddr.is_unspecified();
+    if first_hop && request.option(OPT_RELAY_AGENT_INFO).is_some() {
+        return Err(RelayError::UntrustedAgentInfo);
+    }
+    let giaddr = if first_hop { giaddr } else { request.giaddr };
+
+    let mut writer = PacketWriter::relayed(out, raw, request.hops + 1, giaddr)
+        .map_err(|_| RelayError::BufferTooSmall)?;
+    for (code, data) in request.options_iter() {
+        writer
+            .option(code, data)
+            .map_err(|_| RelayError::BufferTooSmall)?;
+    }
+    let agent_info = config.agent_info();
+    if first_hop && !agent_info.is_empty() {
+        writer
+            .option(OPT_RELAY_AGENT_INFO, &agent_info)
+            .map_err(|_| RelayError::BufferTooSmall)?;
+    }
+    writer.finish().map_err(|_| RelayError::BufferTooSmall)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelayedReply {
+    pub len: usize,
+    pub to: Ipv4Addr,
+}
+
+/// Rewrites a server reply for the AP client it is meant for, dropping the
+/// relay agent information the server echoed back.
+///
+/// Clients without an address get the reply by broadcast even if they
+/// didn't ask for one: there is no way to unicast to an address that the
+/// AP stack can't resolve yet.
+pub fn relay_reply(
+    raw: &[u8],
+    giaddr: Ipv4Addr,
+    out: &mut [u8],
+) -> Result<RelayedReply, RelayError> {
+    let reply = Packet::decode(raw).map_err(|_| RelayError::Malformed)?;
+    if reply.is_request() {
+        return Err(RelayError::WrongDirection);
+    }
+    if reply.giaddr != giaddr {
+        return Err(RelayError::NotOurs);
+    }
+
+    let mut writer = PacketWriter::relayed(out, raw, reply.hops, reply.giaddr)
+        .map_err(|_| RelayError::BufferTooSmall)?;
+    for (code, data) in reply
+        .options_iter()
+        .filter(|(code, _)| *code != OPT_RELAY_AGENT_INFO)
+    {
+        writer
+            .option(code, data)
+            .map_err(|_| RelayError::BufferTooSmall)?;
+    }
+    let len = writer.finish().map_err(|_| RelayError::BufferTooSmall)?;
+
+    let to = if reply.ciaddr.is_unspecified()
+        || reply.is_broadcast()
+        || reply.message_type() == Some(MessageType::Nak)
+    {
+        Ipv4Addr::BROADCAST
+    } else {
+        reply.ciaddr
+    };
+    Ok(RelayedReply { len, to })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::packet::OPT_HOSTNAME;
+
+    const BOOTREQUEST: u8 = 1;
+    const BOOTREPLY: u8 = 2;
+
+    const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const GIADDR: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+    const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 20);
+
+    fn config(circuit_id: &str, remote_id: &str) -> RelayConfig {
+        RelayConfig {
+            enabled: true,
+            server: Ipv4Addr::new(10, 0, 0, 1),
+            circuit_id: circuit_id.try_into().unwrap(),
+            remote_id: remote_id.try_into().unwrap(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn message(
+        op: u8,
+        hops: u8,
+        flags: u16,
+        ciaddr: Ipv4Addr,
+        giaddr: Ipv4Addr,
+        message_type: MessageType,
+        options: &[(u8, &[u8])],
+    ) -> Vec<u8, 600> {
+        let mut buf = [0u8; 600];
+        let mut chaddr = [0u8; 16];
+        chaddr[..6].copy_from_slice(&MAC);
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let mut writer = PacketWriter::new(
+            &mut buf,
+            op,
+            7,
+            flags,
+            ciaddr,
+            unspecified,
+            unspecified,
+            giaddr,
+            &chaddr,
+        )
+        .unwrap();
+        writer.message_type(message_type).unwrap();
+        for (code, data) in options {
+            writer.option(*code, data).unwrap();
+        }
+        let len = writer.finish().unwrap();
+        buf[3] = hops;
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    fn discover(hops: u8, giaddr: Ipv4Addr, options: &[(u8, &[u8])]) -> Vec<u8, 600> {
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        message(
+            BOOTREQUEST,
+            hops,
+            0,
+            unspecified,
+            giaddr,
+            MessageType::Discover,
+            options,
+        )
+    }
+
+    fn relayed(raw: &[u8], config: &RelayConfig) -> Result<Vec<u8, 600>, RelayError> {
+        let mut out = [0u8; 600];
+        let len = relay_request(raw, GIADDR, config, &mut out)?;
+        Ok(Vec::from_slice(&out[..len]).unwrap())
+    }
+
+    #[test]
+    fn first_hop_sets_giaddr_and_agent_info() {
+        let request = discover(0, Ipv4Addr::UNSPECIFIED, &[(OPT_HOSTNAME, b"phone")]);
+        let out = relayed(&request, &config("ap-1", "esp")).unwrap();
+        let packet = Packet::decode(&out).unwrap();
+        assert_eq!(packet.giaddr, GIADDR);
+        assert_eq!(packet.hops, 1);
+        assert_eq!(packet.xid, 7);
+        assert_eq!(packet.mac(), MAC);
+        assert_eq!(packet.message_type(), Some(MessageType::Discover));
+        assert_eq!(packet.hostname(), Some("phone"));
+        assert_eq!(
+            packet.option(OPT_RELAY_AGENT_INFO),
+            Some(b"\x01\x04ap-1\x02\x03esp".as_slice())
+        );
+
+        // Empty IDs are left out, and with neither there is no option at all
+        let out = relayed(&request, &config("", "esp")).unwrap();
+        let packet = Packet::decode(&out).unwrap();
+        assert_eq!(
+            packet.option(OPT_RELAY_AGENT_INFO),
+            Some(b"\x02\x03esp".as_slice())
+        );
+        let out = relayed(&request, &config("", "")).unwrap();
+        let packet = Packet::decode(&out).unwrap();
+        assert_eq!(packet.option(OPT_RELAY_AGENT_INFO), None);
+    }
+
+    #[test]
+    fn later_hops_keep_the_first_relay() {
+        let first_relay = Ipv4Addr::new(192, 168, 4, 2);
+        let request = discover(1, first_relay, &[(OPT_RELAY_AGENT_INFO, b"\x01\x01x")]);
+        let out = relayed(&request, &config("ap-1", "")).unwrap();
+        let packet = Packet::decode(&out).unwrap();
+        assert_eq!(packet.giaddr, first_relay);
+        assert_eq!(packet.hops, 2);
+        // Theirs, not ours
+        assert_eq!(
+            packet.option(OPT_RELAY_AGENT_INFO),
+            Some(b"\x01\x01x".as_slice())
+        );
+    }
+
+    #[test]
+    fn refuses_requests_it_should_not_relay() {
+        let request = discover(MAX_HOPS, Ipv4Addr::new(192, 168, 4, 2), &[]);
+        assert_eq!(
+            relayed(&request, &config("", "")),
+            Err(RelayError::TooManyHops)
+        );
+        let request = discover(MAX_HOPS - 1, Ipv4Addr::new(192, 168, 4, 2), &[]);
+        assert!(relayed(&request, &config("", "")).is_ok());
+
+        let request = discover(
+            0,
+            Ipv4Addr::UNSPECIFIED,
+            &[(OPT_RELAY_AGENT_INFO, b"\x01\x01x")],
+        );
+        assert_eq!(
+            relayed(&request, &config("", "")),
+            Err(RelayError::UntrustedAgentInfo)
+        );
+
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let offer = message(
+            BOOTREPLY,
+            0,
+            0,
+            unspecified,
+            unspecified,
+            MessageType::Offer,
+            &[],
+        );
+        assert_eq!(
+            relayed(&offer, &config("", "")),
+            Err(RelayError::WrongDirection)
+        );
+        assert_eq!(
+            relayed(&[0; 10], &config("", "")),
+            Err(RelayError::Malformed)
+        );
+    }
+
+    #[test]
+    fn replies_lose_the_agent_info() {
+        let agent_info: &[u8] = b"\x01\x04ap-1";
+        let ack = message(
+            BOOTREPLY,
+            1,
+            0,
+            CLIENT_IP,
+            GIADDR,
+            MessageType::Ack,
+            &[(OPT_RELAY_AGENT_INFO, agent_info), (OPT_HOSTNAME, b"phone")],
+        );
+        let mut out = [0u8; 600];
+        let relayed = relay_reply(&ack, GIADDR, &mut out).unwrap();
+        // A renewing client has its address and gets the reply directly
+        assert_eq!(relayed.to, CLIENT_IP);
+        let packet = Packet::decode(&out[..relayed.len]).unwrap();
+        assert_eq!(packet.option(OPT_RELAY_AGENT_INFO), None);
+        assert_eq!(packet.hostname(), Some("phone"));
+        assert_eq!(packet.message_type(), Some(MessageType::Ack));
+        assert_eq!(packet.giaddr, GIADDR);
+        assert_eq!(packet.hops, 1);
+    }
+
+    #[test]
+    fn broadcasts_replies_to_clients_without_an_address() {
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let mut out = [0u8; 600];
+        for reply in [
+            message(
+                BOOTREPLY,
+                1,
+                0,
+                unspecified,
+                GIADDR,
+                MessageType::Offer,
+                &[],
+            ),
+            message(
+                BOOTREPLY,
+                1,
+                0x8000,
+                CLIENT_IP,
+                GIADDR,
+                MessageType::Ack,
+                &[],
+            ),
+            message(BOOTREPLY, 1, 0, CLIENT_IP, GIADDR, MessageType::Nak, &[]),
+        ] {
+            let relayed = relay_reply(&reply, GIADDR, &mut out).unwrap();
+            assert_eq!(relayed.to, Ipv4Addr::BROADCAST);
+        }
+    }
+
+    #[test]
+    fn ignores_replies_for_someone_else() {
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let mut out = [0u8; 600];
+        let other = Ipv4Addr::new(192, 168, 4, 2);
+        let offer = message(BOOTREPLY, 1, 0, unspecified, other, MessageType::Offer, &[]);
+        assert_eq!(
+            relay_reply(&offer, GIADDR, &mut out),
+            Err(RelayError::NotOurs)
+        );
+        let request = discover(1, GIADDR, &[]);
+        assert_eq!(
+            relay_reply(&request, GIADDR, &mut out),
+            Err(RelayError::WrongDirection)
+        );
+    }
+
+    #[test]
+    fn validates_and_stores_the_config() {
+        let config = config("ap-1", "esp");
+        assert_eq!(config.validate(), Ok(()));
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert_eq!(RelayConfig::parse(&text), Some(config));
+
+        let mut no_server = RelayConfig::new();
+        assert_eq!(no_server.validate(), Ok(()));
+        no_server.enabled = true;
+        assert_eq!(no_server.validate(), Err(ConfigError::NoServer));
+        assert_eq!(
+            self::config("a\nb", "").validate(),
+            Err(ConfigError::InvalidAgentId)
+        );
+    }
+}
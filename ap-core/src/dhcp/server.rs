@@ -0,0 +1,763 @@
+use core::net::Ipv4Addr;
+use heapless::{Deque, String, Vec};
+
+use super::conflict::{ConflictTable, DeclineReason, Declined};
+use super::options::DhcpOptions;
+use super::packet::{
+    MessageType, Packet, PacketWriter, CLIENT_PORT, OPT_LEASE_TIME, OPT_REBINDING_TIME,
+    OPT_RENEWAL_TIME, OPT_ROUTER, OPT_SERVER_ID, OPT_SUBNET_MASK, SERVER_PORT,
+};
+use super::stats::{LeaseEvent, LeaseEventKind, Stats};
+use crate::wifi::ap_network::ApNetworkConfig;
+
+// How long an OFFERed address is held for the client before it can be reused
+const OFFER_HOLD_SECS: u64 = 60;
+const DECLINE_COOLDOWN_SECS: u64 = 10 * 60;
+const MAX_DECLINED: usize = 16;
+const MAX_PENDING_EVENTS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    pub expires: u64,
+    pub bound: bool,
+    pub hostname: String<32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub len: usize,
+    pub to: Ipv4Addr,
+    pub port: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Reply(Reply),
+    /// The address has to be probed before it can be offered. Report the
+    /// result with [`Server::record_probe`] and hand the same request in again.
+    Probe(Ipv4Addr),
+    /// A DISCOVER could not be answered because no address is left.
+    PoolExhausted,
+    Ignore,
+}
+
+/// DHCP server state machine. It is independent of the network stack: the
+/// caller feeds decoded requests together with the current time in seconds
+/// and the options resolved for the requesting client, and sends whatever
+/// reply comes back.
+pub struct Server<const N: usize> {
+    network: ApNetworkConfig,
+    lease_secs: u32,
+    leases: Vec<Lease, N>,
+    cursor: Ipv4Addr,
+    conflicts: ConflictTable<MAX_DECLINED>,
+    probe_before_offer: bool,
+    stats: Stats,
+    events: Deque<LeaseEvent, MAX_PENDING_EVENTS>,
+}
+
+impl<const N: usize> Server<N> {
+    pub fn new(network: ApNetworkConfig, lease_secs: u32, probe_before_offer: bool) -> Self {
+        Self {
+            cursor: network.pool.start,
+            network,
+            lease_secs,
+            leases: Vec::new(),
+            conflicts: ConflictTable::new(DECLINE_COOLDOWN_SECS),
+            probe_before_offer,
+            stats: Stats::new(),
+            events: Deque::new(),
+        }
+    }
+
+    pub fn network(&self) -> &ApNetworkConfig {
+        &self.network
+    }
+
+    pub fn leases(&self) -> &[Lease] {
+        &self.leases
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn record_malformed(&mut self) {
+        self.stats.malformed = self.stats.malformed.wrapping_add(1);
+    }
+
+    /// Pops the oldest lease event that hasn't been picked up yet.
+    pub fn take_event(&mut self) -> Option<LeaseEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn declined(&self) -> &[Declined] {
+        self.conflicts.declined()
+    }
+
+    pub fn forget_declined(&mut self, ip: Ipv4Addr) -> bool {
+        self.conflicts.forget(ip)
+    }
+
+    /// Switches to a new subnet or pool, dropping leases that no longer fit.
+    pub fn reconfigure(&mut self, network: ApNetworkConfig) {
+        self.remove_leases(LeaseEventKind::Expired, |lease| {
+            !network.is_assignable(lease.ip)
+        });
+        self.conflicts.clear();
+        self.cursor = network.pool.start;
+        self.network = network;
+    }
+
+    pub fn record_probe(&mut self, ip: Ipv4Addr, in_use: bool, now: u64) {
+        self.conflicts.record_probe(ip, in_use, now);
+    }
+
+    /// Expires leases whose time is up. Called on every request and
+    /// periodically in between so expiry events aren't delayed until the
+    /// next packet.
+    pub fn expire(&mut self, now: u64) {
+        self.remove_leases(LeaseEventKind::Expired, |lease| lease.expires <= now);
+        self.conflicts.expire(now);
+    }
+
+    pub fn handle(
+        &mut self,
+        now: u64,
+        request: &Packet<'_>,
+        options: &DhcpOptions,
+        out: &mut [u8],
+    ) -> Outcome {
+        if !request.is_request() {
+            return Outcome::Ignore;
+        }
+        let Some(message_type) = request.message_type() else {
+            self.record_malformed();
+            return Outcome::Ignore;
+        };
+
+        let outcome = self.respond(now, message_type, request, options, out);
+        // A request that needs a probe comes back again, count it only once
+        if !matches!(outcome, Outcome::Probe(_)) {
+            self.stats.count(message_type);
+        }
+        outcome
+    }
+
+    fn respond(
+        &mut self,
+        now: u64,
+        message_type: MessageType,
+        request: &Packet<'_>,
+        options: &DhcpOptions,
+        out: &mut [u8],
+    ) -> Outcome {
+        self.expire(now);
+
+        let mac = request.mac();
+        let hostname = request
+            .hostname()
+            .and_then(|name| String::try_from(name).ok())
+            .unwrap_or_default();
+        let reply = match message_type {
+            MessageType::Discover => {
+                let Some(ip) = self.pick_address(mac, request.requested_ip()) else {
+                    self.stats.pool_exhausted = self.stats.pool_exhausted.wrapping_add(1);
+                    return Outcome::PoolExhausted;
+                };
+                // Addresses already held for this client were probed before
+                let held = self
+                    .leases
+                    .iter()
+                    .any(|lease| lease.mac == mac && lease.ip == ip);
+                if self.probe_before_offer && !held && self.conflicts.needs_probe(ip) {
+                    return Outcome::Probe(ip);
+                }
+                if !held {
+                    self.cursor = Ipv4Addr::from(u32::from(ip).wrapping_add(1));
+                }
+                self.hold(mac, ip, now + OFFER_HOLD_SECS, false, hostname)
+                    .and_then(|_| self.reply(request, MessageType::Offer, ip, options, out))
+            }
+            MessageType::Request => {
+                if let Some(server_id) = request.server_id() {
+                    if server_id != self.network.gateway {
+                        // The client picked another server's offer
+                        self.leases.retain(|lease| lease.mac != mac || lease.bound);
+                        return Outcome::Ignore;
+                    }
+                }
+
+                let ip = request.requested_ip().unwrap_or(request.ciaddr);
+                let expires = now + self.lease_secs as u64;
+                let renewal = self
+                    .leases
+                    .iter()
+                    .any(|lease| lease.mac == mac && lease.ip == ip && lease.bound);
+                if self.is_free_for(mac, ip)
+                    && self
+                        .hold(mac, ip, expires, true, hostname.clone())
+                        .is_some()
+                {
+                    let kind = if renewal {
+                        LeaseEventKind::Renewed
+                    } else {
+                        LeaseEventKind::Granted
+                    };
+                    self.push_event(LeaseEvent {
+                        kind,
+                        mac,
+                        ip,
+                        hostname,
+                    });
+                    self.reply(request, MessageType::Ack, ip, options, out)
+                } else {
+                    self.reply(
+                        request,
+                        MessageType::Nak,
+                        Ipv4Addr::UNSPECIFIED,
+                        options,
+                        out,
+                    )
+                }
+            }
+            MessageType::Decline => {
+                // The client found the address in use after all, keep it out
+                // of the pool for a while
+                let ip = request.requested_ip().unwrap_or(request.ciaddr);
+                self.remove_leases(LeaseEventKind::Released, |lease| lease.mac == mac);
+                if self.network.contains(ip) {
+                    self.conflicts
+                        .decline(ip, Some(mac), DeclineReason::Client, now);
+                }
+                None
+            }
+            MessageType::Release => {
+                let ip = request.ciaddr;
+                self.remove_leases(LeaseEventKind::Released, |lease| {
+                    lease.mac == mac && (lease.ip == ip || ip.is_unspecified())
+                });
+                None
+            }
+            MessageType::Inform => {
+                // The client configured its address itself and only wants
+                // the other options
+                self.reply(
+                    request,
+                    MessageType::Ack,
+                    Ipv4Addr::UNSPECIFIED,
+                    options,
+                    out,
+                )
+            }
+            _ => None,
+        };
+
+        reply.map_or(Outcome::Ignore, Outcome::Reply)
+    }
+
+    /// Removes the lease of `mac` ahead of time, e.g. because the client left.
+    pub fn release(&mut self, mac: [u8; 6]) -> bool {
+        let before = self.leases.len();
+        self.remove_leases(LeaseEventKind::Released, |lease| lease.mac == mac);
+        self.leases.len() != before
+    }
+
+    /// Records an address an upstream server handed out through the relay,
+    /// so clients can still be looked up by address in relay mode.
+    pub fn record_relayed(&mut self, ack: &Packet<'_>, now: u64) {
+        let mac = ack.mac();
+        let ip = ack.yiaddr;
+        let lease_secs = ack
+            .option(OPT_LEASE_TIME)
+            .and_then(|data| <[u8; 4]>::try_from(data).ok())
+            .map_or(self.lease_secs, u32::from_be_bytes);
+        let hostname = ack
+            .hostname()
+            .and_then(|name| String::try_from(name).ok())
+            .unwrap_or_default();
+        let renewal = self
+            .leases
+            .iter()
+            .any(|lease| lease.mac == mac && lease.ip == ip && lease.bound);
+        if self
+            .hold(mac, ip, now + lease_secs as u64, true, hostname.clone())
+            .is_none()
+        {
+            return;
+        }
+        let kind = if renewal {
+            LeaseEventKind::Renewed
+        } else {
+            LeaseEventKind::Granted
+        };
+        self.push_event(LeaseEvent {
+            kind,
+            mac,
+            ip,
+            hostname,
+        });
+    }
+
+    fn remove_leases(&mut self, kind: LeaseEventKind, mut remove: impl FnMut(&Lease) -> bool) {
+        let mut index = 0;
+        while index < self.leases.len() {
+            if !remove(&self.leases[index]) {
+                index += 1;
+                continue;
+            }
+            let lease = self.leases.swap_remove(index);
+            // Pending offers were never handed out, so nobody cares about them
+            if lease.bound {
+                self.push_event(LeaseEvent {
+                    kind,
+                    mac: lease.mac,
+                    ip: lease.ip,
+                    hostname: lease.hostname,
+                });
+            }
+        }
+    }
+
+    fn push_event(&mut self, event: LeaseEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        _ = self.events.push_back(event);
+    }
+
+    fn is_free_for(&self, mac: [u8; 6], ip: Ipv4Addr) -> bool {
+        self.network.is_assignable(ip)
+            && !self.conflicts.is_declined(ip)
+            && !self
+                .leases
+                .iter()
+                .any(|lease| lease.ip == ip && lease.mac != mac)
+    }
+
+    fn pick_address(&self, mac: [u8; 6], requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(lease) = self.leases.iter().find(|lease| lease.mac == mac) {
+            return Some(lease.ip);
+        }
+        if let Some(ip) = requested.filter(|ip| self.is_free_for(mac, *ip)) {
+            return Some(ip);
+        }
+
+        // Hand out addresses round-robin so a recently released address is
+        // not immediately given to somebody else
+        let mut from = self.cursor;
+        let mut wrapped = false;
+        loop {
+            match self.network.next_assignable(from) {
+                Some(ip) if self.is_free_for(mac, ip) => return Some(ip),
+                Some(ip) => from = Ipv4Addr::from(u32::from(ip) + 1),
+                None if wrapped => return None,
+                None => {
+                    from = self.network.pool.start;
+                    wrapped = true;
+                }
+            }
+        }
+    }
+
+    fn hold(
+        &mut self,
+        mac: [u8; 6],
+        ip: Ipv4Addr,
+        expires: u64,
+        bound: bool,
+        hostname: String<32>,
+    ) -> Option<()> {
+        if let Some(lease) = self.leases.iter_mut().find(|lease| lease.mac == mac) {
+            lease.ip = ip;
+            if !hostname.is_empty() {
+                lease.hostname = hostname;
+            }
+            lease.bound |= bound;
+            lease.expires = if lease.bound && !bound {
+                lease.expires.max(expires)
+            } else {
+                expires
+            };
+            return Some(());
+        }
+
+        if self.leases.is_full() {
+            // Make room by dropping the oldest pending offer
+            let oldest = self
+                .leases
+                .iter()
+                .enumerate()
+                .filter(|(_, lease)| !lease.bound)
+                .min_by_key(|(_, lease)| lease.expires)
+                .map(|(index, _)| index)?;
+            self.leases.swap_remove(oldest);
+        }
+
+        self.leases
+            .push(Lease {
+                mac,
+                ip,
+                expires,
+                bound,
+                hostname,
+            })
+            .ok()
+    }
+
+    fn reply(
+        &mut self,
+        request: &Packet<'_>,
+        message_type: MessageType,
+        yiaddr: Ipv4Addr,
+        options: &DhcpOptions,
+        out: &mut [u8],
+    ) -> Option<Reply> {
+        let gateway = self.network.gateway;
+        let inform = request.message_type() == Some(MessageType::Inform);
+        let mut writer =
+            PacketWriter::reply_to(out, request, yiaddr, Ipv4Addr::UNSPECIFIED).ok()?;
+        writer.message_type(message_type).ok()?;
+        writer.ip_option(OPT_SERVER_ID, &[gateway]).ok()?;
+        if message_type != MessageType::Nak {
+            if !inform {
+                writer.u32_option(OPT_LEASE_TIME, self.lease_secs).ok()?;
+                writer
+                    .u32_option(OPT_RENEWAL_TIME, self.lease_secs / 2)
+                    .ok()?;
+                writer
+                    .u32_option(OPT_REBINDING_TIME, self.lease_secs / 8 * 7)
+                    .ok()?;
+            }
+            writer
+                .ip_option(OPT_SUBNET_MASK, &[self.network.netmask()])
+                .ok()?;
+            writer.ip_option(OPT_ROUTER, &[gateway]).ok()?;
+            options.encode(&mut writer, request.parameter_list(), gateway);
+        }
+        let len = writer.finish().ok()?;
+
+        let (to, port) = if !request.giaddr.is_unspecified() {
+            (request.giaddr, SERVER_PORT)
+        } else if !request.ciaddr.is_unspecified() && message_type != MessageType::Nak {
+            (request.ciaddr, CLIENT_PORT)
+        } else {
+            (Ipv4Addr::BROADCAST, CLIENT_PORT)
+        };
+
+        self.stats.count(message_type);
+        Some(Reply { len, to, port })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+    use crate::dhcp::conflict::AddressProbe;
+    use crate::dhcp::packet::{MAX_MESSAGE_LEN, OPT_REQUESTED_IP};
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+    const FIRST: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 50);
+    const SECOND: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 51);
+    const THIRD: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 52);
+
+    /// Hosts that answer probes, whatever their lease says.
+    struct SimulatedNetwork {
+        hosts: std::vec::Vec<Ipv4Addr>,
+        probed: std::vec::Vec<Ipv4Addr>,
+    }
+
+    impl AddressProbe for SimulatedNetwork {
+        async fn in_use(&mut self, ip: Ipv4Addr) -> bool {
+            self.probed.push(ip);
+            self.hosts.contains(&ip)
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn message(
+        buf: &mut [u8],
+        mac: u8,
+        message_type: MessageType,
+        requested: Option<Ipv4Addr>,
+    ) -> usize {
+        let mut writer =
+            PacketWriter::request(buf, 1, 0, Ipv4Addr::UNSPECIFIED, &[2, 0, 0, 0, 0, mac]).unwrap();
+        writer.message_type(message_type).unwrap();
+        if let Some(ip) = requested {
+            writer.ip_option(OPT_REQUESTED_IP, &[ip]).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Hands a message to the server like the DHCP task does, probing on
+    /// the simulated network when asked to. Returns the address offered or
+    /// acknowledged, if any.
+    fn exchange(
+        server: &mut Server<8>,
+        network: &mut SimulatedNetwork,
+        now: u64,
+        mac: u8,
+        message_type: MessageType,
+        requested: Option<Ipv4Addr>,
+    ) -> Option<(MessageType, Ipv4Addr)> {
+        let mut buf = [0u8; MAX_MESSAGE_LEN];
+        let len = message(&mut buf, mac, message_type, requested);
+        let request = Packet::decode(&buf[..len]).unwrap();
+        let mut out = [0u8; MAX_MESSAGE_LEN];
+        loop {
+            match server.handle(now, &request, &DhcpOptions::new(), &mut out) {
+                Outcome::Probe(ip) => {
+                    let in_use = block_on(network.in_use(ip));
+                    server.record_probe(ip, in_use, now);
+                }
+                Outcome::Reply(reply) => {
+                    let reply = Packet::decode(&out[..reply.len]).unwrap();
+                    return Some((reply.message_type()?, reply.yiaddr));
+                }
+                Outcome::PoolExhausted | Outcome::Ignore => return None,
+            }
+        }
+    }
+
+    fn setup(hosts: &[Ipv4Addr]) -> (Server<8>, SimulatedNetwork) {
+        let network = ApNetworkConfig::from_gateway(GATEWAY, 24);
+        let simulated = SimulatedNetwork {
+            hosts: hosts.to_vec(),
+            probed: std::vec::Vec::new(),
+        };
+        (Server::new(network, 3600, true), simulated)
+    }
+
+    #[test]
+    fn probes_before_offering() {
+        let (mut server, mut network) = setup(&[]);
+        let offer = exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        assert_eq!(offer, Some((MessageType::Offer, FIRST)));
+        assert_eq!(network.probed, [FIRST]);
+
+        // The address is held for the client, no need to probe it again
+        let offer = exchange(&mut server, &mut network, 1, 1, MessageType::Discover, None);
+        assert_eq!(offer, Some((MessageType::Offer, FIRST)));
+        assert_eq!(network.probed, [FIRST]);
+    }
+
+    #[test]
+    fn skips_addresses_in_use() {
+        let (mut server, mut network) = setup(&[FIRST]);
+        let offer = exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        assert_eq!(offer, Some((MessageType::Offer, SECOND)));
+        assert_eq!(network.probed, [FIRST, SECOND]);
+        assert_eq!(server.declined()[0].ip, FIRST);
+        assert_eq!(server.declined()[0].reason, DeclineReason::Probe);
+
+        // Nor is it offered to the next client, nor probed again
+        let offer = exchange(&mut server, &mut network, 1, 2, MessageType::Discover, None);
+        assert_eq!(offer, Some((MessageType::Offer, THIRD)));
+        assert_eq!(network.probed, [FIRST, SECOND, THIRD]);
+        let ack = exchange(
+            &mut server,
+            &mut network,
+            1,
+            2,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        assert_eq!(ack, Some((MessageType::Nak, Ipv4Addr::UNSPECIFIED)));
+    }
+
+    #[test]
+    fn quarantines_declined_addresses() {
+        let (mut server, mut network) = setup(&[]);
+        exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        let ack = exchange(
+            &mut server,
+            &mut network,
+            0,
+            1,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        assert_eq!(ack, Some((MessageType::Ack, FIRST)));
+
+        // The client's ARP check finds somebody else on the address
+        exchange(
+            &mut server,
+            &mut network,
+            5,
+            1,
+            MessageType::Decline,
+            Some(FIRST),
+        );
+        assert!(server.leases().is_empty());
+        assert_eq!(server.declined()[0].reason, DeclineReason::Client);
+        let offer = exchange(&mut server, &mut network, 6, 1, MessageType::Discover, None);
+        assert_eq!(offer, Some((MessageType::Offer, SECOND)));
+
+        // Until the cooldown is over
+        let later = 5 + DECLINE_COOLDOWN_SECS;
+        exchange(
+            &mut server,
+            &mut network,
+            later,
+            3,
+            MessageType::Discover,
+            None,
+        );
+        assert!(server.declined().is_empty());
+    }
+
+    #[test]
+    fn probes_again_once_a_result_is_stale() {
+        let (mut server, mut network) = setup(&[]);
+        exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        server.release([2, 0, 0, 0, 0, 1]);
+        network.hosts.push(FIRST);
+
+        // Still trusted shortly after
+        let offer = exchange(
+            &mut server,
+            &mut network,
+            10,
+            2,
+            MessageType::Discover,
+            Some(FIRST),
+        );
+        assert_eq!(offer, Some((MessageType::Offer, FIRST)));
+        server.release([2, 0, 0, 0, 0, 2]);
+
+        let offer = exchange(
+            &mut server,
+            &mut network,
+            100,
+            3,
+            MessageType::Discover,
+            Some(FIRST),
+        );
+        assert_eq!(offer, Some((MessageType::Offer, SECOND)));
+        assert_eq!(network.probed, [FIRST, FIRST, SECOND]);
+    }
+
+    #[test]
+    fn counts_messages() {
+        let (mut server, mut network) = setup(&[]);
+        exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        exchange(
+            &mut server,
+            &mut network,
+            0,
+            1,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        exchange(
+            &mut server,
+            &mut network,
+            0,
+            2,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        exchange(&mut server, &mut network, 0, 1, MessageType::Release, None);
+        server.record_malformed();
+
+        let stats = server.stats();
+        // A discover that needed a probe is only counted once
+        assert_eq!((stats.discover, stats.offer), (1, 1));
+        assert_eq!((stats.request, stats.ack, stats.nak), (2, 1, 1));
+        assert_eq!((stats.release, stats.malformed), (1, 1));
+    }
+
+    #[test]
+    fn reports_pool_exhaustion() {
+        let (mut server, mut network) = setup(&[]);
+        let mut config = ApNetworkConfig::from_gateway(GATEWAY, 24);
+        config.pool.end = FIRST;
+        server.reconfigure(config);
+
+        exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        assert_eq!(
+            exchange(&mut server, &mut network, 0, 2, MessageType::Discover, None),
+            None
+        );
+        assert_eq!(server.stats().pool_exhausted, 1);
+    }
+
+    #[test]
+    fn publishes_lease_events() {
+        let (mut server, mut network) = setup(&[]);
+        let kinds = |server: &mut Server<8>| {
+            core::iter::from_fn(|| server.take_event())
+                .map(|event| (event.kind, event.ip))
+                .collect::<std::vec::Vec<_>>()
+        };
+
+        exchange(&mut server, &mut network, 0, 1, MessageType::Discover, None);
+        assert_eq!(kinds(&mut server), []);
+        exchange(
+            &mut server,
+            &mut network,
+            0,
+            1,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        exchange(
+            &mut server,
+            &mut network,
+            10,
+            1,
+            MessageType::Request,
+            Some(FIRST),
+        );
+        assert_eq!(
+            kinds(&mut server),
+            [
+                (LeaseEventKind::Granted, FIRST),
+                (LeaseEventKind::Renewed, FIRST)
+            ]
+        );
+
+        exchange(
+            &mut server,
+            &mut network,
+            20,
+            2,
+            MessageType::Discover,
+            None,
+        );
+        exchange(
+            &mut server,
+            &mut network,
+            20,
+            2,
+            MessageType::Request,
+            Some(SECOND),
+        );
+        exchange(&mut server, &mut network, 30, 1, MessageType::Release, None);
+        server.expire(20 + 3600);
+        assert_eq!(
+            kinds(&mut server),
+            [
+                (LeaseEventKind::Granted, SECOND),
+                (LeaseEventKind::Released, FIRST),
+                (LeaseEventKind::Expired, SECOND)
+            ]
+        );
+    }
+}
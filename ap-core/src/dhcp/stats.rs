@@ -0,0 +1,76 @@
+use core::net::Ipv4Addr;
+use heapless::String;
+
+use super::packet::MessageType;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub discover: u32,
+    pub offer: u32,
+    pub request: u32,
+    pub ack: u32,
+    pub nak: u32,
+    pub release: u32,
+    pub decline: u32,
+    pub inform: u32,
+    pub malformed: u32,
+    pub pool_exhausted: u32,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            discover: 0,
+            offer: 0,
+            request: 0,
+            ack: 0,
+            nak: 0,
+            release: 0,
+            decline: 0,
+            inform: 0,
+            malformed: 0,
+            pool_exhausted: 0,
+        }
+    }
+
+    pub fn count(&mut self, message_type: MessageType) {
+        let counter = match message_type {
+            MessageType::Discover => &mut self.discover,
+            MessageType::Offer => &mut self.offer,
+            MessageType::Request => &mut self.request,
+            MessageType::Decline => &mut self.decline,
+            MessageType::Ack => &mut self.ack,
+            MessageType::Nak => &mut self.nak,
+            MessageType::Release => &mut self.release,
+            MessageType::Inform => &mut self.inform,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseEventKind {
+    Granted,
+    Renewed,
+    Expired,
+    Released,
+}
+
+impl LeaseEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Granted => "granted",
+            Self::Renewed => "renewed",
+            Self::Expired => "expired",
+            Self::Released => "released",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaseEvent {
+    pub kind: LeaseEventKind,
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    pub hostname: String<32>,
+}
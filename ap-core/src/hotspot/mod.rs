@@ -0,0 +1,4 @@
+pub mod policy;
+pub mod session;
+pub mod walled_garden;
+pub mod voucher;
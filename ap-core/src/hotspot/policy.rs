@@ -0,0 +1,294 @@
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+use super::walled_garden::domain_matches;
+use crate::dhcp::options::{is_valid_domain, DomainName};
+use crate::dhcp::packet::SERVER_PORT as DHCP_PORT;
+use crate::router::nat::Protocol;
+
+pub const MAX_GARDEN_DOMAINS: usize = 8;
+pub const MAX_LOCAL_USERS: usize = 8;
+
+const DNS_PORT: u16 = 53;
+const HTTP_PORT: u16 = 80;
+const MIN_SESSION_SECS: u32 = 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalUser {
+    pub name: String<32>,
+    pub password: String<32>,
+}
+
+impl LocalUser {
+    /// Parses `name:password`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (name, password) = s.split_once(':')?;
+        Some(Self {
+            name: name.try_into().ok()?,
+            password: password.try_into().ok()?,
+        })
+    }
+}
+
+/// What an AP client may do with a packet headed upstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Allow,
+    /// Plain HTTP from an unauthorized client, sent to the login page.
+    Redirect,
+    Deny,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolicyError {
+    SessionTooShort,
+    InvalidDomain,
+    InvalidUser,
+}
+
+impl PolicyError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::SessionTooShort => "session must last at least a minute",
+            Self::InvalidDomain => "invalid walled garden domain",
+            Self::InvalidUser => "user names must be non-empty and printable",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HotspotPolicy {
+    pub enabled: bool,
+    pub session_secs: u32,
+    pub quota_bytes: Option<u64>,
+    /// Domains (and their subdomains) reachable before logging in.
+    pub walled_garden: Vec<DomainName, MAX_GARDEN_DOMAINS>,
+    pub users: Vec<LocalUser, MAX_LOCAL_USERS>,
+}
+
+impl HotspotPolicy {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            session_secs: 60 * 60,
+            quota_bytes: None,
+            walled_garden: Vec::new(),
+            users: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), PolicyError> {
+        if self.session_secs < MIN_SESSION_SECS {
+            return Err(PolicyError::SessionTooShort);
+        }
+        if !self
+            .walled_garden
+            .iter()
+            .all(|domain| is_valid_domain(domain))
+        {
+            return Err(PolicyError::InvalidDomain);
+        }
+        let printable = |s: &str| s.chars().all(|c| !c.is_control());
+        if !self
+            .users
+            .iter()
+            .all(|user| !user.name.is_empty() && printable(&user.name) && printable(&user.password))
+        {
+            return Err(PolicyError::InvalidUser);
+        }
+        Ok(())
+    }
+
+    pub fn access(
+        &self,
+        authorized: bool,
+        protocol: Protocol,
+        dst_port: u16,
+        walled_garden: bool,
+    ) -> Access {
+        if !self.enabled || authorized || walled_garden {
+            return Access::Allow;
+        }
+        match (protocol, dst_port) {
+            (Protocol::Udp | Protocol::Tcp, DNS_PORT) => Access::Allow,
+            (Protocol::Tcp, HTTP_PORT) => Access::Redirect,
+            _ => Access::Deny,
+        }
+    }
+
+    /// What an AP client may reach on the gateway itself. Before logging in
+    /// that is DHCP, DNS and the web server on `portal_port`, which only
+    /// serves such clients the login page. `service` is the protocol and
+    /// destination port, `None` for packets that have none.
+    pub fn local_access(
+        &self,
+        authorized: bool,
+        service: Option<(Protocol, u16)>,
+        portal_port: u16,
+    ) -> bool {
+        if !self.enabled || authorized {
+            return true;
+        }
+        match service {
+            Some((Protocol::Udp, DHCP_PORT)) => true,
+            Some((Protocol::Udp | Protocol::Tcp, DNS_PORT)) => true,
+            Some((Protocol::Tcp, port)) => port == portal_port,
+            _ => false,
+        }
+    }
+
+    pub fn in_walled_garden(&self, name: &str) -> bool {
+        self.walled_garden
+            .iter()
+            .any(|domain| domain_matches(name, domain))
+    }
+
+    pub fn check_local_user(&self, name: &str, password: &str) -> bool {
+        self.users
+            .iter()
+            .any(|user| user.name == name && user.password == password)
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "enabled={}", self.enabled as u8)?;
+        writeln!(out, "session_secs={}", self.session_secs)?;
+        if let Some(quota) = self.quota_bytes {
+            writeln!(out, "quota_bytes={quota}")?;
+        }
+        for domain in &self.walled_garden {
+            writeln!(out, "garden={domain}")?;
+        }
+        for user in &self.users {
+            writeln!(out, "user={}:{}", user.name, user.password)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut policy = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "enabled" => policy.enabled = value == "1",
+                "session_secs" => policy.session_secs = value.parse().ok()?,
+                "quota_bytes" => policy.quota_bytes = Some(value.parse().ok()?),
+                "garden" => policy.walled_garden.push(value.try_into().ok()?).ok()?,
+                "user" => policy.users.push(LocalUser::parse(value)?).ok()?,
+                _ => {}
+            }
+        }
+        Some(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORTAL_PORT: u16 = 8080;
+
+    fn policy() -> HotspotPolicy {
+        let mut policy = HotspotPolicy::new();
+        policy.enabled = true;
+        policy
+            .walled_garden
+            .push("example.com".try_into().unwrap())
+            .unwrap();
+        policy
+            .users
+            .push(LocalUser::parse("alice:wonderland").unwrap())
+            .unwrap();
+        policy
+    }
+
+    #[test]
+    fn holds_back_unauthorized_clients() {
+        let policy = policy();
+        assert_eq!(
+            policy.access(false, Protocol::Udp, DNS_PORT, false),
+            Access::Allow
+        );
+        assert_eq!(
+            policy.access(false, Protocol::Tcp, HTTP_PORT, false),
+            Access::Redirect
+        );
+        assert_eq!(
+            policy.access(false, Protocol::Tcp, 443, false),
+            Access::Deny
+        );
+        assert_eq!(policy.access(false, Protocol::Icmp, 1, false), Access::Deny);
+        assert_eq!(
+            policy.access(false, Protocol::Tcp, 443, true),
+            Access::Allow
+        );
+        assert_eq!(
+            policy.access(true, Protocol::Tcp, 443, false),
+            Access::Allow
+        );
+
+        let mut disabled = policy.clone();
+        disabled.enabled = false;
+        assert_eq!(
+            disabled.access(false, Protocol::Tcp, 443, false),
+            Access::Allow
+        );
+    }
+
+    #[test]
+    fn limits_what_unauthorized_clients_reach_locally() {
+        let policy = policy();
+        let local = |service| policy.local_access(false, service, PORTAL_PORT);
+        assert!(local(Some((Protocol::Udp, DHCP_PORT))));
+        assert!(local(Some((Protocol::Udp, DNS_PORT))));
+        assert!(local(Some((Protocol::Tcp, DNS_PORT))));
+        assert!(local(Some((Protocol::Tcp, PORTAL_PORT))));
+        assert!(!local(Some((Protocol::Tcp, 22))));
+        assert!(!local(Some((Protocol::Udp, 1812))));
+        assert!(!local(Some((Protocol::Icmp, 7))));
+        assert!(!local(None));
+
+        assert!(policy.local_access(true, None, PORTAL_PORT));
+        let mut disabled = policy.clone();
+        disabled.enabled = false;
+        assert!(disabled.local_access(false, Some((Protocol::Tcp, 22)), PORTAL_PORT));
+    }
+
+    #[test]
+    fn matches_the_walled_garden_and_users() {
+        let policy = policy();
+        assert!(policy.in_walled_garden("example.com"));
+        assert!(policy.in_walled_garden("login.example.com"));
+        assert!(!policy.in_walled_garden("badexample.com"));
+        assert!(policy.check_local_user("alice", "wonderland"));
+        assert!(!policy.check_local_user("alice", "Wonderland"));
+        assert!(!policy.check_local_user("bob", "wonderland"));
+    }
+
+    #[test]
+    fn validates() {
+        assert_eq!(policy().validate(), Ok(()));
+        let mut short = policy();
+        short.session_secs = 59;
+        assert_eq!(short.validate(), Err(PolicyError::SessionTooShort));
+        let mut domain = policy();
+        domain
+            .walled_garden
+            .push("bad domain".try_into().unwrap())
+            .unwrap();
+        assert_eq!(domain.validate(), Err(PolicyError::InvalidDomain));
+        let mut user = policy();
+        user.users
+            .push(LocalUser::parse(":nameless").unwrap())
+            .unwrap();
+        assert_eq!(user.validate(), Err(PolicyError::InvalidUser));
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut policy = policy();
+        policy.quota_bytes = Some(1 << 20);
+        let mut text = std::string::String::new();
+        policy.write_to(&mut text).unwrap();
+        assert_eq!(HotspotPolicy::parse(&text), Some(policy));
+    }
+}
@@ -0,0 +1,258 @@
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    /// Sent as Acct-Session-Id, so it has to stay unique across reboots.
+    pub id: u32,
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    pub username: String<32>,
+    pub started: u64,
+    pub expires: u64,
+    pub quota_bytes: Option<u64>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    /// Logged in with a voucher, whose code is the username.
+    pub voucher: bool,
+}
+
+impl Session {
+    pub fn new(
+        id: u32,
+        mac: [u8; 6],
+        ip: Ipv4Addr,
+        username: &str,
+        now: u64,
+        duration_secs: u64,
+        quota_bytes: Option<u64>,
+    ) -> Self {
+        let mut name = String::new();
+        for c in username.chars() {
+            if name.push(c).is_err() {
+                break;
+            }
+        }
+        Self {
+            id,
+            mac,
+            ip,
+            username: name,
+            started: now,
+            expires: now.saturating_add(duration_secs),
+            quota_bytes,
+            bytes_up: 0,
+            bytes_down: 0,
+            voucher: false,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_up.saturating_add(self.bytes_down)
+    }
+
+    pub fn is_over_quota(&self) -> bool {
+        self.quota_bytes.is_some_and(|quota| self.bytes() >= quota)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires
+    }
+
+    fn end_reason(&self, now: u64) -> Option<EndReason> {
+        if self.is_expired(now) {
+            Some(EndReason::Expired)
+        } else if self.is_over_quota() {
+            Some(EndReason::QuotaExceeded)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndReason {
+    Expired,
+    QuotaExceeded,
+    Revoked,
+}
+
+impl EndReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Expired => "expired",
+            Self::QuotaExceeded => "quota exceeded",
+            Self::Revoked => "revoked",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableFull;
+
+/// Authorized hotspot clients, at most one session per MAC.
+pub struct SessionTable<const N: usize> {
+    sessions: Vec<Session, N>,
+}
+
+impl<const N: usize> SessionTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            sessions: Vec::new(),
+        }
+    }
+
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn get(&self, mac: &[u8; 6]) -> Option<&Session> {
+        self.sessions.iter().find(|session| session.mac == *mac)
+    }
+
+    /// Starts `session`, replacing and returning any earlier session of the
+    /// same client.
+    pub fn start(&mut self, session: Session) -> Result<Option<Session>, TableFull> {
+        if let Some(existing) = self.sessions.iter_mut().find(|s| s.mac == session.mac) {
+            return Ok(Some(core::mem::replace(existing, session)));
+        }
+        self.sessions.push(session).map_err(|_| TableFull)?;
+        Ok(None)
+    }
+
+    /// Both the MAC and the IP have to match, so a client can't ride on
+    /// another one's session by taking its address.
+    pub fn is_authorized(&self, mac: &[u8; 6], ip: Ipv4Addr, now: u64) -> bool {
+        self.get(mac)
+            .is_some_and(|session| session.ip == ip && session.end_reason(now).is_none())
+    }
+
+    pub fn account(&mut self, mac: &[u8; 6], bytes_up: u64, bytes_down: u64) {
+        if let Some(session) = self.sessions.iter_mut().find(|s| s.mac == *mac) {
+            session.bytes_up = session.bytes_up.saturating_add(bytes_up);
+            session.bytes_down = session.bytes_down.saturating_add(bytes_down);
+        }
+    }
+
+    /// Stops every session on voucher `code` where it is, once the voucher
+    /// has run out of data. They end as over quota on the next `take_ended`.
+    pub fn cap_voucher(&mut self, code: &str) {
+        for session in self
+            .sessions
+            .iter_mut()
+            .filter(|session| session.voucher && session.username == code)
+        {
+            session.quota_bytes = Some(session.bytes());
+        }
+    }
+
+    pub fn end(&mut self, mac: &[u8; 6]) -> Option<Session> {
+        let index = self.sessions.iter().position(|s| s.mac == *mac)?;
+        Some(self.sessions.swap_remove(index))
+    }
+
+    /// Removes and returns one session that ran out of time or data.
+    pub fn take_ended(&mut self, now: u64) -> Option<(Session, EndReason)> {
+        let (index, reason) = self
+            .sessions
+            .iter()
+            .enumerate()
+            .find_map(|(index, session)| Some((index, session.end_reason(now)?)))?;
+        Some((self.sessions.swap_remove(index), reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const B: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const A_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 50);
+    const B_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 51);
+
+    fn session(id: u32, mac: [u8; 6], ip: Ipv4Addr, quota_bytes: Option<u64>) -> Session {
+        Session::new(id, mac, ip, "alice", 100, 600, quota_bytes)
+    }
+
+    #[test]
+    fn authorizes_the_mac_and_ip_pair() {
+        let mut table = SessionTable::<2>::new();
+        table.start(session(1, A, A_IP, None)).unwrap();
+        assert!(table.is_authorized(&A, A_IP, 100));
+        // Another client on A's address, or A on another address
+        assert!(!table.is_authorized(&B, A_IP, 100));
+        assert!(!table.is_authorized(&A, B_IP, 100));
+    }
+
+    #[test]
+    fn expires() {
+        let mut table = SessionTable::<2>::new();
+        table.start(session(1, A, A_IP, None)).unwrap();
+        assert!(table.is_authorized(&A, A_IP, 699));
+        assert!(!table.is_authorized(&A, A_IP, 700));
+        assert_eq!(table.take_ended(699), None);
+        let (ended, reason) = table.take_ended(700).unwrap();
+        assert_eq!((ended.id, reason), (1, EndReason::Expired));
+        assert!(table.sessions().is_empty());
+    }
+
+    #[test]
+    fn ends_on_quota() {
+        let mut table = SessionTable::<2>::new();
+        table.start(session(1, A, A_IP, Some(1000))).unwrap();
+        table.account(&A, 600, 300);
+        assert!(table.is_authorized(&A, A_IP, 100));
+        table.account(&A, 0, 100);
+        assert!(!table.is_authorized(&A, A_IP, 100));
+        let (ended, reason) = table.take_ended(100).unwrap();
+        assert_eq!((ended.bytes_up, ended.bytes_down), (600, 400));
+        assert_eq!(reason, EndReason::QuotaExceeded);
+    }
+
+    #[test]
+    fn capping_a_voucher_ends_all_its_sessions() {
+        let mut table = SessionTable::<3>::new();
+        for (id, mac, ip) in [(1, A, A_IP), (2, B, B_IP)] {
+            let mut session = Session::new(id, mac, ip, "ABCD2345", 100, 600, Some(1000));
+            session.voucher = true;
+            table.start(session).unwrap();
+        }
+        // A local user who happens to have the code as their name
+        table
+            .start(Session::new(3, [9; 6], A_IP, "ABCD2345", 100, 600, None))
+            .unwrap();
+        table.account(&A, 100, 200);
+
+        table.cap_voucher("ABCD2345");
+        assert!(!table.is_authorized(&A, A_IP, 100));
+        assert!(!table.is_authorized(&B, B_IP, 100));
+        assert!(table.is_authorized(&[9; 6], A_IP, 100));
+        let (ended, reason) = table.take_ended(100).unwrap();
+        assert_eq!(reason, EndReason::QuotaExceeded);
+        assert_eq!(ended.bytes(), if ended.id == 1 { 300 } else { 0 });
+        assert!(table.take_ended(100).is_some());
+        assert_eq!(table.take_ended(100), None);
+    }
+
+    #[test]
+    fn one_session_per_client() {
+        let mut table = SessionTable::<2>::new();
+        assert_eq!(table.start(session(1, A, A_IP, None)), Ok(None));
+        let replaced = table.start(session(2, A, A_IP, None)).unwrap();
+        assert_eq!(replaced.map(|session| session.id), Some(1));
+        assert_eq!(table.sessions().len(), 1);
+
+        table.start(session(3, B, B_IP, None)).unwrap();
+        assert_eq!(table.start(session(4, [9; 6], A_IP, None)), Err(TableFull));
+        assert_eq!(table.end(&B).map(|session| session.id), Some(3));
+        assert_eq!(table.end(&B), None);
+    }
+
+    #[test]
+    fn truncates_long_usernames() {
+        let long = "x".repeat(40);
+        let session = Session::new(1, A, A_IP, &long, 0, 60, None);
+        assert_eq!(session.username.len(), 32);
+    }
+}
@@ -0,0 +1,540 @@
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+use crate::mac::{self, MacDisplay};
+
+pub const CODE_LEN: usize = 8;
+pub const MAX_DEVICES: usize = 4;
+
+/// The longest line [`VoucherBook::write_to`] writes for a voucher: every
+/// number at its widest and every device slot taken.
+pub const MAX_LINE_LEN: usize = "voucher=".len()
+    + CODE_LEN
+    + ",65535,4294967295,18446744073709551615,255,4294967295,18446744073709551615,".len()
+    + MAX_DEVICES * "aa:bb:cc:dd:ee:ff".len()
+    + (MAX_DEVICES - 1)
+    + 1;
+const HEADER_LINE_LEN: usize = "next_batch=65535\n".len();
+
+/// No 0/O or 1/I: codes are read off paper and typed on phones.
+const ALPHABET: &[u8; 32] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+pub type VoucherCode = String<CODE_LEN>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoucherState {
+    Unused,
+    Active,
+    Expired,
+    Exhausted,
+}
+
+impl VoucherState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unused => "unused",
+            Self::Active => "active",
+            Self::Expired => "expired",
+            Self::Exhausted => "exhausted",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoucherError {
+    UnknownCode,
+    Expired,
+    Exhausted,
+    TooManyDevices,
+    BookFull,
+    InvalidBatch,
+}
+
+impl VoucherError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnknownCode => "unknown voucher code",
+            Self::Expired => "this voucher has expired",
+            Self::Exhausted => "this voucher's data allowance is used up",
+            Self::TooManyDevices => "this voucher is already in use on too many devices",
+            Self::BookFull => "no room for more vouchers, delete an old batch first",
+            Self::InvalidBatch => "count and duration must be non-zero, max_devices 1 to 4",
+        }
+    }
+}
+
+/// Limits shared by every voucher in a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoucherTerms {
+    /// Online time, counted from the first redemption.
+    pub duration_secs: u32,
+    /// Shared by all devices using the voucher.
+    pub quota_bytes: Option<u64>,
+    pub max_devices: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Voucher {
+    pub code: VoucherCode,
+    pub batch: u16,
+    pub terms: VoucherTerms,
+    /// Time the voucher has been running for. Kept as a count rather than a
+    /// timestamp since uptime restarts from zero on every boot.
+    pub used_secs: u32,
+    pub used_bytes: u64,
+    pub devices: Vec<[u8; 6], MAX_DEVICES>,
+}
+
+impl Voucher {
+    pub fn state(&self) -> VoucherState {
+        if self.devices.is_empty() {
+            VoucherState::Unused
+        } else if self.used_secs >= self.terms.duration_secs {
+            VoucherState::Expired
+        } else if self.remaining_bytes() == Some(0) {
+            VoucherState::Exhausted
+        } else {
+            VoucherState::Active
+        }
+    }
+
+    pub fn remaining_secs(&self) -> u32 {
+        self.terms.duration_secs.saturating_sub(self.used_secs)
+    }
+
+    pub fn remaining_bytes(&self) -> Option<u64> {
+        self.terms
+            .quota_bytes
+            .map(|quota| quota.saturating_sub(self.used_bytes))
+    }
+}
+
+/// What a redemption grants the device: the rest of the voucher's time and
+/// data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Grant {
+    pub duration_secs: u32,
+    pub quota_bytes: Option<u64>,
+}
+
+/// How many vouchers are sure to fit in `len` bytes once written out.
+pub const fn capacity(len: usize) -> usize {
+    (len - HEADER_LINE_LEN) / MAX_LINE_LEN
+}
+
+pub struct VoucherBook<const N: usize> {
+    vouchers: Vec<Voucher, N>,
+    next_batch: u16,
+    last_tick: Option<u64>,
+}
+
+impl<const N: usize> VoucherBook<N> {
+    pub const fn new() -> Self {
+        Self {
+            vouchers: Vec::new(),
+            next_batch: 1,
+            last_tick: None,
+        }
+    }
+
+    pub fn vouchers(&self) -> &[Voucher] {
+        &self.vouchers
+    }
+
+    /// Creates `count` vouchers with fresh codes drawn from `random`, and
+    /// returns the new batch number.
+    pub fn generate(
+        &mut self,
+        count: usize,
+        terms: VoucherTerms,
+        mut random: impl FnMut() -> u32,
+    ) -> Result<u16, VoucherError> {
+        if count == 0
+            || terms.duration_secs == 0
+            || !(1..=MAX_DEVICES).contains(&(terms.max_devices as usize))
+        {
+            return Err(VoucherError::InvalidBatch);
+        }
+        if self.vouchers.len() + count > N {
+            return Err(VoucherError::BookFull);
+        }
+
+        let batch = self.next_batch;
+        self.next_batch = self.next_batch.wrapping_add(1).max(1);
+        let mut added = 0;
+        while added < count {
+            let code = random_code(&mut random);
+            if self.find(&code).is_some() {
+                continue;
+            }
+            _ = self.vouchers.push(Voucher {
+                code,
+                batch,
+                terms,
+                used_secs: 0,
+                used_bytes: 0,
+                devices: Vec::new(),
+            });
+            added += 1;
+        }
+        Ok(batch)
+    }
+
+    pub fn remove_batch(&mut self, batch: u16) -> usize {
+        let before = self.vouchers.len();
+        self.vouchers.retain(|voucher| voucher.batch != batch);
+        before - self.vouchers.len()
+    }
+
+    pub fn remove(&mut self, code: &str) -> bool {
+        match self.position(code) {
+            Some(index) => {
+                self.vouchers.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn find(&self, code: &str) -> Option<&Voucher> {
+        self.position(code).map(|index| &self.vouchers[index])
+    }
+
+    /// Lets `mac` use the voucher `code`. A device that already redeemed it
+    /// can do so again, e.g. after its session was revoked or the AP
+    /// rebooted, without taking another device slot.
+    pub fn redeem(&mut self, code: &str, mac: &[u8; 6]) -> Result<Grant, VoucherError> {
+        let index = self.position(code).ok_or(VoucherError::UnknownCode)?;
+        let voucher = &mut self.vouchers[index];
+        match voucher.state() {
+            VoucherState::Expired => return Err(VoucherError::Expired),
+            VoucherState::Exhausted => return Err(VoucherError::Exhausted),
+            VoucherState::Unused | VoucherState::Active => {}
+        }
+        if !voucher.devices.contains(mac) {
+            if voucher.devices.len() >= voucher.terms.max_devices as usize {
+                return Err(VoucherError::TooManyDevices);
+            }
+            voucher
+                .devices
+                .push(*mac)
+                .map_err(|_| VoucherError::TooManyDevices)?;
+        }
+        Ok(Grant {
+            duration_secs: voucher.remaining_secs(),
+            quota_bytes: voucher.remaining_bytes(),
+        })
+    }
+
+    /// Charges traffic of any device using `code` to the voucher, and
+    /// returns its state afterwards.
+    pub fn account(&mut self, code: &str, bytes: u64) -> Option<VoucherState> {
+        let index = self.position(code)?;
+        let voucher = &mut self.vouchers[index];
+        voucher.used_bytes = voucher.used_bytes.saturating_add(bytes);
+        Some(voucher.state())
+    }
+
+    /// Runs the clock of every voucher in use up to `now`. The first call
+    /// only sets the reference point, so time the device was off for is not
+    /// counted.
+    pub fn tick(&mut self, now: u64) {
+        let elapsed = match self.last_tick {
+            Some(last) => now.saturating_sub(last).min(u32::MAX as u64) as u32,
+            None => 0,
+        };
+        self.last_tick = Some(now);
+        for voucher in self.vouchers.iter_mut() {
+            if voucher.state() == VoucherState::Active {
+                voucher.used_secs = voucher
+                    .used_secs
+                    .saturating_add(elapsed)
+                    .min(voucher.terms.duration_secs);
+            }
+        }
+    }
+
+    fn position(&self, code: &str) -> Option<usize> {
+        let code = code.trim();
+        self.vouchers
+            .iter()
+            .position(|voucher| voucher.code.eq_ignore_ascii_case(code))
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "next_batch={}", self.next_batch)?;
+        for voucher in &self.vouchers {
+            write!(
+                out,
+                "voucher={},{},{},",
+                voucher.code, voucher.batch, voucher.terms.duration_secs
+            )?;
+            if let Some(quota) = voucher.terms.quota_bytes {
+                write!(out, "{quota}")?;
+            }
+            write!(
+                out,
+                ",{},{},{},",
+                voucher.terms.max_devices, voucher.used_secs, voucher.used_bytes
+            )?;
+            for (i, device) in voucher.devices.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(' ')?;
+                }
+                write!(out, "{}", MacDisplay(device))?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut book = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "next_batch" => book.next_batch = value.parse().ok()?,
+                "voucher" => book.vouchers.push(parse_voucher(value)?).ok()?,
+                _ => {}
+            }
+        }
+        Some(book)
+    }
+}
+
+fn parse_voucher(value: &str) -> Option<Voucher> {
+    let mut fields = value.split(',');
+    let code = fields.next()?.try_into().ok()?;
+    let batch = fields.next()?.parse().ok()?;
+    let duration_secs = fields.next()?.parse().ok()?;
+    let quota_bytes = match fields.next()? {
+        "" => None,
+        quota => Some(quota.parse().ok()?),
+    };
+    let max_devices = fields.next()?.parse().ok()?;
+    let used_secs = fields.next()?.parse().ok()?;
+    let used_bytes = fields.next()?.parse().ok()?;
+    let mut devices = Vec::new();
+    for device in fields
+        .next()?
+        .split(' ')
+        .filter(|device| !device.is_empty())
+    {
+        devices.push(mac::parse(device)?).ok()?;
+    }
+    Some(Voucher {
+        code,
+        batch,
+        terms: VoucherTerms {
+            duration_secs,
+            quota_bytes,
+            max_devices,
+        },
+        used_secs,
+        used_bytes,
+        devices,
+    })
+}
+
+fn random_code(random: &mut impl FnMut() -> u32) -> VoucherCode {
+    let mut code = String::new();
+    let mut bits = 0u64;
+    let mut available = 0;
+    while code.len() < CODE_LEN {
+        if available < 5 {
+            bits |= (random() as u64) << available;
+            available += 32;
+        }
+        _ = code.push(ALPHABET[(bits & 0x1f) as usize] as char);
+        bits >>= 5;
+        available -= 5;
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const B: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const C: [u8; 6] = [2, 0, 0, 0, 0, 3];
+
+    /// A book with one voucher, its codes drawn from a counter.
+    fn book(terms: VoucherTerms) -> (VoucherBook<4>, VoucherCode) {
+        let mut book = VoucherBook::new();
+        let mut next = 0;
+        book.generate(1, terms, || {
+            next += 1;
+            next
+        })
+        .unwrap();
+        let code = book.vouchers()[0].code.clone();
+        (book, code)
+    }
+
+    fn terms(quota_bytes: Option<u64>) -> VoucherTerms {
+        VoucherTerms {
+            duration_secs: 3600,
+            quota_bytes,
+            max_devices: 2,
+        }
+    }
+
+    #[test]
+    fn codes_avoid_lookalike_characters() {
+        let (book, code) = book(terms(None));
+        assert_eq!(code.len(), CODE_LEN);
+        assert!(code.bytes().all(|c| ALPHABET.contains(&c)));
+        assert!(book.find(&code.to_ascii_lowercase()).is_some());
+    }
+
+    #[test]
+    fn rejects_bad_batches() {
+        let mut book = VoucherBook::<2>::new();
+        let mut random = || 7;
+        let zero_devices = VoucherTerms {
+            max_devices: 0,
+            ..terms(None)
+        };
+        assert_eq!(
+            book.generate(1, zero_devices, &mut random),
+            Err(VoucherError::InvalidBatch)
+        );
+        assert_eq!(
+            book.generate(0, terms(None), &mut random),
+            Err(VoucherError::InvalidBatch)
+        );
+        assert_eq!(
+            book.generate(3, terms(None), &mut random),
+            Err(VoucherError::BookFull)
+        );
+    }
+
+    #[test]
+    fn clock_starts_at_first_redemption() {
+        let (mut book, code) = book(terms(None));
+        book.tick(1000);
+        book.tick(2000);
+        assert_eq!(book.vouchers()[0].state(), VoucherState::Unused);
+        assert_eq!(book.vouchers()[0].used_secs, 0);
+
+        let grant = book.redeem(&code, &A).unwrap();
+        assert_eq!(grant.duration_secs, 3600);
+        book.tick(2600);
+        assert_eq!(book.redeem(&code, &A).unwrap().duration_secs, 3000);
+    }
+
+    #[test]
+    fn expires_after_its_duration() {
+        let (mut book, code) = book(terms(None));
+        book.tick(0);
+        book.redeem(&code, &A).unwrap();
+        book.tick(3599);
+        assert_eq!(book.vouchers()[0].state(), VoucherState::Active);
+        book.tick(4000);
+        assert_eq!(book.vouchers()[0].state(), VoucherState::Expired);
+        assert_eq!(book.vouchers()[0].used_secs, 3600);
+        assert_eq!(book.redeem(&code, &A), Err(VoucherError::Expired));
+    }
+
+    #[test]
+    fn downtime_is_not_counted() {
+        let (mut book, code) = book(terms(None));
+        book.tick(0);
+        book.redeem(&code, &A).unwrap();
+        book.tick(600);
+
+        let mut text = std::string::String::new();
+        book.write_to(&mut text).unwrap();
+        let mut rebooted = VoucherBook::<4>::parse(&text).unwrap();
+        // Uptime starts over, the first tick only sets the reference
+        rebooted.tick(5);
+        rebooted.tick(65);
+        assert_eq!(rebooted.vouchers()[0].used_secs, 660);
+        assert_eq!(rebooted.vouchers()[0].devices, [A]);
+    }
+
+    #[test]
+    fn quota_is_shared_by_all_devices() {
+        let (mut book, code) = book(terms(Some(1000)));
+        book.redeem(&code, &A).unwrap();
+        assert_eq!(book.account(&code, 600), Some(VoucherState::Active));
+        // The second device only gets what the first left
+        assert_eq!(book.redeem(&code, &B).unwrap().quota_bytes, Some(400));
+        assert_eq!(book.account(&code, 300), Some(VoucherState::Active));
+        assert_eq!(book.account(&code, 100), Some(VoucherState::Exhausted));
+        assert_eq!(book.vouchers()[0].remaining_bytes(), Some(0));
+        assert_eq!(book.redeem(&code, &A), Err(VoucherError::Exhausted));
+        assert_eq!(book.account("NOSUCH", 1), None);
+    }
+
+    #[test]
+    fn limits_devices_but_lets_them_come_back() {
+        let (mut book, code) = book(terms(None));
+        book.redeem(&code, &A).unwrap();
+        book.redeem(&code, &B).unwrap();
+        assert_eq!(book.redeem(&code, &C), Err(VoucherError::TooManyDevices));
+        assert!(book.redeem(&code, &A).is_ok());
+        assert_eq!(book.vouchers()[0].devices.len(), 2);
+        assert_eq!(book.redeem("NOSUCH", &A), Err(VoucherError::UnknownCode));
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let (mut book, code) = book(terms(Some(5000)));
+        book.generate(1, terms(None), || 99).unwrap();
+        book.redeem(&code, &A).unwrap();
+        book.account(&code, 1234);
+
+        let mut text = std::string::String::new();
+        book.write_to(&mut text).unwrap();
+        let parsed = VoucherBook::<4>::parse(&text).unwrap();
+        assert_eq!(parsed.vouchers(), book.vouchers());
+        assert_eq!(parsed.next_batch, book.next_batch);
+    }
+
+    #[test]
+    fn a_full_book_fits_in_a_record() {
+        // What one storage sector holds in the firmware
+        const RECORD_LEN: usize = 4088;
+        const FULL: usize = capacity(RECORD_LEN);
+
+        let mut book = VoucherBook::<FULL>::new();
+        let mut next = 0;
+        let worst = VoucherTerms {
+            duration_secs: u32::MAX,
+            quota_bytes: Some(u64::MAX),
+            max_devices: MAX_DEVICES as u8,
+        };
+        book.generate(FULL, worst, || {
+            next += 1;
+            next
+        })
+        .unwrap();
+        book.next_batch = u16::MAX;
+        for voucher in book.vouchers.iter_mut() {
+            voucher.batch = u16::MAX;
+            voucher.terms.max_devices = u8::MAX;
+            voucher.used_secs = u32::MAX;
+            voucher.used_bytes = u64::MAX;
+            for device in 0..MAX_DEVICES as u8 {
+                voucher
+                    .devices
+                    .push([0xaa; 6].map(|byte| byte ^ device))
+                    .unwrap();
+            }
+        }
+
+        let mut text = std::string::String::new();
+        book.write_to(&mut text).unwrap();
+        assert!(text.len() <= RECORD_LEN, "{} bytes", text.len());
+        // Lines are counted with their newline
+        assert!(text.lines().all(|line| line.len() < MAX_LINE_LEN));
+        assert!(text.lines().any(|line| line.len() + 1 == MAX_LINE_LEN));
+        assert_eq!(
+            VoucherBook::<FULL>::parse(&text).unwrap().vouchers,
+            book.vouchers
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+const DNS_TYPE_A: u16 = 1;
+const DNS_CLASS_IN: u16 = 1;
+const MIN_TTL: u32 = 60;
+const MAX_TTL: u32 = 60 * 60;
+
+pub type Name = String<128>;
+
+/// Whether `name` is `domain` or one of its subdomains.
+pub fn domain_matches(name: &str, domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.');
+    let name = name.trim_end_matches('.');
+    let Some(split) = name.len().checked_sub(domain.len()) else {
+        return false;
+    };
+    if !name.is_char_boundary(split) {
+        return false;
+    }
+    let (prefix, suffix) = name.split_at(split);
+    suffix.eq_ignore_ascii_case(domain) && (prefix.is_empty() || prefix.ends_with('.'))
+}
+
+/// The A records of a DNS response, along with the name that was asked for.
+/// Records are attributed to the question rather than their own owner name
+/// so CNAME chains (`www.example.com` -> `cdn.net`) still count.
+pub fn a_records(msg: &[u8]) -> Option<(Name, Vec<(Ipv4Addr, u32), 8>)> {
+    let header = msg.get(..12)?;
+    let is_response = header[2] & 0x80 != 0;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    if !is_response || questions != 1 {
+        return None;
+    }
+
+    let mut name = Name::new();
+    let mut pos = read_name(msg, 12, Some(&mut name))?;
+    pos += 4;
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = read_name(msg, pos, None)?;
+        let fixed = msg.get(pos..pos + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let data = msg.get(pos + 10..pos + 10 + len)?;
+        pos += 10 + len;
+
+        if rtype == DNS_TYPE_A && class == DNS_CLASS_IN && len == 4 {
+            let ip = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            if records.push((ip, ttl)).is_err() {
+                break;
+            }
+        }
+    }
+    Some((name, records))
+}
+
+/// Reads a possibly compressed name starting at `pos` and returns the
+/// position right after it.
+fn read_name(msg: &[u8], mut pos: usize, mut name: Option<&mut Name>) -> Option<usize> {
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        match len & 0xc0 {
+            0xc0 => {
+                let pointer = ((len & 0x3f) << 8) | *msg.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                jumps += 1;
+                if jumps > 8 {
+                    return None;
+                }
+                pos = pointer;
+            }
+            0x00 if len == 0 => return Some(end.unwrap_or(pos + 1)),
+            0x00 => {
+                let label = msg.get(pos + 1..pos + 1 + len)?;
+                if let Some(name) = name.as_deref_mut() {
+                    if !name.is_empty() {
+                        name.push('.').ok()?;
+                    }
+                    for byte in label {
+                        name.push(byte.to_ascii_lowercase() as char).ok()?;
+                    }
+                }
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Addresses that walled-garden domains resolved to, kept for the record's
+/// TTL (clamped so a tiny TTL doesn't break a page that's still loading).
+pub struct GardenCache<const N: usize> {
+    entries: Vec<(Ipv4Addr, u64), N>,
+}
+
+impl<const N: usize> GardenCache<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn learn(&mut self, ip: Ipv4Addr, ttl: u32, now: u64) {
+        let until = now + ttl.clamp(MIN_TTL, MAX_TTL) as u64;
+        if let Some(entry) = self.entries.iter_mut().find(|(known, _)| *known == ip) {
+            entry.1 = entry.1.max(until);
+            return;
+        }
+        self.entries.retain(|(_, expires)| *expires > now);
+        if self.entries.is_full() {
+            if let Some(index) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(index, _)| index)
+            {
+                self.entries.swap_remove(index);
+            }
+        }
+        _ = self.entries.push((ip, until));
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr, now: u64) -> bool {
+        self.entries
+            .iter()
+            .any(|(known, expires)| *known == ip && *expires > now)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,15 @@
+#![cfg_attr(not(test), no_std)]
+// Tables and configs are built with `const fn new()` so they can live in statics
+#![allow(clippy::new_without_default, clippy::len_without_is_empty)]
+
+extern crate alloc;
+
+pub mod dhcp;
+pub mod hotspot;
+pub mod mac;
+pub mod radio;
+pub mod radius;
+pub mod router;
+pub mod sealed;
+pub mod uplink;
+pub mod wifi;
@@ -0,0 +1,161 @@
+use heapless::Deque;
+
+pub const MAX_MOVES: usize = 8;
+/// Beacon intervals the announced switch is counted down over.
+pub const SWITCH_COUNT: u8 = 3;
+pub const CSA_FRAME_LEN: usize = 24 + 2 + 5;
+
+/// The ESP32 has one radio, so the AP always ends up on whatever channel
+/// the station joined on. This decides what to do about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelPolicy {
+    /// Join wherever the upstream network is and move the AP along.
+    FollowStation,
+    /// Keep the AP on this channel and skip upstream access points elsewhere.
+    Pinned(u8),
+}
+
+impl ChannelPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FollowStation => "follow",
+            Self::Pinned(_) => "pinned",
+        }
+    }
+
+    /// The only channel the station may join on, if any.
+    pub fn pinned(&self) -> Option<u8> {
+        match self {
+            Self::FollowStation => None,
+            Self::Pinned(channel) => Some(*channel),
+        }
+    }
+}
+
+/// What joining an upstream access point does to the AP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelChange {
+    /// Same channel, the AP's clients don't notice.
+    Stay,
+    /// The AP is dragged from one channel to the other.
+    Move { from: u8, to: u8 },
+    /// The access point is off the pinned channel, don't join it.
+    Refuse { pinned: u8 },
+}
+
+pub fn plan(policy: ChannelPolicy, ap_channel: u8, target: u8) -> ChannelChange {
+    match policy {
+        ChannelPolicy::Pinned(pinned) if target != pinned => ChannelChange::Refuse { pinned },
+        _ if target == ap_channel => ChannelChange::Stay,
+        _ => ChannelChange::Move {
+            from: ap_channel,
+            to: target,
+        },
+    }
+}
+
+/// A broadcast Channel Switch Announcement action frame (802.11-2016
+/// 9.6.2.6) from the AP, telling its clients to follow it to `channel`
+/// in `count` beacon intervals. Clients using management frame protection
+/// ignore it, as they should an unprotected one.
+pub fn csa_frame(ap_mac: [u8; 6], channel: u8, count: u8) -> [u8; CSA_FRAME_LEN] {
+    let mut frame = [0u8; CSA_FRAME_LEN];
+    // Management frame, subtype action; duration left at zero
+    frame[0] = 0xd0;
+    frame[4..10].copy_from_slice(&[0xff; 6]);
+    frame[10..16].copy_from_slice(&ap_mac);
+    frame[16..22].copy_from_slice(&ap_mac);
+    // Sequence control at 22..24 is filled in by the driver
+    // Category spectrum management, action Channel Switch Announcement
+    frame[24] = 0;
+    frame[25] = 4;
+    // The element: id, length, mode (stop transmitting until the switch),
+    // new channel, count
+    frame[26..31].copy_from_slice(&[37, 3, 1, channel, count]);
+    frame
+}
+
+/// An AP channel change forced by the station joining elsewhere.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelMove {
+    pub from: u8,
+    pub to: u8,
+    /// Seconds since boot.
+    pub at: u64,
+    /// Whether the AP's clients were warned with a switch announcement.
+    pub announced: bool,
+}
+
+/// The most recent forced moves, oldest first.
+pub struct ChannelMoves {
+    moves: Deque<ChannelMove, MAX_MOVES>,
+}
+
+impl ChannelMoves {
+    pub const fn new() -> Self {
+        Self {
+            moves: Deque::new(),
+        }
+    }
+
+    pub fn record(&mut self, channel_move: ChannelMove) {
+        if self.moves.is_full() {
+            self.moves.pop_front();
+        }
+        _ = self.moves.push_back(channel_move);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ChannelMove> {
+        self.moves.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP_MAC: [u8; 6] = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+
+    #[test]
+    fn plans_channel_changes() {
+        let follow = ChannelPolicy::FollowStation;
+        assert_eq!(plan(follow, 6, 6), ChannelChange::Stay);
+        assert_eq!(plan(follow, 6, 11), ChannelChange::Move { from: 6, to: 11 });
+        assert_eq!(follow.pinned(), None);
+
+        let pinned = ChannelPolicy::Pinned(6);
+        assert_eq!(pinned.pinned(), Some(6));
+        assert_eq!(plan(pinned, 6, 6), ChannelChange::Stay);
+        assert_eq!(plan(pinned, 6, 1), ChannelChange::Refuse { pinned: 6 });
+        // Pinned, but the AP isn't there yet
+        assert_eq!(plan(pinned, 1, 6), ChannelChange::Move { from: 1, to: 6 });
+    }
+
+    #[test]
+    fn builds_switch_announcements() {
+        let frame = csa_frame(AP_MAC, 11, SWITCH_COUNT);
+        assert_eq!(frame.len(), CSA_FRAME_LEN);
+        assert_eq!(frame[..4], [0xd0, 0, 0, 0]);
+        assert_eq!(frame[4..10], [0xff; 6]);
+        assert_eq!(frame[10..16], AP_MAC);
+        assert_eq!(frame[16..22], AP_MAC);
+        assert_eq!(frame[22..24], [0, 0]);
+        assert_eq!(frame[24..], [0, 4, 37, 3, 1, 11, SWITCH_COUNT]);
+    }
+
+    #[test]
+    fn keeps_the_latest_moves() {
+        let mut moves = ChannelMoves::new();
+        assert_eq!(moves.iter().count(), 0);
+        for at in 0..MAX_MOVES as u64 + 2 {
+            moves.record(ChannelMove {
+                from: 1,
+                to: 6,
+                at,
+                announced: at % 2 == 0,
+            });
+        }
+        let at: std::vec::Vec<u64> = moves.iter().map(|channel_move| channel_move.at).collect();
+        assert_eq!(at, (2..MAX_MOVES as u64 + 2).collect::<std::vec::Vec<_>>());
+    }
+}
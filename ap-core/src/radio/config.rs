@@ -0,0 +1,229 @@
+use core::fmt::{self, Write};
+
+use super::channel::ChannelPolicy;
+use super::mode::OperatingMode;
+use super::regulatory::{Country, PowerSave, MAX_TX_POWER, MIN_TX_POWER, WORLD};
+use super::survey::AutoChannel;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    InvalidChannel,
+    InvalidRecheck,
+    InvalidTxPower,
+    InvalidFallback,
+}
+
+impl ConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidChannel => "channel is not allowed in the selected country",
+            Self::InvalidRecheck => "re-check interval must be 0 or 5 to 1440 minutes",
+            Self::InvalidTxPower => "TX power must be 2 to 20 dBm and within the country's limit",
+            Self::InvalidFallback => {
+                "fallback AP delay must be 10 to 3600 seconds, grace period at most 3600"
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RadioConfig {
+    pub mode: OperatingMode,
+    pub channel_policy: ChannelPolicy,
+    /// Warn AP clients with a channel switch announcement before the
+    /// station drags the AP to another channel.
+    pub announce_switch: bool,
+    /// Pick the AP's channel from a scan while the station is disconnected.
+    /// Ignored while the channel is pinned.
+    pub auto_channel: AutoChannel,
+    /// Survey again this often while no clients are connected, 0 for never.
+    pub recheck_mins: u16,
+    pub country: Country,
+    /// Transmit power cap in dBm, `None` for the most the country allows.
+    pub tx_power_limit: Option<u8>,
+    pub power_save: PowerSave,
+}
+
+impl RadioConfig {
+    pub const fn new() -> Self {
+        Self {
+            mode: OperatingMode::ApSta,
+            channel_policy: ChannelPolicy::FollowStation,
+            announce_switch: true,
+            auto_channel: AutoChannel::NonOverlapping,
+            recheck_mins: 0,
+            country: WORLD,
+            tx_power_limit: None,
+            power_save: PowerSave::None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let OperatingMode::FallbackAp {
+            after_secs,
+            grace_secs,
+        } = self.mode
+        {
+            if !(10..=3600).contains(&after_secs) || grace_secs > 3600 {
+                return Err(ConfigError::InvalidFallback);
+            }
+        }
+        if let ChannelPolicy::Pinned(channel) = self.channel_policy {
+            if !self.country.allows(channel) {
+                return Err(ConfigError::InvalidChannel);
+            }
+        }
+        if self.recheck_mins != 0 && !(5..=1440).contains(&self.recheck_mins) {
+            return Err(ConfigError::InvalidRecheck);
+        }
+        if let Some(limit) = self.tx_power_limit {
+            if !(MIN_TX_POWER..=MAX_TX_POWER).contains(&limit) || limit > self.country.max_power_dbm
+            {
+                return Err(ConfigError::InvalidTxPower);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tx_power(&self) -> u8 {
+        self.country.tx_power(self.tx_power_limit)
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        match self.mode {
+            OperatingMode::FallbackAp {
+                after_secs,
+                grace_secs,
+            } => writeln!(out, "mode=fallback {after_secs} {grace_secs}")?,
+            mode => writeln!(out, "mode={}", mode.as_str())?,
+        }
+        match self.channel_policy {
+            ChannelPolicy::FollowStation => writeln!(out, "channel_policy=follow")?,
+            ChannelPolicy::Pinned(channel) => writeln!(out, "channel_policy=pinned {channel}")?,
+        }
+        writeln!(out, "announce_switch={}", self.announce_switch as u8)?;
+        writeln!(out, "auto_channel={}", self.auto_channel.as_str())?;
+        writeln!(out, "recheck_mins={}", self.recheck_mins)?;
+        writeln!(out, "country={}", self.country.code)?;
+        if let Some(limit) = self.tx_power_limit {
+            writeln!(out, "tx_power_limit={limit}")?;
+        }
+        writeln!(out, "power_save={}", self.power_save.as_str())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "mode" => config.mode = parse_mode(value)?,
+                "channel_policy" => {
+                    config.channel_policy = match value.split_once(' ') {
+                        None if value == "follow" => ChannelPolicy::FollowStation,
+                        Some(("pinned", channel)) => ChannelPolicy::Pinned(channel.parse().ok()?),
+                        _ => return None,
+                    }
+                }
+                "announce_switch" => config.announce_switch = value == "1",
+                "auto_channel" => config.auto_channel = AutoChannel::parse(value)?,
+                "recheck_mins" => config.recheck_mins = value.parse().ok()?,
+                "country" => config.country = Country::find(value)?,
+                "tx_power_limit" => config.tx_power_limit = Some(value.parse().ok()?),
+                "power_save" => config.power_save = PowerSave::parse(value)?,
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+}
+
+fn parse_mode(value: &str) -> Option<OperatingMode> {
+    let mut parts = value.split(' ');
+    let mode = match parts.next()? {
+        "ap" => OperatingMode::ApOnly,
+        "sta" => OperatingMode::StaOnly,
+        "ap+sta" => OperatingMode::ApSta,
+        "fallback" => OperatingMode::FallbackAp {
+            after_secs: parts.next()?.parse().ok()?,
+            grace_secs: parts.next()?.parse().ok()?,
+        },
+        _ => return None,
+    };
+    parts.next().is_none().then_some(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_country(code: &str) -> RadioConfig {
+        RadioConfig {
+            country: Country::find(code).unwrap(),
+            ..RadioConfig::new()
+        }
+    }
+
+    #[test]
+    fn validates_the_pinned_channel_against_the_country() {
+        let mut config = in_country("US");
+        assert_eq!(config.validate(), Ok(()));
+        config.channel_policy = ChannelPolicy::Pinned(11);
+        assert_eq!(config.validate(), Ok(()));
+        for channel in [0, 12, 13, 14] {
+            config.channel_policy = ChannelPolicy::Pinned(channel);
+            assert_eq!(config.validate(), Err(ConfigError::InvalidChannel));
+        }
+        config.country = Country::find("DE").unwrap();
+        config.channel_policy = ChannelPolicy::Pinned(13);
+        assert_eq!(config.validate(), Ok(()));
+        config.country = WORLD;
+        assert_eq!(config.validate(), Err(ConfigError::InvalidChannel));
+    }
+
+    #[test]
+    fn validates_tx_power_and_recheck() {
+        let mut config = in_country("US");
+        for limit in [MIN_TX_POWER, MAX_TX_POWER] {
+            config.tx_power_limit = Some(limit);
+            assert_eq!(config.validate(), Ok(()));
+            assert_eq!(config.tx_power(), limit);
+        }
+        for limit in [0, 1, 21] {
+            config.tx_power_limit = Some(limit);
+            assert_eq!(config.validate(), Err(ConfigError::InvalidTxPower));
+        }
+        config.tx_power_limit = None;
+        assert_eq!(config.tx_power(), MAX_TX_POWER);
+
+        for mins in [0, 5, 1440] {
+            config.recheck_mins = mins;
+            assert_eq!(config.validate(), Ok(()));
+        }
+        for mins in [1, 4, 1441] {
+            config.recheck_mins = mins;
+            assert_eq!(config.validate(), Err(ConfigError::InvalidRecheck));
+        }
+    }
+
+    #[test]
+    fn stores_the_config() {
+        let config = RadioConfig {
+            channel_policy: ChannelPolicy::Pinned(13),
+            announce_switch: false,
+            auto_channel: AutoChannel::All,
+            recheck_mins: 60,
+            tx_power_limit: Some(14),
+            power_save: PowerSave::Min,
+            ..in_country("GB")
+        };
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert_eq!(RadioConfig::parse(&text), Some(config));
+        assert_eq!(RadioConfig::parse(""), Some(RadioConfig::new()));
+
+        // Unknown countries and malformed policies are refused
+        assert_eq!(RadioConfig::parse("country=XX"), None);
+        assert_eq!(RadioConfig::parse("channel_policy=pinned"), None);
+        assert_eq!(RadioConfig::parse("channel_policy=pinned x"), None);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod channel;
+pub mod config;
+pub mod mode;
+pub mod regulatory;
+pub mod survey;
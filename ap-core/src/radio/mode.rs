@@ -0,0 +1,215 @@
+pub const DEFAULT_FALLBACK_AFTER_SECS: u32 = 60;
+pub const DEFAULT_FALLBACK_GRACE_SECS: u32 = 300;
+
+/// Which interfaces the radio runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperatingMode {
+    ApOnly,
+    StaOnly,
+    ApSta,
+    /// Station only, with the AP brought up as a way in once the station
+    /// has gone `after_secs` without being provisioned, and taken down
+    /// again once it has been provisioned for `grace_secs`.
+    FallbackAp {
+        after_secs: u32,
+        grace_secs: u32,
+    },
+}
+
+impl OperatingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ApOnly => "ap",
+            Self::StaOnly => "sta",
+            Self::ApSta => "ap+sta",
+            Self::FallbackAp { .. } => "fallback",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interfaces {
+    pub ap: bool,
+    pub sta: bool,
+}
+
+/// Decides which interfaces should be up, from the operating mode and how
+/// the station has been doing. Knows nothing of the radio itself; time is
+/// passed in as seconds.
+///
+/// The station counts as provisioned once it is connected and has its
+/// address, so joining a network whose DHCP fails leaves the fallback AP
+/// up, and the grace period only starts once the station is really usable.
+pub struct ModeMachine {
+    mode: OperatingMode,
+    /// When the station got provisioned, `None` while it isn't.
+    provisioned_at: Option<u64>,
+    /// When the station last stopped being provisioned, or we started.
+    unprovisioned_since: u64,
+    /// Whether the fallback AP is up.
+    fallback_up: bool,
+}
+
+impl ModeMachine {
+    /// Starts out with the station not provisioned.
+    pub fn new(mode: OperatingMode, now: u64) -> Self {
+        Self {
+            mode,
+            provisioned_at: None,
+            unprovisioned_since: now,
+            fallback_up: false,
+        }
+    }
+
+    pub fn mode(&self) -> OperatingMode {
+        self.mode
+    }
+
+    /// Switches modes. A fallback AP that is up stays up, so changing the
+    /// timings doesn't cut off whoever is using it.
+    pub fn set_mode(&mut self, mode: OperatingMode) {
+        if mode != self.mode {
+            self.fallback_up = self.fallback_up && matches!(mode, OperatingMode::FallbackAp { .. });
+            self.mode = mode;
+        }
+    }
+
+    pub fn set_provisioned(&mut self, provisioned: bool, now: u64) {
+        match (provisioned, self.provisioned_at) {
+            (true, None) => self.provisioned_at = Some(now),
+            (false, Some(_)) => {
+                self.provisioned_at = None;
+                self.unprovisioned_since = now;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn interfaces(&mut self, now: u64) -> Interfaces {
+        match self.mode {
+            OperatingMode::ApOnly => Interfaces {
+                ap: true,
+                sta: false,
+            },
+            OperatingMode::StaOnly => Interfaces {
+                ap: false,
+                sta: true,
+            },
+            OperatingMode::ApSta => Interfaces {
+                ap: true,
+                sta: true,
+            },
+            OperatingMode::FallbackAp {
+                after_secs,
+                grace_secs,
+            } => {
+                match self.provisioned_at {
+                    Some(at) if now.saturating_sub(at) >= grace_secs as u64 => {
+                        self.fallback_up = false;
+                    }
+                    None if now.saturating_sub(self.unprovisioned_since) >= after_secs as u64 => {
+                        self.fallback_up = true;
+                    }
+                    _ => {}
+                }
+                Interfaces {
+                    ap: self.fallback_up,
+                    sta: true,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FALLBACK: OperatingMode = OperatingMode::FallbackAp {
+        after_secs: 60,
+        grace_secs: 300,
+    };
+
+    fn ap(modes: &mut ModeMachine, now: u64) -> bool {
+        let interfaces = modes.interfaces(now);
+        assert!(interfaces.sta);
+        interfaces.ap
+    }
+
+    #[test]
+    fn fixed_modes_ignore_the_station() {
+        for (mode, ap, sta) in [
+            (OperatingMode::ApOnly, true, false),
+            (OperatingMode::StaOnly, false, true),
+            (OperatingMode::ApSta, true, true),
+        ] {
+            let mut modes = ModeMachine::new(mode, 0);
+            assert_eq!(modes.interfaces(0), Interfaces { ap, sta });
+            modes.set_provisioned(true, 10);
+            assert_eq!(modes.interfaces(10_000), Interfaces { ap, sta });
+            modes.set_provisioned(false, 10_000);
+            assert_eq!(modes.interfaces(20_000), Interfaces { ap, sta });
+        }
+    }
+
+    #[test]
+    fn brings_the_fallback_ap_up_when_the_station_fails() {
+        let mut modes = ModeMachine::new(FALLBACK, 100);
+        assert!(!ap(&mut modes, 100));
+        assert!(!ap(&mut modes, 159));
+        assert!(ap(&mut modes, 160));
+
+        // Provisioned in time, the AP never comes up
+        let mut modes = ModeMachine::new(FALLBACK, 100);
+        modes.set_provisioned(true, 150);
+        assert!(!ap(&mut modes, 1000));
+        // until the station is lost for long enough
+        modes.set_provisioned(false, 1000);
+        assert!(!ap(&mut modes, 1059));
+        assert!(ap(&mut modes, 1060));
+    }
+
+    #[test]
+    fn counts_the_grace_period_from_provisioning() {
+        let mut modes = ModeMachine::new(FALLBACK, 0);
+        assert!(ap(&mut modes, 60));
+        // Repeated reports don't restart the grace period
+        modes.set_provisioned(true, 100);
+        modes.set_provisioned(true, 200);
+        assert!(ap(&mut modes, 399));
+        assert!(!ap(&mut modes, 400));
+
+        // Lost during the grace period, the AP stays up
+        let mut modes = ModeMachine::new(FALLBACK, 0);
+        assert!(ap(&mut modes, 60));
+        modes.set_provisioned(true, 100);
+        modes.set_provisioned(false, 200);
+        assert!(ap(&mut modes, 500));
+        // and the grace period starts over with the next provisioning
+        modes.set_provisioned(true, 600);
+        assert!(ap(&mut modes, 899));
+        assert!(!ap(&mut modes, 900));
+    }
+
+    #[test]
+    fn keeps_the_fallback_ap_across_mode_changes() {
+        let mut modes = ModeMachine::new(FALLBACK, 0);
+        assert!(ap(&mut modes, 60));
+        modes.set_mode(OperatingMode::FallbackAp {
+            after_secs: 600,
+            grace_secs: 10,
+        });
+        assert!(ap(&mut modes, 61));
+
+        // Leaving fallback mode forgets the AP was up
+        modes.set_mode(OperatingMode::StaOnly);
+        assert!(!ap(&mut modes, 62));
+        modes.set_mode(FALLBACK);
+        assert_eq!(modes.mode(), FALLBACK);
+        assert!(ap(&mut modes, 62));
+        modes.set_provisioned(true, 62);
+        modes.set_mode(OperatingMode::ApSta);
+        modes.set_mode(FALLBACK);
+        assert!(!ap(&mut modes, 63));
+    }
+}
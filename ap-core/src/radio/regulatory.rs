@@ -0,0 +1,162 @@
+/// What the ESP32 radio can do, in dBm.
+pub const MIN_TX_POWER: u8 = 2;
+pub const MAX_TX_POWER: u8 = 20;
+
+/// A regulatory domain: which 2.4 GHz channels may be used, from 1 up,
+/// and the most power allowed on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Country {
+    pub code: &'static str,
+    pub channels: u8,
+    pub max_power_dbm: u8,
+}
+
+/// The driver's own default, channels allowed nearly everywhere.
+pub const WORLD: Country = Country {
+    code: "01",
+    channels: 11,
+    max_power_dbm: 20,
+};
+
+// Simplified from each domain's rules. Channel 14 (Japan, 802.11b only)
+// is left out, the radio is never put on it.
+const COUNTRIES: [Country; 17] = [
+    WORLD,
+    country("AU", 13, 30),
+    country("BR", 13, 30),
+    country("CA", 11, 30),
+    country("CN", 13, 20),
+    country("DE", 13, 20),
+    country("ES", 13, 20),
+    country("EU", 13, 20),
+    country("FR", 13, 20),
+    country("GB", 13, 20),
+    country("IN", 13, 30),
+    country("IT", 13, 20),
+    country("JP", 13, 20),
+    country("KR", 13, 23),
+    country("NL", 13, 20),
+    country("RU", 13, 20),
+    country("US", 11, 30),
+];
+
+const fn country(code: &'static str, channels: u8, max_power_dbm: u8) -> Country {
+    Country {
+        code,
+        channels,
+        max_power_dbm,
+    }
+}
+
+impl Country {
+    /// Looks up a two letter code, or `01` for the world domain.
+    pub fn find(code: &str) -> Option<Self> {
+        COUNTRIES
+            .iter()
+            .find(|country| country.code.eq_ignore_ascii_case(code))
+            .copied()
+    }
+
+    pub fn all() -> impl Iterator<Item = &'static Country> {
+        COUNTRIES.iter()
+    }
+
+    pub fn allows(&self, channel: u8) -> bool {
+        (1..=self.channels).contains(&channel)
+    }
+
+    /// The transmit power to use given the configured `limit`.
+    pub fn tx_power(&self, limit: Option<u8>) -> u8 {
+        limit
+            .unwrap_or(MAX_TX_POWER)
+            .min(self.max_power_dbm)
+            .min(MAX_TX_POWER)
+    }
+}
+
+/// Modem sleep for the station. The radio sleeps between the upstream
+/// access point's beacons, longer with `Max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerSave {
+    None,
+    Min,
+    Max,
+}
+
+impl PowerSave {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "none" => Self::None,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            _ => return None,
+        })
+    }
+
+    /// A sleeping radio misses frames from the AP's clients, so there's no
+    /// power saving while any are connected.
+    pub fn effective(self, ap_clients: bool) -> Self {
+        if ap_clients {
+            Self::None
+        } else {
+            self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_countries() {
+        assert_eq!(Country::find("01"), Some(WORLD));
+        let us = Country::find("us").unwrap();
+        assert_eq!(us.code, "US");
+        assert_eq!(Country::find("XX"), None);
+        assert_eq!(Country::find(""), None);
+        for country in Country::all() {
+            assert_eq!(Country::find(country.code), Some(*country));
+            assert!((11..=13).contains(&country.channels));
+        }
+    }
+
+    #[test]
+    fn allows_only_the_country_channels() {
+        let us = Country::find("US").unwrap();
+        assert!(us.allows(1) && us.allows(11));
+        assert!(!us.allows(0) && !us.allows(12) && !us.allows(14));
+        let de = Country::find("DE").unwrap();
+        assert!(de.allows(13) && !de.allows(14));
+        // Channel 14 is left out even in Japan
+        assert!(!Country::find("JP").unwrap().allows(14));
+    }
+
+    #[test]
+    fn caps_tx_power() {
+        let us = Country::find("US").unwrap();
+        assert_eq!(us.tx_power(None), MAX_TX_POWER);
+        assert_eq!(us.tx_power(Some(8)), 8);
+        assert_eq!(us.tx_power(Some(30)), MAX_TX_POWER);
+        let kr = Country::find("KR").unwrap();
+        assert_eq!(kr.tx_power(None), MAX_TX_POWER);
+    }
+
+    #[test]
+    fn sleeps_only_without_clients() {
+        assert_eq!(PowerSave::Max.effective(false), PowerSave::Max);
+        assert_eq!(PowerSave::Max.effective(true), PowerSave::None);
+        for mode in [PowerSave::None, PowerSave::Min, PowerSave::Max] {
+            assert_eq!(PowerSave::parse(mode.as_str()), Some(mode));
+        }
+        assert_eq!(PowerSave::parse("off"), None);
+    }
+}
@@ -0,0 +1,183 @@
+/// Highest 2.4 GHz channel scored. Channel 14 is Japan-only and 802.11b
+/// at that, never worth picking.
+pub const MAX_CHANNEL: u8 = 13;
+const NON_OVERLAPPING: [u8; 3] = [1, 6, 11];
+/// How much a network `n` channels away counts. 2.4 GHz channels are
+/// 5 MHz apart but 20 MHz wide, so neighbours up to four away interfere.
+const OVERLAP: [u32; 5] = [16, 12, 8, 4, 1];
+
+/// Congestion per channel, channel 1 first. Unitless, lower is quieter.
+pub type Scores = [u32; MAX_CHANNEL as usize];
+
+/// Which channels the AP may pick from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoChannel {
+    Off,
+    NonOverlapping,
+    All,
+}
+
+impl AutoChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::NonOverlapping => "1-6-11",
+            Self::All => "all",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "off" => Self::Off,
+            "1-6-11" => Self::NonOverlapping,
+            "all" => Self::All,
+            _ => return None,
+        })
+    }
+}
+
+/// The result of the last channel survey.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Survey {
+    /// Seconds since boot.
+    pub at: u64,
+    pub chosen: u8,
+    pub scores: Scores,
+}
+
+/// Scores each channel from the `(channel, rssi)` of every access point a
+/// scan saw. Stronger networks count for more, and networks on nearby
+/// channels count for part of their weight.
+pub fn scores(seen: impl IntoIterator<Item = (u8, i8)>) -> Scores {
+    let mut scores = [0; MAX_CHANNEL as usize];
+    for (channel, rssi) in seen {
+        // -100 dBm is next to nothing, -30 dBm is right next door
+        let weight = (rssi as i32 + 100).clamp(1, 70) as u32;
+        for (i, score) in scores.iter_mut().enumerate() {
+            let distance = (i as u8 + 1).abs_diff(channel) as usize;
+            if let Some(overlap) = OVERLAP.get(distance) {
+                *score += weight * overlap;
+            }
+        }
+    }
+    scores
+}
+
+/// The quietest allowed channel up to `max_channel`. The `current` one is
+/// kept unless another is at least a quarter quieter, so that similar
+/// scores don't have the AP hop back and forth.
+pub fn choose(
+    scores: &Scores,
+    candidates: AutoChannel,
+    max_channel: u8,
+    current: Option<u8>,
+) -> Option<u8> {
+    let allowed = |channel: u8| {
+        (1..=max_channel.min(MAX_CHANNEL)).contains(&channel)
+            && match candidates {
+                AutoChannel::Off => false,
+                AutoChannel::NonOverlapping => NON_OVERLAPPING.contains(&channel),
+                AutoChannel::All => true,
+            }
+    };
+    let score = |channel: u8| scores[channel as usize - 1];
+    // `min_by_key` keeps the first of equal scores, the lowest channel
+    let best = (1..=MAX_CHANNEL)
+        .filter(|channel| allowed(*channel))
+        .min_by_key(|channel| score(*channel))?;
+    match current.filter(|current| allowed(*current)) {
+        Some(current) if score(best) * 4 >= score(current) * 3 => Some(current),
+        _ => Some(best),
+    }
+}
+
+/// Whether to survey again. The first survey is always due, later ones
+/// only every `recheck_mins`, 0 meaning never.
+pub fn survey_due(recheck_mins: u16, last: Option<u64>, now: u64) -> bool {
+    match last {
+        None => true,
+        Some(_) if recheck_mins == 0 => false,
+        Some(at) => now >= at + recheck_mins as u64 * 60,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_overlapping_channels() {
+        assert_eq!(
+            scores([(6, -50)]),
+            [0, 50, 200, 400, 600, 800, 600, 400, 200, 50, 0, 0, 0]
+        );
+
+        // Weights are clamped, and channel 14 still spills into 10-13
+        assert_eq!(scores([(1, -120)])[0], 16);
+        assert_eq!(scores([(1, -10)])[0], 70 * 16);
+        let japan = scores([(14, -50)]);
+        assert_eq!(japan[9..], [50, 200, 400, 600]);
+        assert_eq!(scores([]), [0; MAX_CHANNEL as usize]);
+
+        // Networks add up
+        let both = scores([(1, -50), (1, -70)]);
+        assert_eq!(both[0], (50 + 30) * 16);
+    }
+
+    #[test]
+    fn chooses_the_quietest_allowed_channel() {
+        let busy_low = scores([(1, -40), (6, -40)]);
+        assert_eq!(
+            choose(&busy_low, AutoChannel::NonOverlapping, 13, None),
+            Some(11)
+        );
+        assert_eq!(choose(&busy_low, AutoChannel::All, 13, None), Some(11));
+        assert_eq!(choose(&busy_low, AutoChannel::Off, 13, None), None);
+
+        // Only up to the country's highest channel
+        let busy_top = scores([(11, -40)]);
+        assert_eq!(choose(&busy_top, AutoChannel::All, 11, None), Some(1));
+        assert_eq!(
+            choose(&busy_top, AutoChannel::NonOverlapping, 11, None),
+            Some(1)
+        );
+        assert_eq!(choose(&busy_top, AutoChannel::All, 0, None), None);
+    }
+
+    #[test]
+    fn keeps_the_current_channel_unless_clearly_worse() {
+        let mut scores = [1000; MAX_CHANNEL as usize];
+        scores[0] = 100;
+        scores[5] = 80;
+        scores[10] = 60;
+        let choose = |current| choose(&scores, AutoChannel::NonOverlapping, 13, current);
+        assert_eq!(choose(None), Some(11));
+        // 60 isn't a quarter below 80
+        assert_eq!(choose(Some(6)), Some(6));
+        // but is below 100
+        assert_eq!(choose(Some(1)), Some(11));
+        // A current channel that isn't allowed is left
+        assert_eq!(choose(Some(3)), Some(11));
+    }
+
+    #[test]
+    fn surveys_when_due() {
+        assert!(survey_due(0, None, 0));
+        assert!(survey_due(10, None, 0));
+        assert!(!survey_due(0, Some(0), 1_000_000));
+        assert!(!survey_due(10, Some(100), 699));
+        assert!(survey_due(10, Some(100), 700));
+    }
+
+    #[test]
+    fn parses_auto_channel() {
+        for auto in [
+            AutoChannel::Off,
+            AutoChannel::NonOverlapping,
+            AutoChannel::All,
+        ] {
+            assert_eq!(AutoChannel::parse(auto.as_str()), Some(auto));
+        }
+        assert_eq!(AutoChannel::parse("on"), None);
+    }
+}
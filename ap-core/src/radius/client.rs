@@ -0,0 +1,836 @@
+use core::fmt::Write;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use heapless::String;
+
+use super::config::{AuthMethod, RadiusConfig, Server};
+use super::packet::{self, Packet, PacketWriter};
+use crate::hotspot::session::{EndReason, Session};
+
+/// Room for any request built here and the replies to it.
+pub const MAX_PACKET_LEN: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    Rejected,
+    RequestTooLarge,
+    NoUplink,
+    Unreachable,
+}
+
+impl AuthError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rejected => "rejected by the RADIUS server",
+            Self::RequestTooLarge => "username or password too long to send to the RADIUS server",
+            Self::NoUplink => "no upstream connection to reach the RADIUS server",
+            Self::Unreachable => "no RADIUS server answered",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcctStatus {
+    Start,
+    Interim,
+    Stop(EndReason),
+}
+
+/// What the server granted in its Access-Accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Accept {
+    pub session_timeout: Option<u32>,
+}
+
+/// A hotspot client logging in.
+#[derive(Clone, Copy, Debug)]
+pub struct Login<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+}
+
+/// The random parts of an Access-Request.
+#[derive(Clone, Copy, Debug)]
+pub struct Nonce {
+    pub identifier: u8,
+    pub authenticator: [u8; 16],
+    /// Only sent with CHAP.
+    pub challenge: [u8; 16],
+}
+
+/// Things going wrong along the way that are worth a log line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    NoAnswer(Ipv4Addr),
+    /// Usually a shared secret that doesn't match the server's.
+    FailedVerification(Ipv4Addr),
+    NoMessageAuthenticator,
+    Rejected {
+        username: &'a str,
+        message: &'a str,
+    },
+    Challenge,
+}
+
+/// How requests reach the servers: a UDP socket bound to the source port
+/// for the kind of request, and a clock for the reply timeout.
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    /// Milliseconds since any fixed point.
+    fn now_ms(&self) -> u64;
+
+    /// Sends one datagram, returns `false` if it couldn't go out.
+    async fn send(&mut self, to: SocketAddrV4, packet: &[u8]) -> bool;
+
+    /// Waits for the next datagram, returning its length and sender, or
+    /// `None` once `deadline_ms` has passed.
+    async fn receive(&mut self, buf: &mut [u8], deadline_ms: u64) -> Option<(usize, SocketAddrV4)>;
+
+    fn report(&mut self, _event: Event<'_>) {}
+}
+
+/// Sends an Access-Request for `login` and waits for the verdict.
+pub async fn authenticate(
+    transport: &mut impl Transport,
+    config: &RadiusConfig,
+    nas_ip: Ipv4Addr,
+    login: &Login<'_>,
+    nonce: &Nonce,
+) -> Result<Accept, AuthError> {
+    let mut request = [0u8; MAX_PACKET_LEN];
+    let len = build_access_request(&mut request, config, nas_ip, login, nonce)
+        .ok_or(AuthError::RequestTooLarge)?;
+    let mut response = [0u8; MAX_PACKET_LEN];
+    let len = exchange(
+        transport,
+        config,
+        |server| server.auth_port,
+        &request[..len],
+        &mut response,
+    )
+    .await
+    .ok_or(AuthError::Unreachable)?;
+    let Ok(reply) = Packet::decode(&response[..len]) else {
+        return Err(AuthError::Unreachable);
+    };
+
+    // Answers to an Access-Request without a Message-Authenticator are
+    // refused, so responses can't be forged by colliding the MD5 authenticator
+    if reply
+        .attribute(packet::ATTR_MESSAGE_AUTHENTICATOR)
+        .is_none()
+    {
+        transport.report(Event::NoMessageAuthenticator);
+        return Err(AuthError::Unreachable);
+    }
+    match reply.code {
+        packet::CODE_ACCESS_ACCEPT => Ok(Accept {
+            session_timeout: reply.u32_attribute(packet::ATTR_SESSION_TIMEOUT),
+        }),
+        code => {
+            if let Some(message) = reply
+                .attribute(packet::ATTR_REPLY_MESSAGE)
+                .and_then(|message| core::str::from_utf8(message).ok())
+            {
+                transport.report(Event::Rejected {
+                    username: login.username,
+                    message,
+                });
+            }
+            if code == packet::CODE_ACCESS_CHALLENGE {
+                transport.report(Event::Challenge);
+            }
+            Err(AuthError::Rejected)
+        }
+    }
+}
+
+/// Sends an accounting record for `session`, returns whether a server
+/// acknowledged it.
+pub async fn account(
+    transport: &mut impl Transport,
+    config: &RadiusConfig,
+    nas_ip: Ipv4Addr,
+    identifier: u8,
+    status: AcctStatus,
+    session: &Session,
+    now: u64,
+) -> bool {
+    let mut request = [0u8; MAX_PACKET_LEN];
+    let Some(len) = build_accounting_request(
+        &mut request,
+        config,
+        identifier,
+        status,
+        session,
+        nas_ip,
+        now,
+    ) else {
+        return false;
+    };
+    let mut response = [0u8; MAX_PACKET_LEN];
+    exchange(
+        transport,
+        config,
+        |server| server.acct_port,
+        &request[..len],
+        &mut response,
+    )
+    .await
+    .is_some()
+}
+
+/// Sends `request` to each configured server in turn, retrying each a few
+/// times, and returns the length of the first valid reply in `response`.
+pub async fn exchange(
+    transport: &mut impl Transport,
+    config: &RadiusConfig,
+    port: fn(&Server) -> u16,
+    request: &[u8],
+    response: &mut [u8],
+) -> Option<usize> {
+    let identifier = request[1];
+    let authenticator: [u8; 16] = request[4..20].try_into().unwrap();
+    let secret = config.secret.as_bytes();
+    let timeout_ms = u64::from(config.timeout_secs) * 1000;
+
+    for server in &config.servers {
+        let endpoint = SocketAddrV4::new(server.address, port(server));
+        for _ in 0..=config.retries {
+            if !transport.send(endpoint, request).await {
+                break;
+            }
+            let deadline = transport.now_ms() + timeout_ms;
+            while let Some((len, from)) = transport.receive(response, deadline).await {
+                if from != endpoint {
+                    continue;
+                }
+                let Ok(reply) = Packet::decode(&response[..len]) else {
+                    continue;
+                };
+                if reply.identifier != identifier {
+                    continue;
+                }
+                if !reply.verify_response(&authenticator, secret) {
+                    transport.report(Event::FailedVerification(server.address));
+                    continue;
+                }
+                return Some(len);
+            }
+            transport.report(Event::NoAnswer(server.address));
+        }
+    }
+    None
+}
+
+fn build_access_request(
+    buf: &mut [u8],
+    config: &RadiusConfig,
+    nas_ip: Ipv4Addr,
+    login: &Login<'_>,
+    nonce: &Nonce,
+) -> Option<usize> {
+    let secret = config.secret.as_bytes();
+    let mut writer = PacketWriter::new(
+        buf,
+        packet::CODE_ACCESS_REQUEST,
+        nonce.identifier,
+        &nonce.authenticator,
+    )
+    .ok()?;
+    writer.message_authenticator().ok()?;
+    writer
+        .attribute(packet::ATTR_USER_NAME, login.username.as_bytes())
+        .ok()?;
+    match config.method {
+        AuthMethod::Pap => {
+            let mut hidden = [0u8; 128];
+            let len = packet::hide_password(
+                login.password.as_bytes(),
+                secret,
+                &nonce.authenticator,
+                &mut hidden,
+            )?;
+            writer
+                .attribute(packet::ATTR_USER_PASSWORD, &hidden[..len])
+                .ok()?;
+        }
+        AuthMethod::Chap => {
+            let response = packet::chap_password(
+                nonce.identifier,
+                login.password.as_bytes(),
+                &nonce.challenge,
+            );
+            writer
+                .attribute(packet::ATTR_CHAP_PASSWORD, &response)
+                .ok()?;
+            writer
+                .attribute(packet::ATTR_CHAP_CHALLENGE, &nonce.challenge)
+                .ok()?;
+        }
+    }
+    write_nas_attributes(&mut writer, config, &login.mac, login.ip, nas_ip)?;
+    writer
+        .u32_attribute(packet::ATTR_SERVICE_TYPE, packet::SERVICE_TYPE_LOGIN)
+        .ok()?;
+    Some(writer.finish_access_request(secret))
+}
+
+fn write_nas_attributes(
+    writer: &mut PacketWriter,
+    config: &RadiusConfig,
+    mac: &[u8; 6],
+    ip: Ipv4Addr,
+    nas_ip: Ipv4Addr,
+) -> Option<()> {
+    let mut calling_station = String::<17>::new();
+    _ = write!(
+        calling_station,
+        "{:02X}-{:02X}-{:02X}-{:02X}-{:02X}-{:02X}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    writer
+        .attribute(packet::ATTR_NAS_IP_ADDRESS, &nas_ip.octets())
+        .ok()?;
+    if !config.nas_identifier.is_empty() {
+        writer
+            .attribute(
+                packet::ATTR_NAS_IDENTIFIER,
+                config.nas_identifier.as_bytes(),
+            )
+            .ok()?;
+    }
+    writer
+        .u32_attribute(packet::ATTR_NAS_PORT_TYPE, packet::NAS_PORT_TYPE_WIRELESS)
+        .ok()?;
+    writer
+        .attribute(packet::ATTR_CALLING_STATION_ID, calling_station.as_bytes())
+        .ok()?;
+    writer
+        .attribute(packet::ATTR_FRAMED_IP_ADDRESS, &ip.octets())
+        .ok()?;
+    Some(())
+}
+
+fn build_accounting_request(
+    buf: &mut [u8],
+    config: &RadiusConfig,
+    identifier: u8,
+    status: AcctStatus,
+    session: &Session,
+    nas_ip: Ipv4Addr,
+    now: u64,
+) -> Option<usize> {
+    let status_type = match status {
+        AcctStatus::Start => packet::ACCT_STATUS_START,
+        AcctStatus::Interim => packet::ACCT_STATUS_INTERIM,
+        AcctStatus::Stop(_) => packet::ACCT_STATUS_STOP,
+    };
+    let mut session_id = String::<8>::new();
+    _ = write!(session_id, "{:08X}", session.id);
+
+    let mut writer =
+        PacketWriter::new(buf, packet::CODE_ACCOUNTING_REQUEST, identifier, &[0; 16]).ok()?;
+    writer
+        .u32_attribute(packet::ATTR_ACCT_STATUS_TYPE, status_type)
+        .ok()?;
+    writer
+        .attribute(packet::ATTR_ACCT_SESSION_ID, session_id.as_bytes())
+        .ok()?;
+    writer
+        .attribute(packet::ATTR_USER_NAME, session.username.as_bytes())
+        .ok()?;
+    write_nas_attributes(&mut writer, config, &session.mac, session.ip, nas_ip)?;
+    if status != AcctStatus::Start {
+        let session_time = now.saturating_sub(session.started).min(u32::MAX as u64) as u32;
+        writer
+            .u32_attribute(packet::ATTR_ACCT_SESSION_TIME, session_time)
+            .ok()?;
+        // Input is what the NAS received from the client
+        writer
+            .u32_attribute(packet::ATTR_ACCT_INPUT_OCTETS, session.bytes_up as u32)
+            .ok()?;
+        writer
+            .u32_attribute(
+                packet::ATTR_ACCT_INPUT_GIGAWORDS,
+                (session.bytes_up >> 32) as u32,
+            )
+            .ok()?;
+        writer
+            .u32_attribute(packet::ATTR_ACCT_OUTPUT_OCTETS, session.bytes_down as u32)
+            .ok()?;
+        writer
+            .u32_attribute(
+                packet::ATTR_ACCT_OUTPUT_GIGAWORDS,
+                (session.bytes_down >> 32) as u32,
+            )
+            .ok()?;
+    }
+    if let AcctStatus::Stop(reason) = status {
+        let cause = match reason {
+            EndReason::Expired => packet::TERMINATE_SESSION_TIMEOUT,
+            EndReason::QuotaExceeded => packet::TERMINATE_NAS_REQUEST,
+            EndReason::Revoked => packet::TERMINATE_ADMIN_RESET,
+        };
+        writer
+            .u32_attribute(packet::ATTR_ACCT_TERMINATE_CAUSE, cause)
+            .ok()?;
+    }
+    Some(writer.finish_accounting_request(config.secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use core::time::Duration;
+    use md5::{Digest, Md5};
+    use std::collections::VecDeque;
+    use std::format;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::vec::Vec;
+
+    const SECRET: &str = "xyzzy5461";
+    const PRIMARY: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const BACKUP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const NAS_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 50);
+    const CLIENT_MAC: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 10);
+    const NONCE: Nonce = Nonce {
+        identifier: 9,
+        authenticator: [7; 16],
+        challenge: [3; 16],
+    };
+
+    /// The futures here never wait, so polling once finishes them.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Servers answer with whatever `respond` makes of each request, and
+    /// are silent otherwise.
+    struct Scripted<F> {
+        respond: F,
+        sent: Vec<(SocketAddrV4, Vec<u8>)>,
+        inbox: VecDeque<(SocketAddrV4, Vec<u8>)>,
+        events: Vec<std::string::String>,
+    }
+
+    impl<F: FnMut(SocketAddrV4, &[u8]) -> Vec<(SocketAddrV4, Vec<u8>)>> Scripted<F> {
+        fn new(respond: F) -> Self {
+            Self {
+                respond,
+                sent: Vec::new(),
+                inbox: VecDeque::new(),
+                events: Vec::new(),
+            }
+        }
+
+        fn destinations(&self) -> Vec<SocketAddrV4> {
+            self.sent.iter().map(|(to, _)| *to).collect()
+        }
+    }
+
+    impl<F: FnMut(SocketAddrV4, &[u8]) -> Vec<(SocketAddrV4, Vec<u8>)>> Transport for Scripted<F> {
+        fn now_ms(&self) -> u64 {
+            0
+        }
+
+        async fn send(&mut self, to: SocketAddrV4, packet: &[u8]) -> bool {
+            self.sent.push((to, packet.to_vec()));
+            let replies = (self.respond)(to, packet);
+            self.inbox.extend(replies);
+            true
+        }
+
+        async fn receive(
+            &mut self,
+            buf: &mut [u8],
+            _deadline_ms: u64,
+        ) -> Option<(usize, SocketAddrV4)> {
+            let (from, datagram) = self.inbox.pop_front()?;
+            buf[..datagram.len()].copy_from_slice(&datagram);
+            Some((datagram.len(), from))
+        }
+
+        fn report(&mut self, event: Event<'_>) {
+            self.events.push(format!("{event:?}"));
+        }
+    }
+
+    fn config(servers: &[Ipv4Addr]) -> RadiusConfig {
+        let mut config = RadiusConfig::new();
+        config.enabled = true;
+        config.secret = SECRET.try_into().unwrap();
+        config.retries = 1;
+        for address in servers {
+            config
+                .servers
+                .push(Server::parse(&format!("{address}")).unwrap())
+                .unwrap();
+        }
+        config
+    }
+
+    fn login() -> Login<'static> {
+        Login {
+            username: "alice",
+            password: "wonderland",
+            mac: CLIENT_MAC,
+            ip: CLIENT_IP,
+        }
+    }
+
+    /// Signs a reply to `request` the way a server does.
+    fn reply(request: &[u8], code: u8, attributes: &[(u8, &[u8])], secret: &str) -> Vec<u8> {
+        let request = Packet::decode(request).unwrap();
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let mut writer =
+            PacketWriter::new(&mut buf, code, request.identifier, &request.authenticator).unwrap();
+        writer.message_authenticator().unwrap();
+        for (kind, value) in attributes {
+            writer.attribute(*kind, value).unwrap();
+        }
+        // With the request authenticator in place, this fills in the
+        // Message-Authenticator as the server computes it
+        let len = writer.finish_access_request(secret.as_bytes());
+        let mut md5 = Md5::new();
+        md5.update(&buf[..len]);
+        md5.update(secret.as_bytes());
+        let digest = md5.finalize();
+        buf[4..20].copy_from_slice(&digest);
+        buf[..len].to_vec()
+    }
+
+    fn accept(request: &[u8]) -> Vec<u8> {
+        reply(
+            request,
+            packet::CODE_ACCESS_ACCEPT,
+            &[(packet::ATTR_SESSION_TIMEOUT, &3600u32.to_be_bytes())],
+            SECRET,
+        )
+    }
+
+    #[test]
+    fn sends_the_login() {
+        let mut transport = Scripted::new(|to, request: &[u8]| vec![(to, accept(request))]);
+        let result = block_on(authenticate(
+            &mut transport,
+            &config(&[PRIMARY]),
+            NAS_IP,
+            &login(),
+            &NONCE,
+        ));
+        assert_eq!(
+            result,
+            Ok(Accept {
+                session_timeout: Some(3600)
+            })
+        );
+
+        let (to, request) = &transport.sent[0];
+        assert_eq!(*to, SocketAddrV4::new(PRIMARY, 1812));
+        let request = Packet::decode(request).unwrap();
+        assert_eq!(request.code, packet::CODE_ACCESS_REQUEST);
+        assert_eq!(request.identifier, NONCE.identifier);
+        assert_eq!(
+            request.attribute(packet::ATTR_USER_NAME),
+            Some(&b"alice"[..])
+        );
+        // PAP hides the password
+        let hidden = request.attribute(packet::ATTR_USER_PASSWORD).unwrap();
+        assert_eq!(hidden.len(), 16);
+        assert_ne!(&hidden[..10], b"wonderland");
+        assert_eq!(
+            request.attribute(packet::ATTR_CALLING_STATION_ID),
+            Some(&b"02-11-22-33-44-55"[..])
+        );
+        assert_eq!(
+            request.attribute(packet::ATTR_NAS_IP_ADDRESS),
+            Some(&NAS_IP.octets()[..])
+        );
+        assert!(request
+            .attribute(packet::ATTR_MESSAGE_AUTHENTICATOR)
+            .is_some());
+    }
+
+    #[test]
+    fn retries_then_fails_over() {
+        let mut transport = Scripted::new(|to: SocketAddrV4, request: &[u8]| {
+            if *to.ip() == BACKUP {
+                vec![(to, accept(request))]
+            } else {
+                Vec::new()
+            }
+        });
+        let result = block_on(authenticate(
+            &mut transport,
+            &config(&[PRIMARY, BACKUP]),
+            NAS_IP,
+            &login(),
+            &NONCE,
+        ));
+        assert!(result.is_ok());
+        // Once plus one retry each
+        assert_eq!(
+            transport.destinations(),
+            [
+                SocketAddrV4::new(PRIMARY, 1812),
+                SocketAddrV4::new(PRIMARY, 1812),
+                SocketAddrV4::new(BACKUP, 1812),
+            ]
+        );
+        assert_eq!(
+            transport.events,
+            [
+                format!("{:?}", Event::NoAnswer(PRIMARY)),
+                format!("{:?}", Event::NoAnswer(PRIMARY)),
+            ]
+        );
+        // The same request goes everywhere
+        assert!(transport.sent.windows(2).all(|pair| pair[0].1 == pair[1].1));
+    }
+
+    #[test]
+    fn gives_up_when_no_server_answers() {
+        let mut transport = Scripted::new(|_, _: &[u8]| Vec::new());
+        let result = block_on(authenticate(
+            &mut transport,
+            &config(&[PRIMARY, BACKUP]),
+            NAS_IP,
+            &login(),
+            &NONCE,
+        ));
+        assert_eq!(result, Err(AuthError::Unreachable));
+        assert_eq!(transport.sent.len(), 4);
+    }
+
+    #[test]
+    fn skips_stray_and_forged_replies() {
+        let mut transport = Scripted::new(|to: SocketAddrV4, request: &[u8]| {
+            let mut wrong_identifier = accept(request);
+            wrong_identifier[1] ^= 1;
+            vec![
+                (SocketAddrV4::new(BACKUP, 1812), accept(request)),
+                (to, wrong_identifier),
+                (
+                    to,
+                    reply(request, packet::CODE_ACCESS_ACCEPT, &[], "not-the-secret"),
+                ),
+                (to, accept(request)),
+            ]
+        });
+        let result = block_on(authenticate(
+            &mut transport,
+            &config(&[PRIMARY]),
+            NAS_IP,
+            &login(),
+            &NONCE,
+        ));
+        assert!(result.is_ok());
+        assert_eq!(transport.sent.len(), 1);
+        assert_eq!(
+            transport.events,
+            [format!("{:?}", Event::FailedVerification(PRIMARY))]
+        );
+    }
+
+    #[test]
+    fn reports_rejections() {
+        let mut transport = Scripted::new(|to, request: &[u8]| {
+            // Access-Reject
+            let reject = reply(
+                request,
+                3,
+                &[(packet::ATTR_REPLY_MESSAGE, b"expired")],
+                SECRET,
+            );
+            vec![(to, reject)]
+        });
+        let result = block_on(authenticate(
+            &mut transport,
+            &config(&[PRIMARY]),
+            NAS_IP,
+            &login(),
+            &NONCE,
+        ));
+        assert_eq!(result, Err(AuthError::Rejected));
+        assert_eq!(
+            transport.events,
+            [format!(
+                "{:?}",
+                Event::Rejected {
+                    username: "alice",
+                    message: "expired"
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn sends_accounting_to_the_accounting_port() {
+        let mut session = Session::new(0xabc, CLIENT_MAC, CLIENT_IP, "alice", 100, 3600, None);
+        session.bytes_up = (1 << 32) + 5;
+        session.bytes_down = 7;
+        let mut transport = Scripted::new(|to, request: &[u8]| {
+            let request = Packet::decode(request).unwrap();
+            // Accounting-Response
+            let mut buf = [0u8; 64];
+            let writer =
+                PacketWriter::new(&mut buf, 5, request.identifier, &request.authenticator).unwrap();
+            let len = writer.finish_access_request(SECRET.as_bytes());
+            let mut md5 = Md5::new();
+            md5.update(&buf[..len]);
+            md5.update(SECRET);
+            let digest = md5.finalize();
+            buf[4..20].copy_from_slice(&digest);
+            vec![(to, buf[..len].to_vec())]
+        });
+        let acknowledged = block_on(account(
+            &mut transport,
+            &config(&[PRIMARY]),
+            NAS_IP,
+            4,
+            AcctStatus::Stop(EndReason::Expired),
+            &session,
+            160,
+        ));
+        assert!(acknowledged);
+
+        let (to, request) = &transport.sent[0];
+        assert_eq!(*to, SocketAddrV4::new(PRIMARY, 1813));
+        let request = Packet::decode(request).unwrap();
+        assert_eq!(request.code, packet::CODE_ACCOUNTING_REQUEST);
+        assert_eq!(
+            request.attribute(packet::ATTR_ACCT_SESSION_ID),
+            Some(&b"00000ABC"[..])
+        );
+        assert_eq!(
+            request.u32_attribute(packet::ATTR_ACCT_SESSION_TIME),
+            Some(60)
+        );
+        assert_eq!(
+            request.u32_attribute(packet::ATTR_ACCT_INPUT_OCTETS),
+            Some(5)
+        );
+        assert_eq!(
+            request.u32_attribute(packet::ATTR_ACCT_INPUT_GIGAWORDS),
+            Some(1)
+        );
+        assert_eq!(
+            request.u32_attribute(packet::ATTR_ACCT_TERMINATE_CAUSE),
+            Some(packet::TERMINATE_SESSION_TIMEOUT)
+        );
+    }
+
+    /// A blocking socket is all one request at a time needs.
+    struct Udp(UdpSocket);
+
+    impl Transport for Udp {
+        fn now_ms(&self) -> u64 {
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            since_epoch.as_millis() as u64
+        }
+
+        async fn send(&mut self, to: SocketAddrV4, packet: &[u8]) -> bool {
+            self.0.send_to(packet, to).is_ok()
+        }
+
+        async fn receive(
+            &mut self,
+            buf: &mut [u8],
+            deadline_ms: u64,
+        ) -> Option<(usize, SocketAddrV4)> {
+            loop {
+                let left = deadline_ms
+                    .checked_sub(self.now_ms())
+                    .filter(|ms| *ms > 0)?;
+                self.0
+                    .set_read_timeout(Some(Duration::from_millis(left)))
+                    .ok()?;
+                if let (len, SocketAddr::V4(from)) = self.0.recv_from(buf).ok()? {
+                    return Some((len, from));
+                }
+            }
+        }
+
+        fn report(&mut self, event: Event<'_>) {
+            std::println!("{event:?}");
+        }
+    }
+
+    // FreeRADIUS takes requests from localhost with the secret `testing123`
+    // out of the box. Add `bob Cleartext-Password := "hello"` to its users
+    // file, start it with `radiusd -X` and run
+    // `cargo test -- --ignored talks_to_a_local_server`.
+    #[test]
+    #[ignore = "needs a RADIUS server on localhost:1812 and 1813"]
+    fn talks_to_a_local_server() {
+        let mut config = RadiusConfig::new();
+        config.enabled = true;
+        config
+            .servers
+            .push(Server::parse("127.0.0.1").unwrap())
+            .unwrap();
+        config.secret = "testing123".try_into().unwrap();
+        config.nas_identifier = "ap-core-test".try_into().unwrap();
+        config.retries = 0;
+        let mut transport = Udp(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let nas_ip = Ipv4Addr::LOCALHOST;
+
+        let bob = Login {
+            username: "bob",
+            password: "hello",
+            mac: CLIENT_MAC,
+            ip: CLIENT_IP,
+        };
+        for method in [AuthMethod::Pap, AuthMethod::Chap] {
+            config.method = method;
+            let result = block_on(authenticate(&mut transport, &config, nas_ip, &bob, &NONCE));
+            assert!(result.is_ok(), "{method:?}: {result:?}");
+            let wrong = Login {
+                password: "wrong",
+                ..bob
+            };
+            let result = block_on(authenticate(
+                &mut transport,
+                &config,
+                nas_ip,
+                &wrong,
+                &NONCE,
+            ));
+            assert_eq!(result, Err(AuthError::Rejected), "{method:?}");
+        }
+
+        let session = Session::new(1, CLIENT_MAC, CLIENT_IP, "bob", 0, 3600, None);
+        for (identifier, status) in [
+            AcctStatus::Start,
+            AcctStatus::Interim,
+            AcctStatus::Stop(EndReason::Revoked),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let acknowledged = block_on(account(
+                &mut transport,
+                &config,
+                nas_ip,
+                identifier as u8,
+                status,
+                &session,
+                60,
+            ));
+            assert!(acknowledged, "{status:?}");
+        }
+    }
+}
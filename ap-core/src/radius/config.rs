@@ -0,0 +1,181 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+pub const MAX_SERVERS: usize = 3;
+pub const DEFAULT_AUTH_PORT: u16 = 1812;
+pub const DEFAULT_ACCT_PORT: u16 = 1813;
+/// Local ports requests go out from, fixed and below the NAT's so replies
+/// are never taken for translated traffic.
+pub const AUTH_SOURCE_PORT: u16 = 39812;
+pub const ACCT_SOURCE_PORT: u16 = 39813;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthMethod {
+    Pap,
+    Chap,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pap => "pap",
+            Self::Chap => "chap",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pap" => Some(Self::Pap),
+            "chap" => Some(Self::Chap),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Server {
+    pub address: Ipv4Addr,
+    pub auth_port: u16,
+    pub acct_port: u16,
+}
+
+impl Server {
+    /// Parses `address[:auth_port[:acct_port]]`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split(':');
+        let address = parts.next()?.parse().ok()?;
+        let auth_port = match parts.next() {
+            Some(port) => port.parse().ok()?,
+            None => DEFAULT_AUTH_PORT,
+        };
+        let acct_port = match parts.next() {
+            Some(port) => port.parse().ok()?,
+            None => DEFAULT_ACCT_PORT,
+        };
+        if parts.next().is_some() || auth_port == 0 || acct_port == 0 {
+            return None;
+        }
+        Some(Self {
+            address,
+            auth_port,
+            acct_port,
+        })
+    }
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.address, self.auth_port, self.acct_port)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    NoServers,
+    NoSecret,
+    InvalidNasIdentifier,
+    InvalidTimeout,
+    InvalidInterim,
+}
+
+impl ConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoServers => "at least one RADIUS server is required",
+            Self::NoSecret => "the shared secret must be non-empty and printable",
+            Self::InvalidNasIdentifier => "the NAS identifier must be printable",
+            Self::InvalidTimeout => "timeout must be between 1 and 30 seconds",
+            Self::InvalidInterim => "interim interval must be 0 or at least 60 seconds",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RadiusConfig {
+    /// Authenticate hotspot logins against the servers instead of the local
+    /// user list.
+    pub enabled: bool,
+    /// Tried in order, the next one is used once a server stops answering.
+    pub servers: Vec<Server, MAX_SERVERS>,
+    pub secret: String<64>,
+    pub nas_identifier: String<32>,
+    pub method: AuthMethod,
+    pub timeout_secs: u8,
+    pub retries: u8,
+    /// 0 disables Interim-Update.
+    pub interim_secs: u32,
+    pub accounting: bool,
+}
+
+impl RadiusConfig {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            servers: Vec::new(),
+            secret: String::new(),
+            nas_identifier: String::new(),
+            method: AuthMethod::Pap,
+            timeout_secs: 3,
+            retries: 2,
+            interim_secs: 300,
+            accounting: true,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.servers.is_empty() {
+            return Err(ConfigError::NoServers);
+        }
+        if self.secret.is_empty() || self.secret.chars().any(char::is_control) {
+            return Err(ConfigError::NoSecret);
+        }
+        if self.nas_identifier.chars().any(char::is_control) {
+            return Err(ConfigError::InvalidNasIdentifier);
+        }
+        if !(1..=30).contains(&self.timeout_secs) {
+            return Err(ConfigError::InvalidTimeout);
+        }
+        if self.interim_secs != 0 && self.interim_secs < 60 {
+            return Err(ConfigError::InvalidInterim);
+        }
+        Ok(())
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "enabled={}", self.enabled as u8)?;
+        for server in &self.servers {
+            writeln!(out, "server={server}")?;
+        }
+        writeln!(out, "secret={}", self.secret)?;
+        writeln!(out, "nas_identifier={}", self.nas_identifier)?;
+        writeln!(out, "method={}", self.method.as_str())?;
+        writeln!(out, "timeout_secs={}", self.timeout_secs)?;
+        writeln!(out, "retries={}", self.retries)?;
+        writeln!(out, "interim_secs={}", self.interim_secs)?;
+        writeln!(out, "accounting={}", self.accounting as u8)
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "enabled" => config.enabled = value == "1",
+                "server" => config.servers.push(Server::parse(value)?).ok()?,
+                "secret" => config.secret = value.try_into().ok()?,
+                "nas_identifier" => config.nas_identifier = value.try_into().ok()?,
+                "method" => config.method = AuthMethod::parse(value)?,
+                "timeout_secs" => config.timeout_secs = value.parse().ok()?,
+                "retries" => config.retries = value.parse().ok()?,
+                "interim_secs" => config.interim_secs = value.parse().ok()?,
+                "accounting" => config.accounting = value == "1",
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+}
@@ -0,0 +1,488 @@
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+
+pub const CODE_ACCESS_REQUEST: u8 = 1;
+pub const CODE_ACCESS_ACCEPT: u8 = 2;
+pub const CODE_ACCOUNTING_REQUEST: u8 = 4;
+pub const CODE_ACCESS_CHALLENGE: u8 = 11;
+
+pub const ATTR_USER_NAME: u8 = 1;
+pub const ATTR_USER_PASSWORD: u8 = 2;
+pub const ATTR_CHAP_PASSWORD: u8 = 3;
+pub const ATTR_NAS_IP_ADDRESS: u8 = 4;
+pub const ATTR_SERVICE_TYPE: u8 = 6;
+pub const ATTR_FRAMED_IP_ADDRESS: u8 = 8;
+pub const ATTR_REPLY_MESSAGE: u8 = 18;
+pub const ATTR_SESSION_TIMEOUT: u8 = 27;
+pub const ATTR_CALLING_STATION_ID: u8 = 31;
+pub const ATTR_NAS_IDENTIFIER: u8 = 32;
+pub const ATTR_ACCT_STATUS_TYPE: u8 = 40;
+pub const ATTR_ACCT_INPUT_OCTETS: u8 = 42;
+pub const ATTR_ACCT_OUTPUT_OCTETS: u8 = 43;
+pub const ATTR_ACCT_SESSION_ID: u8 = 44;
+pub const ATTR_ACCT_SESSION_TIME: u8 = 46;
+pub const ATTR_ACCT_TERMINATE_CAUSE: u8 = 49;
+pub const ATTR_ACCT_INPUT_GIGAWORDS: u8 = 52;
+pub const ATTR_ACCT_OUTPUT_GIGAWORDS: u8 = 53;
+pub const ATTR_CHAP_CHALLENGE: u8 = 60;
+pub const ATTR_NAS_PORT_TYPE: u8 = 61;
+pub const ATTR_MESSAGE_AUTHENTICATOR: u8 = 80;
+
+pub const SERVICE_TYPE_LOGIN: u32 = 1;
+pub const NAS_PORT_TYPE_WIRELESS: u32 = 19;
+
+pub const ACCT_STATUS_START: u32 = 1;
+pub const ACCT_STATUS_STOP: u32 = 2;
+pub const ACCT_STATUS_INTERIM: u32 = 3;
+
+pub const TERMINATE_SESSION_TIMEOUT: u32 = 5;
+pub const TERMINATE_ADMIN_RESET: u32 = 6;
+pub const TERMINATE_NAS_REQUEST: u32 = 10;
+
+const HEADER_LEN: usize = 20;
+const MAX_PACKET_LEN: usize = 4096;
+const MAX_PASSWORD_LEN: usize = 128;
+
+type HmacMd5 = Hmac<Md5>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    BadLength,
+    BadAttribute,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferTooSmall;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Packet<'a> {
+    pub code: u8,
+    pub identifier: u8,
+    pub authenticator: [u8; 16],
+    pub attributes: &'a [u8],
+    raw: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        if buf.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        if !(HEADER_LEN..=MAX_PACKET_LEN).contains(&len) || len > buf.len() {
+            return Err(DecodeError::BadLength);
+        }
+        let packet = Self {
+            code: buf[0],
+            identifier: buf[1],
+            authenticator: buf[4..20].try_into().unwrap(),
+            attributes: &buf[HEADER_LEN..len],
+            raw: &buf[..len],
+        };
+        if packet.attributes().any(|attribute| attribute.is_err()) {
+            return Err(DecodeError::BadAttribute);
+        }
+        Ok(packet)
+    }
+
+    pub fn attributes(&self) -> Attributes<'a> {
+        Attributes {
+            data: self.attributes,
+        }
+    }
+
+    pub fn attribute(&self, kind: u8) -> Option<&'a [u8]> {
+        self.attributes()
+            .flatten()
+            .find(|(code, _)| *code == kind)
+            .map(|(_, value)| value)
+    }
+
+    pub fn u32_attribute(&self, kind: u8) -> Option<u32> {
+        let value = self.attribute(kind)?;
+        Some(u32::from_be_bytes(value.try_into().ok()?))
+    }
+
+    /// Checks the Response Authenticator, and the Message-Authenticator if
+    /// the server sent one, against the request this is a reply to.
+    pub fn verify_response(&self, request_authenticator: &[u8; 16], secret: &[u8]) -> bool {
+        let mut md5 = Md5::new();
+        md5.update(&self.raw[..4]);
+        md5.update(request_authenticator);
+        md5.update(self.attributes);
+        md5.update(secret);
+        if md5.finalize()[..] != self.authenticator[..] {
+            return false;
+        }
+
+        match self.attribute(ATTR_MESSAGE_AUTHENTICATOR) {
+            Some(received) => {
+                let Ok(mut mac) = HmacMd5::new_from_slice(secret) else {
+                    return false;
+                };
+                mac.update(&self.raw[..4]);
+                mac.update(request_authenticator);
+                update_zeroing_message_authenticator(&mut mac, self.attributes);
+                mac.verify_slice(received).is_ok()
+            }
+            None => true,
+        }
+    }
+}
+
+/// Feeds `attributes` to `mac` with the Message-Authenticator value zeroed,
+/// which is how it's defined to be computed.
+fn update_zeroing_message_authenticator(mac: &mut HmacMd5, attributes: &[u8]) {
+    let mut rest = attributes;
+    while rest.len() >= 2 {
+        let len = (rest[1] as usize).clamp(2, rest.len());
+        if rest[0] == ATTR_MESSAGE_AUTHENTICATOR {
+            mac.update(&rest[..2]);
+            mac.update(&[0; 16][..len - 2]);
+        } else {
+            mac.update(&rest[..len]);
+        }
+        rest = &rest[len..];
+    }
+}
+
+pub struct Attributes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = Result<(u8, &'a [u8]), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        if self.data.len() < 2 {
+            self.data = &[];
+            return Some(Err(DecodeError::BadAttribute));
+        }
+        let kind = self.data[0];
+        let len = self.data[1] as usize;
+        if len < 2 || len > self.data.len() {
+            self.data = &[];
+            return Some(Err(DecodeError::BadAttribute));
+        }
+        let value = &self.data[2..len];
+        self.data = &self.data[len..];
+        Some(Ok((kind, value)))
+    }
+}
+
+/// Builds a request packet in `buf`.
+pub struct PacketWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    message_authenticator: Option<usize>,
+}
+
+impl<'a> PacketWriter<'a> {
+    pub fn new(
+        buf: &'a mut [u8],
+        code: u8,
+        identifier: u8,
+        authenticator: &[u8; 16],
+    ) -> Result<Self, BufferTooSmall> {
+        if buf.len() < HEADER_LEN {
+            return Err(BufferTooSmall);
+        }
+        buf[0] = code;
+        buf[1] = identifier;
+        buf[4..20].copy_from_slice(authenticator);
+        Ok(Self {
+            buf,
+            len: HEADER_LEN,
+            message_authenticator: None,
+        })
+    }
+
+    pub fn attribute(&mut self, kind: u8, value: &[u8]) -> Result<&mut Self, BufferTooSmall> {
+        if value.len() > 253 || self.len + 2 + value.len() > self.buf.len() {
+            return Err(BufferTooSmall);
+        }
+        self.buf[self.len] = kind;
+        self.buf[self.len + 1] = (value.len() + 2) as u8;
+        self.buf[self.len + 2..self.len + 2 + value.len()].copy_from_slice(value);
+        self.len += 2 + value.len();
+        Ok(self)
+    }
+
+    pub fn u32_attribute(&mut self, kind: u8, value: u32) -> Result<&mut Self, BufferTooSmall> {
+        self.attribute(kind, &value.to_be_bytes())
+    }
+
+    /// Reserves a Message-Authenticator, filled in by `finish_access_request`.
+    pub fn message_authenticator(&mut self) -> Result<&mut Self, BufferTooSmall> {
+        self.message_authenticator = Some(self.len + 2);
+        self.attribute(ATTR_MESSAGE_AUTHENTICATOR, &[0; 16])
+    }
+
+    /// Finishes an Access-Request, whose authenticator is the random one
+    /// passed to `new`.
+    pub fn finish_access_request(mut self, secret: &[u8]) -> usize {
+        self.write_length();
+        if let Some(offset) = self.message_authenticator {
+            let mut mac = HmacMd5::new_from_slice(secret).expect("HMAC accepts any key length");
+            mac.update(&self.buf[..self.len]);
+            let digest = mac.finalize().into_bytes();
+            self.buf[offset..offset + 16].copy_from_slice(&digest);
+        }
+        self.len
+    }
+
+    /// Finishes an Accounting-Request, whose authenticator is an MD5 over the
+    /// packet and the secret (RFC 2866 section 3).
+    pub fn finish_accounting_request(mut self, secret: &[u8]) -> usize {
+        self.write_length();
+        self.buf[4..20].fill(0);
+        let mut md5 = Md5::new();
+        md5.update(&self.buf[..self.len]);
+        md5.update(secret);
+        let digest = md5.finalize();
+        self.buf[4..20].copy_from_slice(&digest);
+        self.len
+    }
+
+    fn write_length(&mut self) {
+        self.buf[2..4].copy_from_slice(&(self.len as u16).to_be_bytes());
+    }
+}
+
+/// Hides a PAP password as described in RFC 2865 section 5.2.
+pub fn hide_password(
+    password: &[u8],
+    secret: &[u8],
+    authenticator: &[u8; 16],
+    out: &mut [u8; MAX_PASSWORD_LEN],
+) -> Option<usize> {
+    if password.len() > MAX_PASSWORD_LEN {
+        return None;
+    }
+    let len = password.len().div_ceil(16).max(1) * 16;
+    out[..len].fill(0);
+    out[..password.len()].copy_from_slice(password);
+
+    let mut previous = *authenticator;
+    for chunk in out[..len].chunks_mut(16) {
+        let mut md5 = Md5::new();
+        md5.update(secret);
+        md5.update(previous);
+        let digest = md5.finalize();
+        for (byte, key) in chunk.iter_mut().zip(digest.iter()) {
+            *byte ^= key;
+        }
+        previous.copy_from_slice(chunk);
+    }
+    Some(len)
+}
+
+/// The CHAP-Password value: the CHAP identifier followed by
+/// MD5(identifier || password || challenge).
+pub fn chap_password(identifier: u8, password: &[u8], challenge: &[u8]) -> [u8; 17] {
+    let mut md5 = Md5::new();
+    md5.update([identifier]);
+    md5.update(password);
+    md5.update(challenge);
+    let mut value = [0u8; 17];
+    value[0] = identifier;
+    value[1..].copy_from_slice(&md5.finalize());
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"xyzzy5461";
+    const AUTHENTICATOR: [u8; 16] = [7; 16];
+
+    /// Signs a reply the way a server does, over the request's authenticator.
+    fn reply(code: u8, attributes: &[(u8, &[u8])], with_message_authenticator: bool) -> Vec<u8> {
+        let mut buf = [0u8; 256];
+        let mut writer = PacketWriter::new(&mut buf, code, 1, &AUTHENTICATOR).unwrap();
+        if with_message_authenticator {
+            writer.message_authenticator().unwrap();
+        }
+        for (kind, value) in attributes {
+            writer.attribute(*kind, value).unwrap();
+        }
+        // With the request authenticator in place, this fills in the
+        // Message-Authenticator as the server computes it
+        let len = writer.finish_access_request(SECRET);
+        let mut md5 = Md5::new();
+        md5.update(&buf[..len]);
+        md5.update(SECRET);
+        let digest = md5.finalize();
+        buf[4..20].copy_from_slice(&digest);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn writer_output_decodes() {
+        let mut buf = [0u8; 128];
+        let mut writer =
+            PacketWriter::new(&mut buf, CODE_ACCESS_REQUEST, 42, &AUTHENTICATOR).unwrap();
+        writer.attribute(ATTR_USER_NAME, b"alice").unwrap();
+        writer
+            .u32_attribute(ATTR_SERVICE_TYPE, SERVICE_TYPE_LOGIN)
+            .unwrap();
+        let len = writer.finish_access_request(SECRET);
+
+        let packet = Packet::decode(&buf[..len]).unwrap();
+        assert_eq!(packet.code, CODE_ACCESS_REQUEST);
+        assert_eq!(packet.identifier, 42);
+        assert_eq!(packet.authenticator, AUTHENTICATOR);
+        assert_eq!(packet.attribute(ATTR_USER_NAME), Some(&b"alice"[..]));
+        assert_eq!(
+            packet.u32_attribute(ATTR_SERVICE_TYPE),
+            Some(SERVICE_TYPE_LOGIN)
+        );
+        assert_eq!(packet.attribute(ATTR_REPLY_MESSAGE), None);
+    }
+
+    #[test]
+    fn writer_refuses_to_overflow() {
+        let mut buf = [0u8; HEADER_LEN + 4];
+        let mut writer =
+            PacketWriter::new(&mut buf, CODE_ACCESS_REQUEST, 1, &AUTHENTICATOR).unwrap();
+        assert!(writer.attribute(ATTR_USER_NAME, b"ab").is_ok());
+        assert_eq!(
+            writer.attribute(ATTR_USER_NAME, b"c").err(),
+            Some(BufferTooSmall)
+        );
+        assert!(PacketWriter::new(&mut [0u8; 8], CODE_ACCESS_REQUEST, 1, &AUTHENTICATOR).is_err());
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        let good = reply(CODE_ACCESS_ACCEPT, &[(ATTR_REPLY_MESSAGE, b"hi")], false);
+        assert_eq!(
+            Packet::decode(&good[..HEADER_LEN - 1]).err(),
+            Some(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Packet::decode(&good[..good.len() - 1]).err(),
+            Some(DecodeError::BadLength)
+        );
+        let mut bad = good.clone();
+        bad[HEADER_LEN + 1] = 1;
+        assert_eq!(Packet::decode(&bad).err(), Some(DecodeError::BadAttribute));
+    }
+
+    #[test]
+    fn verifies_response_authenticators() {
+        let accept = reply(
+            CODE_ACCESS_ACCEPT,
+            &[(ATTR_SESSION_TIMEOUT, &[0, 0, 14, 16])],
+            true,
+        );
+        let packet = Packet::decode(&accept).unwrap();
+        assert!(packet.verify_response(&AUTHENTICATOR, SECRET));
+        assert_eq!(packet.u32_attribute(ATTR_SESSION_TIMEOUT), Some(3600));
+        assert!(!packet.verify_response(&AUTHENTICATOR, b"wrong"));
+        assert!(!packet.verify_response(&[8; 16], SECRET));
+
+        let mut tampered = accept.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let packet = Packet::decode(&tampered).unwrap();
+        assert!(!packet.verify_response(&AUTHENTICATOR, SECRET));
+    }
+
+    #[test]
+    fn checks_the_message_authenticator() {
+        let accept = reply(CODE_ACCESS_ACCEPT, &[], true);
+        // Flip a bit of the Message-Authenticator, then fix up the response
+        // authenticator so only the HMAC is wrong
+        let mut forged = accept.clone();
+        forged[HEADER_LEN + 2] ^= 1;
+        forged[4..20].copy_from_slice(&AUTHENTICATOR);
+        let mut md5 = Md5::new();
+        md5.update(&forged);
+        md5.update(SECRET);
+        let digest = md5.finalize();
+        forged[4..20].copy_from_slice(&digest);
+
+        assert!(Packet::decode(&accept)
+            .unwrap()
+            .verify_response(&AUTHENTICATOR, SECRET));
+        assert!(!Packet::decode(&forged)
+            .unwrap()
+            .verify_response(&AUTHENTICATOR, SECRET));
+    }
+
+    #[test]
+    fn accounting_authenticator_covers_the_packet() {
+        let mut buf = [0u8; 64];
+        let mut writer =
+            PacketWriter::new(&mut buf, CODE_ACCOUNTING_REQUEST, 3, &AUTHENTICATOR).unwrap();
+        writer
+            .u32_attribute(ATTR_ACCT_STATUS_TYPE, ACCT_STATUS_START)
+            .unwrap();
+        let len = writer.finish_accounting_request(SECRET);
+
+        let mut expected = buf[..len].to_vec();
+        expected[4..20].fill(0);
+        let mut md5 = Md5::new();
+        md5.update(&expected);
+        md5.update(SECRET);
+        assert_eq!(buf[4..20], md5.finalize()[..]);
+    }
+
+    #[test]
+    fn hidden_passwords_are_padded_and_reversible() {
+        let mut hidden = [0u8; MAX_PASSWORD_LEN];
+        let password = b"a password longer than sixteen bytes";
+        let len = hide_password(password, SECRET, &AUTHENTICATOR, &mut hidden).unwrap();
+        assert_eq!(len, 48);
+        assert_ne!(&hidden[..password.len()], &password[..]);
+
+        // Undo it the way the server does
+        let mut previous = AUTHENTICATOR;
+        let mut plain = Vec::new();
+        for chunk in hidden[..len].chunks(16) {
+            let mut md5 = Md5::new();
+            md5.update(SECRET);
+            md5.update(previous);
+            let digest = md5.finalize();
+            plain.extend(
+                chunk
+                    .iter()
+                    .zip(digest.iter())
+                    .map(|(byte, key)| byte ^ key),
+            );
+            previous.copy_from_slice(chunk);
+        }
+        assert_eq!(&plain[..password.len()], &password[..]);
+        assert!(plain[password.len()..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(
+            hide_password(b"", SECRET, &AUTHENTICATOR, &mut hidden),
+            Some(16)
+        );
+        assert_eq!(
+            hide_password(
+                &[b'x'; MAX_PASSWORD_LEN + 1],
+                SECRET,
+                &AUTHENTICATOR,
+                &mut hidden
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn chap_response_is_md5_of_id_password_challenge() {
+        let value = chap_password(9, b"secret", &[1; 16]);
+        assert_eq!(value[0], 9);
+        let mut md5 = Md5::new();
+        md5.update([9]);
+        md5.update(b"secret");
+        md5.update([1; 16]);
+        assert_eq!(value[1..], md5.finalize()[..]);
+    }
+}
@@ -0,0 +1,624 @@
+use core::net::Ipv4Addr;
+use core::ops::Range;
+use heapless::Vec;
+use smoltcp::wire::{
+    ArpHardware, ArpOperation, ArpPacket, EthernetAddress, EthernetFrame, EthernetProtocol,
+    IpAddress, IpProtocol, Ipv4Packet, UdpPacket,
+};
+
+use crate::dhcp::packet::{self as dhcp, MessageType};
+use crate::wifi::ap_network::ApNetworkConfig;
+
+/// How long a client's address is remembered without seeing traffic from
+/// it, like an ARP cache entry.
+const IDLE_TIMEOUT: u64 = 10 * 60;
+// Where the flags field sits in a BOOTP message
+const DHCP_FLAGS: Range<usize> = 10..12;
+
+/// Features that only apply to the routed AP network. Bridged frames go
+/// straight between the interfaces, so none of these see them.
+pub const DISABLED_FEATURES: [&str; 7] = [
+    "hotspot",
+    "firewall",
+    "rate_limits",
+    "port_forwards",
+    "nat",
+    "dhcp_server",
+    "ipv6_router",
+];
+
+/// What to do with a frame after `Bridge` has rewritten it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Hand it to the receiving interface's own stack.
+    Local,
+    /// Send it out on the other interface.
+    Forward,
+    /// Both of the above, for broadcasts from upstream.
+    Both,
+    /// Send it back out on the interface it came from, for proxy ARP replies.
+    Reply,
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Host {
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+    pub last_seen: u64,
+}
+
+/// MAC translation between AP clients and the upstream network. The STA
+/// can only send from its own MAC, so every client appears upstream under
+/// it, and frames coming back are told apart by their IP address.
+pub struct Bridge<const N: usize> {
+    hosts: Vec<Host, N>,
+}
+
+impl<const N: usize> Bridge<N> {
+    pub const fn new() -> Self {
+        Self { hosts: Vec::new() }
+    }
+
+    pub fn hosts(&self) -> &[Host] {
+        &self.hosts
+    }
+
+    pub fn clear(&mut self) {
+        self.hosts.clear();
+    }
+
+    /// Handles a frame from an AP client. Everything is sent upstream except
+    /// traffic for the AP network itself, which keeps the web UI reachable
+    /// from a client with a static address on it.
+    pub fn from_ap(
+        &mut self,
+        frame: &mut [u8],
+        sta_mac: [u8; 6],
+        network: &ApNetworkConfig,
+        now: u64,
+    ) -> Verdict {
+        let Ok(mut eth) = EthernetFrame::new_checked(frame) else {
+            return Verdict::Drop;
+        };
+        let client_mac = eth.src_addr().0;
+        match eth.ethertype() {
+            EthernetProtocol::Arp => {
+                let Some(mut arp) = arp_packet(eth.payload_mut()) else {
+                    return Verdict::Drop;
+                };
+                let sender = ipv4(arp.source_protocol_addr());
+                if network.contains(ipv4(arp.target_protocol_addr())) {
+                    return Verdict::Local;
+                }
+                if !sender.is_unspecified() && !network.contains(sender) {
+                    self.learn(client_mac, sender, now);
+                }
+                arp.set_source_hardware_addr(&sta_mac);
+            }
+            EthernetProtocol::Ipv4 => {
+                let Ok(mut ip) = Ipv4Packet::new_checked(eth.payload_mut()) else {
+                    return Verdict::Drop;
+                };
+                let src = ip.src_addr();
+                if network.contains(ip.dst_addr()) {
+                    return Verdict::Local;
+                }
+                if !src.is_unspecified() && !network.contains(src) {
+                    self.learn(client_mac, src, now);
+                }
+                request_broadcast_reply(&mut ip);
+            }
+            // IPv6 and the rest aren't bridged
+            _ => return Verdict::Drop,
+        }
+        eth.set_src_addr(EthernetAddress(sta_mac));
+        Verdict::Forward
+    }
+
+    /// Handles a frame that arrived on the STA interface.
+    pub fn from_sta(&mut self, frame: &mut [u8], sta_mac: [u8; 6], now: u64) -> Verdict {
+        let Ok(mut eth) = EthernetFrame::new_checked(frame) else {
+            return Verdict::Local;
+        };
+        let dst_mac = eth.dst_addr();
+        match eth.ethertype() {
+            EthernetProtocol::Arp => {
+                let Some(mut arp) = arp_packet(eth.payload_mut()) else {
+                    return Verdict::Local;
+                };
+                let target = ipv4(arp.target_protocol_addr());
+                match arp.operation() {
+                    ArpOperation::Request => {
+                        // Answer for known clients ourselves, so upstream sends
+                        // their traffic to the STA's MAC
+                        if self.mac_for(target, now).is_none() {
+                            return Verdict::Both;
+                        }
+                        let mut sender_mac = [0; 6];
+                        sender_mac.copy_from_slice(arp.source_hardware_addr());
+                        let sender = ipv4(arp.source_protocol_addr());
+                        arp.set_operation(ArpOperation::Reply);
+                        arp.set_target_hardware_addr(&sender_mac);
+                        arp.set_target_protocol_addr(&sender.octets());
+                        arp.set_source_hardware_addr(&sta_mac);
+                        arp.set_source_protocol_addr(&target.octets());
+                        eth.set_dst_addr(EthernetAddress(sender_mac));
+                        eth.set_src_addr(EthernetAddress(sta_mac));
+                        Verdict::Reply
+                    }
+                    ArpOperation::Reply => {
+                        let Some(client_mac) = self.mac_for(target, now) else {
+                            return Verdict::Local;
+                        };
+                        arp.set_target_hardware_addr(&client_mac);
+                        eth.set_dst_addr(EthernetAddress(client_mac));
+                        Verdict::Forward
+                    }
+                    _ => Verdict::Local,
+                }
+            }
+            EthernetProtocol::Ipv4 => {
+                let Ok(ip) = Ipv4Packet::new_checked(eth.payload_mut()) else {
+                    return Verdict::Local;
+                };
+                if dst_mac.is_broadcast() || dst_mac.is_multicast() {
+                    self.learn_from_dhcp_ack(&ip, now);
+                    return Verdict::Both;
+                }
+                let Some(client_mac) = self.mac_for(ip.dst_addr(), now) else {
+                    return Verdict::Local;
+                };
+                eth.set_dst_addr(EthernetAddress(client_mac));
+                Verdict::Forward
+            }
+            _ => Verdict::Local,
+        }
+    }
+
+    fn learn(&mut self, mac: [u8; 6], ip: Ipv4Addr, now: u64) {
+        // An address belongs to one client, and a client has one address
+        self.hosts.retain(|host| host.ip != ip || host.mac == mac);
+        if let Some(host) = self.hosts.iter_mut().find(|host| host.mac == mac) {
+            host.ip = ip;
+            host.last_seen = now;
+            return;
+        }
+        self.hosts
+            .retain(|host| now.saturating_sub(host.last_seen) <= IDLE_TIMEOUT);
+        if self.hosts.is_full() {
+            if let Some(oldest) = self
+                .hosts
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, host)| host.last_seen)
+                .map(|(index, _)| index)
+            {
+                self.hosts.swap_remove(oldest);
+            }
+        }
+        _ = self.hosts.push(Host {
+            mac,
+            ip,
+            last_seen: now,
+        });
+    }
+
+    fn mac_for(&self, ip: Ipv4Addr, now: u64) -> Option<[u8; 6]> {
+        self.hosts
+            .iter()
+            .find(|host| host.ip == ip && now.saturating_sub(host.last_seen) <= IDLE_TIMEOUT)
+            .map(|host| host.mac)
+    }
+
+    /// Picks up the address a client was given before it sends anything from
+    /// it, so the first packets sent to it aren't lost.
+    fn learn_from_dhcp_ack(&mut self, ip: &Ipv4Packet<&mut [u8]>, now: u64) {
+        if ip.next_header() != IpProtocol::Udp || ip.more_frags() || ip.frag_offset() != 0 {
+            return;
+        }
+        let Ok(udp) = UdpPacket::new_checked(Ipv4Packet::new_unchecked(ip.as_ref()).payload())
+        else {
+            return;
+        };
+        if udp.src_port() != dhcp::SERVER_PORT || udp.dst_port() != dhcp::CLIENT_PORT {
+            return;
+        }
+        let Ok(reply) = dhcp::Packet::decode(udp.payload()) else {
+            return;
+        };
+        if !reply.is_request()
+            && reply.message_type() == Some(MessageType::Ack)
+            && !reply.yiaddr.is_unspecified()
+        {
+            self.learn(reply.mac(), reply.yiaddr, now);
+        }
+    }
+}
+
+/// Sets the broadcast flag on a client's DHCP request. The server would
+/// otherwise unicast its reply to the client's own MAC, which never reaches
+/// the STA.
+fn request_broadcast_reply(ip: &mut Ipv4Packet<&mut [u8]>) {
+    if ip.next_header() != IpProtocol::Udp || ip.more_frags() || ip.frag_offset() != 0 {
+        return;
+    }
+    let src = IpAddress::Ipv4(ip.src_addr());
+    let dst = IpAddress::Ipv4(ip.dst_addr());
+    let Ok(mut udp) = UdpPacket::new_checked(ip.payload_mut()) else {
+        return;
+    };
+    if udp.src_port() != dhcp::CLIENT_PORT || udp.dst_port() != dhcp::SERVER_PORT {
+        return;
+    }
+    let Some(flags) = udp.payload_mut().get_mut(DHCP_FLAGS) else {
+        return;
+    };
+    let value = u16::from_be_bytes([flags[0], flags[1]]) | dhcp::BROADCAST_FLAG;
+    flags.copy_from_slice(&value.to_be_bytes());
+    // A zero checksum means the sender didn't compute one
+    if udp.checksum() != 0 {
+        udp.fill_checksum(&src, &dst);
+    }
+}
+
+/// An Ethernet/IPv4 ARP packet, the only kind there is on Wi-Fi.
+fn arp_packet(payload: &mut [u8]) -> Option<ArpPacket<&mut [u8]>> {
+    let arp = ArpPacket::new_checked(payload).ok()?;
+    (arp.hardware_type() == ArpHardware::Ethernet
+        && arp.protocol_type() == EthernetProtocol::Ipv4
+        && arp.hardware_len() == 6
+        && arp.protocol_len() == 4)
+        .then_some(arp)
+}
+
+fn ipv4(bytes: &[u8]) -> Ipv4Addr {
+    <[u8; 4]>::try_from(bytes).map_or(Ipv4Addr::UNSPECIFIED, Ipv4Addr::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhcp::packet::PacketWriter;
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{ArpRepr, EthernetRepr, Ipv4Repr, UdpRepr};
+
+    const STA_MAC: [u8; 6] = [2, 0, 0, 0, 0, 0x53];
+    const CLIENT_MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const ROUTER_MAC: [u8; 6] = [2, 0, 0, 0, 0, 0xfe];
+    const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 20);
+    const ROUTER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const BROADCAST: [u8; 6] = [0xff; 6];
+
+    fn network() -> ApNetworkConfig {
+        ApNetworkConfig::from_gateway(Ipv4Addr::new(192, 168, 4, 1), 24)
+    }
+
+    fn ethernet(
+        dst: [u8; 6],
+        src: [u8; 6],
+        ethertype: EthernetProtocol,
+        payload: &[u8],
+    ) -> Vec<u8, 1024> {
+        let repr = EthernetRepr {
+            src_addr: EthernetAddress(src),
+            dst_addr: EthernetAddress(dst),
+            ethertype,
+        };
+        let mut frame = Vec::new();
+        frame.resize(repr.buffer_len() + payload.len(), 0).unwrap();
+        let mut eth = EthernetFrame::new_unchecked(&mut frame[..]);
+        repr.emit(&mut eth);
+        eth.payload_mut().copy_from_slice(payload);
+        frame
+    }
+
+    fn arp(
+        operation: ArpOperation,
+        (sender_mac, sender_ip): ([u8; 6], Ipv4Addr),
+        (target_mac, target_ip): ([u8; 6], Ipv4Addr),
+    ) -> [u8; 28] {
+        let repr = ArpRepr::EthernetIpv4 {
+            operation,
+            source_hardware_addr: EthernetAddress(sender_mac),
+            source_protocol_addr: sender_ip,
+            target_hardware_addr: EthernetAddress(target_mac),
+            target_protocol_addr: target_ip,
+        };
+        let mut buf = [0u8; 28];
+        repr.emit(&mut ArpPacket::new_unchecked(&mut buf[..]));
+        buf
+    }
+
+    fn udp(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), payload: &[u8]) -> Vec<u8, 1024> {
+        let udp = UdpRepr {
+            src_port: src.1,
+            dst_port: dst.1,
+        };
+        let ip = Ipv4Repr {
+            src_addr: src.0,
+            dst_addr: dst.0,
+            next_header: IpProtocol::Udp,
+            payload_len: udp.header_len() + payload.len(),
+            hop_limit: 64,
+        };
+        let mut packet = Vec::new();
+        packet.resize(ip.buffer_len() + ip.payload_len, 0).unwrap();
+        let caps = ChecksumCapabilities::default();
+        let mut ipv4 = Ipv4Packet::new_unchecked(&mut packet[..]);
+        ip.emit(&mut ipv4, &caps);
+        udp.emit(
+            &mut UdpPacket::new_unchecked(ipv4.payload_mut()),
+            &IpAddress::Ipv4(src.0),
+            &IpAddress::Ipv4(dst.0),
+            payload.len(),
+            |buf| buf.copy_from_slice(payload),
+            &caps,
+        );
+        packet
+    }
+
+    fn dhcp(op_reply: bool, message_type: MessageType, yiaddr: Ipv4Addr) -> Vec<u8, 600> {
+        let mut buf = [0u8; 600];
+        let mut chaddr = [0u8; 16];
+        chaddr[..6].copy_from_slice(&CLIENT_MAC);
+        let op = if op_reply { 2 } else { 1 };
+        let unspecified = Ipv4Addr::UNSPECIFIED;
+        let mut writer = PacketWriter::new(
+            &mut buf,
+            op,
+            7,
+            0,
+            unspecified,
+            yiaddr,
+            unspecified,
+            unspecified,
+            &chaddr,
+        )
+        .unwrap();
+        writer.message_type(message_type).unwrap();
+        let len = writer.finish().unwrap();
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    fn eth_of(frame: &[u8]) -> EthernetFrame<&[u8]> {
+        EthernetFrame::new_checked(frame).unwrap()
+    }
+
+    fn arp_of(frame: &[u8]) -> ArpRepr {
+        ArpRepr::parse(&ArpPacket::new_checked(eth_of(frame).payload()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn learns_clients_from_their_traffic() {
+        let mut bridge = Bridge::<4>::new();
+        let payload = udp((CLIENT_IP, 5000), (ROUTER_IP, 53), b"query");
+        let mut frame = ethernet(ROUTER_MAC, CLIENT_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Forward
+        );
+        // Upstream only ever sees the STA's MAC
+        assert_eq!(eth_of(&frame).src_addr(), EthernetAddress(STA_MAC));
+        assert_eq!(
+            bridge.hosts(),
+            [Host {
+                mac: CLIENT_MAC,
+                ip: CLIENT_IP,
+                last_seen: 100,
+            }]
+        );
+
+        // The reply is readdressed to the client
+        let payload = udp((ROUTER_IP, 53), (CLIENT_IP, 5000), b"answer");
+        let mut frame = ethernet(STA_MAC, ROUTER_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 101), Verdict::Forward);
+        assert_eq!(eth_of(&frame).dst_addr(), EthernetAddress(CLIENT_MAC));
+
+        // Traffic for the STA itself stays there
+        let payload = udp(
+            (ROUTER_IP, 53),
+            (Ipv4Addr::new(10, 0, 0, 2), 5000),
+            b"answer",
+        );
+        let mut frame = ethernet(STA_MAC, ROUTER_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 101), Verdict::Local);
+    }
+
+    #[test]
+    fn forgets_idle_clients() {
+        let mut bridge = Bridge::<4>::new();
+        let payload = udp((CLIENT_IP, 5000), (ROUTER_IP, 53), b"query");
+        let mut frame = ethernet(ROUTER_MAC, CLIENT_MAC, EthernetProtocol::Ipv4, &payload);
+        bridge.from_ap(&mut frame, STA_MAC, &network(), 100);
+
+        let payload = udp((ROUTER_IP, 53), (CLIENT_IP, 5000), b"answer");
+        let mut frame = ethernet(STA_MAC, ROUTER_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(
+            bridge.from_sta(&mut frame, STA_MAC, 100 + IDLE_TIMEOUT + 1),
+            Verdict::Local
+        );
+    }
+
+    #[test]
+    fn an_address_moves_to_its_latest_client() {
+        let mut bridge = Bridge::<4>::new();
+        let other_mac = [2, 0, 0, 0, 0, 2];
+        for (mac, now) in [(CLIENT_MAC, 100), (other_mac, 110)] {
+            let payload = udp((CLIENT_IP, 5000), (ROUTER_IP, 53), b"query");
+            let mut frame = ethernet(ROUTER_MAC, mac, EthernetProtocol::Ipv4, &payload);
+            bridge.from_ap(&mut frame, STA_MAC, &network(), now);
+        }
+        assert_eq!(bridge.hosts().len(), 1);
+        assert_eq!(bridge.hosts()[0].mac, other_mac);
+    }
+
+    #[test]
+    fn answers_arp_for_known_clients() {
+        let mut bridge = Bridge::<4>::new();
+        let request = arp(
+            ArpOperation::Request,
+            (CLIENT_MAC, CLIENT_IP),
+            ([0; 6], ROUTER_IP),
+        );
+        let mut frame = ethernet(BROADCAST, CLIENT_MAC, EthernetProtocol::Arp, &request);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Forward
+        );
+        let ArpRepr::EthernetIpv4 {
+            source_hardware_addr,
+            ..
+        } = arp_of(&frame)
+        else {
+            unreachable!()
+        };
+        assert_eq!(source_hardware_addr, EthernetAddress(STA_MAC));
+
+        // Upstream asking for the client gets the STA's MAC
+        let request = arp(
+            ArpOperation::Request,
+            (ROUTER_MAC, ROUTER_IP),
+            ([0; 6], CLIENT_IP),
+        );
+        let mut frame = ethernet(BROADCAST, ROUTER_MAC, EthernetProtocol::Arp, &request);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 101), Verdict::Reply);
+        assert_eq!(eth_of(&frame).dst_addr(), EthernetAddress(ROUTER_MAC));
+        assert_eq!(eth_of(&frame).src_addr(), EthernetAddress(STA_MAC));
+        assert_eq!(
+            arp_of(&frame),
+            ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Reply,
+                source_hardware_addr: EthernetAddress(STA_MAC),
+                source_protocol_addr: CLIENT_IP,
+                target_hardware_addr: EthernetAddress(ROUTER_MAC),
+                target_protocol_addr: ROUTER_IP,
+            }
+        );
+
+        // Requests for anyone else go to both sides
+        let request = arp(
+            ArpOperation::Request,
+            (ROUTER_MAC, ROUTER_IP),
+            ([0; 6], Ipv4Addr::new(10, 0, 0, 99)),
+        );
+        let mut frame = ethernet(BROADCAST, ROUTER_MAC, EthernetProtocol::Arp, &request);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 101), Verdict::Both);
+    }
+
+    #[test]
+    fn passes_arp_replies_on_to_the_client() {
+        let mut bridge = Bridge::<4>::new();
+        let request = arp(
+            ArpOperation::Request,
+            (CLIENT_MAC, CLIENT_IP),
+            ([0; 6], ROUTER_IP),
+        );
+        let mut frame = ethernet(BROADCAST, CLIENT_MAC, EthernetProtocol::Arp, &request);
+        bridge.from_ap(&mut frame, STA_MAC, &network(), 100);
+
+        let reply = arp(
+            ArpOperation::Reply,
+            (ROUTER_MAC, ROUTER_IP),
+            (STA_MAC, CLIENT_IP),
+        );
+        let mut frame = ethernet(STA_MAC, ROUTER_MAC, EthernetProtocol::Arp, &reply);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 101), Verdict::Forward);
+        assert_eq!(eth_of(&frame).dst_addr(), EthernetAddress(CLIENT_MAC));
+        let ArpRepr::EthernetIpv4 {
+            target_hardware_addr,
+            ..
+        } = arp_of(&frame)
+        else {
+            unreachable!()
+        };
+        assert_eq!(target_hardware_addr, EthernetAddress(CLIENT_MAC));
+    }
+
+    #[test]
+    fn keeps_ap_network_traffic_local() {
+        let mut bridge = Bridge::<4>::new();
+        let gateway = network().gateway;
+        let request = arp(
+            ArpOperation::Request,
+            (CLIENT_MAC, CLIENT_IP),
+            ([0; 6], gateway),
+        );
+        let mut frame = ethernet(BROADCAST, CLIENT_MAC, EthernetProtocol::Arp, &request);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Local
+        );
+
+        let payload = udp((CLIENT_IP, 5000), (gateway, 8080), b"GET /");
+        let mut frame = ethernet(STA_MAC, CLIENT_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Local
+        );
+        assert!(bridge.hosts().is_empty());
+
+        let mut frame = ethernet(BROADCAST, CLIENT_MAC, EthernetProtocol::Ipv6, &[0; 40]);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Drop
+        );
+    }
+
+    #[test]
+    fn asks_for_broadcast_dhcp_replies() {
+        let mut bridge = Bridge::<4>::new();
+        let discover = dhcp(false, MessageType::Discover, Ipv4Addr::UNSPECIFIED);
+        let payload = udp(
+            (Ipv4Addr::UNSPECIFIED, dhcp::CLIENT_PORT),
+            (Ipv4Addr::BROADCAST, dhcp::SERVER_PORT),
+            &discover,
+        );
+        let mut frame = ethernet(BROADCAST, CLIENT_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(
+            bridge.from_ap(&mut frame, STA_MAC, &network(), 100),
+            Verdict::Forward
+        );
+
+        let eth = eth_of(&frame);
+        let ip = Ipv4Packet::new_checked(eth.payload()).unwrap();
+        let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+        let message = dhcp::Packet::decode(udp.payload()).unwrap();
+        assert!(message.is_broadcast());
+        // The rest is untouched, and the checksum still adds up
+        assert_eq!(message.mac(), CLIENT_MAC);
+        assert!(udp.verify_checksum(
+            &IpAddress::Ipv4(ip.src_addr()),
+            &IpAddress::Ipv4(ip.dst_addr())
+        ));
+        // An unconfigured client isn't learned
+        assert!(bridge.hosts().is_empty());
+    }
+
+    #[test]
+    fn learns_clients_from_dhcp_acks() {
+        let mut bridge = Bridge::<4>::new();
+        let ack = dhcp(true, MessageType::Ack, CLIENT_IP);
+        let payload = udp(
+            (ROUTER_IP, dhcp::SERVER_PORT),
+            (Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT),
+            &ack,
+        );
+        let mut frame = ethernet(BROADCAST, ROUTER_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 100), Verdict::Both);
+        assert_eq!(bridge.hosts()[0].mac, CLIENT_MAC);
+        assert_eq!(bridge.hosts()[0].ip, CLIENT_IP);
+
+        // An offer isn't an assignment yet
+        let mut bridge = Bridge::<4>::new();
+        let offer = dhcp(true, MessageType::Offer, CLIENT_IP);
+        let payload = udp(
+            (ROUTER_IP, dhcp::SERVER_PORT),
+            (Ipv4Addr::BROADCAST, dhcp::CLIENT_PORT),
+            &offer,
+        );
+        let mut frame = ethernet(BROADCAST, ROUTER_MAC, EthernetProtocol::Ipv4, &payload);
+        assert_eq!(bridge.from_sta(&mut frame, STA_MAC, 100), Verdict::Both);
+        assert!(bridge.hosts().is_empty());
+    }
+}
@@ -0,0 +1,836 @@
+use core::net::Ipv6Addr;
+use heapless::Vec;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{
+    EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, IpProtocol, Ipv6Packet,
+    Ipv6Repr, UdpPacket, UdpRepr,
+};
+
+use super::ipv6::{link_local, Prefix, MAX_DNS_SERVERS};
+
+pub const CLIENT_PORT: u16 = 546;
+pub const SERVER_PORT: u16 = 547;
+/// Longest message kept. Replies carry little more than two IAs and DNS.
+pub const MAX_MESSAGE_LEN: usize = 512;
+/// DUIDs are at most 128 bytes plus their type (RFC 8415 section 11.1).
+const MAX_DUID_LEN: usize = 130;
+
+const ALL_SERVERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 2);
+const ALL_SERVERS_MAC: [u8; 6] = [0x33, 0x33, 0, 1, 0, 2];
+
+// Retransmissions back off from 1 s (RFC 8415 section 15). The RFC lets
+// SOLICITs back off to an hour, capping lower finds a late server sooner
+const FIRST_RETRY_SECS: u64 = 1;
+const MAX_RETRY_SECS: u64 = 120;
+/// REQUESTs sent for an advertisement before soliciting again (REQ_MAX_RC).
+const MAX_REQUESTS: u8 = 10;
+/// Shortest wait between retransmissions while renewing or rebinding.
+const MIN_RENEW_RETRY_SECS: u64 = 60;
+
+const SOLICIT: u8 = 1;
+const ADVERTISE: u8 = 2;
+const REQUEST: u8 = 3;
+const RENEW: u8 = 5;
+const REBIND: u8 = 6;
+const REPLY: u8 = 7;
+
+const OPT_CLIENT_ID: u16 = 1;
+const OPT_SERVER_ID: u16 = 2;
+const OPT_IA_NA: u16 = 3;
+const OPT_IA_ADDR: u16 = 5;
+const OPT_ORO: u16 = 6;
+const OPT_ELAPSED_TIME: u16 = 8;
+const OPT_STATUS_CODE: u16 = 13;
+const OPT_DNS_SERVERS: u16 = 23;
+const OPT_IA_PD: u16 = 25;
+const OPT_IA_PREFIX: u16 = 26;
+/// DUID-LL, made of the link-layer address (RFC 8415 section 11.4).
+const DUID_LL: [u8; 4] = [0, 3, 0, 1];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    /// From the IA_NA, `None` when the server only delegated a prefix.
+    pub address: Option<Ipv6Addr>,
+    /// From the IA_PD, `None` when the server only gave an address.
+    pub prefix: Option<Prefix>,
+    pub dns_servers: Vec<Ipv6Addr, MAX_DNS_SERVERS>,
+    pub server_id: Vec<u8, MAX_DUID_LEN>,
+    /// The shorter valid lifetime of the address and prefix.
+    pub valid_secs: u32,
+    pub renew_secs: u32,
+    pub rebind_secs: u32,
+    pub acquired_at: u64,
+}
+
+impl Lease {
+    fn from_reply(reply: &Message<'_>, iaid: u32, delegation: bool, now: u64) -> Option<Self> {
+        let address = reply.ia_address(iaid);
+        let prefix = reply.ia_prefix(iaid).filter(|_| delegation);
+        let timers = || {
+            address
+                .map(|(_, timers)| timers)
+                .into_iter()
+                .chain(prefix.map(|(_, timers)| timers))
+        };
+        let valid_secs = timers().map(|timers| timers.valid).min()?;
+        let preferred = timers().map(|timers| timers.preferred).min()?;
+        // T1 and T2 of 0 leave the timing to us, RFC 8415 section 21.4
+        // suggests half and 80% of the preferred lifetime
+        let renew_secs = timers()
+            .map(|timers| timers.renew)
+            .filter(|secs| *secs > 0)
+            .min()
+            .unwrap_or(preferred / 2)
+            .min(valid_secs);
+        let rebind_secs = timers()
+            .map(|timers| timers.rebind)
+            .filter(|secs| *secs > 0)
+            .min()
+            .unwrap_or((preferred as u64 * 4 / 5) as u32)
+            .clamp(renew_secs, valid_secs);
+        let mut dns_servers = Vec::new();
+        if let Some(servers) = reply.option(OPT_DNS_SERVERS) {
+            dns_servers.extend(servers.chunks_exact(16).map(ipv6).take(MAX_DNS_SERVERS));
+        }
+        Some(Self {
+            address: address.map(|(address, _)| address),
+            prefix: prefix.map(|(prefix, _)| prefix),
+            dns_servers,
+            server_id: Vec::from_slice(reply.option(OPT_SERVER_ID)?).ok()?,
+            valid_secs,
+            renew_secs,
+            rebind_secs,
+            acquired_at: now,
+        })
+    }
+
+    pub fn renew_at(&self) -> u64 {
+        self.at(self.renew_secs)
+    }
+
+    pub fn rebind_at(&self) -> u64 {
+        self.at(self.rebind_secs)
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.at(self.valid_secs)
+    }
+
+    fn at(&self, secs: u32) -> u64 {
+        // Infinite lifetimes never need renewing
+        if self.valid_secs == u32::MAX {
+            return u64::MAX;
+        }
+        self.acquired_at + secs as u64
+    }
+}
+
+/// T1 and T2 of an IA, and the lifetimes of the address or prefix in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Timers {
+    renew: u32,
+    rebind: u32,
+    preferred: u32,
+    valid: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Init,
+    Soliciting,
+    /// Asking the advertising server for what it offered.
+    Requesting {
+        address: Option<Ipv6Addr>,
+        prefix: Option<Prefix>,
+    },
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A frame of this length is ready to go out on the station interface,
+    /// from our link-local address to all DHCPv6 servers.
+    Send(usize),
+    /// An address or prefix was granted or renewed, see [`Client::lease`].
+    Bound,
+    /// The lease expired or the server took it back.
+    Lost,
+}
+
+/// DHCPv6 client state machine for the station interface (RFC 8415), asking
+/// for an address (IA_NA) and optionally a delegated prefix (IA_PD). Like
+/// the DHCPv4 client, time is passed in as seconds and frames are built
+/// into buffers supplied by the caller.
+pub struct Client {
+    mac: [u8; 6],
+    iaid: u32,
+    delegation: bool,
+    xid: u32,
+    state: State,
+    /// The server picked from the advertisements, or the one of the lease.
+    server_id: Vec<u8, MAX_DUID_LEN>,
+    lease: Option<Lease>,
+    next_at: u64,
+    attempts: u8,
+    /// When the current exchange started, for the elapsed time option.
+    started_at: u64,
+}
+
+impl Client {
+    /// With `delegation` the client also asks for a prefix to hand on.
+    pub fn new(mac: [u8; 6], xid: u32, delegation: bool) -> Self {
+        Self {
+            mac,
+            // Stays the same across restarts, so servers hand back the
+            // same address
+            iaid: u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]),
+            delegation,
+            xid: xid & 0xff_ffff,
+            state: State::Init,
+            server_id: Vec::new(),
+            lease: None,
+            next_at: 0,
+            attempts: 0,
+            started_at: 0,
+        }
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// When [`Client::poll`] has something to do next.
+    pub fn next_at(&self) -> u64 {
+        self.next_at
+    }
+
+    /// Runs the timers: (re)transmits, renews and expires the lease.
+    pub fn poll(&mut self, now: u64, buf: &mut [u8]) -> Option<Event> {
+        if now < self.next_at {
+            return None;
+        }
+        match self.state {
+            State::Init => {
+                self.begin(now, State::Soliciting);
+                self.retransmit(now, buf, SOLICIT)
+            }
+            State::Soliciting => self.retransmit(now, buf, SOLICIT),
+            State::Requesting { .. } => {
+                if self.attempts >= MAX_REQUESTS {
+                    self.restart(now);
+                    return None;
+                }
+                self.retransmit(now, buf, REQUEST)
+            }
+            State::Bound | State::Renewing | State::Rebinding => {
+                let lease = self.lease.clone()?;
+                if now >= lease.expires_at() {
+                    self.restart(now);
+                    return Some(Event::Lost);
+                }
+                // RENEW goes to the server we have the lease from, REBIND to
+                // whichever one answers. Each is a new exchange.
+                let (state, message_type, until) = if now >= lease.rebind_at() {
+                    (State::Rebinding, REBIND, lease.expires_at())
+                } else {
+                    (State::Renewing, RENEW, lease.rebind_at())
+                };
+                if self.state != state {
+                    self.begin(now, state);
+                }
+                let len = self.frame(buf, now, message_type)?;
+                self.next_at = until.min(now + ((until - now) / 2).max(MIN_RENEW_RETRY_SECS));
+                Some(Event::Send(len))
+            }
+        }
+    }
+
+    /// Handles a message from a server, as returned by [`client_message`].
+    pub fn handle(&mut self, raw: &[u8], now: u64) -> Option<Event> {
+        let message = Message::parse(raw)?;
+        if message.xid != self.xid || message.option(OPT_CLIENT_ID) != Some(&self.duid()[..]) {
+            return None;
+        }
+        let server_id = message.option(OPT_SERVER_ID)?;
+        match (self.state, message.message_type) {
+            (State::Soliciting, ADVERTISE) => {
+                let address = message.ia_address(self.iaid).map(|(address, _)| address);
+                let prefix = message
+                    .ia_prefix(self.iaid)
+                    .filter(|_| self.delegation)
+                    .map(|(prefix, _)| prefix);
+                if !message.is_success() || (address.is_none() && prefix.is_none()) {
+                    return None;
+                }
+                self.server_id = Vec::from_slice(server_id).ok()?;
+                self.begin(now, State::Requesting { address, prefix });
+                self.next_at = now;
+                None
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, REPLY) => {
+                if matches!(self.state, State::Requesting { .. }) && server_id != self.server_id {
+                    return None;
+                }
+                match Lease::from_reply(&message, self.iaid, self.delegation, now) {
+                    Some(lease) if message.is_success() => {
+                        self.server_id = lease.server_id.clone();
+                        self.next_at = lease.renew_at();
+                        self.lease = Some(lease);
+                        self.state = State::Bound;
+                        Some(Event::Bound)
+                    }
+                    // Nothing granted: back to soliciting, like a DHCPv4 NAK
+                    _ => {
+                        let had_lease = self.lease.is_some();
+                        self.restart(now);
+                        had_lease.then_some(Event::Lost)
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts a new exchange, which gets its own transaction ID.
+    fn begin(&mut self, now: u64, state: State) {
+        self.xid = self.xid.wrapping_add(1) & 0xff_ffff;
+        self.state = state;
+        self.attempts = 0;
+        self.started_at = now;
+    }
+
+    fn restart(&mut self, now: u64) {
+        self.lease = None;
+        self.state = State::Init;
+        self.next_at = now;
+    }
+
+    fn retransmit(&mut self, now: u64, buf: &mut [u8], message_type: u8) -> Option<Event> {
+        let len = self.frame(buf, now, message_type)?;
+        let delay = (FIRST_RETRY_SECS << self.attempts.min(7)).min(MAX_RETRY_SECS);
+        self.attempts = self.attempts.saturating_add(1);
+        self.next_at = now + delay;
+        Some(Event::Send(len))
+    }
+
+    fn duid(&self) -> [u8; 10] {
+        let mut duid = [0u8; 10];
+        duid[..4].copy_from_slice(&DUID_LL);
+        duid[4..].copy_from_slice(&self.mac);
+        duid
+    }
+
+    fn frame(&self, buf: &mut [u8], now: u64, message_type: u8) -> Option<usize> {
+        let mut message = Vec::<u8, MAX_MESSAGE_LEN>::new();
+        message.push(message_type).ok()?;
+        message
+            .extend_from_slice(&self.xid.to_be_bytes()[1..])
+            .ok()?;
+        push_option(&mut message, OPT_CLIENT_ID, &self.duid())?;
+        if matches!(message_type, REQUEST | RENEW) {
+            push_option(&mut message, OPT_SERVER_ID, &self.server_id)?;
+        }
+        // In hundredths of a second, and saturating
+        let elapsed = ((now - self.started_at) * 100).min(u16::MAX as u64) as u16;
+        push_option(&mut message, OPT_ELAPSED_TIME, &elapsed.to_be_bytes())?;
+        push_option(&mut message, OPT_ORO, &OPT_DNS_SERVERS.to_be_bytes())?;
+
+        // What was offered or leased goes along in the IAs, the lifetimes
+        // are the server's to pick
+        let (address, prefix) = match (self.state, &self.lease) {
+            (State::Requesting { address, prefix }, _) => (address, prefix),
+            (_, Some(lease)) => (lease.address, lease.prefix),
+            _ => (None, None),
+        };
+        let mut ia = Vec::<u8, 48>::new();
+        ia.extend_from_slice(&self.iaid.to_be_bytes()).ok()?;
+        ia.extend_from_slice(&[0; 8]).ok()?;
+        let header_len = ia.len();
+        if let Some(address) = address {
+            let mut option = [0u8; 24];
+            option[..16].copy_from_slice(&address.octets());
+            push_option(&mut ia, OPT_IA_ADDR, &option)?;
+        }
+        push_option(&mut message, OPT_IA_NA, &ia)?;
+        if self.delegation {
+            ia.truncate(header_len);
+            if let Some(prefix) = prefix {
+                let mut option = [0u8; 25];
+                option[8] = prefix.prefix_len();
+                option[9..].copy_from_slice(&prefix.addr().octets());
+                push_option(&mut ia, OPT_IA_PREFIX, &option)?;
+            }
+            push_option(&mut message, OPT_IA_PD, &ia)?;
+        }
+        write_frame(buf, self.mac, &message)
+    }
+}
+
+/// The DHCPv6 message in an IPv6 packet, if it is one from a server to a
+/// client with a valid checksum.
+pub fn client_message(packet: &[u8]) -> Option<&[u8]> {
+    let ip = Ipv6Packet::new_checked(packet).ok()?;
+    if ip.next_header() != IpProtocol::Udp {
+        return None;
+    }
+    let udp = UdpPacket::new_checked(ip.payload()).ok()?;
+    let valid = udp.src_port() == SERVER_PORT
+        && udp.dst_port() == CLIENT_PORT
+        && udp.verify_checksum(&ip.src_addr().into(), &ip.dst_addr().into());
+    valid.then(|| udp.payload())
+}
+
+/// Wraps `message` in a frame from the link-local address of `mac` to all
+/// DHCPv6 servers on the link.
+fn write_frame(buf: &mut [u8], mac: [u8; 6], message: &[u8]) -> Option<usize> {
+    let src = link_local(&mac);
+    let eth = EthernetRepr {
+        src_addr: EthernetAddress(mac),
+        dst_addr: EthernetAddress(ALL_SERVERS_MAC),
+        ethertype: EthernetProtocol::Ipv6,
+    };
+    let udp = UdpRepr {
+        src_port: CLIENT_PORT,
+        dst_port: SERVER_PORT,
+    };
+    let ip = Ipv6Repr {
+        src_addr: src,
+        dst_addr: ALL_SERVERS,
+        next_header: IpProtocol::Udp,
+        payload_len: udp.header_len() + message.len(),
+        hop_limit: 1,
+    };
+    let len = eth.buffer_len() + ip.buffer_len() + ip.payload_len;
+    let mut frame = EthernetFrame::new_unchecked(buf.get_mut(..len)?);
+    eth.emit(&mut frame);
+    let mut packet = Ipv6Packet::new_unchecked(frame.payload_mut());
+    ip.emit(&mut packet);
+    udp.emit(
+        &mut UdpPacket::new_unchecked(packet.payload_mut()),
+        &src.into(),
+        &ALL_SERVERS.into(),
+        message.len(),
+        |payload| payload.copy_from_slice(message),
+        &ChecksumCapabilities::default(),
+    );
+    Some(len)
+}
+
+fn push_option<const N: usize>(buf: &mut Vec<u8, N>, code: u16, data: &[u8]) -> Option<()> {
+    buf.extend_from_slice(&code.to_be_bytes()).ok()?;
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes())
+        .ok()?;
+    buf.extend_from_slice(data).ok()
+}
+
+struct Message<'a> {
+    message_type: u8,
+    xid: u32,
+    options: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn parse(raw: &'a [u8]) -> Option<Self> {
+        let header = raw.get(..4)?;
+        Some(Self {
+            message_type: header[0],
+            xid: u32::from_be_bytes([0, header[1], header[2], header[3]]),
+            options: &raw[4..],
+        })
+    }
+
+    fn option(&self, code: u16) -> Option<&'a [u8]> {
+        options(self.options).find_map(|(found, data)| (found == code).then_some(data))
+    }
+
+    /// Whether the message carries no status code, or one of success.
+    fn is_success(&self) -> bool {
+        is_success(self.options)
+    }
+
+    fn ia_address(&self, iaid: u32) -> Option<(Ipv6Addr, Timers)> {
+        let (renew, rebind, option) = ia(self.option(OPT_IA_NA)?, iaid, OPT_IA_ADDR)?;
+        let address = ipv6(option.get(..16)?);
+        let timers = Timers {
+            renew,
+            rebind,
+            preferred: be32(option.get(16..20)?),
+            valid: be32(option.get(20..24)?),
+        };
+        is_usable(&timers, &option[24..]).then_some((address, timers))
+    }
+
+    fn ia_prefix(&self, iaid: u32) -> Option<(Prefix, Timers)> {
+        let (renew, rebind, option) = ia(self.option(OPT_IA_PD)?, iaid, OPT_IA_PREFIX)?;
+        let prefix = Prefix::new(ipv6(option.get(9..25)?), option[8])?;
+        let timers = Timers {
+            renew,
+            rebind,
+            preferred: be32(&option[..4]),
+            valid: be32(&option[4..8]),
+        };
+        is_usable(&timers, &option[25..]).then_some((prefix, timers))
+    }
+}
+
+/// T1, T2 and the first option of type `inner` of an IA_NA or IA_PD with
+/// our IAID, unless the server says it has nothing for it.
+fn ia(data: &[u8], iaid: u32, inner: u16) -> Option<(u32, u32, &[u8])> {
+    if data.len() < 12 || be32(&data[..4]) != iaid || !is_success(&data[12..]) {
+        return None;
+    }
+    let (_, option) = options(&data[12..]).find(|(code, _)| *code == inner)?;
+    Some((be32(&data[4..8]), be32(&data[8..12]), option))
+}
+
+/// A binding with a status code other than success, or whose lifetimes
+/// are over or inconsistent, is ignored (RFC 8415 section 21.6).
+fn is_usable(timers: &Timers, options: &[u8]) -> bool {
+    timers.valid > 0 && timers.preferred <= timers.valid && is_success(options)
+}
+
+fn is_success(data: &[u8]) -> bool {
+    options(data).all(|(code, status)| code != OPT_STATUS_CODE || status.get(..2) == Some(&[0, 0]))
+}
+
+/// Iterates over the options in `data`, stopping at one that is cut short.
+fn options(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    core::iter::from_fn(move || {
+        let header = data.get(..4)?;
+        let code = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let option = data.get(4..4 + len)?;
+        data = &data[4 + len..];
+        Some((code, option))
+    })
+}
+
+fn ipv6(bytes: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(bytes);
+    Ipv6Addr::from(octets)
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+    const IAID: u32 = 0x2801_0203;
+    const SERVER_ID: [u8; 14] = [0, 1, 0, 1, 0x2c, 0x5e, 0x11, 0x22, 2, 0, 0, 0, 0, 0xfe];
+    const ADDRESS: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0x1234);
+    const DNS: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0x53);
+
+    fn delegated() -> Prefix {
+        Prefix::parse("2001:db8:2:100::/56").unwrap()
+    }
+
+    /// What a server reads out of the frame the client sent.
+    fn sent(client: &mut Client, now: u64) -> Vec<u8, MAX_MESSAGE_LEN> {
+        let mut buf = [0u8; 600];
+        let len = match client.poll(now, &mut buf) {
+            Some(Event::Send(len)) => len,
+            other => panic!("expected a message, got {other:?}"),
+        };
+        let frame = EthernetFrame::new_checked(&buf[..len]).unwrap();
+        assert_eq!(frame.dst_addr(), EthernetAddress(ALL_SERVERS_MAC));
+        assert_eq!(frame.src_addr(), EthernetAddress(MAC));
+        let ip = Ipv6Packet::new_checked(frame.payload()).unwrap();
+        assert_eq!(ip.src_addr(), link_local(&MAC));
+        assert_eq!(ip.dst_addr(), ALL_SERVERS);
+        let udp = UdpPacket::new_checked(ip.payload()).unwrap();
+        assert_eq!((udp.src_port(), udp.dst_port()), (CLIENT_PORT, SERVER_PORT));
+        assert!(udp.verify_checksum(&ip.src_addr().into(), &ip.dst_addr().into()));
+        Vec::from_slice(udp.payload()).unwrap()
+    }
+
+    fn ia_na(t1: u32, t2: u32, preferred: u32, valid: u32) -> Vec<u8, 64> {
+        let mut address = Vec::<u8, 24>::new();
+        address.extend_from_slice(&ADDRESS.octets()).unwrap();
+        address.extend_from_slice(&preferred.to_be_bytes()).unwrap();
+        address.extend_from_slice(&valid.to_be_bytes()).unwrap();
+        ia_with(t1, t2, OPT_IA_ADDR, &address)
+    }
+
+    fn ia_pd(t1: u32, t2: u32, preferred: u32, valid: u32) -> Vec<u8, 64> {
+        let prefix = delegated();
+        let mut option = Vec::<u8, 25>::new();
+        option.extend_from_slice(&preferred.to_be_bytes()).unwrap();
+        option.extend_from_slice(&valid.to_be_bytes()).unwrap();
+        option.push(prefix.prefix_len()).unwrap();
+        option.extend_from_slice(&prefix.addr().octets()).unwrap();
+        ia_with(t1, t2, OPT_IA_PREFIX, &option)
+    }
+
+    fn ia_with(t1: u32, t2: u32, code: u16, option: &[u8]) -> Vec<u8, 64> {
+        let mut ia = Vec::new();
+        ia.extend_from_slice(&IAID.to_be_bytes()).unwrap();
+        ia.extend_from_slice(&t1.to_be_bytes()).unwrap();
+        ia.extend_from_slice(&t2.to_be_bytes()).unwrap();
+        push_option(&mut ia, code, option).unwrap();
+        ia
+    }
+
+    fn status(code: u16) -> Vec<u8, 16> {
+        let mut status = Vec::from_slice(&code.to_be_bytes()).unwrap();
+        status.extend_from_slice(b"none").unwrap();
+        status
+    }
+
+    /// A server's answer to `request`, echoing its transaction and client ID.
+    fn answer(
+        message_type: u8,
+        request: &[u8],
+        options: &[(u16, &[u8])],
+    ) -> Vec<u8, MAX_MESSAGE_LEN> {
+        let request = Message::parse(request).unwrap();
+        let mut message = Vec::new();
+        message.push(message_type).unwrap();
+        message
+            .extend_from_slice(&request.xid.to_be_bytes()[1..])
+            .unwrap();
+        push_option(&mut message, OPT_SERVER_ID, &SERVER_ID).unwrap();
+        push_option(
+            &mut message,
+            OPT_CLIENT_ID,
+            request.option(OPT_CLIENT_ID).unwrap(),
+        )
+        .unwrap();
+        for (code, data) in options {
+            push_option(&mut message, *code, data).unwrap();
+        }
+        message
+    }
+
+    fn both() -> [(u16, Vec<u8, 64>); 2] {
+        [
+            (OPT_IA_NA, ia_na(1800, 2880, 3600, 7200)),
+            (OPT_IA_PD, ia_pd(1000, 2000, 3600, 5400)),
+        ]
+    }
+
+    fn bound(delegation: bool) -> Client {
+        let mut client = Client::new(MAC, 7, delegation);
+        let solicit = sent(&mut client, 0);
+        let options = both();
+        let options = options.each_ref().map(|(code, data)| (*code, &data[..]));
+        let advertise = answer(ADVERTISE, &solicit, &options);
+        assert_eq!(client.handle(&advertise, 1), None);
+        let request = sent(&mut client, 1);
+        let mut dns = [0u8; 16];
+        dns.copy_from_slice(&DNS.octets());
+        let mut reply_options = options.to_vec();
+        reply_options.push((OPT_DNS_SERVERS, &dns));
+        let reply = answer(REPLY, &request, &reply_options);
+        assert_eq!(client.handle(&reply, 2), Some(Event::Bound));
+        client
+    }
+
+    #[test]
+    fn solicits_an_address_and_a_prefix() {
+        let mut client = Client::new(MAC, 7, true);
+        let solicit = sent(&mut client, 0);
+        let message = Message::parse(&solicit).unwrap();
+        assert_eq!(message.message_type, SOLICIT);
+        assert_eq!(message.xid, 8);
+        assert_eq!(
+            message.option(OPT_CLIENT_ID),
+            Some(&[0, 3, 0, 1, 0x24, 0x6f, 0x28, 1, 2, 3][..])
+        );
+        assert_eq!(message.option(OPT_SERVER_ID), None);
+        assert_eq!(message.option(OPT_ELAPSED_TIME), Some(&[0, 0][..]));
+        assert_eq!(message.option(OPT_ORO), Some(&[0, 23][..]));
+        let mut empty_ia = [0u8; 12];
+        empty_ia[..4].copy_from_slice(&IAID.to_be_bytes());
+        assert_eq!(message.option(OPT_IA_NA), Some(&empty_ia[..]));
+        assert_eq!(message.option(OPT_IA_PD), Some(&empty_ia[..]));
+
+        // Backs off, in the same exchange
+        assert_eq!(client.poll(0, &mut [0u8; 600]), None);
+        let again = sent(&mut client, 1);
+        assert_eq!(Message::parse(&again).unwrap().xid, 8);
+        assert_eq!(client.next_at(), 3);
+        let later = sent(&mut client, 3);
+        assert_eq!(
+            Message::parse(&later).unwrap().option(OPT_ELAPSED_TIME),
+            Some(&300u16.to_be_bytes()[..])
+        );
+
+        let mut client = Client::new(MAC, 7, false);
+        let solicit = sent(&mut client, 0);
+        assert_eq!(Message::parse(&solicit).unwrap().option(OPT_IA_PD), None);
+    }
+
+    #[test]
+    fn requests_what_was_advertised() {
+        let mut client = Client::new(MAC, 7, true);
+        let solicit = sent(&mut client, 0);
+        let options = both();
+        let options = options.each_ref().map(|(code, data)| (*code, &data[..]));
+        client.handle(&answer(ADVERTISE, &solicit, &options), 1);
+
+        let request = sent(&mut client, 1);
+        let message = Message::parse(&request).unwrap();
+        assert_eq!(message.message_type, REQUEST);
+        assert_ne!(message.xid, Message::parse(&solicit).unwrap().xid);
+        assert_eq!(message.option(OPT_SERVER_ID), Some(&SERVER_ID[..]));
+        // The hints carry no lifetimes of their own
+        assert_eq!(
+            message.ia_address(IAID),
+            None,
+            "a zero valid lifetime is no binding"
+        );
+        let (_, _, address) = ia(message.option(OPT_IA_NA).unwrap(), IAID, OPT_IA_ADDR).unwrap();
+        assert_eq!(ipv6(&address[..16]), ADDRESS);
+        let (_, _, prefix) = ia(message.option(OPT_IA_PD).unwrap(), IAID, OPT_IA_PREFIX).unwrap();
+        assert_eq!(prefix[8], 56);
+        assert_eq!(ipv6(&prefix[9..25]), delegated().addr());
+    }
+
+    #[test]
+    fn binds_the_address_prefix_and_dns_servers() {
+        let client = bound(true);
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.address, Some(ADDRESS));
+        assert_eq!(lease.prefix, Some(delegated()));
+        assert_eq!(lease.dns_servers, [DNS]);
+        assert_eq!(lease.server_id, SERVER_ID);
+        // The earlier of the two IAs' timers, the shorter lifetime
+        assert_eq!(lease.renew_secs, 1000);
+        assert_eq!(lease.rebind_secs, 2000);
+        assert_eq!(lease.valid_secs, 5400);
+        assert_eq!(client.next_at(), 1002);
+    }
+
+    #[test]
+    fn renews_then_rebinds_then_expires() {
+        let mut client = bound(true);
+        let renew = sent(&mut client, 1002);
+        let message = Message::parse(&renew).unwrap();
+        assert_eq!(message.message_type, RENEW);
+        assert_eq!(message.option(OPT_SERVER_ID), Some(&SERVER_ID[..]));
+        let (_, _, address) = ia(message.option(OPT_IA_NA).unwrap(), IAID, OPT_IA_ADDR).unwrap();
+        assert_eq!(ipv6(&address[..16]), ADDRESS);
+
+        // Retried within the same exchange until T2
+        let retry_at = client.next_at();
+        let again = sent(&mut client, retry_at);
+        assert_eq!(Message::parse(&again).unwrap().xid, message.xid);
+
+        let rebind = sent(&mut client, 2002);
+        let rebind = Message::parse(&rebind).unwrap();
+        assert_eq!(rebind.message_type, REBIND);
+        assert_eq!(rebind.option(OPT_SERVER_ID), None);
+        assert_ne!(rebind.xid, message.xid);
+
+        assert_eq!(client.poll(5402, &mut [0u8; 600]), Some(Event::Lost));
+        assert_eq!(client.lease(), None);
+        let solicit = sent(&mut client, 5402);
+        assert_eq!(Message::parse(&solicit).unwrap().message_type, SOLICIT);
+    }
+
+    #[test]
+    fn a_renewal_moves_the_timers() {
+        let mut client = bound(false);
+        let renew = sent(&mut client, 1802);
+        let ia = ia_na(1800, 2880, 3600, 7200);
+        let reply = answer(REPLY, &renew, &[(OPT_IA_NA, &ia)]);
+        assert_eq!(client.handle(&reply, 1803), Some(Event::Bound));
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.prefix, None);
+        assert_eq!(lease.renew_at(), 1803 + 1800);
+        assert_eq!(lease.expires_at(), 1803 + 7200);
+    }
+
+    #[test]
+    fn takes_a_prefix_when_there_are_no_addresses() {
+        let mut client = Client::new(MAC, 7, true);
+        let solicit = sent(&mut client, 0);
+        let mut refused = Vec::<u8, 64>::new();
+        refused.extend_from_slice(&IAID.to_be_bytes()).unwrap();
+        refused.extend_from_slice(&[0; 8]).unwrap();
+        // NoAddrsAvail
+        push_option(&mut refused, OPT_STATUS_CODE, &status(2)).unwrap();
+        let pd = ia_pd(0, 0, 3600, 7200);
+        let options = [(OPT_IA_NA, &refused[..]), (OPT_IA_PD, &pd[..])];
+        client.handle(&answer(ADVERTISE, &solicit, &options), 1);
+        let request = sent(&mut client, 1);
+        assert_eq!(
+            client.handle(&answer(REPLY, &request, &options), 2),
+            Some(Event::Bound)
+        );
+
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.address, None);
+        assert_eq!(lease.prefix, Some(delegated()));
+        // No T1 or T2 from the server
+        assert_eq!(lease.renew_secs, 1800);
+        assert_eq!(lease.rebind_secs, 2880);
+    }
+
+    #[test]
+    fn ignores_other_transactions_and_empty_offers() {
+        let mut client = Client::new(MAC, 7, true);
+        let solicit = sent(&mut client, 0);
+        let options = both();
+        let options = options.each_ref().map(|(code, data)| (*code, &data[..]));
+
+        let mut stale = answer(ADVERTISE, &solicit, &options);
+        stale[3] ^= 1;
+        assert_eq!(client.handle(&stale, 1), None);
+        let mut someone_else = answer(ADVERTISE, &solicit, &options);
+        // Client ID comes right after the 4 byte header and server ID
+        someone_else[4 + 4 + SERVER_ID.len() + 4 + 9] ^= 1;
+        assert_eq!(client.handle(&someone_else, 1), None);
+        assert_eq!(client.handle(&answer(ADVERTISE, &solicit, &[]), 1), None);
+        let no_addrs = status(2);
+        let refused = answer(ADVERTISE, &solicit, &[(OPT_STATUS_CODE, &no_addrs)]);
+        assert_eq!(client.handle(&refused, 1), None);
+        assert_eq!(client.handle(&answer(REPLY, &solicit, &options), 1), None);
+        assert_eq!(client.lease(), None);
+
+        // Still soliciting
+        let again = sent(&mut client, 1);
+        assert_eq!(Message::parse(&again).unwrap().message_type, SOLICIT);
+    }
+
+    #[test]
+    fn loses_the_lease_when_the_server_has_no_binding() {
+        let mut client = bound(true);
+        let renew = sent(&mut client, 1002);
+        let no_binding = status(3);
+        let reply = answer(REPLY, &renew, &[(OPT_STATUS_CODE, &no_binding)]);
+        assert_eq!(client.handle(&reply, 1003), Some(Event::Lost));
+        assert_eq!(client.lease(), None);
+        let solicit = sent(&mut client, 1003);
+        assert_eq!(Message::parse(&solicit).unwrap().message_type, SOLICIT);
+    }
+
+    #[test]
+    fn reads_server_messages_out_of_packets() {
+        let mut client = Client::new(MAC, 7, false);
+        let mut buf = [0u8; 600];
+        let Some(Event::Send(len)) = client.poll(0, &mut buf) else {
+            panic!("expected a solicit");
+        };
+        let packet = &mut buf[14..len];
+        // The client's own message goes the other way
+        assert_eq!(client_message(packet), None);
+
+        let mut udp = UdpPacket::new_unchecked(&mut packet[40..]);
+        udp.set_src_port(SERVER_PORT);
+        udp.set_dst_port(CLIENT_PORT);
+        udp.fill_checksum(&link_local(&MAC).into(), &ALL_SERVERS.into());
+        let message = client_message(packet).unwrap();
+        assert_eq!(message[0], SOLICIT);
+
+        packet[60] ^= 1;
+        assert_eq!(client_message(packet), None);
+    }
+}
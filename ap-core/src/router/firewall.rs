@@ -0,0 +1,809 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::Vec;
+
+use super::nat::Protocol;
+use super::policer::Direction;
+
+pub const MAX_RULES: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleError {
+    UnknownAction,
+    UnknownDirection,
+    UnknownProtocol,
+    InvalidAddress,
+    InvalidPorts,
+    PortsWithoutProtocol,
+    UnknownField,
+    TooManyRules,
+}
+
+impl RuleError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnknownAction => "action must be allow, deny or log",
+            Self::UnknownDirection => "direction must be out, in or any",
+            Self::UnknownProtocol => "protocol must be tcp, udp, icmp or any",
+            Self::InvalidAddress => "addresses must be a.b.c.d/len or any",
+            Self::InvalidPorts => "ports must be a port or a first-last range",
+            Self::PortsWithoutProtocol => "ports need protocol tcp or udp",
+            Self::UnknownField => "unknown rule field",
+            Self::TooManyRules => "too many firewall rules",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleAction {
+    Allow,
+    Deny,
+    /// Logs the packet and carries on with the next rule.
+    Log,
+}
+
+impl RuleAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+            Self::Log => "log",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            "log" => Some(Self::Log),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: u32,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        if prefix_len > 32 {
+            return None;
+        }
+        Some(Self {
+            network: u32::from(addr) & mask(prefix_len),
+            prefix_len,
+        })
+    }
+
+    /// Parses `a.b.c.d/len`, or a bare address as a /32.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.split_once('/') {
+            Some((addr, len)) => Self::new(addr.parse().ok()?, len.parse().ok()?),
+            None => Self::new(s.parse().ok()?, 32),
+        }
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & mask(self.prefix_len) == self.network
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.network), self.prefix_len)
+    }
+}
+
+fn mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let port = s.parse().ok()?;
+                (port, port)
+            }
+        };
+        (first <= last).then_some(Self { first, last })
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// A forwarded packet as the firewall sees it, always from the point of view
+/// of the AP client: `client` is the inside address after undoing any NAT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub direction: Direction,
+    pub protocol: Protocol,
+    pub client: Ipv4Addr,
+    pub client_port: u16,
+    pub remote: Ipv4Addr,
+    pub remote_port: u16,
+}
+
+impl Packet {
+    fn src(&self) -> (Ipv4Addr, u16) {
+        match self.direction {
+            Direction::Up => (self.client, self.client_port),
+            Direction::Down => (self.remote, self.remote_port),
+        }
+    }
+
+    fn dst(&self) -> (Ipv4Addr, u16) {
+        match self.direction {
+            Direction::Up => (self.remote, self.remote_port),
+            Direction::Down => (self.client, self.client_port),
+        }
+    }
+}
+
+/// `None` fields match anything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub action: RuleAction,
+    pub direction: Option<Direction>,
+    pub protocol: Option<Protocol>,
+    pub src: Option<Cidr>,
+    pub src_ports: Option<PortRange>,
+    pub dst: Option<Cidr>,
+    pub dst_ports: Option<PortRange>,
+}
+
+impl Rule {
+    /// Compiles `action=deny dir=out proto=tcp dst=10.0.0.0/8 dport=22`.
+    /// Fields left out match anything.
+    pub fn parse(s: &str) -> Result<Self, RuleError> {
+        let mut rule = Self {
+            action: RuleAction::Allow,
+            direction: None,
+            protocol: None,
+            src: None,
+            src_ports: None,
+            dst: None,
+            dst_ports: None,
+        };
+        let mut action = None;
+        for field in s.split_whitespace() {
+            let (key, value) = field.split_once('=').ok_or(RuleError::UnknownField)?;
+            let any = value == "any";
+            match key {
+                "action" => action = RuleAction::parse(value),
+                "dir" => {
+                    rule.direction = match value {
+                        "out" => Some(Direction::Up),
+                        "in" => Some(Direction::Down),
+                        "any" => None,
+                        _ => return Err(RuleError::UnknownDirection),
+                    }
+                }
+                "proto" => {
+                    rule.protocol = match value {
+                        "tcp" => Some(Protocol::Tcp),
+                        "udp" => Some(Protocol::Udp),
+                        "icmp" => Some(Protocol::Icmp),
+                        "any" => None,
+                        _ => return Err(RuleError::UnknownProtocol),
+                    }
+                }
+                "src" | "dst" => {
+                    let cidr = match any {
+                        true => None,
+                        false => Some(Cidr::parse(value).ok_or(RuleError::InvalidAddress)?),
+                    };
+                    match key {
+                        "src" => rule.src = cidr,
+                        _ => rule.dst = cidr,
+                    }
+                }
+                "sport" | "dport" => {
+                    let ports = match any {
+                        true => None,
+                        false => Some(PortRange::parse(value).ok_or(RuleError::InvalidPorts)?),
+                    };
+                    match key {
+                        "sport" => rule.src_ports = ports,
+                        _ => rule.dst_ports = ports,
+                    }
+                }
+                _ => return Err(RuleError::UnknownField),
+            }
+        }
+        rule.action = action.ok_or(RuleError::UnknownAction)?;
+        let has_ports = rule.src_ports.is_some() || rule.dst_ports.is_some();
+        if has_ports && !matches!(rule.protocol, Some(Protocol::Tcp | Protocol::Udp)) {
+            return Err(RuleError::PortsWithoutProtocol);
+        }
+        Ok(rule)
+    }
+
+    pub fn matches(&self, packet: &Packet) -> bool {
+        let (src, src_port) = packet.src();
+        let (dst, dst_port) = packet.dst();
+        self.direction.is_none_or(|d| d == packet.direction)
+            && self.protocol.is_none_or(|p| p == packet.protocol)
+            && self.src.is_none_or(|cidr| cidr.contains(src))
+            && self.dst.is_none_or(|cidr| cidr.contains(dst))
+            && self.src_ports.is_none_or(|ports| ports.contains(src_port))
+            && self.dst_ports.is_none_or(|ports| ports.contains(dst_port))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "action={}", self.action.as_str())?;
+        match self.direction {
+            Some(Direction::Up) => write!(f, " dir=out")?,
+            Some(Direction::Down) => write!(f, " dir=in")?,
+            None => {}
+        }
+        if let Some(protocol) = self.protocol {
+            write!(f, " proto={}", protocol.as_str())?;
+        }
+        if let Some(src) = self.src {
+            write!(f, " src={src}")?;
+        }
+        if let Some(ports) = self.src_ports {
+            write!(f, " sport={ports}")?;
+        }
+        if let Some(dst) = self.dst {
+            write!(f, " dst={dst}")?;
+        }
+        if let Some(ports) = self.dst_ports {
+            write!(f, " dport={ports}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules that keep guests off private networks upstream, while still
+/// letting them use a DNS server there.
+pub fn block_private_preset() -> [Rule; 5] {
+    let deny_to = |cidr: &str| Rule {
+        action: RuleAction::Deny,
+        direction: Some(Direction::Up),
+        protocol: None,
+        src: None,
+        src_ports: None,
+        dst: Cidr::parse(cidr),
+        dst_ports: None,
+    };
+    let dns = |protocol| Rule {
+        action: RuleAction::Allow,
+        direction: Some(Direction::Up),
+        protocol: Some(protocol),
+        src: None,
+        src_ports: None,
+        dst: None,
+        dst_ports: Some(PortRange {
+            first: 53,
+            last: 53,
+        }),
+    };
+    [
+        dns(Protocol::Udp),
+        dns(Protocol::Tcp),
+        deny_to("10.0.0.0/8"),
+        deny_to("172.16.0.0/12"),
+        deny_to("192.168.0.0/16"),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Part of, or related to, a connection that was let through before.
+    Established,
+    Allowed,
+    Denied,
+}
+
+impl Verdict {
+    pub fn is_allowed(&self) -> bool {
+        *self != Self::Denied
+    }
+}
+
+/// The outcome of `Firewall::check`. `logged` lists the log rules that
+/// matched on the way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub verdict: Verdict,
+    /// Index of the rule that decided, `None` for the default policy or an
+    /// established connection.
+    pub rule: Option<usize>,
+    pub logged: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FirewallConfig {
+    pub enabled: bool,
+    pub default_out: RuleAction,
+    pub default_in: RuleAction,
+    pub rules: Vec<Rule, MAX_RULES>,
+}
+
+impl FirewallConfig {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            default_out: RuleAction::Allow,
+            // Only NAT replies and port forwards reach clients from upstream
+            // anyway, so letting them through is what users expect
+            default_in: RuleAction::Allow,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Inserts `rule` before the rule at `position`, or at the end.
+    pub fn insert(&mut self, position: Option<usize>, rule: Rule) -> Result<(), RuleError> {
+        let position = position.map_or(self.rules.len(), |p| p.min(self.rules.len()));
+        self.rules
+            .insert(position, rule)
+            .map_err(|_| RuleError::TooManyRules)
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "enabled={}", self.enabled as u8)?;
+        writeln!(out, "default_out={}", self.default_out.as_str())?;
+        writeln!(out, "default_in={}", self.default_in.as_str())?;
+        for rule in &self.rules {
+            writeln!(out, "rule={rule}")?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "enabled" => config.enabled = value == "1",
+                "default_out" => config.default_out = parse_policy(value)?,
+                "default_in" => config.default_in = parse_policy(value)?,
+                "rule" => config.rules.push(Rule::parse(value).ok()?).ok()?,
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+}
+
+/// Default policies can only allow or deny.
+pub fn parse_policy(s: &str) -> Option<RuleAction> {
+    RuleAction::parse(s).filter(|action| *action != RuleAction::Log)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Connection {
+    protocol: Protocol,
+    client: Ipv4Addr,
+    client_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+    last_seen: u64,
+}
+
+impl Connection {
+    fn is_for(&self, packet: &Packet) -> bool {
+        self.protocol == packet.protocol
+            && self.client == packet.client
+            && self.client_port == packet.client_port
+            && self.remote == packet.remote
+            && self.remote_port == packet.remote_port
+    }
+
+    fn is_idle(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_seen) > self.protocol.idle_timeout()
+    }
+}
+
+/// Ordered rules plus connection tracking. Rules only see the first packet
+/// of a connection; everything after it, in either direction, is let
+/// through as established.
+pub struct Firewall<const N: usize> {
+    config: FirewallConfig,
+    hits: [u32; MAX_RULES],
+    default_hits: [u32; 2],
+    connections: Vec<Connection, N>,
+}
+
+impl<const N: usize> Firewall<N> {
+    pub const fn new() -> Self {
+        Self {
+            config: FirewallConfig::new(),
+            hits: [0; MAX_RULES],
+            default_hits: [0; 2],
+            connections: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FirewallConfig {
+        &self.config
+    }
+
+    /// Replaces the rules. Counters start over and tracked connections are
+    /// dropped, so new deny rules apply to them straight away.
+    pub fn set_config(&mut self, config: FirewallConfig) {
+        self.config = config;
+        self.hits = [0; MAX_RULES];
+        self.default_hits = [0; 2];
+        self.connections.clear();
+    }
+
+    pub fn hits(&self) -> &[u32] {
+        &self.hits[..self.config.rules.len()]
+    }
+
+    /// Packets that fell through to the default policy, outbound and inbound.
+    pub fn default_hits(&self) -> [u32; 2] {
+        self.default_hits
+    }
+
+    pub fn reset_hits(&mut self) {
+        self.hits = [0; MAX_RULES];
+        self.default_hits = [0; 2];
+    }
+
+    pub fn check(&mut self, packet: &Packet, now: u64) -> Decision {
+        if !self.config.enabled {
+            return Decision {
+                verdict: Verdict::Allowed,
+                rule: None,
+                logged: false,
+            };
+        }
+        if let Some(connection) = self.connections.iter_mut().find(|c| c.is_for(packet)) {
+            if !connection.is_idle(now) {
+                connection.last_seen = now;
+                return Decision {
+                    verdict: Verdict::Established,
+                    rule: None,
+                    logged: false,
+                };
+            }
+        }
+
+        let mut logged = false;
+        let mut decided = None;
+        for (index, rule) in self.config.rules.iter().enumerate() {
+            if !rule.matches(packet) {
+                continue;
+            }
+            self.hits[index] = self.hits[index].saturating_add(1);
+            match rule.action {
+                RuleAction::Log => logged = true,
+                action => {
+                    decided = Some((index, action));
+                    break;
+                }
+            }
+        }
+        let (rule, action) = match decided {
+            Some((index, action)) => (Some(index), action),
+            None => {
+                let (policy, hits) = match packet.direction {
+                    Direction::Up => (self.config.default_out, &mut self.default_hits[0]),
+                    Direction::Down => (self.config.default_in, &mut self.default_hits[1]),
+                };
+                *hits = hits.saturating_add(1);
+                (None, policy)
+            }
+        };
+
+        let verdict = match action {
+            RuleAction::Deny => Verdict::Denied,
+            _ => {
+                self.track(packet, now);
+                Verdict::Allowed
+            }
+        };
+        Decision {
+            verdict,
+            rule,
+            logged,
+        }
+    }
+
+    /// Whether an ICMP error about `packet`'s connection may go back to the
+    /// client, which it can if the connection is tracked.
+    pub fn is_related(&self, packet: &Packet, now: u64) -> bool {
+        !self.config.enabled
+            || self
+                .connections
+                .iter()
+                .any(|c| c.is_for(packet) && !c.is_idle(now))
+    }
+
+    fn track(&mut self, packet: &Packet, now: u64) {
+        self.connections.retain(|c| !c.is_idle(now));
+        if self.connections.is_full() {
+            if let Some(index) = self
+                .connections
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| c.last_seen)
+                .map(|(index, _)| index)
+            {
+                self.connections.swap_remove(index);
+            }
+        }
+        _ = self.connections.push(Connection {
+            protocol: packet.protocol,
+            client: packet.client,
+            client_port: packet.client_port,
+            remote: packet.remote,
+            remote_port: packet.remote_port,
+            last_seen: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 20);
+
+    fn out(protocol: Protocol, remote: [u8; 4], remote_port: u16) -> Packet {
+        Packet {
+            direction: Direction::Up,
+            protocol,
+            client: CLIENT,
+            client_port: 50000,
+            remote: Ipv4Addr::from(remote),
+            remote_port,
+        }
+    }
+
+    /// The reply to `packet`.
+    fn reply(packet: Packet) -> Packet {
+        Packet {
+            direction: Direction::Down,
+            ..packet
+        }
+    }
+
+    fn firewall(rules: &[&str]) -> Firewall<4> {
+        let mut config = FirewallConfig::new();
+        config.enabled = true;
+        for rule in rules {
+            config.insert(None, Rule::parse(rule).unwrap()).unwrap();
+        }
+        let mut firewall = Firewall::new();
+        firewall.set_config(config);
+        firewall
+    }
+
+    #[test]
+    fn parses_and_prints_rules() {
+        let text = "action=deny dir=out proto=tcp src=192.168.4.0/24 sport=1000-2000 dst=10.0.0.0/8 dport=22";
+        let rule = Rule::parse(text).unwrap();
+        assert_eq!(rule.direction, Some(Direction::Up));
+        assert_eq!(
+            rule.dst_ports,
+            Some(PortRange {
+                first: 22,
+                last: 22
+            })
+        );
+        assert_eq!(std::format!("{rule}"), text);
+        // Host bits are dropped, `any` is the same as leaving a field out
+        let rule = Rule::parse("action=allow dst=10.1.2.3/8 src=any dir=any").unwrap();
+        assert_eq!(std::format!("{rule}"), "action=allow dst=10.0.0.0/8");
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        let cases = [
+            ("dir=out", RuleError::UnknownAction),
+            ("action=drop", RuleError::UnknownAction),
+            ("action=deny dir=up", RuleError::UnknownDirection),
+            ("action=deny proto=gre", RuleError::UnknownProtocol),
+            ("action=deny dst=10.0.0.0/33", RuleError::InvalidAddress),
+            ("action=deny proto=tcp dport=80-22", RuleError::InvalidPorts),
+            ("action=deny dport=22", RuleError::PortsWithoutProtocol),
+            (
+                "action=deny proto=icmp sport=1",
+                RuleError::PortsWithoutProtocol,
+            ),
+            ("action=deny colour=red", RuleError::UnknownField),
+            ("action=deny dst", RuleError::UnknownField),
+        ];
+        for (text, error) in cases {
+            assert_eq!(Rule::parse(text), Err(error), "{text}");
+        }
+    }
+
+    #[test]
+    fn matches_addresses_from_the_senders_side() {
+        let rule = Rule::parse("action=deny src=8.8.8.0/24 dport=50000 proto=udp").unwrap();
+        let query = out(Protocol::Udp, [8, 8, 8, 8], 53);
+        // Going up, the client is the source
+        assert!(!rule.matches(&query));
+        assert!(rule.matches(&reply(query)));
+        assert!(!rule.matches(&reply(out(Protocol::Tcp, [8, 8, 8, 8], 53))));
+        assert!(!rule.matches(&reply(out(Protocol::Udp, [8, 8, 4, 4], 53))));
+    }
+
+    #[test]
+    fn first_deciding_rule_wins() {
+        let mut firewall = firewall(&[
+            "action=log proto=tcp",
+            "action=allow proto=tcp dst=10.0.0.5 dport=443",
+            "action=deny dst=10.0.0.0/8",
+        ]);
+        let allowed = firewall.check(&out(Protocol::Tcp, [10, 0, 0, 5], 443), 0);
+        assert_eq!(
+            allowed,
+            Decision {
+                verdict: Verdict::Allowed,
+                rule: Some(1),
+                logged: true,
+            }
+        );
+        let denied = firewall.check(&out(Protocol::Udp, [10, 0, 0, 5], 443), 0);
+        assert_eq!(
+            (denied.verdict, denied.rule, denied.logged),
+            (Verdict::Denied, Some(2), false)
+        );
+        let default = firewall.check(&out(Protocol::Udp, [1, 1, 1, 1], 53), 0);
+        assert_eq!((default.verdict, default.rule), (Verdict::Allowed, None));
+        assert_eq!(firewall.hits(), [1, 1, 1]);
+        assert_eq!(firewall.default_hits(), [1, 0]);
+
+        firewall.reset_hits();
+        assert_eq!(firewall.hits(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn tracked_connections_skip_the_rules() {
+        let mut firewall = firewall(&[]);
+        let mut config = firewall.config().clone();
+        config.default_in = RuleAction::Deny;
+        firewall.set_config(config);
+
+        let request = out(Protocol::Tcp, [1, 1, 1, 1], 80);
+        assert_eq!(firewall.check(&reply(request), 0).verdict, Verdict::Denied);
+        assert_eq!(firewall.check(&request, 0).verdict, Verdict::Allowed);
+        assert_eq!(
+            firewall.check(&reply(request), 1).verdict,
+            Verdict::Established
+        );
+        assert!(firewall.is_related(&request, 1));
+        // Another port on the same server isn't part of it
+        let other = out(Protocol::Tcp, [1, 1, 1, 1], 81);
+        assert_eq!(firewall.check(&reply(other), 1).verdict, Verdict::Denied);
+        assert!(!firewall.is_related(&other, 1));
+    }
+
+    #[test]
+    fn connections_expire_when_idle() {
+        let mut firewall = firewall(&["action=deny dir=in"]);
+        let query = out(Protocol::Udp, [1, 1, 1, 1], 53);
+        firewall.check(&query, 0);
+        assert_eq!(
+            firewall.check(&reply(query), 60).verdict,
+            Verdict::Established
+        );
+        // Each packet restarts the timeout
+        assert_eq!(
+            firewall.check(&reply(query), 120).verdict,
+            Verdict::Established
+        );
+        assert_eq!(firewall.check(&reply(query), 181).verdict, Verdict::Denied);
+        assert!(!firewall.is_related(&query, 181));
+    }
+
+    #[test]
+    fn full_table_forgets_the_oldest_connection() {
+        let mut firewall = firewall(&["action=deny dir=in"]);
+        for (i, now) in (0..5).zip(0..) {
+            firewall.check(&out(Protocol::Tcp, [1, 1, 1, i], 80), now);
+        }
+        let first = out(Protocol::Tcp, [1, 1, 1, 0], 80);
+        let last = out(Protocol::Tcp, [1, 1, 1, 4], 80);
+        assert_eq!(firewall.check(&reply(first), 5).verdict, Verdict::Denied);
+        assert_eq!(
+            firewall.check(&reply(last), 5).verdict,
+            Verdict::Established
+        );
+    }
+
+    #[test]
+    fn new_rules_apply_to_open_connections() {
+        let mut firewall = firewall(&[]);
+        let request = out(Protocol::Tcp, [10, 0, 0, 5], 22);
+        firewall.check(&request, 0);
+        let mut config = firewall.config().clone();
+        config
+            .insert(Some(0), Rule::parse("action=deny dst=10.0.0.0/8").unwrap())
+            .unwrap();
+        firewall.set_config(config);
+        assert_eq!(firewall.check(&request, 1).verdict, Verdict::Denied);
+    }
+
+    #[test]
+    fn disabled_firewall_allows_everything() {
+        let mut firewall = firewall(&["action=deny"]);
+        let mut config = firewall.config().clone();
+        config.enabled = false;
+        firewall.set_config(config);
+        let packet = out(Protocol::Tcp, [1, 1, 1, 1], 80);
+        assert!(firewall.check(&packet, 0).verdict.is_allowed());
+        assert!(firewall.is_related(&packet, 0));
+    }
+
+    #[test]
+    fn private_preset_keeps_dns_upstream() {
+        let mut config = FirewallConfig::new();
+        config.enabled = true;
+        for rule in block_private_preset() {
+            config.insert(None, rule).unwrap();
+        }
+        let mut firewall = Firewall::<4>::new();
+        firewall.set_config(config);
+
+        for (remote, port, allowed) in [
+            ([192, 168, 1, 1], 53, true),
+            ([10, 0, 0, 1], 53, true),
+            ([192, 168, 1, 1], 80, false),
+            ([172, 16, 0, 1], 443, false),
+            ([172, 31, 255, 255], 22, false),
+            ([172, 32, 0, 1], 443, true),
+            ([10, 255, 0, 1], 80, false),
+            ([1, 1, 1, 1], 443, true),
+        ] {
+            let verdict = firewall.check(&out(Protocol::Tcp, remote, port), 0).verdict;
+            assert_eq!(verdict.is_allowed(), allowed, "{remote:?}:{port}");
+        }
+        let udp_dns = out(Protocol::Udp, [192, 168, 1, 1], 53);
+        assert!(firewall.check(&udp_dns, 0).verdict.is_allowed());
+    }
+
+    #[test]
+    fn config_round_trips_through_text() {
+        let mut config = FirewallConfig::new();
+        config.enabled = true;
+        config.default_in = RuleAction::Deny;
+        config
+            .insert(
+                None,
+                Rule::parse("action=deny proto=udp dport=137-139").unwrap(),
+            )
+            .unwrap();
+        config
+            .insert(Some(0), Rule::parse("action=log dir=in").unwrap())
+            .unwrap();
+        assert_eq!(config.rules[0].action, RuleAction::Log);
+
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert_eq!(FirewallConfig::parse(&text), Some(config));
+        assert_eq!(parse_policy("log"), None);
+        assert_eq!(FirewallConfig::parse("default_in=log"), None);
+    }
+}
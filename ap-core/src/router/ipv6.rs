@@ -0,0 +1,349 @@
+use core::fmt::{self, Write};
+use core::net::Ipv6Addr;
+use heapless::Vec;
+
+use super::ndp::{PrefixInfo, RouterAdvert};
+
+pub const MAX_DNS_SERVERS: usize = 3;
+/// How often the AP network is sent unsolicited Router Advertisements.
+pub const ADVERT_INTERVAL_SECS: u32 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Prefix {
+    addr: Ipv6Addr,
+    len: u8,
+}
+
+impl Prefix {
+    pub fn new(addr: Ipv6Addr, len: u8) -> Option<Self> {
+        if len > 128 {
+            return None;
+        }
+        Some(Self {
+            addr: Ipv6Addr::from(u128::from(addr) & mask(len)),
+            len,
+        })
+    }
+
+    /// Parses `fd12:3456:789a:1::/64`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, len) = s.trim().split_once('/')?;
+        Self::new(addr.parse().ok()?, len.parse().ok()?)
+    }
+
+    /// A /64 from a unique local prefix (RFC 4193), `fdXX:XXXX:XXXX:SSSS::/64`
+    /// with a random 40-bit global ID and the given subnet ID.
+    pub fn unique_local(global_id: [u8; 5], subnet: u16) -> Self {
+        let mut octets = [0u8; 16];
+        octets[0] = 0xfd;
+        octets[1..6].copy_from_slice(&global_id);
+        octets[6..8].copy_from_slice(&subnet.to_be_bytes());
+        Self {
+            addr: Ipv6Addr::from(octets),
+            len: 64,
+        }
+    }
+
+    pub fn addr(&self) -> Ipv6Addr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    /// The address for `interface_id` in this prefix. Only /64s can be used
+    /// for stateless autoconfiguration.
+    pub fn address(&self, interface_id: [u8; 8]) -> Option<Ipv6Addr> {
+        if self.len != 64 {
+            return None;
+        }
+        let mut octets = self.addr.octets();
+        octets[8..].copy_from_slice(&interface_id);
+        Some(Ipv6Addr::from(octets))
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+fn mask(len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - len as u32).unwrap_or(0)
+}
+
+/// Modified EUI-64 interface identifier (RFC 4291 appendix A).
+pub fn interface_id(mac: &[u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+pub fn link_local(mac: &[u8; 6]) -> Ipv6Addr {
+    let link_local = Prefix {
+        addr: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0),
+        len: 64,
+    };
+    link_local.address(interface_id(mac)).unwrap()
+}
+
+/// The address stateless autoconfiguration picks from a Router
+/// Advertisement: the first usable autonomous prefix plus our EUI-64.
+pub fn slaac_address(advert: &RouterAdvert, mac: &[u8; 6]) -> Option<(Ipv6Addr, PrefixInfo)> {
+    advert
+        .prefixes
+        .iter()
+        .filter(|info| {
+            info.autonomous
+                && info.valid_lifetime > 0
+                && info.preferred_lifetime <= info.valid_lifetime
+                && !info.prefix.addr.is_unicast_link_local()
+        })
+        .find_map(|info| Some((info.prefix.address(interface_id(mac))?, *info)))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    PrefixNot64,
+    NotUniqueLocal,
+    InvalidDnsServer,
+}
+
+impl ConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PrefixNot64 => "the AP prefix must be a /64",
+            Self::NotUniqueLocal => "the AP prefix must be unique local (fc00::/7)",
+            Self::InvalidDnsServer => "DNS servers must be unicast addresses",
+        }
+    }
+}
+
+/// IPv6 on the AP network: a unique local /64 announced by Router
+/// Advertisements. We don't route IPv6 upstream, so the advertisements
+/// don't offer us as a default router.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv6Config {
+    pub enabled: bool,
+    pub prefix: Prefix,
+    /// Announced with the RDNSS option, left out when empty.
+    pub dns_servers: Vec<Ipv6Addr, MAX_DNS_SERVERS>,
+}
+
+impl Ipv6Config {
+    pub fn new(prefix: Prefix) -> Self {
+        Self {
+            enabled: true,
+            prefix,
+            dns_servers: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.prefix.len != 64 {
+            return Err(ConfigError::PrefixNot64);
+        }
+        if !self.prefix.addr.is_unique_local() {
+            return Err(ConfigError::NotUniqueLocal);
+        }
+        if self
+            .dns_servers
+            .iter()
+            .any(|ip| ip.is_unspecified() || ip.is_multicast() || ip.is_loopback())
+        {
+            return Err(ConfigError::InvalidDnsServer);
+        }
+        Ok(())
+    }
+
+    /// The AP's own address on the prefix.
+    pub fn address(&self) -> Ipv6Addr {
+        self.prefix.address([0, 0, 0, 0, 0, 0, 0, 1]).unwrap()
+    }
+
+    pub fn advert(&self) -> RouterAdvert {
+        let mut prefixes = Vec::new();
+        _ = prefixes.push(PrefixInfo {
+            prefix: self.prefix,
+            on_link: true,
+            autonomous: true,
+            valid_lifetime: 24 * 60 * 60,
+            preferred_lifetime: 4 * 60 * 60,
+        });
+        RouterAdvert {
+            hop_limit: 64,
+            managed: false,
+            other: false,
+            router_lifetime: 0,
+            mtu: None,
+            prefixes,
+            dns_servers: self.dns_servers.clone(),
+            // Three missed advertisements before clients give up on them
+            dns_lifetime: 3 * ADVERT_INTERVAL_SECS,
+        }
+    }
+
+    /// An advertisement with zero lifetimes, sent when the prefix is
+    /// replaced or turned off.
+    pub fn withdrawal(&self) -> RouterAdvert {
+        let mut advert = self.advert();
+        for info in &mut advert.prefixes {
+            info.valid_lifetime = 0;
+            info.preferred_lifetime = 0;
+        }
+        advert.dns_lifetime = 0;
+        advert
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "enabled={}", self.enabled as u8)?;
+        writeln!(out, "prefix={}", self.prefix)?;
+        for ip in &self.dns_servers {
+            writeln!(out, "dns={ip}")?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut enabled = true;
+        let mut prefix = None;
+        let mut dns_servers = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "enabled" => enabled = value == "1",
+                "prefix" => prefix = Some(Prefix::parse(value)?),
+                "dns" => dns_servers.push(value.parse().ok()?).ok()?,
+                _ => {}
+            }
+        }
+        Some(Self {
+            enabled,
+            prefix: prefix?,
+            dns_servers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+
+    fn config() -> Ipv6Config {
+        Ipv6Config::new(Prefix::parse("fd12:3456:789a:1::/64").unwrap())
+    }
+
+    #[test]
+    fn parses_and_masks_prefixes() {
+        let prefix = Prefix::parse(" fd12:3456:789a:1::99/48 ").unwrap();
+        assert_eq!(
+            prefix.addr(),
+            "fd12:3456:789a::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(prefix.prefix_len(), 48);
+        assert_eq!(std::format!("{prefix}"), "fd12:3456:789a::/48");
+        assert_eq!(Prefix::parse("::/0").unwrap().prefix_len(), 0);
+        assert_eq!(Prefix::parse("fd00::/129"), None);
+        assert_eq!(Prefix::parse("fd00::"), None);
+
+        let ula = Prefix::unique_local([0x12, 0x34, 0x56, 0x78, 0x9a], 1);
+        assert_eq!(ula, Prefix::parse("fd12:3456:789a:1::/64").unwrap());
+        // Only /64s can be autoconfigured
+        assert_eq!(prefix.address(interface_id(&MAC)), None);
+    }
+
+    #[test]
+    fn derives_eui64_addresses() {
+        assert_eq!(
+            interface_id(&MAC),
+            [0x26, 0x6f, 0x28, 0xff, 0xfe, 0x01, 0x02, 0x03]
+        );
+        assert_eq!(
+            link_local(&MAC),
+            "fe80::266f:28ff:fe01:203".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            config().address(),
+            "fd12:3456:789a:1::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn advertises_the_prefix_without_a_default_router() {
+        let mut config = config();
+        config.dns_servers.push(config.address()).unwrap();
+        let advert = config.advert();
+        assert_eq!(advert.router_lifetime, 0);
+        assert!(!advert.managed && !advert.other);
+        assert_eq!(
+            advert.prefixes.as_slice(),
+            [PrefixInfo {
+                prefix: config.prefix,
+                on_link: true,
+                autonomous: true,
+                valid_lifetime: 86400,
+                preferred_lifetime: 14400,
+            }]
+        );
+        assert_eq!(advert.dns_servers, config.dns_servers);
+        assert_eq!(advert.dns_lifetime, 3 * ADVERT_INTERVAL_SECS);
+
+        let withdrawal = config.withdrawal();
+        assert_eq!(withdrawal.prefixes[0].prefix, config.prefix);
+        assert_eq!(withdrawal.prefixes[0].valid_lifetime, 0);
+        assert_eq!(withdrawal.prefixes[0].preferred_lifetime, 0);
+        assert_eq!(withdrawal.dns_lifetime, 0);
+    }
+
+    #[test]
+    fn picks_a_slaac_address() {
+        let mut advert = config().advert();
+        let (addr, info) = slaac_address(&advert, &MAC).unwrap();
+        assert_eq!(
+            addr,
+            "fd12:3456:789a:1:266f:28ff:fe01:203"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        assert_eq!(info, advert.prefixes[0]);
+
+        // Withdrawn, not autonomous or not a /64 prefixes are skipped
+        assert_eq!(slaac_address(&config().withdrawal(), &MAC), None);
+        advert.prefixes[0].autonomous = false;
+        assert_eq!(slaac_address(&advert, &MAC), None);
+        advert.prefixes[0].autonomous = true;
+        advert.prefixes[0].prefix = Prefix::parse("2001:db8::/56").unwrap();
+        assert_eq!(slaac_address(&advert, &MAC), None);
+    }
+
+    #[test]
+    fn validates_and_stores_the_config() {
+        let mut config = config();
+        config
+            .dns_servers
+            .push("fd12:3456:789a:1::53".parse().unwrap())
+            .unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert_eq!(Ipv6Config::parse(&text), Some(config.clone()));
+
+        config.dns_servers[0] = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(config.validate(), Err(ConfigError::InvalidDnsServer));
+        config.prefix = Prefix::parse("2001:db8:1::/64").unwrap();
+        assert_eq!(config.validate(), Err(ConfigError::NotUniqueLocal));
+        config.prefix = Prefix::parse("fd12:3456::/48").unwrap();
+        assert_eq!(config.validate(), Err(ConfigError::PrefixNot64));
+    }
+}
@@ -0,0 +1,8 @@
+pub mod nat;
+pub mod port_forward;
+pub mod policer;
+pub mod firewall;
+pub mod bridge;
+pub mod ipv6;
+pub mod ndp;
+pub mod dhcpv6;
@@ -0,0 +1,201 @@
+use core::net::Ipv4Addr;
+use core::ops::Range;
+use heapless::Vec;
+
+/// Outside ports handed out to translated flows. The STA stack picks the
+/// ports of its own unbound sockets from all of 1025-65535, so a packet to
+/// one of these ports without a mapping still goes to the stack. Sockets
+/// the firmware binds itself use fixed ports below this range.
+pub const PORT_RANGE: Range<u16> = 40000..49152;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    /// ICMP echo, with the identifier standing in for the port.
+    Icmp,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Icmp => "icmp",
+        }
+    }
+
+    pub fn idle_timeout(&self) -> u64 {
+        match self {
+            Self::Tcp => 10 * 60,
+            Self::Udp => 60,
+            Self::Icmp => 30,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub protocol: Protocol,
+    pub inside_mac: [u8; 6],
+    pub inside_ip: Ipv4Addr,
+    pub inside_port: u16,
+    pub outside_port: u16,
+    pub last_seen: u64,
+}
+
+impl Mapping {
+    fn is_idle(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_seen) > self.protocol.idle_timeout()
+    }
+}
+
+/// Port-translation table for traffic from AP clients out of the STA address.
+pub struct NatTable<const N: usize> {
+    mappings: Vec<Mapping, N>,
+    next_port: u16,
+}
+
+impl<const N: usize> NatTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            next_port: PORT_RANGE.start,
+        }
+    }
+
+    /// Returns the outside port for a flow leaving an AP client, creating a
+    /// mapping if needed. `None` means every slot is taken by a live flow.
+    pub fn outbound(
+        &mut self,
+        protocol: Protocol,
+        mac: [u8; 6],
+        ip: Ipv4Addr,
+        port: u16,
+        now: u64,
+    ) -> Option<u16> {
+        if let Some(mapping) = self
+            .mappings
+            .iter_mut()
+            .find(|m| m.protocol == protocol && m.inside_ip == ip && m.inside_port == port)
+        {
+            mapping.inside_mac = mac;
+            mapping.last_seen = now;
+            return Some(mapping.outside_port);
+        }
+
+        self.mappings.retain(|mapping| !mapping.is_idle(now));
+        let outside_port = self.free_port(protocol)?;
+        self.mappings
+            .push(Mapping {
+                protocol,
+                inside_mac: mac,
+                inside_ip: ip,
+                inside_port: port,
+                outside_port,
+                last_seen: now,
+            })
+            .ok()?;
+        Some(outside_port)
+    }
+
+    /// Looks up the AP client a packet arriving on `outside_port` belongs to.
+    pub fn inbound(&mut self, protocol: Protocol, outside_port: u16, now: u64) -> Option<Mapping> {
+        let mapping = self
+            .mappings
+            .iter_mut()
+            .find(|m| m.protocol == protocol && m.outside_port == outside_port)?;
+        if mapping.is_idle(now) {
+            return None;
+        }
+        mapping.last_seen = now;
+        Some(*mapping)
+    }
+
+    pub fn remove_client(&mut self, mac: &[u8; 6]) {
+        self.mappings.retain(|mapping| mapping.inside_mac != *mac);
+    }
+
+    fn free_port(&mut self, protocol: Protocol) -> Option<u16> {
+        let range_len = PORT_RANGE.end - PORT_RANGE.start;
+        for _ in 0..range_len {
+            let port = self.next_port;
+            self.next_port = if port + 1 >= PORT_RANGE.end {
+                PORT_RANGE.start
+            } else {
+                port + 1
+            };
+            if !self
+                .mappings
+                .iter()
+                .any(|m| m.protocol == protocol && m.outside_port == port)
+            {
+                return Some(port);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Redirect {
+    client_ip: Ipv4Addr,
+    client_port: u16,
+    target: Ipv4Addr,
+    last_seen: u64,
+}
+
+/// Connections from AP clients that were rewritten to the captive portal,
+/// remembered so replies can be made to look like they came from the
+/// address the client originally asked for.
+pub struct RedirectTable<const N: usize> {
+    redirects: Vec<Redirect, N>,
+}
+
+impl<const N: usize> RedirectTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            redirects: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.redirects.is_empty()
+    }
+
+    pub fn insert(&mut self, client_ip: Ipv4Addr, client_port: u16, target: Ipv4Addr, now: u64) {
+        if let Some(redirect) = self
+            .redirects
+            .iter_mut()
+            .find(|r| r.client_ip == client_ip && r.client_port == client_port)
+        {
+            redirect.target = target;
+            redirect.last_seen = now;
+            return;
+        }
+        if self.redirects.is_full() {
+            if let Some(index) = self
+                .redirects
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, redirect)| redirect.last_seen)
+                .map(|(index, _)| index)
+            {
+                self.redirects.swap_remove(index);
+            }
+        }
+        _ = self.redirects.push(Redirect {
+            client_ip,
+            client_port,
+            target,
+            last_seen: now,
+        });
+    }
+
+    pub fn target(&self, client_ip: Ipv4Addr, client_port: u16) -> Option<Ipv4Addr> {
+        self.redirects
+            .iter()
+            .find(|r| r.client_ip == client_ip && r.client_port == client_port)
+            .map(|r| r.target)
+    }
+}
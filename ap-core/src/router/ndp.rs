@@ -0,0 +1,468 @@
+use core::net::Ipv6Addr;
+use heapless::Vec;
+
+use super::ipv6::{link_local, Prefix, MAX_DNS_SERVERS};
+
+pub const MAX_PREFIXES: usize = 4;
+/// Router Advertisements get at least this far apart (RFC 4861 section 10).
+pub const MIN_ADVERT_DELAY_SECS: u64 = 3;
+
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+const ETH_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+const NEXT_HEADER_ICMPV6: u8 = 58;
+/// Neighbor Discovery messages from off-link are forged (RFC 4861 6.1).
+const NDP_HOP_LIMIT: u8 = 255;
+
+const ROUTER_SOLICIT: u8 = 133;
+const ROUTER_ADVERT: u8 = 134;
+const NEIGHBOR_SOLICIT: u8 = 135;
+const NEIGHBOR_ADVERT: u8 = 136;
+const ADVERT_HEADER_LEN: usize = 16;
+const NEIGHBOR_HEADER_LEN: usize = 24;
+const FLAG_MANAGED: u8 = 0x80;
+const FLAG_OTHER: u8 = 0x40;
+const FLAG_ROUTER: u8 = 0x80;
+const FLAG_SOLICITED: u8 = 0x40;
+const FLAG_OVERRIDE: u8 = 0x20;
+
+const OPT_SOURCE_LLADDR: u8 = 1;
+const OPT_TARGET_LLADDR: u8 = 2;
+const OPT_PREFIX_INFO: u8 = 3;
+const OPT_MTU: u8 = 5;
+const OPT_RDNSS: u8 = 25;
+const PREFIX_ON_LINK: u8 = 0x80;
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrefixInfo {
+    pub prefix: Prefix,
+    pub on_link: bool,
+    pub autonomous: bool,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouterAdvert {
+    pub hop_limit: u8,
+    /// Addresses come from DHCPv6 rather than SLAAC.
+    pub managed: bool,
+    /// Other configuration (DNS...) comes from DHCPv6.
+    pub other: bool,
+    /// Seconds the sender may be used as default router, 0 for none.
+    pub router_lifetime: u16,
+    pub mtu: Option<u32>,
+    pub prefixes: Vec<PrefixInfo, MAX_PREFIXES>,
+    pub dns_servers: Vec<Ipv6Addr, MAX_DNS_SERVERS>,
+    pub dns_lifetime: u32,
+}
+
+impl RouterAdvert {
+    /// Parses a Router Advertisement out of an IPv6 packet, returning it
+    /// along with the router's link-local address. Anything else, or an
+    /// advertisement that didn't come from the local link, gives `None`.
+    pub fn parse(packet: &[u8]) -> Option<(Ipv6Addr, Self)> {
+        let (src, icmp) = ndp_message(packet, ROUTER_ADVERT)?;
+        if !src.is_unicast_link_local() || icmp.len() < ADVERT_HEADER_LEN {
+            return None;
+        }
+        let mut advert = Self {
+            hop_limit: icmp[4],
+            managed: icmp[5] & FLAG_MANAGED != 0,
+            other: icmp[5] & FLAG_OTHER != 0,
+            router_lifetime: u16::from_be_bytes([icmp[6], icmp[7]]),
+            mtu: None,
+            prefixes: Vec::new(),
+            dns_servers: Vec::new(),
+            dns_lifetime: 0,
+        };
+        let mut options = &icmp[ADVERT_HEADER_LEN..];
+        while !options.is_empty() {
+            let len = *options.get(1)? as usize * 8;
+            if len == 0 || len > options.len() {
+                return None;
+            }
+            let (option, rest) = options.split_at(len);
+            options = rest;
+            match option[0] {
+                OPT_PREFIX_INFO if len == 32 => {
+                    let Some(prefix) = Prefix::new(ipv6(&option[16..32]), option[2]) else {
+                        continue;
+                    };
+                    // Extra prefixes beyond what we keep are ignored
+                    _ = advert.prefixes.push(PrefixInfo {
+                        prefix,
+                        on_link: option[3] & PREFIX_ON_LINK != 0,
+                        autonomous: option[3] & PREFIX_AUTONOMOUS != 0,
+                        valid_lifetime: be32(&option[4..8]),
+                        preferred_lifetime: be32(&option[8..12]),
+                    });
+                }
+                OPT_MTU if len == 8 => advert.mtu = Some(be32(&option[4..8])),
+                OPT_RDNSS if len >= 24 => {
+                    advert.dns_lifetime = be32(&option[4..8]);
+                    for addr in option[8..].chunks_exact(16) {
+                        _ = advert.dns_servers.push(ipv6(addr));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some((src, advert))
+    }
+
+    /// Writes the advertisement as an Ethernet frame to all nodes, sent from
+    /// the link-local address of `mac`. Returns the frame length, or `None`
+    /// if `buf` is too small.
+    pub fn write_frame(&self, buf: &mut [u8], mac: [u8; 6]) -> Option<usize> {
+        let mut icmp = [0u8; 256];
+        icmp[0] = ROUTER_ADVERT;
+        icmp[4] = self.hop_limit;
+        icmp[5] =
+            if self.managed { FLAG_MANAGED } else { 0 } | if self.other { FLAG_OTHER } else { 0 };
+        icmp[6..8].copy_from_slice(&self.router_lifetime.to_be_bytes());
+        let mut len = ADVERT_HEADER_LEN;
+
+        icmp[len] = OPT_SOURCE_LLADDR;
+        icmp[len + 1] = 1;
+        icmp[len + 2..len + 8].copy_from_slice(&mac);
+        len += 8;
+        if let Some(mtu) = self.mtu {
+            icmp[len] = OPT_MTU;
+            icmp[len + 1] = 1;
+            icmp[len + 4..len + 8].copy_from_slice(&mtu.to_be_bytes());
+            len += 8;
+        }
+        for info in &self.prefixes {
+            let option = &mut icmp[len..len + 32];
+            option[0] = OPT_PREFIX_INFO;
+            option[1] = 4;
+            option[2] = info.prefix.prefix_len();
+            option[3] = if info.on_link { PREFIX_ON_LINK } else { 0 }
+                | if info.autonomous {
+                    PREFIX_AUTONOMOUS
+                } else {
+                    0
+                };
+            option[4..8].copy_from_slice(&info.valid_lifetime.to_be_bytes());
+            option[8..12].copy_from_slice(&info.preferred_lifetime.to_be_bytes());
+            option[16..32].copy_from_slice(&info.prefix.addr().octets());
+            len += 32;
+        }
+        if !self.dns_servers.is_empty() {
+            let option_len = 8 + 16 * self.dns_servers.len();
+            let option = &mut icmp[len..len + option_len];
+            option[0] = OPT_RDNSS;
+            option[1] = (option_len / 8) as u8;
+            option[4..8].copy_from_slice(&self.dns_lifetime.to_be_bytes());
+            for (addr, chunk) in self
+                .dns_servers
+                .iter()
+                .zip(option[8..].chunks_exact_mut(16))
+            {
+                chunk.copy_from_slice(&addr.octets());
+            }
+            len += option_len;
+        }
+
+        write_frame(
+            buf,
+            mac,
+            [0x33, 0x33, 0, 0, 0, 1],
+            link_local(&mac),
+            ALL_NODES,
+            &mut icmp[..len],
+        )
+    }
+}
+
+/// Whether an IPv6 packet is a Router Solicitation.
+pub fn is_router_solicit(packet: &[u8]) -> bool {
+    ndp_message(packet, ROUTER_SOLICIT).is_some()
+}
+
+/// A Router Solicitation to all routers. It is sent from the unspecified
+/// address, which is allowed before we have one and gets the answer
+/// multicast to all nodes.
+pub fn router_solicit_frame(buf: &mut [u8], mac: [u8; 6]) -> Option<usize> {
+    let mut icmp = [0u8; 8];
+    icmp[0] = ROUTER_SOLICIT;
+    write_frame(
+        buf,
+        mac,
+        [0x33, 0x33, 0, 0, 0, 2],
+        Ipv6Addr::UNSPECIFIED,
+        ALL_ROUTERS,
+        &mut icmp,
+    )
+}
+
+/// A Neighbor Solicitation, asking who has `target`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NeighborSolicit {
+    /// Unspecified while the sender checks its own address is unique.
+    pub src: Ipv6Addr,
+    pub target: Ipv6Addr,
+}
+
+impl NeighborSolicit {
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let (src, icmp) = ndp_message(packet, NEIGHBOR_SOLICIT)?;
+        let target = ipv6(icmp.get(8..NEIGHBOR_HEADER_LEN)?);
+        if target.is_multicast() {
+            return None;
+        }
+        Some(Self { src, target })
+    }
+
+    /// Writes the Neighbor Advertisement saying `mac` has the target,
+    /// unicast to the sender at `src_mac`, or to all nodes when it had no
+    /// address yet (RFC 4861 section 7.2.4).
+    pub fn answer_frame(
+        &self,
+        buf: &mut [u8],
+        mac: [u8; 6],
+        src_mac: [u8; 6],
+        router: bool,
+    ) -> Option<usize> {
+        let mut icmp = [0u8; NEIGHBOR_HEADER_LEN + 8];
+        icmp[0] = NEIGHBOR_ADVERT;
+        icmp[4] = if router { FLAG_ROUTER } else { 0 } | FLAG_OVERRIDE;
+        icmp[8..NEIGHBOR_HEADER_LEN].copy_from_slice(&self.target.octets());
+        icmp[NEIGHBOR_HEADER_LEN] = OPT_TARGET_LLADDR;
+        icmp[NEIGHBOR_HEADER_LEN + 1] = 1;
+        icmp[NEIGHBOR_HEADER_LEN + 2..].copy_from_slice(&mac);
+
+        let (dst_mac, dst) = if self.src.is_unspecified() {
+            ([0x33, 0x33, 0, 0, 0, 1], ALL_NODES)
+        } else {
+            icmp[4] |= FLAG_SOLICITED;
+            (src_mac, self.src)
+        };
+        write_frame(buf, mac, dst_mac, self.target, dst, &mut icmp)
+    }
+}
+
+/// Checks the IPv6 and ICMPv6 headers of a Neighbor Discovery message of
+/// type `kind`, returning the source address and the ICMPv6 message.
+fn ndp_message(packet: &[u8], kind: u8) -> Option<(Ipv6Addr, &[u8])> {
+    let header = packet.get(..IPV6_HEADER_LEN)?;
+    if header[0] >> 4 != 6 || header[6] != NEXT_HEADER_ICMPV6 || header[7] != NDP_HOP_LIMIT {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let icmp = packet.get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)?;
+    if icmp.len() < 8 || icmp[0] != kind || icmp[1] != 0 {
+        return None;
+    }
+    let src = ipv6(&header[8..24]);
+    let dst = ipv6(&header[24..40]);
+    (checksum(src, dst, icmp) == 0).then_some((src, icmp))
+}
+
+fn write_frame(
+    buf: &mut [u8],
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    src: Ipv6Addr,
+    dst: Ipv6Addr,
+    icmp: &mut [u8],
+) -> Option<usize> {
+    let len = ETH_HEADER_LEN + IPV6_HEADER_LEN + icmp.len();
+    let frame = buf.get_mut(..len)?;
+    frame[..6].copy_from_slice(&dst_mac);
+    frame[6..12].copy_from_slice(&src_mac);
+    frame[12..14].copy_from_slice(&ETHERTYPE_IPV6);
+
+    let ip = &mut frame[ETH_HEADER_LEN..];
+    ip[..4].copy_from_slice(&[0x60, 0, 0, 0]);
+    ip[4..6].copy_from_slice(&(icmp.len() as u16).to_be_bytes());
+    ip[6] = NEXT_HEADER_ICMPV6;
+    ip[7] = NDP_HOP_LIMIT;
+    ip[8..24].copy_from_slice(&src.octets());
+    ip[24..40].copy_from_slice(&dst.octets());
+
+    icmp[2..4].fill(0);
+    let sum = checksum(src, dst, icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    ip[IPV6_HEADER_LEN..].copy_from_slice(icmp);
+    Some(len)
+}
+
+/// The ICMPv6 checksum, including the IPv6 pseudo-header. Comes out as 0
+/// over a message that carries a valid one.
+fn checksum(src: Ipv6Addr, dst: Ipv6Addr, icmp: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let word = match chunk {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+                [hi] => u16::from_be_bytes([*hi, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+        }
+    };
+    add(&src.octets());
+    add(&dst.octets());
+    add(&(icmp.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, NEXT_HEADER_ICMPV6]);
+    add(icmp);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ipv6(bytes: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(bytes);
+    Ipv6Addr::from(octets)
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::ipv6::Ipv6Config;
+
+    const MAC: [u8; 6] = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+
+    fn advert() -> RouterAdvert {
+        let mut config = Ipv6Config::new(Prefix::parse("fd12:3456:789a:1::/64").unwrap());
+        config.dns_servers.push(config.address()).unwrap();
+        config
+            .dns_servers
+            .push("fd12:3456:789a:1::53".parse().unwrap())
+            .unwrap();
+        let mut advert = config.advert();
+        advert.mtu = Some(1400);
+        advert
+    }
+
+    fn frame(advert: &RouterAdvert) -> Vec<u8, 512> {
+        let mut buf = [0u8; 512];
+        let len = advert.write_frame(&mut buf, MAC).unwrap();
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn writes_adverts_to_all_nodes() {
+        let advert = advert();
+        let frame = frame(&advert);
+        assert_eq!(frame[..6], [0x33, 0x33, 0, 0, 0, 1]);
+        assert_eq!(frame[6..12], MAC);
+        assert_eq!(frame[12..14], ETHERTYPE_IPV6);
+        assert_eq!(ipv6(&frame[38..54]), ALL_NODES);
+        // Header, source link-layer address, MTU, prefix, RDNSS with two
+        assert_eq!(
+            frame.len(),
+            ETH_HEADER_LEN + IPV6_HEADER_LEN + 16 + 8 + 8 + 32 + 40
+        );
+
+        let (src, parsed) = RouterAdvert::parse(&frame[ETH_HEADER_LEN..]).unwrap();
+        assert_eq!(src, link_local(&MAC));
+        assert_eq!(parsed, advert);
+    }
+
+    #[test]
+    fn leaves_out_empty_options() {
+        let mut advert = advert();
+        advert.mtu = None;
+        advert.dns_servers.clear();
+        advert.dns_lifetime = 0;
+        let frame = frame(&advert);
+        assert_eq!(frame.len(), ETH_HEADER_LEN + IPV6_HEADER_LEN + 16 + 8 + 32);
+        assert_eq!(
+            RouterAdvert::parse(&frame[ETH_HEADER_LEN..]).unwrap().1,
+            advert
+        );
+        assert_eq!(advert.write_frame(&mut [0u8; 100], MAC), None);
+    }
+
+    #[test]
+    fn rejects_forged_or_damaged_adverts() {
+        let frame = frame(&advert());
+        let mut packet = Vec::<u8, 512>::from_slice(&frame[ETH_HEADER_LEN..]).unwrap();
+        packet[IPV6_HEADER_LEN + 20] ^= 1;
+        assert_eq!(RouterAdvert::parse(&packet), None);
+
+        // Routed here from off-link
+        let mut packet = Vec::<u8, 512>::from_slice(&frame[ETH_HEADER_LEN..]).unwrap();
+        packet[7] = 64;
+        assert_eq!(RouterAdvert::parse(&packet), None);
+        assert_eq!(RouterAdvert::parse(&packet[..30]), None);
+        assert!(!is_router_solicit(&frame[ETH_HEADER_LEN..]));
+    }
+
+    #[test]
+    fn writes_router_solicits() {
+        let mut buf = [0u8; 128];
+        let len = router_solicit_frame(&mut buf, MAC).unwrap();
+        assert_eq!(buf[..6], [0x33, 0x33, 0, 0, 0, 2]);
+        let packet = &buf[ETH_HEADER_LEN..len];
+        assert!(is_router_solicit(packet));
+        assert_eq!(ipv6(&packet[8..24]), Ipv6Addr::UNSPECIFIED);
+        assert_eq!(RouterAdvert::parse(packet), None);
+    }
+
+    fn neighbor_solicit(src: Ipv6Addr, target: Ipv6Addr) -> Vec<u8, 128> {
+        let mut icmp = [0u8; NEIGHBOR_HEADER_LEN];
+        icmp[0] = NEIGHBOR_SOLICIT;
+        icmp[8..].copy_from_slice(&target.octets());
+        let mut buf = [0u8; 128];
+        // Sent to the solicited-node address, the MACs don't matter here
+        let dst: Ipv6Addr = "ff02::1:ff02:303".parse().unwrap();
+        let len = write_frame(&mut buf, [2; 6], [0x33; 6], src, dst, &mut icmp).unwrap();
+        Vec::from_slice(&buf[ETH_HEADER_LEN..len]).unwrap()
+    }
+
+    #[test]
+    fn answers_neighbor_solicits() {
+        let peer = [0x02, 0, 0, 0, 0, 9];
+        let from: Ipv6Addr = "fe80::9".parse().unwrap();
+        let solicit = NeighborSolicit::parse(&neighbor_solicit(from, link_local(&MAC))).unwrap();
+        assert_eq!(solicit.src, from);
+        assert_eq!(solicit.target, link_local(&MAC));
+
+        let mut buf = [0u8; 128];
+        let len = solicit.answer_frame(&mut buf, MAC, peer, true).unwrap();
+        assert_eq!(buf[..6], peer);
+        assert_eq!(buf[6..12], MAC);
+        let packet = &buf[ETH_HEADER_LEN..len];
+        assert_eq!(ipv6(&packet[8..24]), link_local(&MAC));
+        assert_eq!(ipv6(&packet[24..40]), from);
+        let (_, icmp) = ndp_message(packet, NEIGHBOR_ADVERT).unwrap();
+        assert_eq!(icmp[4], FLAG_ROUTER | FLAG_SOLICITED | FLAG_OVERRIDE);
+        assert_eq!(ipv6(&icmp[8..24]), link_local(&MAC));
+        assert_eq!(
+            icmp[24..],
+            [OPT_TARGET_LLADDR, 1, 0x24, 0x6f, 0x28, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn answers_duplicate_address_checks_to_all_nodes() {
+        let packet = neighbor_solicit(Ipv6Addr::UNSPECIFIED, link_local(&MAC));
+        let solicit = NeighborSolicit::parse(&packet).unwrap();
+        let mut buf = [0u8; 128];
+        let len = solicit.answer_frame(&mut buf, MAC, [2; 6], false).unwrap();
+        assert_eq!(buf[..6], [0x33, 0x33, 0, 0, 0, 1]);
+        let (_, icmp) = ndp_message(&buf[ETH_HEADER_LEN..len], NEIGHBOR_ADVERT).unwrap();
+        assert_eq!(icmp[4], FLAG_OVERRIDE);
+
+        assert_eq!(
+            NeighborSolicit::parse(&frame(&advert())[ETH_HEADER_LEN..]),
+            None
+        );
+        let packet = neighbor_solicit(Ipv6Addr::UNSPECIFIED, ALL_NODES);
+        assert_eq!(NeighborSolicit::parse(&packet), None);
+    }
+}
@@ -0,0 +1,446 @@
+use core::fmt::{self, Write};
+use heapless::Vec;
+
+use crate::mac::{self, MacDisplay};
+
+pub const MAX_OVERRIDES: usize = 16;
+
+/// How much traffic a bucket lets through at once after being idle, in
+/// milliseconds worth of its rate.
+const BURST_MS: u64 = 250;
+/// Never less than two full frames, or slow limits couldn't pass any.
+const MIN_BURST_BYTES: u64 = 2 * 1514;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From an AP client to the upstream network.
+    Up,
+    Down,
+}
+
+/// Rates in kbit/s, 0 meaning unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limit {
+    pub up_kbps: u32,
+    pub down_kbps: u32,
+}
+
+impl Limit {
+    pub const UNLIMITED: Self = Self {
+        up_kbps: 0,
+        down_kbps: 0,
+    };
+
+    fn kbps(&self, direction: Direction) -> u32 {
+        match direction {
+            Direction::Up => self.up_kbps,
+            Direction::Down => self.down_kbps,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Override {
+    pub mac: [u8; 6],
+    pub limit: Limit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverridesFull;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    pub default: Limit,
+    pub overrides: Vec<Override, MAX_OVERRIDES>,
+}
+
+impl RateLimits {
+    pub const fn new() -> Self {
+        Self {
+            default: Limit::UNLIMITED,
+            overrides: Vec::new(),
+        }
+    }
+
+    pub fn limit_for(&self, mac: &[u8; 6]) -> Limit {
+        self.overrides
+            .iter()
+            .find(|o| o.mac == *mac)
+            .map_or(self.default, |o| o.limit)
+    }
+
+    /// Adds or replaces the override for `mac`.
+    pub fn set_override(&mut self, mac: [u8; 6], limit: Limit) -> Result<(), OverridesFull> {
+        if let Some(existing) = self.overrides.iter_mut().find(|o| o.mac == mac) {
+            existing.limit = limit;
+            return Ok(());
+        }
+        self.overrides
+            .push(Override { mac, limit })
+            .map_err(|_| OverridesFull)
+    }
+
+    pub fn remove_override(&mut self, mac: &[u8; 6]) -> bool {
+        let before = self.overrides.len();
+        self.overrides.retain(|o| o.mac != *mac);
+        self.overrides.len() != before
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(
+            out,
+            "default={},{}",
+            self.default.up_kbps, self.default.down_kbps
+        )?;
+        for o in &self.overrides {
+            writeln!(
+                out,
+                "override={},{},{}",
+                MacDisplay(&o.mac),
+                o.limit.up_kbps,
+                o.limit.down_kbps
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut config = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "default" => config.default = parse_limit(value)?,
+                "override" => {
+                    let (mac, limit) = value.split_once(',')?;
+                    config
+                        .overrides
+                        .push(Override {
+                            mac: mac::parse(mac)?,
+                            limit: parse_limit(limit)?,
+                        })
+                        .ok()?;
+                }
+                _ => {}
+            }
+        }
+        Some(config)
+    }
+}
+
+fn parse_limit(value: &str) -> Option<Limit> {
+    let (up, down) = value.split_once(',')?;
+    Some(Limit {
+        up_kbps: up.parse().ok()?,
+        down_kbps: down.parse().ok()?,
+    })
+}
+
+/// Token bucket over a millisecond clock. Tokens are kept in thousandths of
+/// a byte so slow rates still refill on every tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenBucket {
+    bytes_per_sec: u64,
+    tokens: u64,
+    last_refill: u64,
+}
+
+impl TokenBucket {
+    pub fn new(kbps: u32, now_ms: u64) -> Self {
+        let mut bucket = Self {
+            bytes_per_sec: 0,
+            tokens: 0,
+            last_refill: now_ms,
+        };
+        bucket.set_rate(kbps);
+        bucket.tokens = bucket.capacity();
+        bucket
+    }
+
+    pub fn set_rate(&mut self, kbps: u32) {
+        self.bytes_per_sec = kbps as u64 * 1000 / 8;
+        self.tokens = self.tokens.min(self.capacity());
+    }
+
+    fn capacity(&self) -> u64 {
+        (self.bytes_per_sec * BURST_MS / 1000).max(MIN_BURST_BYTES) * 1000
+    }
+
+    /// Takes `len` bytes worth of tokens if there are enough.
+    pub fn take(&mut self, len: usize, now_ms: u64) -> bool {
+        if self.bytes_per_sec == 0 {
+            return true;
+        }
+        let elapsed = now_ms.saturating_sub(self.last_refill);
+        self.last_refill = now_ms;
+        self.tokens = self
+            .tokens
+            .saturating_add(elapsed.saturating_mul(self.bytes_per_sec))
+            .min(self.capacity());
+
+        let needed = len as u64 * 1000;
+        if self.tokens < needed {
+            return false;
+        }
+        self.tokens -= needed;
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub packets_up: u64,
+    pub packets_down: u64,
+    pub dropped_up: u64,
+    pub dropped_down: u64,
+}
+
+impl Counters {
+    fn count(&mut self, direction: Direction, len: usize, passed: bool) {
+        let (bytes, packets, dropped) = match direction {
+            Direction::Up => (
+                &mut self.bytes_up,
+                &mut self.packets_up,
+                &mut self.dropped_up,
+            ),
+            Direction::Down => (
+                &mut self.bytes_down,
+                &mut self.packets_down,
+                &mut self.dropped_down,
+            ),
+        };
+        if passed {
+            *bytes += len as u64;
+            *packets += 1;
+        } else {
+            *dropped += 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientTraffic {
+    pub mac: [u8; 6],
+    pub limit: Limit,
+    pub counters: Counters,
+    up: TokenBucket,
+    down: TokenBucket,
+    last_seen: u64,
+}
+
+/// Per-client rate limits on forwarded traffic. This polices rather than
+/// shapes: there's no memory to hold frames back in, so a packet over the
+/// limit is dropped and TCP backs off on its own.
+pub struct Policer<const N: usize> {
+    config: RateLimits,
+    clients: Vec<ClientTraffic, N>,
+}
+
+impl<const N: usize> Policer<N> {
+    pub const fn new() -> Self {
+        Self {
+            config: RateLimits::new(),
+            clients: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimits {
+        &self.config
+    }
+
+    /// Switches to `config`, keeping counters and the tokens each client
+    /// has saved up.
+    pub fn set_config(&mut self, config: RateLimits) {
+        for client in self.clients.iter_mut() {
+            client.limit = config.limit_for(&client.mac);
+            client.up.set_rate(client.limit.up_kbps);
+            client.down.set_rate(client.limit.down_kbps);
+        }
+        self.config = config;
+    }
+
+    pub fn clients(&self) -> &[ClientTraffic] {
+        &self.clients
+    }
+
+    /// Decides whether a `len` byte packet of `mac` may be forwarded now,
+    /// and counts it either way. Packets over the limit are to be dropped.
+    pub fn admit(&mut self, mac: &[u8; 6], direction: Direction, len: usize, now_ms: u64) -> bool {
+        let index = match self.clients.iter().position(|c| c.mac == *mac) {
+            Some(index) => index,
+            None => self.add(*mac, now_ms),
+        };
+        let client = &mut self.clients[index];
+        client.last_seen = now_ms;
+        let bucket = match direction {
+            Direction::Up => &mut client.up,
+            Direction::Down => &mut client.down,
+        };
+        let passed = bucket.take(len, now_ms);
+        client.counters.count(direction, len, passed);
+        passed
+    }
+
+    /// Clears the counters of `mac`, or of every client.
+    pub fn reset(&mut self, mac: Option<&[u8; 6]>) {
+        for client in self.clients.iter_mut() {
+            if mac.is_none_or(|mac| client.mac == *mac) {
+                client.counters = Counters::default();
+            }
+        }
+    }
+
+    fn add(&mut self, mac: [u8; 6], now_ms: u64) -> usize {
+        if self.clients.is_full() {
+            // Whoever has been quiet the longest loses their counters
+            if let Some(index) = self
+                .clients
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, client)| client.last_seen)
+                .map(|(index, _)| index)
+            {
+                self.clients.swap_remove(index);
+            }
+        }
+        let limit = self.config.limit_for(&mac);
+        _ = self.clients.push(ClientTraffic {
+            mac,
+            limit,
+            counters: Counters::default(),
+            up: TokenBucket::new(limit.kbps(Direction::Up), now_ms),
+            down: TokenBucket::new(limit.kbps(Direction::Down), now_ms),
+            last_seen: now_ms,
+        });
+        self.clients.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const B: [u8; 6] = [2, 0, 0, 0, 0, 2];
+
+    #[test]
+    fn bucket_starts_full_then_refills_at_its_rate() {
+        // 800 kbit/s is 100 bytes/ms, 25000 bytes of burst
+        let mut bucket = TokenBucket::new(800, 0);
+        assert!(bucket.take(25_000, 0));
+        assert!(!bucket.take(1, 0));
+        assert!(!bucket.take(1500, 10));
+        // The failed take still counted the time, 15 ms makes 1500 bytes
+        assert!(bucket.take(1500, 15));
+        assert!(!bucket.take(100, 15));
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_a_burst() {
+        let mut bucket = TokenBucket::new(800, 0);
+        assert!(bucket.take(25_000, 60_000));
+        assert!(!bucket.take(1, 60_000));
+    }
+
+    #[test]
+    fn slow_buckets_still_pass_full_frames() {
+        // 8 kbit/s is 1 byte/ms, a burst of two frames
+        let mut bucket = TokenBucket::new(8, 0);
+        assert!(bucket.take(1514, 0));
+        assert!(bucket.take(1514, 0));
+        assert!(!bucket.take(1514, 0));
+        assert!(!bucket.take(1514, 1513));
+        assert!(bucket.take(1514, 1514));
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let mut bucket = TokenBucket::new(0, 0);
+        for _ in 0..1000 {
+            assert!(bucket.take(1514, 0));
+        }
+    }
+
+    #[test]
+    fn lowering_the_rate_trims_saved_tokens() {
+        let mut bucket = TokenBucket::new(8000, 0);
+        bucket.set_rate(8);
+        assert!(bucket.take(2 * 1514, 0));
+        assert!(!bucket.take(1, 0));
+    }
+
+    #[test]
+    fn limits_each_client_and_direction_apart() {
+        let mut limits = RateLimits::new();
+        limits.default = Limit {
+            up_kbps: 8,
+            down_kbps: 0,
+        };
+        limits.set_override(B, Limit::UNLIMITED).unwrap();
+        let mut policer = Policer::<4>::new();
+        policer.set_config(limits);
+
+        assert!(policer.admit(&A, Direction::Up, 3000, 0));
+        assert!(!policer.admit(&A, Direction::Up, 100, 0));
+        assert!(policer.admit(&A, Direction::Down, 100_000, 0));
+        assert!(policer.admit(&B, Direction::Up, 100_000, 0));
+
+        let a = policer.clients().iter().find(|c| c.mac == A).unwrap();
+        assert_eq!(a.counters.bytes_up, 3000);
+        assert_eq!(a.counters.packets_up, 1);
+        assert_eq!(a.counters.dropped_up, 1);
+        assert_eq!(a.counters.bytes_down, 100_000);
+    }
+
+    #[test]
+    fn new_limits_apply_to_known_clients() {
+        let mut policer = Policer::<4>::new();
+        assert!(policer.admit(&A, Direction::Up, 100_000, 0));
+        let mut limits = RateLimits::new();
+        limits
+            .set_override(
+                A,
+                Limit {
+                    up_kbps: 8,
+                    down_kbps: 0,
+                },
+            )
+            .unwrap();
+        policer.set_config(limits);
+        assert_eq!(policer.clients()[0].limit.up_kbps, 8);
+        // What was saved up is cut down to the new burst
+        assert!(policer.admit(&A, Direction::Up, 2 * 1514, 0));
+        assert!(!policer.admit(&A, Direction::Up, 1, 0));
+        assert_eq!(policer.clients()[0].counters.bytes_up, 100_000 + 2 * 1514);
+    }
+
+    #[test]
+    fn full_table_forgets_the_quietest_client() {
+        let mut policer = Policer::<2>::new();
+        policer.admit(&A, Direction::Up, 100, 0);
+        policer.admit(&B, Direction::Up, 100, 10);
+        policer.admit(&A, Direction::Up, 100, 20);
+        policer.admit(&[9; 6], Direction::Up, 100, 30);
+        let macs: std::vec::Vec<_> = policer.clients().iter().map(|c| c.mac).collect();
+        assert!(macs.contains(&A) && macs.contains(&[9; 6]));
+
+        policer.reset(Some(&A));
+        let a = policer.clients().iter().find(|c| c.mac == A).unwrap();
+        assert_eq!(a.counters, Counters::default());
+    }
+
+    #[test]
+    fn limits_round_trip_through_text() {
+        let mut limits = RateLimits::new();
+        limits.default = Limit {
+            up_kbps: 512,
+            down_kbps: 2048,
+        };
+        limits.set_override(A, Limit::UNLIMITED).unwrap();
+        let mut text = std::string::String::new();
+        limits.write_to(&mut text).unwrap();
+        assert_eq!(RateLimits::parse(&text), Some(limits));
+        assert_eq!(RateLimits::parse("default=1"), None);
+    }
+}
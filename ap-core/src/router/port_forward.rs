@@ -0,0 +1,364 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use super::nat::{self, Protocol};
+use crate::radius::config as radius;
+use crate::wifi::ap_network::ApNetworkConfig;
+
+pub const MAX_RULES: usize = 16;
+
+/// Ports the firmware answers on itself, which can't be handed to a client.
+const RESERVED: [(Protocol, u16); 7] = [
+    (Protocol::Tcp, 8080),
+    (Protocol::Tcp, 53),
+    (Protocol::Udp, 53),
+    (Protocol::Udp, 67),
+    (Protocol::Udp, 68),
+    (Protocol::Udp, radius::AUTH_SOURCE_PORT),
+    (Protocol::Udp, radius::ACCT_SOURCE_PORT),
+];
+const _: () = assert!(radius::ACCT_SOURCE_PORT < nat::PORT_RANGE.start);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleError {
+    UnsupportedProtocol,
+    ZeroPort,
+    ReservedPort,
+    NatPort,
+    NotOnApNetwork,
+    InvalidName,
+    Duplicate,
+    TooManyRules,
+}
+
+impl RuleError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnsupportedProtocol => "protocol must be tcp or udp",
+            Self::ZeroPort => "ports must be non-zero",
+            Self::ReservedPort => {
+                "external port is used by the firmware (HTTP 8080, DNS, DHCP, RADIUS)"
+            }
+            Self::NatPort => "external port is in the range used for NAT (40000-49151)",
+            Self::NotOnApNetwork => "internal address must be a client address on the AP network",
+            Self::InvalidName => "name must be printable",
+            Self::Duplicate => "external port is already forwarded",
+            Self::TooManyRules => "too many port forwards",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortForward {
+    pub protocol: Protocol,
+    pub external_port: u16,
+    pub internal_ip: Ipv4Addr,
+    pub internal_port: u16,
+    pub name: String<24>,
+}
+
+impl PortForward {
+    /// Parses `tcp,8081,192.168.4.20,80[,name]`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut fields = s.splitn(5, ',');
+        let protocol = match fields.next()?.trim() {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => return None,
+        };
+        Some(Self {
+            protocol,
+            external_port: fields.next()?.trim().parse().ok()?,
+            internal_ip: fields.next()?.trim().parse().ok()?,
+            internal_port: fields.next()?.trim().parse().ok()?,
+            name: fields.next().unwrap_or("").trim().try_into().ok()?,
+        })
+    }
+
+    fn validate(&self, network: &ApNetworkConfig) -> Result<(), RuleError> {
+        if self.protocol == Protocol::Icmp {
+            return Err(RuleError::UnsupportedProtocol);
+        }
+        if self.external_port == 0 || self.internal_port == 0 {
+            return Err(RuleError::ZeroPort);
+        }
+        if RESERVED.contains(&(self.protocol, self.external_port)) {
+            return Err(RuleError::ReservedPort);
+        }
+        // Traffic to these goes to whichever client the NAT gave the port
+        if nat::PORT_RANGE.contains(&self.external_port) {
+            return Err(RuleError::NatPort);
+        }
+        if !network.contains(self.internal_ip)
+            || self.internal_ip == network.gateway
+            || self.internal_ip == network.network()
+            || self.internal_ip == network.broadcast()
+        {
+            return Err(RuleError::NotOnApNetwork);
+        }
+        if self.name.chars().any(char::is_control) {
+            return Err(RuleError::InvalidName);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{}",
+            self.protocol.as_str(),
+            self.external_port,
+            self.internal_ip,
+            self.internal_port,
+            self.name
+        )
+    }
+}
+
+/// Inbound connections to the STA address that are sent on to AP clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortForwards {
+    rules: Vec<PortForward, MAX_RULES>,
+}
+
+impl PortForwards {
+    pub const fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn rules(&self) -> &[PortForward] {
+        &self.rules
+    }
+
+    pub fn add(&mut self, rule: PortForward, network: &ApNetworkConfig) -> Result<(), RuleError> {
+        rule.validate(network)?;
+        if self.inbound(rule.protocol, rule.external_port).is_some() {
+            return Err(RuleError::Duplicate);
+        }
+        self.rules.push(rule).map_err(|_| RuleError::TooManyRules)
+    }
+
+    pub fn remove(&mut self, protocol: Protocol, external_port: u16) -> bool {
+        let before = self.rules.len();
+        self.rules
+            .retain(|rule| !(rule.protocol == protocol && rule.external_port == external_port));
+        self.rules.len() != before
+    }
+
+    /// The rule for a packet arriving on `external_port` of the STA address.
+    pub fn inbound(&self, protocol: Protocol, external_port: u16) -> Option<&PortForward> {
+        self.rules
+            .iter()
+            .find(|rule| rule.protocol == protocol && rule.external_port == external_port)
+    }
+
+    /// The external port for a reply coming from a forwarded client service.
+    pub fn outbound(
+        &self,
+        protocol: Protocol,
+        internal_ip: Ipv4Addr,
+        internal_port: u16,
+    ) -> Option<u16> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.protocol == protocol
+                    && rule.internal_ip == internal_ip
+                    && rule.internal_port == internal_port
+            })
+            .map(|rule| rule.external_port)
+    }
+
+    /// Rules that no longer point at a client address of `network`, after
+    /// the AP network was changed under them.
+    pub fn stale<'a>(
+        &'a self,
+        network: &'a ApNetworkConfig,
+    ) -> impl Iterator<Item = &'a PortForward> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.validate(network).is_err())
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        for rule in &self.rules {
+            writeln!(out, "forward={rule}")?;
+        }
+        Ok(())
+    }
+
+    /// Reads stored rules. They were validated when added, and are kept even
+    /// if the AP network has changed since, so they show up to be fixed.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut forwards = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            if key == "forward" {
+                forwards.rules.push(PortForward::parse(value)?).ok()?;
+            }
+        }
+        Some(forwards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network() -> ApNetworkConfig {
+        ApNetworkConfig::from_gateway(Ipv4Addr::new(192, 168, 4, 1), 24)
+    }
+
+    fn rule(s: &str) -> PortForward {
+        PortForward::parse(s).unwrap()
+    }
+
+    fn check(s: &str) -> Result<(), RuleError> {
+        rule(s).validate(&network())
+    }
+
+    #[test]
+    fn parses_rules() {
+        let parsed = rule("udp, 5000 ,192.168.4.20,5001,game, server");
+        assert_eq!(parsed.protocol, Protocol::Udp);
+        assert_eq!(parsed.external_port, 5000);
+        assert_eq!(parsed.internal_ip, Ipv4Addr::new(192, 168, 4, 20));
+        assert_eq!(parsed.internal_port, 5001);
+        assert_eq!(parsed.name, "game, server");
+        assert_eq!(rule("tcp,8081,192.168.4.20,80").name, "");
+        assert_eq!(PortForward::parse("icmp,1,192.168.4.20,1"), None);
+        assert_eq!(PortForward::parse("tcp,70000,192.168.4.20,80"), None);
+    }
+
+    #[test]
+    fn accepts_ports_on_either_side_of_the_nat_range() {
+        assert_eq!(check("tcp,8081,192.168.4.20,80"), Ok(()));
+        let below = nat::PORT_RANGE.start - 1;
+        let above = nat::PORT_RANGE.end;
+        assert_eq!(
+            rule(&format!("tcp,{below},192.168.4.20,80")).validate(&network()),
+            Ok(())
+        );
+        assert_eq!(
+            rule(&format!("udp,{above},192.168.4.20,80")).validate(&network()),
+            Ok(())
+        );
+        assert_eq!(check("udp,65535,192.168.4.20,80"), Ok(()));
+    }
+
+    #[test]
+    fn rejects_nat_ports() {
+        for port in [nat::PORT_RANGE.start, nat::PORT_RANGE.end - 1] {
+            let forward = rule(&format!("tcp,{port},192.168.4.20,80"));
+            assert_eq!(forward.validate(&network()), Err(RuleError::NatPort));
+        }
+        // The message names the range that is actually refused
+        let message = RuleError::NatPort.as_str();
+        assert!(message.contains(&format!(
+            "({}-{})",
+            nat::PORT_RANGE.start,
+            nat::PORT_RANGE.end - 1
+        )));
+    }
+
+    #[test]
+    fn rejects_ports_the_firmware_uses() {
+        assert_eq!(
+            check("tcp,8080,192.168.4.20,80"),
+            Err(RuleError::ReservedPort)
+        );
+        assert_eq!(
+            check("udp,53,192.168.4.20,53"),
+            Err(RuleError::ReservedPort)
+        );
+        assert_eq!(
+            check("udp,67,192.168.4.20,67"),
+            Err(RuleError::ReservedPort)
+        );
+        let radius = format!("udp,{},192.168.4.20,1", radius::AUTH_SOURCE_PORT);
+        assert_eq!(
+            rule(&radius).validate(&network()),
+            Err(RuleError::ReservedPort)
+        );
+        // Only for the protocol it's used with
+        assert_eq!(check("udp,8080,192.168.4.20,80"), Ok(()));
+        assert_eq!(check("tcp,0,192.168.4.20,80"), Err(RuleError::ZeroPort));
+        assert_eq!(check("tcp,8081,192.168.4.20,0"), Err(RuleError::ZeroPort));
+    }
+
+    #[test]
+    fn internal_address_must_be_a_client() {
+        for ip in [
+            "192.168.5.20",
+            "192.168.4.1",
+            "192.168.4.0",
+            "192.168.4.255",
+        ] {
+            let forward = rule(&format!("tcp,8081,{ip},80"));
+            assert_eq!(forward.validate(&network()), Err(RuleError::NotOnApNetwork));
+        }
+        assert_eq!(
+            check("tcp,8081,192.168.4.20,80,a\tb"),
+            Err(RuleError::InvalidName)
+        );
+    }
+
+    #[test]
+    fn looks_up_both_directions() {
+        let mut forwards = PortForwards::new();
+        forwards
+            .add(rule("tcp,8081,192.168.4.20,80,web"), &network())
+            .unwrap();
+        assert_eq!(
+            forwards.add(rule("tcp,8081,192.168.4.21,80"), &network()),
+            Err(RuleError::Duplicate)
+        );
+        forwards
+            .add(rule("udp,8081,192.168.4.21,80"), &network())
+            .unwrap();
+
+        let inbound = forwards.inbound(Protocol::Tcp, 8081).unwrap();
+        assert_eq!(inbound.internal_ip, Ipv4Addr::new(192, 168, 4, 20));
+        assert_eq!(
+            forwards.outbound(Protocol::Udp, Ipv4Addr::new(192, 168, 4, 21), 80),
+            Some(8081)
+        );
+        assert_eq!(
+            forwards.outbound(Protocol::Tcp, Ipv4Addr::new(192, 168, 4, 21), 80),
+            None
+        );
+
+        let mut text = std::string::String::new();
+        forwards.write_to(&mut text).unwrap();
+        assert_eq!(PortForwards::parse(&text), Some(forwards.clone()));
+
+        assert!(forwards.remove(Protocol::Tcp, 8081));
+        assert!(!forwards.remove(Protocol::Tcp, 8081));
+        assert_eq!(forwards.rules().len(), 1);
+    }
+
+    #[test]
+    fn finds_rules_left_behind_by_a_network_change() {
+        let mut forwards = PortForwards::new();
+        forwards
+            .add(rule("tcp,8081,192.168.4.20,80"), &network())
+            .unwrap();
+        forwards
+            .add(rule("tcp,8082,192.168.4.200,80"), &network())
+            .unwrap();
+        assert_eq!(forwards.stale(&network()).count(), 0);
+
+        let moved = network().relocate(Ipv4Addr::new(10, 42, 0, 1));
+        assert_eq!(forwards.stale(&moved).count(), 2);
+        // A narrower subnet keeps some of them
+        let narrower = ApNetworkConfig::from_gateway(Ipv4Addr::new(192, 168, 4, 1), 25);
+        let stale: std::vec::Vec<u16> = forwards
+            .stale(&narrower)
+            .map(|rule| rule.external_port)
+            .collect();
+        assert_eq!(stale, [8082]);
+    }
+}
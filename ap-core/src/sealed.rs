@@ -0,0 +1,107 @@
+use alloc::vec::Vec;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+pub const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// What sealing adds to the data: the nonce in front, the tag behind.
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+const KEY_INFO: &[u8] = b"ap storage sealing key v1";
+
+/// Encrypts and authenticates secrets kept in flash (private keys,
+/// passwords) with ChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct SealingKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SealingKey {
+    /// Derives the key from `secret` with HKDF-SHA256, salted with the
+    /// device's MAC so one firmware image gives every device its own key.
+    pub fn derive(secret: &[u8], mac: &[u8; 6]) -> Self {
+        let mut key = Key::default();
+        Hkdf::<Sha256>::new(Some(mac), secret)
+            .expand(KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            cipher: ChaCha20Poly1305::new(&key),
+        }
+    }
+
+    /// Returns `nonce || ciphertext || tag`. `nonce` must never repeat for
+    /// this key, so it has to come from the hardware RNG. `aad` names what
+    /// is sealed, so one record can't be passed off as another.
+    pub fn seal(&self, nonce: [u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let sealed = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("ChaCha20-Poly1305 encrypts any length that fits in memory");
+        let mut out = Vec::with_capacity(NONCE_LEN + sealed.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        out
+    }
+
+    /// The plaintext, or `None` if `sealed` was made with another key or
+    /// `aad`, or was tampered with.
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < OVERHEAD {
+            return None;
+        }
+        let (nonce, msg) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+    #[test]
+    fn round_trips() {
+        let key = SealingKey::derive(b"build secret", &MAC);
+        let sealed = key.seal([7; NONCE_LEN], b"profiles", b"eap_password=hunter2");
+        assert_eq!(sealed.len(), 20 + OVERHEAD);
+        assert_eq!(sealed[..NONCE_LEN], [7; NONCE_LEN]);
+        assert!(!sealed.windows(7).any(|window| window == b"hunter2"));
+        assert_eq!(
+            key.open(b"profiles", &sealed).as_deref(),
+            Some(b"eap_password=hunter2".as_slice())
+        );
+        assert_eq!(
+            key.open(b"", &key.seal([0; NONCE_LEN], b"", b"")),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn refuses_anything_else() {
+        let key = SealingKey::derive(b"build secret", &MAC);
+        let sealed = key.seal([7; NONCE_LEN], b"profiles", b"eap_password=hunter2");
+
+        assert_eq!(key.open(b"certificates", &sealed), None);
+        let other_device = SealingKey::derive(b"build secret", &[2, 0, 0, 0, 0, 2]);
+        assert_eq!(other_device.open(b"profiles", &sealed), None);
+        let other_secret = SealingKey::derive(b"another secret", &MAC);
+        assert_eq!(other_secret.open(b"profiles", &sealed), None);
+
+        for i in [0, NONCE_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert_eq!(key.open(b"profiles", &tampered), None);
+        }
+        assert_eq!(key.open(b"profiles", &sealed[..OVERHEAD - 1]), None);
+    }
+}
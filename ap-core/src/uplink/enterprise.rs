@@ -0,0 +1,375 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use heapless::String;
+
+/// Inner authentication inside a TTLS tunnel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase2 {
+    Mschapv2,
+    Mschap,
+    Pap,
+    Chap,
+}
+
+impl Phase2 {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mschapv2 => "mschapv2",
+            Self::Mschap => "mschap",
+            Self::Pap => "pap",
+            Self::Chap => "chap",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "mschapv2" => Self::Mschapv2,
+            "mschap" => Self::Mschap,
+            "pap" => Self::Pap,
+            "chap" => Self::Chap,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EapMethod {
+    Peap,
+    Ttls(Phase2),
+    Tls,
+}
+
+impl EapMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Peap => "peap",
+            Self::Ttls(_) => "ttls",
+            Self::Tls => "tls",
+        }
+    }
+
+    /// PEAP and TTLS log in with a password, TLS with a client certificate.
+    pub fn uses_password(&self) -> bool {
+        !matches!(self, Self::Tls)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnterpriseError {
+    InvalidIdentity,
+    PasswordRequired,
+}
+
+impl EnterpriseError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidIdentity => "identities must be printable ASCII",
+            Self::PasswordRequired => "PEAP and TTLS need a password",
+        }
+    }
+}
+
+/// 802.1X login for a WPA2-Enterprise network. Each network has its own
+/// certificates, see [`Certificates`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Enterprise {
+    pub method: EapMethod,
+    pub identity: String<64>,
+    /// Sent in the clear before the tunnel is up, instead of `identity`.
+    pub anonymous_identity: Option<String<64>>,
+    pub password: Option<String<64>>,
+    /// Check the server's certificate against the uploaded CA certificate.
+    pub validate_server: bool,
+}
+
+impl Enterprise {
+    pub fn validate(&self) -> Result<(), EnterpriseError> {
+        let printable = |s: &str| !s.is_empty() && s.bytes().all(|b| (0x20..0x7f).contains(&b));
+        if !printable(&self.identity)
+            || self
+                .anonymous_identity
+                .as_deref()
+                .is_some_and(|identity| !printable(identity))
+        {
+            return Err(EnterpriseError::InvalidIdentity);
+        }
+        if self.method.uses_password() && matches!(self.password.as_deref(), None | Some("")) {
+            return Err(EnterpriseError::PasswordRequired);
+        }
+        Ok(())
+    }
+
+    /// The identity sent outside the tunnel.
+    pub fn outer_identity(&self) -> &str {
+        self.anonymous_identity.as_deref().unwrap_or(&self.identity)
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "eap={}", self.method.as_str())?;
+        if let EapMethod::Ttls(phase2) = self.method {
+            writeln!(out, "phase2={}", phase2.as_str())?;
+        }
+        writeln!(out, "identity={}", self.identity)?;
+        if let Some(identity) = &self.anonymous_identity {
+            writeln!(out, "anonymous_identity={identity}")?;
+        }
+        if let Some(password) = &self.password {
+            writeln!(out, "eap_password={password}")?;
+        }
+        writeln!(out, "validate_server={}", self.validate_server as u8)
+    }
+
+    /// Applies one line written by [`Enterprise::write_to`], returning
+    /// `None` for malformed values and `Some(false)` for other keys.
+    pub fn parse_line(this: &mut Option<Self>, key: &str, value: &str) -> Option<bool> {
+        if key == "eap" {
+            let method = match value {
+                "peap" => EapMethod::Peap,
+                "ttls" => EapMethod::Ttls(Phase2::Mschapv2),
+                "tls" => EapMethod::Tls,
+                _ => return None,
+            };
+            *this = Some(Self {
+                method,
+                identity: String::new(),
+                anonymous_identity: None,
+                password: None,
+                validate_server: true,
+            });
+            return Some(true);
+        }
+        let Some(enterprise) = this else {
+            return Some(false);
+        };
+        match key {
+            "phase2" => enterprise.method = EapMethod::Ttls(Phase2::parse(value)?),
+            "identity" => enterprise.identity = value.try_into().ok()?,
+            "anonymous_identity" => enterprise.anonymous_identity = Some(value.try_into().ok()?),
+            "eap_password" => enterprise.password = Some(value.try_into().ok()?),
+            "validate_server" => enterprise.validate_server = value == "1",
+            _ => return Some(false),
+        }
+        Some(true)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertificateKind {
+    Ca,
+    ClientCert,
+    ClientKey,
+}
+
+impl CertificateKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ca => "ca",
+            Self::ClientCert => "client_cert",
+            Self::ClientKey => "client_key",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "ca" => Self::Ca,
+            "client_cert" => Self::ClientCert,
+            "client_key" => Self::ClientKey,
+            _ => return None,
+        })
+    }
+
+    /// PEM labels accepted on upload.
+    pub fn pem_labels(&self) -> &'static [&'static str] {
+        match self {
+            Self::Ca | Self::ClientCert => &["CERTIFICATE"],
+            Self::ClientKey => &["PRIVATE KEY", "RSA PRIVATE KEY", "EC PRIVATE KEY"],
+        }
+    }
+}
+
+/// A network's DER certificates and key for 802.1X, stored together as
+/// `[kind][length, little endian u16][DER]` entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Certificates<'a> {
+    pub ca: Option<&'a [u8]>,
+    pub client_cert: Option<&'a [u8]>,
+    pub client_key: Option<&'a [u8]>,
+}
+
+impl<'a> Certificates<'a> {
+    pub fn get(&self, kind: CertificateKind) -> Option<&'a [u8]> {
+        match kind {
+            CertificateKind::Ca => self.ca,
+            CertificateKind::ClientCert => self.client_cert,
+            CertificateKind::ClientKey => self.client_key,
+        }
+    }
+
+    pub fn set(&mut self, kind: CertificateKind, der: Option<&'a [u8]>) {
+        match kind {
+            CertificateKind::Ca => self.ca = der,
+            CertificateKind::ClientCert => self.client_cert = der,
+            CertificateKind::ClientKey => self.client_key = der,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (tag, der) in [(1u8, self.ca), (2, self.client_cert), (3, self.client_key)] {
+            if let Some(der) = der {
+                out.push(tag);
+                out.extend_from_slice(&(der.len() as u16).to_le_bytes());
+                out.extend_from_slice(der);
+            }
+        }
+        out
+    }
+
+    pub fn decode(mut data: &'a [u8]) -> Option<Self> {
+        let mut certificates = Self::default();
+        while let [tag, len_lo, len_hi, rest @ ..] = data {
+            let len = u16::from_le_bytes([*len_lo, *len_hi]) as usize;
+            let der = rest.get(..len)?;
+            let kind = match tag {
+                1 => CertificateKind::Ca,
+                2 => CertificateKind::ClientCert,
+                3 => CertificateKind::ClientKey,
+                _ => return None,
+            };
+            certificates.set(kind, Some(der));
+            data = &rest[len..];
+        }
+        data.is_empty().then_some(certificates)
+    }
+
+    /// The certificates of network `ssid`, as kept in its own storage slot:
+    /// `[SSID length][SSID]` followed by [`Certificates::encode`].
+    pub fn encode_slot(&self, ssid: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(ssid.len() as u8);
+        out.extend_from_slice(ssid.as_bytes());
+        out.extend_from_slice(&self.encode());
+        out
+    }
+
+    pub fn decode_slot(data: &'a [u8]) -> Option<(&'a str, Self)> {
+        let (len, rest) = data.split_first()?;
+        let ssid = rest.get(..*len as usize)?;
+        let ssid = core::str::from_utf8(ssid)
+            .ok()
+            .filter(|ssid| ssid.len() <= 32)?;
+        Some((ssid, Self::decode(&rest[*len as usize..])?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_certificates() {
+        let ca = std::vec![0x30; 1200];
+        let key = std::vec![0x30, 1, 2];
+        let mut certificates = Certificates::default();
+        assert_eq!(certificates.encode(), []);
+        certificates.set(CertificateKind::Ca, Some(&ca));
+        certificates.set(CertificateKind::ClientKey, Some(&key));
+
+        let encoded = certificates.encode();
+        assert_eq!(encoded.len(), 3 + 1200 + 3 + 3);
+        let decoded = Certificates::decode(&encoded).unwrap();
+        assert_eq!(decoded, certificates);
+        assert_eq!(decoded.get(CertificateKind::Ca), Some(ca.as_slice()));
+        assert_eq!(decoded.get(CertificateKind::ClientCert), None);
+
+        certificates.set(CertificateKind::Ca, None);
+        assert_eq!(
+            Certificates::decode(&certificates.encode()),
+            Some(certificates)
+        );
+        assert_eq!(Certificates::decode(&[]), Some(Certificates::default()));
+    }
+
+    #[test]
+    fn rejects_damaged_certificates() {
+        let der = [0x30, 1, 2];
+        let certificates = Certificates {
+            client_cert: Some(&der),
+            ..Default::default()
+        };
+        let encoded = certificates.encode();
+        assert_eq!(Certificates::decode(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(Certificates::decode(&encoded[..2]), None);
+        let mut unknown = encoded.clone();
+        unknown[0] = 9;
+        assert_eq!(Certificates::decode(&unknown), None);
+    }
+
+    #[test]
+    fn keys_certificates_by_network() {
+        let der = [0x30, 1, 2];
+        let certificates = Certificates {
+            ca: Some(&der),
+            ..Default::default()
+        };
+        let slot = certificates.encode_slot("corp wifi");
+        assert_eq!(
+            Certificates::decode_slot(&slot),
+            Some(("corp wifi", certificates))
+        );
+        let empty = Certificates::default().encode_slot("lab");
+        assert_eq!(
+            Certificates::decode_slot(&empty),
+            Some(("lab", Certificates::default()))
+        );
+        assert_eq!(Certificates::decode_slot(&slot[..5]), None);
+        assert_eq!(Certificates::decode_slot(&[]), None);
+    }
+
+    #[test]
+    fn stores_logins() {
+        let ttls = Enterprise {
+            method: EapMethod::Ttls(Phase2::Pap),
+            identity: "alice@corp".try_into().unwrap(),
+            anonymous_identity: Some("anonymous@corp".try_into().unwrap()),
+            password: Some("hunter2".try_into().unwrap()),
+            validate_server: false,
+        };
+        assert_eq!(ttls.validate(), Ok(()));
+        assert_eq!(ttls.outer_identity(), "anonymous@corp");
+
+        let mut text = std::string::String::new();
+        ttls.write_to(&mut text).unwrap();
+        let mut parsed = None;
+        for line in text.lines() {
+            let (key, value) = line.split_once('=').unwrap();
+            assert_eq!(Enterprise::parse_line(&mut parsed, key, value), Some(true));
+        }
+        assert_eq!(parsed, Some(ttls));
+        assert_eq!(
+            Enterprise::parse_line(&mut parsed, "mode", "dhcp"),
+            Some(false)
+        );
+        assert_eq!(Enterprise::parse_line(&mut parsed, "phase2", "eap"), None);
+    }
+
+    #[test]
+    fn validates_logins() {
+        let mut tls = Enterprise {
+            method: EapMethod::Tls,
+            identity: "device-1".try_into().unwrap(),
+            anonymous_identity: None,
+            password: None,
+            validate_server: true,
+        };
+        assert_eq!(tls.validate(), Ok(()));
+        assert_eq!(tls.outer_identity(), "device-1");
+        tls.method = EapMethod::Peap;
+        assert_eq!(tls.validate(), Err(EnterpriseError::PasswordRequired));
+        tls.password = Some(String::new());
+        assert_eq!(tls.validate(), Err(EnterpriseError::PasswordRequired));
+        tls.password = Some("hunter2".try_into().unwrap());
+        tls.identity = "caf\u{e9}".try_into().unwrap();
+        assert_eq!(tls.validate(), Err(EnterpriseError::InvalidIdentity));
+    }
+}
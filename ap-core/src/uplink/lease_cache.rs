@@ -0,0 +1,156 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use crate::mac::{self, MacDisplay};
+
+pub const MAX_CACHED_LEASES: usize = 8;
+
+/// The address last leased on an upstream network, asked for again with
+/// INIT-REBOOT after a reconnect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedLease {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub address: Ipv4Addr,
+}
+
+/// Most recently used first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LeaseCache {
+    pub leases: Vec<CachedLease, MAX_CACHED_LEASES>,
+}
+
+impl LeaseCache {
+    pub const fn new() -> Self {
+        Self { leases: Vec::new() }
+    }
+
+    /// The address for this access point, or failing that one from another
+    /// access point of the same network, which usually shares a DHCP server.
+    pub fn address(&self, ssid: &str, bssid: &[u8; 6]) -> Option<Ipv4Addr> {
+        let same_ssid = |lease: &&CachedLease| lease.ssid == ssid;
+        self.leases
+            .iter()
+            .filter(same_ssid)
+            .find(|lease| lease.bssid == *bssid)
+            .or_else(|| self.leases.iter().find(same_ssid))
+            .map(|lease| lease.address)
+    }
+
+    /// Records a lease, returns whether the cache changed and needs saving.
+    pub fn remember(&mut self, ssid: &str, bssid: [u8; 6], address: Ipv4Addr) -> bool {
+        let Ok(ssid) = String::try_from(ssid) else {
+            return false;
+        };
+        let lease = CachedLease {
+            ssid,
+            bssid,
+            address,
+        };
+        if self.leases.first() == Some(&lease) {
+            return false;
+        }
+        self.leases
+            .retain(|cached| cached.ssid != lease.ssid || cached.bssid != lease.bssid);
+        if self.leases.is_full() {
+            self.leases.pop();
+        }
+        // Newest first, the oldest falls off the end
+        _ = self.leases.insert(0, lease);
+        true
+    }
+
+    /// One `lease=<bssid> <address> <ssid>` line per entry, the SSID last
+    /// since it may contain spaces.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        for lease in &self.leases {
+            writeln!(
+                out,
+                "lease={} {} {}",
+                MacDisplay(&lease.bssid),
+                lease.address,
+                lease.ssid
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut cache = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let value = line.strip_prefix("lease=")?;
+            let (bssid, rest) = value.split_once(' ')?;
+            let (address, ssid) = rest.split_once(' ')?;
+            cache
+                .leases
+                .push(CachedLease {
+                    ssid: ssid.try_into().ok()?,
+                    bssid: mac::parse(bssid)?,
+                    address: address.parse().ok()?,
+                })
+                .ok()?;
+        }
+        Some(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP1: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const AP2: [u8; 6] = [2, 0, 0, 0, 0, 2];
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 168, 1, last)
+    }
+
+    #[test]
+    fn prefers_the_same_access_point() {
+        let mut cache = LeaseCache::new();
+        assert!(cache.remember("office", AP1, ip(10)));
+        assert!(cache.remember("office", AP2, ip(20)));
+        assert_eq!(cache.address("office", &AP1), Some(ip(10)));
+        assert_eq!(cache.address("office", &AP2), Some(ip(20)));
+        // Another access point of the network gets the most recent lease
+        assert_eq!(cache.address("office", &[2, 0, 0, 0, 0, 3]), Some(ip(20)));
+        assert_eq!(cache.address("cafe", &AP1), None);
+    }
+
+    #[test]
+    fn keeps_the_newest_leases() {
+        let mut cache = LeaseCache::new();
+        assert!(cache.remember("office", AP1, ip(10)));
+        assert!(!cache.remember("office", AP1, ip(10)));
+        assert!(cache.remember("office", AP1, ip(11)));
+        assert_eq!(cache.leases.len(), 1);
+
+        for i in 0..MAX_CACHED_LEASES as u8 {
+            cache.remember("net", [2, 0, 0, 0, 1, i], ip(i));
+        }
+        assert_eq!(cache.leases.len(), MAX_CACHED_LEASES);
+        assert_eq!(cache.address("office", &AP1), None);
+        assert_eq!(cache.leases[0].address, ip(MAX_CACHED_LEASES as u8 - 1));
+
+        // Seeing an old one again moves it to the front
+        assert!(cache.remember("net", [2, 0, 0, 0, 1, 0], ip(0)));
+        assert_eq!(cache.leases[0].address, ip(0));
+        assert_eq!(cache.leases.len(), MAX_CACHED_LEASES);
+    }
+
+    #[test]
+    fn stores_leases() {
+        let mut cache = LeaseCache::new();
+        cache.remember("office", AP1, ip(10));
+        cache.remember("my home wifi", AP2, ip(20));
+        let mut text = std::string::String::new();
+        cache.write_to(&mut text).unwrap();
+        assert_eq!(LeaseCache::parse(&text), Some(cache));
+        assert!(!LeaseCache::new().remember(&"x".repeat(33), AP1, ip(10)));
+        assert_eq!(
+            LeaseCache::parse("lease=02:00:00:00:00:01 192.168.1.10"),
+            None
+        );
+    }
+}
@@ -0,0 +1,7 @@
+pub mod enterprise;
+pub mod lease_cache;
+pub mod pem;
+pub mod profile;
+pub mod reconnect;
+pub mod roaming;
+pub mod security;
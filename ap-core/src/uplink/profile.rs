@@ -0,0 +1,594 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::{String, Vec};
+
+use crate::dhcp::client::ClientOptions;
+use crate::dhcp::options::MAX_DNS_SERVERS;
+use crate::uplink::enterprise::{Enterprise, EnterpriseError};
+use crate::uplink::security::Security;
+
+pub const MAX_PROFILES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileError {
+    InvalidSsid,
+    InvalidAddress,
+    InvalidPrefix,
+    GatewayOutsideSubnet,
+    InvalidDnsServer,
+    InvalidHostname,
+    InvalidClientId,
+    InvalidVendorClass,
+    UnsupportedAuth,
+    Enterprise(EnterpriseError),
+    TooManyProfiles,
+}
+
+impl ProfileError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidSsid => "SSID must be 1-32 printable characters",
+            Self::InvalidAddress => "address must be a usable host address",
+            Self::InvalidPrefix => "prefix length must be between 1 and 32",
+            Self::GatewayOutsideSubnet => "gateway must be another address in the subnet",
+            Self::InvalidDnsServer => "DNS servers must be unicast addresses",
+            Self::InvalidHostname => "hostname must be letters, digits, '-' and '.'",
+            Self::InvalidClientId => "client ID must be printable ASCII",
+            Self::InvalidVendorClass => "vendor class must be printable ASCII",
+            Self::UnsupportedAuth => "auth must be auto, open, wpa2, wpa2-wpa3 or wpa3",
+            Self::Enterprise(e) => e.as_str(),
+            Self::TooManyProfiles => "too many networks",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticAddress {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr, MAX_DNS_SERVERS>,
+}
+
+impl StaticAddress {
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if !(1..=32).contains(&self.prefix_len) {
+            return Err(ProfileError::InvalidPrefix);
+        }
+        if !is_unicast(self.address) {
+            return Err(ProfileError::InvalidAddress);
+        }
+        let mask = u32::MAX << (32 - self.prefix_len as u32);
+        if !self.is_host(self.address, mask) {
+            return Err(ProfileError::InvalidAddress);
+        }
+        if let Some(gateway) = self.gateway {
+            let same_subnet = u32::from(gateway) & mask == u32::from(self.address) & mask;
+            if !is_unicast(gateway)
+                || gateway == self.address
+                || !same_subnet
+                || !self.is_host(gateway, mask)
+            {
+                return Err(ProfileError::GatewayOutsideSubnet);
+            }
+        }
+        if !self.dns_servers.iter().all(|ip| is_unicast(*ip)) {
+            return Err(ProfileError::InvalidDnsServer);
+        }
+        Ok(())
+    }
+
+    /// /31 and /32 have no network and broadcast addresses (RFC 3021).
+    fn is_host(&self, ip: Ipv4Addr, mask: u32) -> bool {
+        let host = u32::from(ip) & !mask;
+        self.prefix_len > 30 || (host != 0 && host != !mask)
+    }
+}
+
+fn is_unicast(ip: Ipv4Addr) -> bool {
+    !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || ip.is_loopback())
+}
+
+/// How the station gets its address on a network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Addressing {
+    Dhcp(ClientOptions),
+    Static(StaticAddress),
+}
+
+impl Default for Addressing {
+    fn default() -> Self {
+        Self::Dhcp(ClientOptions::default())
+    }
+}
+
+impl Addressing {
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        match self {
+            Self::Static(config) => config.validate(),
+            Self::Dhcp(options) => validate_options(options),
+        }
+    }
+}
+
+fn validate_options(options: &ClientOptions) -> Result<(), ProfileError> {
+    if let Some(hostname) = &options.hostname {
+        validate_hostname(hostname)?;
+    }
+    if options
+        .client_id
+        .as_deref()
+        .is_some_and(|id| !is_printable(id))
+    {
+        return Err(ProfileError::InvalidClientId);
+    }
+    if options
+        .vendor_class
+        .as_deref()
+        .is_some_and(|class| !is_printable(class))
+    {
+        return Err(ProfileError::InvalidVendorClass);
+    }
+    Ok(())
+}
+
+fn is_printable(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+}
+
+/// RFC 1123 host names: dot-separated labels of letters, digits and
+/// hyphens, not starting or ending with a hyphen.
+fn validate_hostname(hostname: &str) -> Result<(), ProfileError> {
+    let valid = !hostname.is_empty()
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(ProfileError::InvalidHostname)
+    }
+}
+
+/// Settings for one upstream network, picked by SSID when the station
+/// joins it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkProfile {
+    pub ssid: String<32>,
+    /// The network doesn't broadcast its SSID, so it has to be probed for.
+    pub hidden: bool,
+    /// `None` takes whatever the network advertises.
+    pub auth: Option<Security>,
+    /// 802.1X login, for WPA2-Enterprise networks.
+    pub enterprise: Option<Enterprise>,
+    pub addressing: Addressing,
+}
+
+impl NetworkProfile {
+    pub fn new(ssid: String<32>) -> Self {
+        Self {
+            ssid,
+            hidden: false,
+            auth: None,
+            enterprise: None,
+            addressing: Addressing::default(),
+        }
+    }
+
+    /// The authentication the network must offer, `None` for any.
+    pub fn wanted_auth(&self) -> Option<Security> {
+        match self.enterprise {
+            Some(_) => Some(Security::Wpa2Enterprise),
+            None => self.auth,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if self.ssid.is_empty() || self.ssid.chars().any(char::is_control) {
+            return Err(ProfileError::InvalidSsid);
+        }
+        // WEP and WPA are only ever accepted when advertised, never asked for,
+        // and enterprise networks are set up through their login
+        if let Some(Security::Wep | Security::Wpa | Security::WpaWpa2 | Security::Wpa2Enterprise) =
+            self.auth
+        {
+            return Err(ProfileError::UnsupportedAuth);
+        }
+        if let Some(enterprise) = &self.enterprise {
+            if self.auth.is_some() {
+                return Err(ProfileError::UnsupportedAuth);
+            }
+            enterprise.validate().map_err(ProfileError::Enterprise)?;
+        }
+        self.addressing.validate()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profiles {
+    /// DHCP options sent on every network, unless its profile sets them.
+    pub defaults: ClientOptions,
+    pub profiles: Vec<NetworkProfile, MAX_PROFILES>,
+}
+
+impl Profiles {
+    pub const fn new() -> Self {
+        Self {
+            defaults: ClientOptions {
+                hostname: None,
+                client_id: None,
+                vendor_class: None,
+            },
+            profiles: Vec::new(),
+        }
+    }
+
+    pub fn network(&self, ssid: &str) -> Option<&NetworkProfile> {
+        self.profiles.iter().find(|profile| profile.ssid == ssid)
+    }
+
+    /// Addressing for `ssid`, plain DHCP for networks without a profile.
+    pub fn addressing(&self, ssid: &str) -> Addressing {
+        let addressing = self
+            .network(ssid)
+            .map(|profile| profile.addressing.clone())
+            .unwrap_or_default();
+        match addressing {
+            Addressing::Dhcp(options) => Addressing::Dhcp(ClientOptions {
+                hostname: options.hostname.or(self.defaults.hostname.clone()),
+                client_id: options.client_id.or(self.defaults.client_id.clone()),
+                vendor_class: options.vendor_class.or(self.defaults.vendor_class.clone()),
+            }),
+            addressing => addressing,
+        }
+    }
+
+    pub fn set_defaults(&mut self, defaults: ClientOptions) -> Result<(), ProfileError> {
+        validate_options(&defaults)?;
+        self.defaults = defaults;
+        Ok(())
+    }
+
+    /// Adds a profile, replacing the one for the same SSID.
+    pub fn set(&mut self, profile: NetworkProfile) -> Result<(), ProfileError> {
+        profile.validate()?;
+        match self.profiles.iter_mut().find(|p| p.ssid == profile.ssid) {
+            Some(existing) => *existing = profile,
+            None => self
+                .profiles
+                .push(profile)
+                .map_err(|_| ProfileError::TooManyProfiles)?,
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, ssid: &str) -> bool {
+        let before = self.profiles.len();
+        self.profiles.retain(|profile| profile.ssid != ssid);
+        self.profiles.len() != before
+    }
+
+    /// The default DHCP options, then one `network=<ssid>` line per profile
+    /// followed by its settings.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        write_options(out, &self.defaults)?;
+        for profile in &self.profiles {
+            writeln!(out, "network={}", profile.ssid)?;
+            if profile.hidden {
+                writeln!(out, "hidden=1")?;
+            }
+            if let Some(auth) = profile.auth {
+                writeln!(out, "auth={}", auth.as_str())?;
+            }
+            if let Some(enterprise) = &profile.enterprise {
+                enterprise.write_to(out)?;
+            }
+            match &profile.addressing {
+                Addressing::Dhcp(options) => {
+                    writeln!(out, "mode=dhcp")?;
+                    write_options(out, options)?;
+                }
+                Addressing::Static(config) => {
+                    writeln!(out, "mode=static")?;
+                    writeln!(out, "address={}/{}", config.address, config.prefix_len)?;
+                    if let Some(gateway) = config.gateway {
+                        writeln!(out, "gateway={gateway}")?;
+                    }
+                    for ip in &config.dns_servers {
+                        writeln!(out, "dns={ip}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut profiles = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            if key == "network" {
+                profiles
+                    .profiles
+                    .push(NetworkProfile::new(value.try_into().ok()?))
+                    .ok()?;
+                continue;
+            }
+            let Some(profile) = profiles.profiles.last_mut() else {
+                parse_option(&mut profiles.defaults, key, value)?;
+                continue;
+            };
+            if Enterprise::parse_line(&mut profile.enterprise, key, value)? {
+                continue;
+            }
+            match (key, &mut profile.addressing) {
+                ("hidden", _) => profile.hidden = value == "1",
+                ("auth", _) => profile.auth = Some(Security::parse(value)?),
+                ("mode", addressing) => {
+                    *addressing = match value {
+                        "dhcp" => Addressing::default(),
+                        "static" => Addressing::Static(StaticAddress {
+                            address: Ipv4Addr::UNSPECIFIED,
+                            prefix_len: 0,
+                            gateway: None,
+                            dns_servers: Vec::new(),
+                        }),
+                        _ => return None,
+                    }
+                }
+                (key, Addressing::Dhcp(options)) => parse_option(options, key, value)?,
+                ("address", Addressing::Static(config)) => {
+                    let (address, prefix_len) = parse_cidr(value)?;
+                    config.address = address;
+                    config.prefix_len = prefix_len;
+                }
+                ("gateway", Addressing::Static(config)) => {
+                    config.gateway = Some(value.parse().ok()?)
+                }
+                ("dns", Addressing::Static(config)) => {
+                    config.dns_servers.push(value.parse().ok()?).ok()?
+                }
+                _ => {}
+            }
+        }
+        let valid = validate_options(&profiles.defaults).is_ok()
+            && profiles
+                .profiles
+                .iter()
+                .all(|profile| profile.validate().is_ok());
+        valid.then_some(profiles)
+    }
+}
+
+fn write_options(out: &mut impl Write, options: &ClientOptions) -> fmt::Result {
+    if let Some(hostname) = &options.hostname {
+        writeln!(out, "hostname={hostname}")?;
+    }
+    if let Some(id) = &options.client_id {
+        writeln!(out, "client_id={id}")?;
+    }
+    if let Some(class) = &options.vendor_class {
+        writeln!(out, "vendor_class={class}")?;
+    }
+    Ok(())
+}
+
+fn parse_option(options: &mut ClientOptions, key: &str, value: &str) -> Option<()> {
+    match key {
+        "hostname" => options.hostname = Some(value.try_into().ok()?),
+        "client_id" => options.client_id = Some(value.try_into().ok()?),
+        "vendor_class" => options.vendor_class = Some(value.try_into().ok()?),
+        _ => {}
+    }
+    Some(())
+}
+
+/// Parses `192.168.1.20/24`.
+pub fn parse_cidr(s: &str) -> Option<(Ipv4Addr, u8)> {
+    let (address, prefix_len) = s.trim().split_once('/')?;
+    let prefix_len = prefix_len.parse().ok().filter(|len| *len <= 32)?;
+    Some((address.parse().ok()?, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(address: &str, gateway: Option<&str>, dns: &[&str]) -> StaticAddress {
+        let (address, prefix_len) = parse_cidr(address).unwrap();
+        StaticAddress {
+            address,
+            prefix_len,
+            gateway: gateway.map(|ip| ip.parse().unwrap()),
+            dns_servers: dns.iter().map(|ip| ip.parse().unwrap()).collect(),
+        }
+    }
+
+    fn options(hostname: Option<&str>, client_id: Option<&str>) -> ClientOptions {
+        ClientOptions {
+            hostname: hostname.map(|s| s.try_into().unwrap()),
+            client_id: client_id.map(|s| s.try_into().unwrap()),
+            vendor_class: None,
+        }
+    }
+
+    #[test]
+    fn accepts_usable_static_addresses() {
+        let config = fixed("192.168.1.20/24", Some("192.168.1.1"), &["1.1.1.1"]);
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(fixed("10.0.0.5/8", None, &[]).validate(), Ok(()));
+        // Point-to-point links use both addresses
+        assert_eq!(
+            fixed("10.0.0.0/31", Some("10.0.0.1"), &[]).validate(),
+            Ok(())
+        );
+        assert_eq!(fixed("10.0.0.7/32", None, &[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unusable_static_addresses() {
+        for (address, error) in [
+            ("192.168.1.0/24", ProfileError::InvalidAddress),
+            ("192.168.1.255/24", ProfileError::InvalidAddress),
+            ("0.0.0.0/24", ProfileError::InvalidAddress),
+            ("224.0.0.5/24", ProfileError::InvalidAddress),
+            ("127.0.0.1/8", ProfileError::InvalidAddress),
+            ("192.168.1.20/0", ProfileError::InvalidPrefix),
+        ] {
+            assert_eq!(
+                fixed(address, None, &[]).validate(),
+                Err(error),
+                "{address}"
+            );
+        }
+        assert_eq!(parse_cidr("192.168.1.20/33"), None);
+        assert_eq!(parse_cidr("192.168.1.20"), None);
+    }
+
+    #[test]
+    fn gateway_and_dns_must_be_reachable() {
+        for gateway in [
+            "192.168.2.1",
+            "192.168.1.20",
+            "192.168.1.0",
+            "192.168.1.255",
+        ] {
+            let config = fixed("192.168.1.20/24", Some(gateway), &[]);
+            assert_eq!(
+                config.validate(),
+                Err(ProfileError::GatewayOutsideSubnet),
+                "{gateway}"
+            );
+        }
+        let config = fixed("192.168.1.20/24", None, &["1.1.1.1", "255.255.255.255"]);
+        assert_eq!(config.validate(), Err(ProfileError::InvalidDnsServer));
+    }
+
+    #[test]
+    fn validates_dhcp_options() {
+        let valid = options(Some("ap-1.lab"), Some("ap 1"));
+        assert_eq!(Addressing::Dhcp(valid).validate(), Ok(()));
+        for hostname in ["", "-ap", "ap-", "ap..lab", "ap_1"] {
+            assert_eq!(
+                Addressing::Dhcp(options(Some(hostname), None)).validate(),
+                Err(ProfileError::InvalidHostname),
+                "{hostname}"
+            );
+        }
+        assert_eq!(
+            Addressing::Dhcp(options(None, Some("ap\t1"))).validate(),
+            Err(ProfileError::InvalidClientId)
+        );
+        let mut vendor = options(None, None);
+        vendor.vendor_class = Some("".try_into().unwrap());
+        assert_eq!(
+            Addressing::Dhcp(vendor).validate(),
+            Err(ProfileError::InvalidVendorClass)
+        );
+    }
+
+    #[test]
+    fn validates_networks() {
+        let mut profile = NetworkProfile::new("office".try_into().unwrap());
+        assert_eq!(profile.validate(), Ok(()));
+        profile.auth = Some(Security::Wpa2Wpa3);
+        assert_eq!(profile.validate(), Ok(()));
+        for auth in [Security::Wep, Security::Wpa, Security::Wpa2Enterprise] {
+            profile.auth = Some(auth);
+            assert_eq!(profile.validate(), Err(ProfileError::UnsupportedAuth));
+        }
+        let profile = NetworkProfile::new("a\nb".try_into().unwrap());
+        assert_eq!(profile.validate(), Err(ProfileError::InvalidSsid));
+        let profile = NetworkProfile::new(String::new());
+        assert_eq!(profile.validate(), Err(ProfileError::InvalidSsid));
+    }
+
+    #[test]
+    fn profiles_fill_in_the_defaults() {
+        let mut profiles = Profiles::new();
+        profiles
+            .set_defaults(options(Some("ap"), Some("ap-id")))
+            .unwrap();
+        let mut office = NetworkProfile::new("office".try_into().unwrap());
+        office.addressing = Addressing::Dhcp(options(Some("office-ap"), None));
+        profiles.set(office).unwrap();
+
+        assert_eq!(
+            profiles.addressing("office"),
+            Addressing::Dhcp(options(Some("office-ap"), Some("ap-id")))
+        );
+        assert_eq!(
+            profiles.addressing("cafe"),
+            Addressing::Dhcp(options(Some("ap"), Some("ap-id")))
+        );
+
+        // Static addressing ignores them
+        let mut lab = NetworkProfile::new("lab".try_into().unwrap());
+        let config = fixed("10.0.0.5/24", Some("10.0.0.1"), &[]);
+        lab.addressing = Addressing::Static(config.clone());
+        profiles.set(lab).unwrap();
+        assert_eq!(profiles.addressing("lab"), Addressing::Static(config));
+
+        assert_eq!(
+            profiles.set_defaults(options(Some("bad host"), None)),
+            Err(ProfileError::InvalidHostname)
+        );
+    }
+
+    #[test]
+    fn replaces_and_removes_profiles() {
+        let mut profiles = Profiles::new();
+        for i in 0..MAX_PROFILES {
+            let ssid = std::format!("net{i}");
+            profiles
+                .set(NetworkProfile::new(ssid.as_str().try_into().unwrap()))
+                .unwrap();
+        }
+        let mut hidden = NetworkProfile::new("net0".try_into().unwrap());
+        hidden.hidden = true;
+        profiles.set(hidden).unwrap();
+        assert!(profiles.network("net0").unwrap().hidden);
+        assert_eq!(
+            profiles.set(NetworkProfile::new("net9".try_into().unwrap())),
+            Err(ProfileError::TooManyProfiles)
+        );
+        assert!(profiles.remove("net0"));
+        assert!(!profiles.remove("net0"));
+        assert_eq!(profiles.profiles.len(), MAX_PROFILES - 1);
+    }
+
+    #[test]
+    fn stores_profiles() {
+        let mut profiles = Profiles::new();
+        profiles.set_defaults(options(Some("ap"), None)).unwrap();
+        let mut lab = NetworkProfile::new("lab".try_into().unwrap());
+        lab.hidden = true;
+        lab.auth = Some(Security::Wpa3);
+        lab.addressing = Addressing::Static(fixed(
+            "10.0.0.5/24",
+            Some("10.0.0.1"),
+            &["10.0.0.53", "1.1.1.1"],
+        ));
+        profiles.set(lab).unwrap();
+        let mut office = NetworkProfile::new("office".try_into().unwrap());
+        office.addressing = Addressing::Dhcp(options(None, Some("ap 1")));
+        profiles.set(office).unwrap();
+
+        let mut text = std::string::String::new();
+        profiles.write_to(&mut text).unwrap();
+        assert_eq!(Profiles::parse(&text), Some(profiles));
+
+        // A stored profile that no longer validates isn't used
+        assert_eq!(
+            Profiles::parse("network=lab\nmode=static\naddress=10.0.0.0/24\n"),
+            None
+        );
+        assert_eq!(Profiles::parse("network=lab\nmode=bootp\n"), None);
+    }
+}
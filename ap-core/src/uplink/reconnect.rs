@@ -0,0 +1,331 @@
+/// Direct connects to the cached access point before it is forgotten and
+/// the next attempt scans.
+pub const MAX_DIRECT_ATTEMPTS: u8 = 2;
+/// Wait between scans that didn't lead to a connection.
+pub const SCAN_RETRY_SECS: u64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Straight to the cached BSSID and channel.
+    Direct,
+    Scan,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Scan => "scan",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Plan<T> {
+    Direct(T),
+    Scan { wait_secs: u64 },
+}
+
+/// How the last connection was made and how long it took, from the first
+/// attempt (or losing the previous connection) until associated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectStats {
+    pub method: Method,
+    pub millis: u64,
+    pub attempts: u8,
+}
+
+/// Decides how the station reconnects: a direct connect to the access point
+/// of the last successful connection (`T`, whatever the radio needs to skip
+/// the scan), falling back to scanning when that fails. Time is passed in
+/// as milliseconds.
+pub struct Reconnect<T> {
+    cached: Option<T>,
+    direct_failures: u8,
+    scan_failed: bool,
+    started_at: Option<u64>,
+    attempts: u8,
+}
+
+impl<T: Clone> Default for Reconnect<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Reconnect<T> {
+    pub const fn new() -> Self {
+        Self {
+            cached: None,
+            direct_failures: 0,
+            scan_failed: false,
+            started_at: None,
+            attempts: 0,
+        }
+    }
+
+    /// Starts the clock when a connection is lost, so time-to-connect
+    /// includes noticing the loss.
+    pub fn disconnected(&mut self, now_ms: u64) {
+        self.started_at.get_or_insert(now_ms);
+    }
+
+    /// What to try next.
+    pub fn attempt(&mut self, now_ms: u64) -> Plan<T> {
+        self.started_at.get_or_insert(now_ms);
+        self.attempts = self.attempts.saturating_add(1);
+        match &self.cached {
+            Some(ap) if self.direct_failures < MAX_DIRECT_ATTEMPTS => Plan::Direct(ap.clone()),
+            _ => Plan::Scan {
+                wait_secs: if self.scan_failed { SCAN_RETRY_SECS } else { 0 },
+            },
+        }
+    }
+
+    pub fn failed(&mut self, method: Method) {
+        match method {
+            Method::Direct => {
+                self.direct_failures += 1;
+                // The access point moved or went away, find it again
+                if self.direct_failures >= MAX_DIRECT_ATTEMPTS {
+                    self.cached = None;
+                }
+            }
+            Method::Scan => self.scan_failed = true,
+        }
+    }
+
+    /// Points the next attempt at `ap`, when deliberately moving to another
+    /// access point of the same network.
+    pub fn roam_to(&mut self, ap: T) {
+        self.cached = Some(ap);
+        self.direct_failures = 0;
+    }
+
+    /// Caches `ap` for the next reconnect.
+    pub fn connected(&mut self, ap: T, method: Method, now_ms: u64) -> ConnectStats {
+        let stats = ConnectStats {
+            method,
+            millis: now_ms.saturating_sub(self.started_at.unwrap_or(now_ms)),
+            attempts: self.attempts,
+        };
+        *self = Self {
+            cached: Some(ap),
+            ..Self::new()
+        };
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Ap {
+        bssid: u8,
+        channel: u8,
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Call {
+        Wait(u64),
+        Scan,
+        Connect(Ap),
+    }
+
+    /// Stands in for the Wi-Fi controller: access points in range can be
+    /// found by scanning, reachable ones can be joined.
+    #[derive(Default)]
+    struct MockController {
+        in_scan: Option<Ap>,
+        reachable: Vec<Ap>,
+        calls: Vec<Call>,
+    }
+
+    impl MockController {
+        fn scan(&mut self) -> Option<Ap> {
+            self.calls.push(Call::Scan);
+            self.in_scan
+        }
+
+        fn connect(&mut self, ap: Ap) -> bool {
+            self.calls.push(Call::Connect(ap));
+            self.reachable.contains(&ap)
+        }
+    }
+
+    /// One pass of the station's connection loop, each taking 100 ms.
+    fn step(
+        reconnect: &mut Reconnect<Ap>,
+        controller: &mut MockController,
+        now_ms: &mut u64,
+    ) -> Option<ConnectStats> {
+        *now_ms += 100;
+        let (method, ap) = match reconnect.attempt(*now_ms) {
+            Plan::Direct(ap) => (Method::Direct, ap),
+            Plan::Scan { wait_secs } => {
+                if wait_secs > 0 {
+                    controller.calls.push(Call::Wait(wait_secs));
+                    *now_ms += wait_secs * 1000;
+                }
+                let Some(ap) = controller.scan() else {
+                    reconnect.failed(Method::Scan);
+                    return None;
+                };
+                (Method::Scan, ap)
+            }
+        };
+        if controller.connect(ap) {
+            Some(reconnect.connected(ap, method, *now_ms))
+        } else {
+            reconnect.failed(method);
+            None
+        }
+    }
+
+    const AP1: Ap = Ap {
+        bssid: 1,
+        channel: 6,
+    };
+    const AP2: Ap = Ap {
+        bssid: 2,
+        channel: 11,
+    };
+
+    fn connected_to(ap: Ap, now_ms: &mut u64) -> Reconnect<Ap> {
+        let mut reconnect = Reconnect::new();
+        let mut controller = MockController {
+            in_scan: Some(ap),
+            reachable: std::vec![ap],
+            ..Default::default()
+        };
+        step(&mut reconnect, &mut controller, now_ms).unwrap();
+        reconnect
+    }
+
+    #[test]
+    fn scans_without_a_cached_access_point() {
+        let mut now_ms = 0;
+        let mut reconnect = Reconnect::new();
+        let mut controller = MockController {
+            in_scan: Some(AP1),
+            reachable: std::vec![AP1],
+            ..Default::default()
+        };
+        let stats = step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        assert_eq!(controller.calls, [Call::Scan, Call::Connect(AP1)]);
+        assert_eq!(
+            stats,
+            ConnectStats {
+                method: Method::Scan,
+                millis: 0,
+                attempts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn reconnects_directly_to_the_cached_access_point() {
+        let mut now_ms = 0;
+        let mut reconnect = connected_to(AP1, &mut now_ms);
+        reconnect.disconnected(now_ms);
+        // The loop only notices a while later
+        now_ms += 2000;
+        let mut controller = MockController {
+            reachable: std::vec![AP1],
+            ..Default::default()
+        };
+        let stats = step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        // No scan, no wait
+        assert_eq!(controller.calls, [Call::Connect(AP1)]);
+        assert_eq!(
+            stats,
+            ConnectStats {
+                method: Method::Direct,
+                millis: 2100,
+                attempts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_scanning_when_the_access_point_moved() {
+        let mut now_ms = 0;
+        let mut reconnect = connected_to(AP1, &mut now_ms);
+        reconnect.disconnected(now_ms);
+        let mut controller = MockController {
+            in_scan: Some(AP2),
+            reachable: std::vec![AP2],
+            ..Default::default()
+        };
+        assert_eq!(step(&mut reconnect, &mut controller, &mut now_ms), None);
+        assert_eq!(step(&mut reconnect, &mut controller, &mut now_ms), None);
+        let stats = step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        assert_eq!(
+            controller.calls,
+            [
+                Call::Connect(AP1),
+                Call::Connect(AP1),
+                Call::Scan,
+                Call::Connect(AP2),
+            ]
+        );
+        assert_eq!(stats.method, Method::Scan);
+        assert_eq!(stats.attempts, MAX_DIRECT_ATTEMPTS + 1);
+        assert_eq!(stats.millis, 300);
+
+        // The new access point is the one tried next time
+        reconnect.disconnected(now_ms);
+        controller.calls.clear();
+        step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        assert_eq!(controller.calls, [Call::Connect(AP2)]);
+    }
+
+    #[test]
+    fn waits_between_failed_scans() {
+        let mut now_ms = 0;
+        let mut reconnect = Reconnect::new();
+        let mut controller = MockController::default();
+        assert_eq!(step(&mut reconnect, &mut controller, &mut now_ms), None);
+        assert_eq!(step(&mut reconnect, &mut controller, &mut now_ms), None);
+        controller.in_scan = Some(AP1);
+        controller.reachable.push(AP1);
+        let stats = step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        assert_eq!(
+            controller.calls,
+            [
+                Call::Scan,
+                Call::Wait(SCAN_RETRY_SECS),
+                Call::Scan,
+                Call::Wait(SCAN_RETRY_SECS),
+                Call::Scan,
+                Call::Connect(AP1),
+            ]
+        );
+        assert_eq!(stats.attempts, 3);
+        assert_eq!(stats.millis, 200 + 2 * SCAN_RETRY_SECS * 1000);
+
+        // A success resets the backoff
+        reconnect.disconnected(now_ms);
+        reconnect.failed(Method::Direct);
+        reconnect.failed(Method::Direct);
+        assert_eq!(reconnect.attempt(now_ms), Plan::Scan { wait_secs: 0 });
+    }
+
+    #[test]
+    fn roaming_points_the_next_attempt_elsewhere() {
+        let mut now_ms = 0;
+        let mut reconnect = connected_to(AP1, &mut now_ms);
+        reconnect.failed(Method::Direct);
+        reconnect.roam_to(AP2);
+        let mut controller = MockController {
+            reachable: std::vec![AP2],
+            ..Default::default()
+        };
+        let stats = step(&mut reconnect, &mut controller, &mut now_ms).unwrap();
+        assert_eq!(controller.calls, [Call::Connect(AP2)]);
+        assert_eq!(stats.method, Method::Direct);
+    }
+}
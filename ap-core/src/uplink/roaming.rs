@@ -0,0 +1,184 @@
+/// When to look for, and move to, another access point of the same network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoamConfig {
+    /// Start scanning once the smoothed signal drops below this, in dBm.
+    pub scan_below: i8,
+    /// Stop scanning once it climbs back above this. Keeping it higher than
+    /// `scan_below` stops a signal hovering at the threshold from flapping.
+    pub stop_above: i8,
+    /// How much stronger another access point has to be, in dB.
+    pub min_gain: u8,
+    pub scan_interval_secs: u64,
+    /// No roaming right after joining, the first samples are unreliable.
+    pub settle_secs: u64,
+}
+
+impl Default for RoamConfig {
+    fn default() -> Self {
+        Self {
+            scan_below: -70,
+            stop_above: -65,
+            min_gain: 8,
+            scan_interval_secs: 30,
+            settle_secs: 15,
+        }
+    }
+}
+
+/// Roaming policy for the station. Fed RSSI samples of the current access
+/// point, it says when to scan, and picks a clearly better access point
+/// from the scan results. Time is passed in as seconds.
+pub struct Roaming {
+    config: RoamConfig,
+    /// Exponential moving average, in dBm times four to keep some precision.
+    smoothed: Option<i16>,
+    degraded: bool,
+    joined_at: u64,
+    last_scan_at: Option<u64>,
+}
+
+impl Roaming {
+    pub fn new(config: RoamConfig) -> Self {
+        Self {
+            config,
+            smoothed: None,
+            degraded: false,
+            joined_at: 0,
+            last_scan_at: None,
+        }
+    }
+
+    /// Starts over after (re)associating.
+    pub fn joined(&mut self, now: u64) {
+        *self = Self {
+            joined_at: now,
+            ..Self::new(self.config)
+        };
+    }
+
+    /// The smoothed signal of the current access point.
+    pub fn rssi(&self) -> Option<i8> {
+        self.smoothed.map(|smoothed| (smoothed / 4) as i8)
+    }
+
+    /// Takes a sample, returns whether to scan for a better access point.
+    pub fn sample(&mut self, now: u64, rssi: i8) -> bool {
+        let rssi = rssi as i16 * 4;
+        // Each sample counts for a quarter, single dips don't trigger scans
+        let smoothed = match self.smoothed {
+            Some(smoothed) => (smoothed * 3 + rssi) / 4,
+            None => rssi,
+        };
+        self.smoothed = Some(smoothed);
+
+        if smoothed < self.config.scan_below as i16 * 4 {
+            self.degraded = true;
+        } else if smoothed > self.config.stop_above as i16 * 4 {
+            self.degraded = false;
+        }
+        let settled = now >= self.joined_at + self.config.settle_secs;
+        let scanned_recently = self
+            .last_scan_at
+            .is_some_and(|at| now < at + self.config.scan_interval_secs);
+        if !self.degraded || !settled || scanned_recently {
+            return false;
+        }
+        self.last_scan_at = Some(now);
+        true
+    }
+
+    /// The strongest access point from a scan, if it beats the current one
+    /// by at least `min_gain`.
+    pub fn choose(
+        &self,
+        current: &[u8; 6],
+        seen: impl IntoIterator<Item = ([u8; 6], i8)>,
+    ) -> Option<([u8; 6], i8)> {
+        let rssi = self.rssi()?;
+        seen.into_iter()
+            .filter(|(bssid, _)| bssid != current)
+            .max_by_key(|(_, rssi)| *rssi)
+            .filter(|(_, best)| *best as i16 >= rssi as i16 + self.config.min_gain as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const OTHER: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const THIRD: [u8; 6] = [2, 0, 0, 0, 0, 3];
+
+    fn joined_at(now: u64) -> Roaming {
+        let mut roaming = Roaming::new(RoamConfig::default());
+        roaming.joined(now);
+        roaming
+    }
+
+    #[test]
+    fn scans_once_settled_and_not_too_often() {
+        let mut roaming = joined_at(100);
+        assert!(!roaming.sample(100, -80));
+        assert!(!roaming.sample(114, -80));
+        assert!(roaming.sample(115, -80));
+        assert!(!roaming.sample(116, -80));
+        assert!(!roaming.sample(144, -80));
+        assert!(roaming.sample(145, -80));
+
+        // Joining again starts over, waiting to settle
+        roaming.joined(200);
+        assert_eq!(roaming.rssi(), None);
+        assert!(!roaming.sample(200, -80));
+        assert!(roaming.sample(215, -80));
+    }
+
+    #[test]
+    fn smooths_out_single_dips() {
+        let mut roaming = joined_at(0);
+        assert!(!roaming.sample(20, -60));
+        assert_eq!(roaming.rssi(), Some(-60));
+        assert!(!roaming.sample(21, -90));
+        assert_eq!(roaming.rssi(), Some(-67));
+        assert!(!roaming.sample(22, -60));
+    }
+
+    #[test]
+    fn keeps_scanning_until_the_signal_recovers() {
+        let mut roaming = joined_at(0);
+        assert!(roaming.sample(20, -75));
+        // Between scan_below and stop_above it keeps scanning
+        for now in 21..50 {
+            roaming.sample(now, -68);
+        }
+        assert_eq!(roaming.rssi(), Some(-68));
+        assert!(roaming.sample(50, -68));
+
+        for now in 51..60 {
+            roaming.sample(now, -60);
+        }
+        assert!(roaming.rssi().unwrap() > -65);
+        assert!(!roaming.sample(100, -60));
+        // and only starts again below scan_below
+        for now in 101..130 {
+            assert!(!roaming.sample(now, -68));
+        }
+        assert!((-70..-65).contains(&roaming.rssi().unwrap()));
+    }
+
+    #[test]
+    fn chooses_a_clearly_better_access_point() {
+        let mut roaming = joined_at(0);
+        assert_eq!(roaming.choose(&CURRENT, [(OTHER, -40)]), None);
+        roaming.sample(0, -75);
+
+        assert_eq!(
+            roaming.choose(&CURRENT, [(OTHER, -67), (THIRD, -60), (CURRENT, -30)]),
+            Some((THIRD, -60))
+        );
+        assert_eq!(roaming.choose(&CURRENT, [(OTHER, -67)]), Some((OTHER, -67)));
+        assert_eq!(roaming.choose(&CURRENT, [(OTHER, -68)]), None);
+        assert_eq!(roaming.choose(&CURRENT, [(CURRENT, -30)]), None);
+        assert_eq!(roaming.choose(&CURRENT, []), None);
+    }
+}
@@ -0,0 +1,267 @@
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+pub const MAX_REMEMBERED: usize = 8;
+
+/// Authentication an upstream network advertises, weakest first so the
+/// derived ordering compares strength.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Security {
+    Open,
+    Wep,
+    Wpa,
+    WpaWpa2,
+    Wpa2,
+    /// 802.1X, see [`super::enterprise`].
+    Wpa2Enterprise,
+    /// WPA3 transition mode, accepting both WPA2 and WPA3-SAE clients.
+    Wpa2Wpa3,
+    Wpa3,
+}
+
+impl Security {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Wep => "wep",
+            Self::Wpa => "wpa",
+            Self::WpaWpa2 => "wpa-wpa2",
+            Self::Wpa2 => "wpa2",
+            Self::Wpa2Enterprise => "wpa2-enterprise",
+            Self::Wpa2Wpa3 => "wpa2-wpa3",
+            Self::Wpa3 => "wpa3",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "open" => Self::Open,
+            "wep" => Self::Wep,
+            "wpa" => Self::Wpa,
+            "wpa-wpa2" => Self::WpaWpa2,
+            "wpa2" => Self::Wpa2,
+            "wpa2-enterprise" => Self::Wpa2Enterprise,
+            "wpa2-wpa3" => Self::Wpa2Wpa3,
+            "wpa3" => Self::Wpa3,
+            _ => return None,
+        })
+    }
+
+    /// Whether a network set to `wanted` may be joined when it advertises
+    /// `self`. Transition mode networks also take plain WPA2 or WPA3.
+    fn satisfies(self, wanted: Security) -> bool {
+        self == wanted
+            || matches!(
+                (wanted, self),
+                (Self::Wpa2 | Self::Wpa3, Self::Wpa2Wpa3)
+                    | (Self::Wpa2Wpa3, Self::Wpa2 | Self::Wpa3)
+            )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityError {
+    /// The network advertises weaker authentication than it did before.
+    Downgrade { seen: Security, offered: Security },
+    /// The network doesn't offer the authentication it was set up with.
+    Mismatch { wanted: Security, offered: Security },
+}
+
+/// Picks the authentication to connect with: `wanted` if the network was
+/// set up with one, otherwise whatever it advertises. Refuses networks that
+/// advertise less than `seen`, the strongest seen on earlier connections,
+/// so a rogue access point can't talk us into a weaker handshake.
+pub fn choose(
+    wanted: Option<Security>,
+    offered: Security,
+    seen: Option<Security>,
+) -> Result<Security, SecurityError> {
+    if let Some(seen) = seen.filter(|seen| offered < *seen) {
+        return Err(SecurityError::Downgrade { seen, offered });
+    }
+    match wanted {
+        None => Ok(offered),
+        Some(wanted) if !offered.satisfies(wanted) => {
+            Err(SecurityError::Mismatch { wanted, offered })
+        }
+        // Transition mode on our side means whichever the network speaks
+        Some(Security::Wpa2Wpa3) => Ok(offered),
+        Some(wanted) => Ok(wanted),
+    }
+}
+
+/// The strongest authentication each network was joined with, most recent
+/// first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeenSecurity {
+    pub networks: Vec<(String<32>, Security), MAX_REMEMBERED>,
+}
+
+impl SeenSecurity {
+    pub const fn new() -> Self {
+        Self {
+            networks: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, ssid: &str) -> Option<Security> {
+        self.networks
+            .iter()
+            .find(|(seen, _)| seen == ssid)
+            .map(|(_, security)| *security)
+    }
+
+    /// Records a connection, returns whether anything changed. Networks
+    /// only ever move up.
+    pub fn record(&mut self, ssid: &str, security: Security) -> bool {
+        let Ok(ssid) = String::try_from(ssid) else {
+            return false;
+        };
+        let strongest = self.get(&ssid).map_or(security, |seen| seen.max(security));
+        if self.networks.first() == Some(&(ssid.clone(), strongest)) {
+            return false;
+        }
+        self.forget(&ssid);
+        if self.networks.is_full() {
+            self.networks.pop();
+        }
+        _ = self.networks.insert(0, (ssid, strongest));
+        true
+    }
+
+    /// Forgets a network, e.g. after it was set up again by hand.
+    pub fn forget(&mut self, ssid: &str) -> bool {
+        let before = self.networks.len();
+        self.networks.retain(|(seen, _)| seen != ssid);
+        self.networks.len() != before
+    }
+
+    /// One `seen=<security> <ssid>` line per network.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        for (ssid, security) in &self.networks {
+            writeln!(out, "seen={} {}", security.as_str(), ssid)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut seen = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (security, ssid) = line.strip_prefix("seen=")?.split_once(' ')?;
+            seen.networks
+                .push((ssid.try_into().ok()?, Security::parse(security)?))
+                .ok()?;
+        }
+        Some(seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Security::*;
+
+    #[test]
+    fn takes_what_is_advertised_by_default() {
+        for offered in [Open, Wpa, Wpa2, Wpa2Wpa3, Wpa3] {
+            assert_eq!(choose(None, offered, None), Ok(offered));
+            assert_eq!(choose(None, offered, Some(offered)), Ok(offered));
+        }
+        // Upgrades are always fine
+        assert_eq!(choose(None, Wpa3, Some(Wpa2)), Ok(Wpa3));
+    }
+
+    #[test]
+    fn refuses_downgrades() {
+        for (seen, offered) in [
+            (Wpa2, Open),
+            (Wpa2, WpaWpa2),
+            (Wpa3, Wpa2Wpa3),
+            (Wpa3, Wpa2),
+            (Wpa2Wpa3, Wpa2),
+            (Wpa2Enterprise, Wpa2),
+        ] {
+            assert_eq!(
+                choose(None, offered, Some(seen)),
+                Err(SecurityError::Downgrade { seen, offered }),
+                "{seen:?} to {offered:?}"
+            );
+        }
+        // Even when set up to accept it
+        assert_eq!(
+            choose(Some(Wpa2), Wpa2, Some(Wpa3)),
+            Err(SecurityError::Downgrade {
+                seen: Wpa3,
+                offered: Wpa2
+            })
+        );
+    }
+
+    #[test]
+    fn holds_networks_to_their_setting() {
+        assert_eq!(choose(Some(Wpa2), Wpa2, None), Ok(Wpa2));
+        assert_eq!(choose(Some(Wpa3), Wpa3, None), Ok(Wpa3));
+        // A transition network speaks either
+        assert_eq!(choose(Some(Wpa2), Wpa2Wpa3, None), Ok(Wpa2));
+        assert_eq!(choose(Some(Wpa3), Wpa2Wpa3, None), Ok(Wpa3));
+        // And our transition setting takes either
+        assert_eq!(choose(Some(Wpa2Wpa3), Wpa2, None), Ok(Wpa2));
+        assert_eq!(choose(Some(Wpa2Wpa3), Wpa3, None), Ok(Wpa3));
+        for (wanted, offered) in [(Wpa2, Open), (Wpa3, Wpa2), (Wpa2, Wpa3), (Open, Wpa2)] {
+            assert_eq!(
+                choose(Some(wanted), offered, None),
+                Err(SecurityError::Mismatch { wanted, offered }),
+                "{wanted:?} offered {offered:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn remembers_the_strongest_seen() {
+        let mut seen = SeenSecurity::new();
+        assert!(seen.record("office", Wpa2));
+        assert!(seen.record("office", Wpa3));
+        // Joining with less later doesn't lower the bar
+        assert!(!seen.record("office", Wpa2));
+        assert_eq!(seen.get("office"), Some(Wpa3));
+        assert_eq!(seen.get("cafe"), None);
+
+        assert!(seen.forget("office"));
+        assert!(!seen.forget("office"));
+        assert_eq!(seen.get("office"), None);
+    }
+
+    #[test]
+    fn forgets_the_oldest_networks() {
+        let mut seen = SeenSecurity::new();
+        for i in 0..=MAX_REMEMBERED {
+            seen.record(&std::format!("net{i}"), Wpa2);
+        }
+        assert_eq!(seen.networks.len(), MAX_REMEMBERED);
+        assert_eq!(seen.get("net0"), None);
+        assert_eq!(seen.get(&std::format!("net{MAX_REMEMBERED}")), Some(Wpa2));
+    }
+
+    #[test]
+    fn stores_what_was_seen() {
+        let mut seen = SeenSecurity::new();
+        seen.record("office", Wpa3);
+        seen.record("my home", Wpa2Wpa3);
+        let mut text = std::string::String::new();
+        seen.write_to(&mut text).unwrap();
+        assert_eq!(SeenSecurity::parse(&text), Some(seen));
+        assert_eq!(SeenSecurity::parse("seen=wpa4 office"), None);
+        for security in [
+            Open,
+            Wep,
+            Wpa,
+            WpaWpa2,
+            Wpa2,
+            Wpa2Enterprise,
+            Wpa2Wpa3,
+            Wpa3,
+        ] {
+            assert_eq!(Security::parse(security.as_str()), Some(security));
+        }
+    }
+}
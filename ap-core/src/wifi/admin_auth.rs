@@ -0,0 +1,261 @@
+use core::fmt::{self, Write};
+use heapless::String;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::uplink::pem;
+
+/// The user name the admin pages and API ask for.
+pub const ADMIN_USER: &str = "admin";
+const MIN_PASSWORD_LEN: usize = 8;
+pub const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const HASH_ROUNDS: u32 = 1000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdminError {
+    PasswordTooShort,
+    InvalidPassword,
+}
+
+impl AdminError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PasswordTooShort => "admin password must be at least 8 characters",
+            Self::InvalidPassword => "admin password must be printable",
+        }
+    }
+}
+
+/// Credentials for the admin pages and `/api`, checked with HTTP basic
+/// authentication. Only a hash of the password is kept, see
+/// [`PasswordHash`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdminConfig {
+    pub password: String<32>,
+}
+
+impl AdminConfig {
+    pub fn validate(&self) -> Result<(), AdminError> {
+        if self.password.len() < MIN_PASSWORD_LEN {
+            return Err(AdminError::PasswordTooShort);
+        }
+        if self.password.chars().any(|c| c.is_control()) {
+            return Err(AdminError::InvalidPassword);
+        }
+        Ok(())
+    }
+
+    /// Reads the plaintext `password=` record older firmware stored, so it
+    /// can be replaced by a hash.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut password = None;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            if key == "password" {
+                password = Some(value.try_into().ok()?);
+            }
+        }
+        Some(Self {
+            password: password?,
+        })
+    }
+}
+
+/// A salted PBKDF2-HMAC-SHA256 hash of the admin password, which is what
+/// gets stored. It is checked on every request, so the round count is
+/// kept to what the ESP32 gets through in a few tens of milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasswordHash {
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+impl PasswordHash {
+    pub fn new(password: &str, salt: [u8; SALT_LEN]) -> Self {
+        Self {
+            salt,
+            hash: pbkdf2(password.as_bytes(), &salt, HASH_ROUNDS),
+        }
+    }
+
+    pub fn matches(&self, password: &str) -> bool {
+        same(
+            &pbkdf2(password.as_bytes(), &self.salt, HASH_ROUNDS),
+            &self.hash,
+        )
+    }
+
+    /// Checks the value of a request's `Authorization` header.
+    pub fn authorizes(&self, authorization: &str) -> bool {
+        let Some((scheme, credentials)) = authorization.trim().split_once(' ') else {
+            return false;
+        };
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return false;
+        }
+        let Some(decoded) = pem::base64(credentials) else {
+            return false;
+        };
+        let Some((user, password)) = core::str::from_utf8(&decoded)
+            .ok()
+            .and_then(|credentials| credentials.split_once(':'))
+        else {
+            return false;
+        };
+        user == ADMIN_USER && self.matches(password)
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        out.write_str("salt=")?;
+        write_hex(out, &self.salt)?;
+        out.write_str("\nhash=")?;
+        write_hex(out, &self.hash)?;
+        out.write_char('\n')
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let (mut salt, mut hash) = (None, None);
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "salt" => salt = Some(parse_hex(value)?),
+                "hash" => hash = Some(parse_hex(value)?),
+                _ => {}
+            }
+        }
+        Some(Self {
+            salt: salt?,
+            hash: hash?,
+        })
+    }
+}
+
+// PBKDF2 (RFC 8018) for a single block, which is all a 32 byte hash needs
+fn pbkdf2(password: &[u8], salt: &[u8], rounds: u32) -> [u8; HASH_LEN] {
+    let keyed = HmacSha256::new_from_slice(password).expect("HMAC accepts any key length");
+    let mut mac = keyed.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block: [u8; HASH_LEN] = mac.finalize().into_bytes().into();
+    let mut hash = block;
+    for _ in 1..rounds {
+        let mut mac = keyed.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes().into();
+        hash.iter_mut()
+            .zip(&block)
+            .for_each(|(out, byte)| *out ^= byte);
+    }
+    hash
+}
+
+fn write_hex(out: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(out, "{byte:02x}"))
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+// Compares in the same time wherever the first difference is, so the
+// password can't be guessed a byte at a time
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; SALT_LEN] = [7; SALT_LEN];
+
+    fn config() -> AdminConfig {
+        AdminConfig {
+            password: "s3cret-pass".try_into().unwrap(),
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> std::string::String {
+        let mut text = std::string::String::new();
+        write_hex(&mut text, bytes).unwrap();
+        text
+    }
+
+    #[test]
+    fn checks_basic_credentials() {
+        let hash = PasswordHash::new("s3cret-pass", SALT);
+        // admin:s3cret-pass
+        assert!(hash.authorizes("Basic YWRtaW46czNjcmV0LXBhc3M="));
+        assert!(hash.authorizes("basic YWRtaW46czNjcmV0LXBhc3M="));
+        // admin:s3cret-pasS
+        assert!(!hash.authorizes("Basic YWRtaW46czNjcmV0LXBhc1M="));
+        // root:s3cret-pass
+        assert!(!hash.authorizes("Basic cm9vdDpzM2NyZXQtcGFzcw=="));
+        assert!(!hash.authorizes("Bearer YWRtaW46czNjcmV0LXBhc3M="));
+        assert!(!hash.authorizes("Basic not base64"));
+        assert!(!hash.authorizes(""));
+    }
+
+    #[test]
+    fn validates_passwords() {
+        assert_eq!(config().validate(), Ok(()));
+        let short = AdminConfig {
+            password: "short".try_into().unwrap(),
+        };
+        assert_eq!(short.validate(), Err(AdminError::PasswordTooShort));
+        let control = AdminConfig {
+            password: "bad\tpassword".try_into().unwrap(),
+        };
+        assert_eq!(control.validate(), Err(AdminError::InvalidPassword));
+    }
+
+    #[test]
+    fn hashes_with_pbkdf2() {
+        // RFC 7914 section 11 and the usual PBKDF2-HMAC-SHA256 vectors
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 1)),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 2)),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        assert_eq!(
+            hex(&pbkdf2(b"password", b"salt", 4096)),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn salts_the_hash() {
+        let hash = PasswordHash::new("s3cret-pass", SALT);
+        assert!(hash.matches("s3cret-pass"));
+        assert!(!hash.matches("s3cret-pas"));
+        assert_ne!(hash, PasswordHash::new("s3cret-pass", [8; SALT_LEN]));
+    }
+
+    #[test]
+    fn stores_only_the_hash() {
+        let hash = PasswordHash::new("s3cret-pass", SALT);
+        let mut text = std::string::String::new();
+        hash.write_to(&mut text).unwrap();
+        assert!(!text.contains("s3cret-pass"));
+        assert_eq!(PasswordHash::parse(&text), Some(hash));
+        assert_eq!(PasswordHash::parse("salt=0707"), None);
+        assert_eq!(PasswordHash::parse(""), None);
+
+        // What older firmware stored
+        assert_eq!(AdminConfig::parse("password=s3cret-pass\n"), Some(config()));
+        assert_eq!(PasswordHash::parse("password=s3cret-pass\n"), None);
+    }
+}
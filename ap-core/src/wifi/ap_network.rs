@@ -0,0 +1,481 @@
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+use heapless::Vec;
+
+pub const MAX_EXCLUDED_RANGES: usize = 4;
+
+// Gateways tried, in order, when the AP subnet collides with the upstream one
+const FALLBACK_GATEWAYS: [Ipv4Addr; 4] = [
+    Ipv4Addr::new(192, 168, 4, 1),
+    Ipv4Addr::new(192, 168, 42, 1),
+    Ipv4Addr::new(10, 42, 0, 1),
+    Ipv4Addr::new(172, 16, 42, 1),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv4Range {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+impl Ipv4Range {
+    pub const fn new(start: Ipv4Addr, end: Ipv4Addr) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let ip = u32::from(ip);
+        ip >= u32::from(self.start) && ip <= u32::from(self.end)
+    }
+
+    pub fn overlaps(&self, other: &Ipv4Range) -> bool {
+        u32::from(self.start) <= u32::from(other.end)
+            && u32::from(other.start) <= u32::from(self.end)
+    }
+
+    pub fn len(&self) -> u32 {
+        u32::from(self.end)
+            .saturating_sub(u32::from(self.start))
+            .saturating_add(1)
+    }
+
+    fn is_reversed(&self) -> bool {
+        u32::from(self.start) > u32::from(self.end)
+    }
+
+    /// Parses `a.b.c.d-e.f.g.h`, or a single address as a one-element range.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        match s.split_once('-') {
+            Some((start, end)) => Some(Self::new(
+                start.trim().parse().ok()?,
+                end.trim().parse().ok()?,
+            )),
+            None => {
+                let ip = s.parse().ok()?;
+                Some(Self::new(ip, ip))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApNetworkError {
+    InvalidPrefix,
+    GatewayNotHost,
+    PoolReversed,
+    PoolOutsideSubnet,
+    PoolContainsGateway,
+    ExcludedOutsidePool,
+    TooManyExclusions,
+    PoolFullyExcluded,
+    OverlapsUpstream,
+}
+
+impl ApNetworkError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidPrefix => "prefix length must be between 8 and 30",
+            Self::GatewayNotHost => "gateway must be a host address of its subnet",
+            Self::PoolReversed => "pool start is after pool end",
+            Self::PoolOutsideSubnet => "pool must lie inside the AP subnet",
+            Self::PoolContainsGateway => "pool must not contain the gateway address",
+            Self::ExcludedOutsidePool => "excluded range is outside the pool",
+            Self::TooManyExclusions => "too many excluded ranges",
+            Self::PoolFullyExcluded => "excluded ranges leave no address to hand out",
+            Self::OverlapsUpstream => "AP subnet overlaps the upstream network",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApNetworkConfig {
+    pub gateway: Ipv4Addr,
+    pub prefix_len: u8,
+    pub pool: Ipv4Range,
+    pub excluded: Vec<Ipv4Range, MAX_EXCLUDED_RANGES>,
+}
+
+impl fmt::Display for Ipv4Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl ApNetworkConfig {
+    /// Builds a config with the default pool of the subnet: `.50`-`.200` for
+    /// a /24, otherwise every host address except the gateway side of it.
+    pub fn from_gateway(gateway: Ipv4Addr, prefix_len: u8) -> Self {
+        let mask = netmask_bits(prefix_len);
+        let network = u32::from(gateway) & mask;
+        let broadcast = network | !mask;
+        let pool = if prefix_len == 24 {
+            Ipv4Range::new(Ipv4Addr::from(network | 50), Ipv4Addr::from(network | 200))
+        } else if u32::from(gateway) == network + 1 {
+            Ipv4Range::new(
+                Ipv4Addr::from(network + 2),
+                Ipv4Addr::from(broadcast.saturating_sub(1)),
+            )
+        } else {
+            Ipv4Range::new(
+                Ipv4Addr::from(network + 1),
+                Ipv4Addr::from(u32::from(gateway).saturating_sub(1)),
+            )
+        };
+
+        Self {
+            gateway,
+            prefix_len,
+            pool,
+            excluded: Vec::new(),
+        }
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(netmask_bits(self.prefix_len))
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.gateway) & netmask_bits(self.prefix_len))
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.gateway) | !netmask_bits(self.prefix_len))
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = netmask_bits(self.prefix_len);
+        u32::from(ip) & mask == u32::from(self.gateway) & mask
+    }
+
+    /// Whether `ip` may be leased to a client.
+    pub fn is_assignable(&self, ip: Ipv4Addr) -> bool {
+        ip != self.gateway
+            && self.pool.contains(ip)
+            && !self.excluded.iter().any(|range| range.contains(ip))
+    }
+
+    pub fn validate(&self) -> Result<(), ApNetworkError> {
+        if !(8..=30).contains(&self.prefix_len) {
+            return Err(ApNetworkError::InvalidPrefix);
+        }
+        if self.gateway == self.network() || self.gateway == self.broadcast() {
+            return Err(ApNetworkError::GatewayNotHost);
+        }
+        if self.pool.is_reversed() {
+            return Err(ApNetworkError::PoolReversed);
+        }
+        let hosts = Ipv4Range::new(
+            Ipv4Addr::from(u32::from(self.network()) + 1),
+            Ipv4Addr::from(u32::from(self.broadcast()) - 1),
+        );
+        if !hosts.contains(self.pool.start) || !hosts.contains(self.pool.end) {
+            return Err(ApNetworkError::PoolOutsideSubnet);
+        }
+        if self.pool.contains(self.gateway) {
+            return Err(ApNetworkError::PoolContainsGateway);
+        }
+
+        if self
+            .excluded
+            .iter()
+            .any(|range| range.is_reversed() || !self.pool.overlaps(range))
+        {
+            return Err(ApNetworkError::ExcludedOutsidePool);
+        }
+        if self.next_assignable(self.pool.start).is_none() {
+            return Err(ApNetworkError::PoolFullyExcluded);
+        }
+
+        Ok(())
+    }
+
+    /// Validates the config and additionally rejects it if it overlaps the
+    /// upstream subnet the station interface is on.
+    pub fn validate_against(&self, upstream: Option<(Ipv4Addr, u8)>) -> Result<(), ApNetworkError> {
+        self.validate()?;
+        match upstream {
+            Some((addr, prefix_len)) if self.conflicts_with(addr, prefix_len) => {
+                Err(ApNetworkError::OverlapsUpstream)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn conflicts_with(&self, upstream_addr: Ipv4Addr, upstream_prefix_len: u8) -> bool {
+        subnets_overlap(
+            self.gateway,
+            self.prefix_len,
+            upstream_addr,
+            upstream_prefix_len,
+        )
+    }
+
+    /// Moves the config into the subnet of `gateway`, keeping the host part of
+    /// the pool and the excluded ranges.
+    pub fn relocate(&self, gateway: Ipv4Addr) -> Self {
+        let mask = netmask_bits(self.prefix_len);
+        let network = u32::from(gateway) & mask;
+        let shift = |ip: Ipv4Addr| Ipv4Addr::from(network | (u32::from(ip) & !mask));
+        let shift_range = |range: &Ipv4Range| Ipv4Range::new(shift(range.start), shift(range.end));
+
+        Self {
+            gateway,
+            prefix_len: self.prefix_len,
+            pool: shift_range(&self.pool),
+            excluded: self.excluded.iter().map(shift_range).collect(),
+        }
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "gateway={}/{}", self.gateway, self.prefix_len)?;
+        writeln!(out, "pool={}", self.pool)?;
+        for range in &self.excluded {
+            writeln!(out, "exclude={range}")?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut gateway = None;
+        let mut pool = None;
+        let mut excluded = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "gateway" => {
+                    let (ip, prefix_len) = value.split_once('/')?;
+                    gateway = Some((ip.parse().ok()?, prefix_len.parse().ok()?));
+                }
+                "pool" => pool = Some(Ipv4Range::parse(value)?),
+                "exclude" => excluded.push(Ipv4Range::parse(value)?).ok()?,
+                _ => {}
+            }
+        }
+        let (gateway, prefix_len) = gateway?;
+        Some(Self {
+            gateway,
+            prefix_len,
+            pool: pool?,
+            excluded,
+        })
+    }
+
+    /// First assignable address at or after `from`, without wrapping around.
+    pub fn next_assignable(&self, from: Ipv4Addr) -> Option<Ipv4Addr> {
+        let mut candidate = u32::from(from).max(u32::from(self.pool.start));
+        while candidate <= u32::from(self.pool.end) {
+            let ip = Ipv4Addr::from(candidate);
+            match self.excluded.iter().find(|range| range.contains(ip)) {
+                Some(range) => candidate = u32::from(range.end).checked_add(1)?,
+                None if ip == self.gateway => candidate += 1,
+                None => return Some(ip),
+            }
+        }
+        None
+    }
+}
+
+/// Returns a config that does not overlap the upstream subnet, or `None` if
+/// `current` is already fine. The host part of the gateway and pool is kept
+/// and only the network part is moved to one of the fallback subnets.
+pub fn avoid_upstream_conflict(
+    current: &ApNetworkConfig,
+    upstream_addr: Ipv4Addr,
+    upstream_prefix_len: u8,
+) -> Option<ApNetworkConfig> {
+    if !current.conflicts_with(upstream_addr, upstream_prefix_len) {
+        return None;
+    }
+
+    let host_bits = !netmask_bits(current.prefix_len);
+    FALLBACK_GATEWAYS
+        .iter()
+        .map(|fallback| {
+            let network = u32::from(*fallback) & !host_bits;
+            current.relocate(Ipv4Addr::from(
+                network | (u32::from(current.gateway) & host_bits),
+            ))
+        })
+        .chain(
+            FALLBACK_GATEWAYS
+                .iter()
+                .map(|fallback| ApNetworkConfig::from_gateway(*fallback, 24)),
+        )
+        .find(|candidate| {
+            candidate
+                .validate_against(Some((upstream_addr, upstream_prefix_len)))
+                .is_ok()
+        })
+}
+
+pub fn subnets_overlap(a: Ipv4Addr, a_prefix_len: u8, b: Ipv4Addr, b_prefix_len: u8) -> bool {
+    let mask = netmask_bits(a_prefix_len.min(b_prefix_len));
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+pub fn netmask_bits(prefix_len: u8) -> u32 {
+    match prefix_len {
+        0 => 0,
+        len if len >= 32 => u32::MAX,
+        len => u32::MAX << (32 - len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    fn config() -> ApNetworkConfig {
+        ApNetworkConfig::from_gateway(ip("192.168.4.1"), 24)
+    }
+
+    #[test]
+    fn default_pool() {
+        let config = config();
+        assert_eq!(
+            config.pool,
+            Ipv4Range::parse("192.168.4.50-192.168.4.200").unwrap()
+        );
+        assert_eq!(config.netmask(), ip("255.255.255.0"));
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = ApNetworkConfig::from_gateway(ip("10.0.0.1"), 16);
+        assert_eq!(
+            config.pool,
+            Ipv4Range::parse("10.0.0.2-10.0.255.254").unwrap()
+        );
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let mut bad = config();
+        bad.prefix_len = 31;
+        assert_eq!(bad.validate(), Err(ApNetworkError::InvalidPrefix));
+
+        let mut bad = config();
+        bad.gateway = ip("192.168.4.0");
+        assert_eq!(bad.validate(), Err(ApNetworkError::GatewayNotHost));
+
+        let mut bad = config();
+        bad.pool = Ipv4Range::new(ip("192.168.4.200"), ip("192.168.4.50"));
+        assert_eq!(bad.validate(), Err(ApNetworkError::PoolReversed));
+
+        let mut bad = config();
+        bad.pool = Ipv4Range::new(ip("192.168.4.50"), ip("192.168.4.255"));
+        assert_eq!(bad.validate(), Err(ApNetworkError::PoolOutsideSubnet));
+
+        let mut bad = config();
+        bad.pool = Ipv4Range::new(ip("192.168.4.1"), ip("192.168.4.10"));
+        assert_eq!(bad.validate(), Err(ApNetworkError::PoolContainsGateway));
+
+        let mut bad = config();
+        bad.excluded
+            .push(Ipv4Range::parse("192.168.4.210-192.168.4.220").unwrap())
+            .unwrap();
+        assert_eq!(bad.validate(), Err(ApNetworkError::ExcludedOutsidePool));
+
+        let mut bad = config();
+        bad.excluded
+            .push(Ipv4Range::parse("192.168.4.40-192.168.4.210").unwrap())
+            .unwrap();
+        assert_eq!(bad.validate(), Err(ApNetworkError::PoolFullyExcluded));
+    }
+
+    #[test]
+    fn skips_excluded_addresses() {
+        let mut config = config();
+        config
+            .excluded
+            .push(Ipv4Range::parse("192.168.4.50-192.168.4.59").unwrap())
+            .unwrap();
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            config.next_assignable(ip("192.168.4.1")),
+            Some(ip("192.168.4.60"))
+        );
+        assert_eq!(config.next_assignable(ip("192.168.4.201")), None);
+        assert!(!config.is_assignable(ip("192.168.4.55")));
+        assert!(config.is_assignable(ip("192.168.4.60")));
+    }
+
+    #[test]
+    fn rejects_overlap_with_upstream() {
+        let config = config();
+        assert_eq!(
+            config.validate_against(Some((ip("192.168.1.20"), 24))),
+            Ok(())
+        );
+        assert_eq!(
+            config.validate_against(Some((ip("192.168.4.20"), 24))),
+            Err(ApNetworkError::OverlapsUpstream)
+        );
+        // A wider upstream subnet swallows the AP's
+        assert_eq!(
+            config.validate_against(Some((ip("192.168.0.20"), 16))),
+            Err(ApNetworkError::OverlapsUpstream)
+        );
+    }
+
+    #[test]
+    fn stores_the_config() {
+        let mut config = ApNetworkConfig::from_gateway(ip("10.42.0.1"), 20);
+        config.pool = Ipv4Range::parse("10.42.1.0-10.42.2.255").unwrap();
+        config
+            .excluded
+            .push(Ipv4Range::parse("10.42.1.10-10.42.1.19").unwrap())
+            .unwrap();
+        config
+            .excluded
+            .push(Ipv4Range::parse("10.42.2.1").unwrap())
+            .unwrap();
+        let mut text = std::string::String::new();
+        config.write_to(&mut text).unwrap();
+        assert_eq!(
+            text,
+            "gateway=10.42.0.1/20\npool=10.42.1.0-10.42.2.255\n\
+             exclude=10.42.1.10-10.42.1.19\nexclude=10.42.2.1-10.42.2.1\n"
+        );
+        assert_eq!(ApNetworkConfig::parse(&text), Some(config));
+
+        assert_eq!(ApNetworkConfig::parse("pool=10.42.1.0-10.42.2.255"), None);
+        assert_eq!(ApNetworkConfig::parse("gateway=10.42.0.1"), None);
+    }
+
+    #[test]
+    fn moves_away_from_upstream() {
+        let mut current = config();
+        current
+            .excluded
+            .push(Ipv4Range::parse("192.168.4.60-192.168.4.69").unwrap())
+            .unwrap();
+        assert_eq!(
+            avoid_upstream_conflict(&current, ip("192.168.1.20"), 24),
+            None
+        );
+
+        let moved = avoid_upstream_conflict(&current, ip("192.168.4.20"), 24).unwrap();
+        assert_eq!(moved.gateway, ip("192.168.42.1"));
+        assert_eq!(
+            moved.pool,
+            Ipv4Range::parse("192.168.42.50-192.168.42.200").unwrap()
+        );
+        assert_eq!(
+            moved.excluded[0],
+            Ipv4Range::parse("192.168.42.60-192.168.42.69").unwrap()
+        );
+
+        // Nothing in 192.168/16 will do
+        let moved = avoid_upstream_conflict(&current, ip("192.168.0.20"), 16).unwrap();
+        assert_eq!(moved.gateway, ip("10.42.0.1"));
+        assert_eq!(
+            moved.validate_against(Some((ip("192.168.0.20"), 16))),
+            Ok(())
+        );
+    }
+}
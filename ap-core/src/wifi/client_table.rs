@@ -0,0 +1,120 @@
+use heapless::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Client {
+    pub mac: [u8; 6],
+    pub aid: u8,
+    pub connected_at: u64,
+    pub rssi: Option<i8>,
+}
+
+/// Stations currently associated with the access point, keyed by MAC.
+pub struct ClientTable<const N: usize> {
+    clients: Vec<Client, N>,
+}
+
+impl<const N: usize> ClientTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            clients: Vec::new(),
+        }
+    }
+
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    pub fn get(&self, mac: &[u8; 6]) -> Option<&Client> {
+        self.clients.iter().find(|client| client.mac == *mac)
+    }
+
+    pub fn connected(&mut self, mac: [u8; 6], aid: u8, now: u64) {
+        // A station that reassociates keeps its slot but gets a new AID
+        if let Some(client) = self.clients.iter_mut().find(|client| client.mac == mac) {
+            client.aid = aid;
+            client.connected_at = now;
+            return;
+        }
+        if self.clients.is_full() {
+            // We missed a disconnect; the oldest entry is the most likely stale one
+            if let Some(index) = self
+                .clients
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, client)| client.connected_at)
+                .map(|(index, _)| index)
+            {
+                self.clients.swap_remove(index);
+            }
+        }
+        _ = self.clients.push(Client {
+            mac,
+            aid,
+            connected_at: now,
+            rssi: None,
+        });
+    }
+
+    pub fn disconnected(&mut self, mac: &[u8; 6]) -> Option<Client> {
+        let index = self.clients.iter().position(|client| client.mac == *mac)?;
+        Some(self.clients.swap_remove(index))
+    }
+
+    pub fn update_rssi(&mut self, mac: &[u8; 6], rssi: i8) {
+        if let Some(client) = self.clients.iter_mut().find(|client| client.mac == *mac) {
+            client.rssi = Some(rssi);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const B: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const C: [u8; 6] = [2, 0, 0, 0, 0, 3];
+
+    #[test]
+    fn tracks_connections() {
+        let mut table = ClientTable::<4>::new();
+        table.connected(A, 1, 10);
+        table.connected(B, 2, 20);
+        table.update_rssi(&B, -55);
+        assert_eq!(table.clients().len(), 2);
+        assert_eq!(table.get(&B).unwrap().rssi, Some(-55));
+        assert_eq!(table.get(&A).unwrap().rssi, None);
+
+        let gone = table.disconnected(&A).unwrap();
+        assert_eq!((gone.aid, gone.connected_at), (1, 10));
+        assert!(table.get(&A).is_none());
+        assert!(table.disconnected(&A).is_none());
+        // Unknown stations are ignored
+        table.update_rssi(&A, -40);
+        assert!(table.get(&A).is_none());
+    }
+
+    #[test]
+    fn reassociation_keeps_the_slot() {
+        let mut table = ClientTable::<4>::new();
+        table.connected(A, 1, 10);
+        table.update_rssi(&A, -60);
+        table.connected(A, 3, 30);
+        assert_eq!(table.clients().len(), 1);
+        let client = table.get(&A).unwrap();
+        assert_eq!(
+            (client.aid, client.connected_at, client.rssi),
+            (3, 30, Some(-60))
+        );
+    }
+
+    #[test]
+    fn full_table_drops_the_oldest() {
+        let mut table = ClientTable::<2>::new();
+        table.connected(A, 1, 10);
+        table.connected(B, 2, 5);
+        table.connected(C, 3, 20);
+        assert!(table.get(&B).is_none());
+        assert!(table.get(&A).is_some() && table.get(&C).is_some());
+    }
+}
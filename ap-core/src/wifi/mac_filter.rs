@@ -0,0 +1,327 @@
+use core::fmt::{self, Write};
+use heapless::Vec;
+
+use crate::mac::{self, MacDisplay};
+
+pub const MAX_ENTRIES: usize = 16;
+pub const MAX_BANS: usize = 8;
+
+/// A full MAC address, or an OUI (`aa:bb:cc:*`) matching every device from
+/// one vendor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacPattern {
+    Exact([u8; 6]),
+    Oui([u8; 3]),
+}
+
+impl MacPattern {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if let Some(oui) = s.strip_suffix(":*").or_else(|| s.strip_suffix("-*")) {
+            let mut bytes = [0u8; 3];
+            let mut parts = oui.split([':', '-']);
+            for byte in bytes.iter_mut() {
+                let part = parts.next()?;
+                if part.len() != 2 {
+                    return None;
+                }
+                *byte = u8::from_str_radix(part, 16).ok()?;
+            }
+            return parts.next().is_none().then_some(Self::Oui(bytes));
+        }
+        mac::parse(s).map(Self::Exact)
+    }
+
+    pub fn matches(&self, mac: &[u8; 6]) -> bool {
+        match self {
+            Self::Exact(exact) => exact == mac,
+            Self::Oui(oui) => mac[..3] == oui[..],
+        }
+    }
+}
+
+impl fmt::Display for MacPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(mac) => write!(f, "{}", MacDisplay(mac)),
+            Self::Oui([a, b, c]) => write!(f, "{a:02x}:{b:02x}:{c:02x}:*"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Only temporary bans are enforced.
+    #[default]
+    Open,
+    /// Only stations on the allow list may join.
+    Allowlist,
+    /// Everyone except stations on the deny list may join.
+    Denylist,
+}
+
+impl FilterMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Allowlist => "allowlist",
+            Self::Denylist => "denylist",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "open" => Some(Self::Open),
+            "allowlist" => Some(Self::Allowlist),
+            "denylist" => Some(Self::Denylist),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    NotAllowed,
+    Denied,
+    Banned,
+}
+
+impl Verdict {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Self::Allowed)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allowed => "allowed",
+            Self::NotAllowed => "not on the allow list",
+            Self::Denied => "on the deny list",
+            Self::Banned => "temporarily banned",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ban {
+    pub mac: [u8; 6],
+    pub until: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MacFilter {
+    pub mode: FilterMode,
+    pub allow: Vec<MacPattern, MAX_ENTRIES>,
+    pub deny: Vec<MacPattern, MAX_ENTRIES>,
+    // Bans come from kicks and only live until the next reboot
+    bans: Vec<Ban, MAX_BANS>,
+}
+
+impl MacFilter {
+    pub const fn new() -> Self {
+        Self {
+            mode: FilterMode::Open,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            bans: Vec::new(),
+        }
+    }
+
+    pub fn evaluate(&self, mac: &[u8; 6], now: u64) -> Verdict {
+        if self
+            .bans
+            .iter()
+            .any(|ban| ban.mac == *mac && ban.until > now)
+        {
+            return Verdict::Banned;
+        }
+        match self.mode {
+            FilterMode::Open => Verdict::Allowed,
+            FilterMode::Allowlist if self.allow.iter().any(|p| p.matches(mac)) => Verdict::Allowed,
+            FilterMode::Allowlist => Verdict::NotAllowed,
+            FilterMode::Denylist if self.deny.iter().any(|p| p.matches(mac)) => Verdict::Denied,
+            FilterMode::Denylist => Verdict::Allowed,
+        }
+    }
+
+    pub fn bans(&self) -> &[Ban] {
+        &self.bans
+    }
+
+    /// Bans `mac` until `until`, replacing the ban that runs out first when
+    /// the table is full.
+    pub fn ban(&mut self, mac: [u8; 6], until: u64) {
+        if let Some(ban) = self.bans.iter_mut().find(|ban| ban.mac == mac) {
+            ban.until = ban.until.max(until);
+            return;
+        }
+        if self.bans.is_full() {
+            if let Some(index) = self
+                .bans
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, ban)| ban.until)
+                .map(|(index, _)| index)
+            {
+                self.bans.swap_remove(index);
+            }
+        }
+        _ = self.bans.push(Ban { mac, until });
+    }
+
+    pub fn unban(&mut self, mac: &[u8; 6]) -> bool {
+        let before = self.bans.len();
+        self.bans.retain(|ban| ban.mac != *mac);
+        self.bans.len() != before
+    }
+
+    pub fn expire_bans(&mut self, now: u64) {
+        self.bans.retain(|ban| ban.until > now);
+    }
+
+    /// Persistent part of the filter as `key=value` lines. Bans are left out.
+    pub fn write_to(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "mode={}", self.mode.as_str())?;
+        for pattern in &self.allow {
+            writeln!(out, "allow={pattern}")?;
+        }
+        for pattern in &self.deny {
+            writeln!(out, "deny={pattern}")?;
+        }
+        Ok(())
+    }
+
+    /// Reverse of [`MacFilter::write_to`]. Unknown keys are skipped so older
+    /// firmware can read settings written by newer firmware.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut filter = Self::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "mode" => filter.mode = FilterMode::parse(value)?,
+                "allow" => filter.allow.push(MacPattern::parse(value)?).ok()?,
+                "deny" => filter.deny.push(MacPattern::parse(value)?).ok()?,
+                _ => {}
+            }
+        }
+        Some(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHONE: [u8; 6] = [0xaa, 0xbb, 0xcc, 0x01, 0x02, 0x03];
+    const LAPTOP: [u8; 6] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(
+            MacPattern::parse("AA:BB:CC:*"),
+            Some(MacPattern::Oui([0xaa, 0xbb, 0xcc]))
+        );
+        assert_eq!(
+            MacPattern::parse("aa-bb-cc-*"),
+            Some(MacPattern::Oui([0xaa, 0xbb, 0xcc]))
+        );
+        assert_eq!(
+            MacPattern::parse("11:22:33:44:55:66"),
+            Some(MacPattern::Exact(LAPTOP))
+        );
+        assert_eq!(MacPattern::parse("aa:bb:*"), None);
+        assert_eq!(MacPattern::parse("aa:bb:cc:dd:*"), None);
+        assert_eq!(MacPattern::parse("a:bb:cc:*"), None);
+    }
+
+    #[test]
+    fn oui_matches_the_vendor() {
+        let oui = MacPattern::parse("aa:bb:cc:*").unwrap();
+        assert!(oui.matches(&PHONE));
+        assert!(!oui.matches(&LAPTOP));
+    }
+
+    #[test]
+    fn evaluates_lists() {
+        let mut filter = MacFilter::new();
+        filter
+            .allow
+            .push(MacPattern::Oui([0xaa, 0xbb, 0xcc]))
+            .unwrap();
+        filter.deny.push(MacPattern::Exact(PHONE)).unwrap();
+        assert_eq!(filter.evaluate(&LAPTOP, 0), Verdict::Allowed);
+
+        filter.mode = FilterMode::Allowlist;
+        assert_eq!(filter.evaluate(&PHONE, 0), Verdict::Allowed);
+        assert_eq!(filter.evaluate(&LAPTOP, 0), Verdict::NotAllowed);
+
+        filter.mode = FilterMode::Denylist;
+        assert_eq!(filter.evaluate(&PHONE, 0), Verdict::Denied);
+        assert_eq!(filter.evaluate(&LAPTOP, 0), Verdict::Allowed);
+    }
+
+    #[test]
+    fn bans_run_out() {
+        let mut filter = MacFilter::new();
+        filter.mode = FilterMode::Allowlist;
+        filter.allow.push(MacPattern::Exact(LAPTOP)).unwrap();
+        filter.ban(LAPTOP, 100);
+        // Bans win over the allow list
+        assert_eq!(filter.evaluate(&LAPTOP, 99), Verdict::Banned);
+        assert_eq!(filter.evaluate(&LAPTOP, 100), Verdict::Allowed);
+
+        // A shorter ban doesn't cut a longer one short
+        filter.ban(LAPTOP, 50);
+        assert_eq!(
+            filter.bans(),
+            [Ban {
+                mac: LAPTOP,
+                until: 100
+            }]
+        );
+        filter.expire_bans(100);
+        assert!(filter.bans().is_empty());
+
+        filter.ban(PHONE, 100);
+        assert!(filter.unban(&PHONE));
+        assert!(!filter.unban(&PHONE));
+    }
+
+    #[test]
+    fn full_ban_table_replaces_the_shortest() {
+        let mut filter = MacFilter::new();
+        for i in 0..MAX_BANS as u8 {
+            filter.ban([2, 0, 0, 0, 0, i], 100 + i as u64);
+        }
+        filter.ban(LAPTOP, 50);
+        assert_eq!(filter.bans().len(), MAX_BANS);
+        assert_eq!(filter.evaluate(&[2, 0, 0, 0, 0, 0], 0), Verdict::Allowed);
+        assert_eq!(filter.evaluate(&LAPTOP, 0), Verdict::Banned);
+    }
+
+    #[test]
+    fn round_trips_without_bans() {
+        let mut filter = MacFilter::new();
+        filter.mode = FilterMode::Denylist;
+        filter.allow.push(MacPattern::Exact(LAPTOP)).unwrap();
+        filter
+            .deny
+            .push(MacPattern::Oui([0xaa, 0xbb, 0xcc]))
+            .unwrap();
+        filter.ban(PHONE, 100);
+
+        let mut text = std::string::String::new();
+        filter.write_to(&mut text).unwrap();
+        assert_eq!(
+            text,
+            "mode=denylist\nallow=11:22:33:44:55:66\ndeny=aa:bb:cc:*\n"
+        );
+        let parsed = MacFilter::parse(&text).unwrap();
+        assert_eq!(
+            (parsed.mode, &parsed.allow, &parsed.deny),
+            (filter.mode, &filter.allow, &filter.deny)
+        );
+        assert!(parsed.bans().is_empty());
+        assert!(MacFilter::parse("mode=closed").is_none());
+    }
+}
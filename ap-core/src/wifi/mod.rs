@@ -0,0 +1,4 @@
+pub mod admin_auth;
+pub mod ap_network;
+pub mod client_table;
+pub mod mac_filter;
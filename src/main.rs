@@ -1,13 +1,20 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 use esp_alloc as _;
 use esp_backtrace as _;
 use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
 
+mod random;
+mod router;
+mod secrets;
+mod storage;
 mod wifi;
+use ap_core::{dhcp, hotspot, mac, radio, radius, sealed, uplink};
 use wifi::wifi_controller;
 
 #[esp_hal_embassy::main]
@@ -21,6 +28,7 @@ async fn main(spawner: Spawner) -> ! {
     esp_hal_embassy::init(timg0.timer0);
 
     let rng = Rng::new(peripherals.RNG);
+    random::init(rng.clone());
 
     spawner
         .spawn(wifi_controller::init_wifi(
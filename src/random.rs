@@ -0,0 +1,26 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use esp_hal::rng::Rng;
+
+static RNG: Mutex<CriticalSectionRawMutex, RefCell<Option<Rng>>> = Mutex::new(RefCell::new(None));
+
+/// Keeps a handle to the hardware RNG for everything that needs
+/// unpredictable values once the radio is up.
+pub fn init(rng: Rng) {
+    RNG.lock(|current| current.replace(Some(rng)));
+}
+
+pub fn fill(buf: &mut [u8]) {
+    RNG.lock(|rng| {
+        rng.borrow_mut()
+            .as_mut()
+            .expect("random::init was not called")
+            .read(buf)
+    });
+}
+
+pub fn u32() -> u32 {
+    let mut buf = [0u8; 4];
+    fill(&mut buf);
+    u32::from_le_bytes(buf)
+}
@@ -0,0 +1,604 @@
+use core::cell::{Cell, RefCell};
+use core::net::Ipv4Addr;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, Icmpv4Message, Icmpv4Packet, IpAddress, IpProtocol, Ipv4Packet, TcpPacket,
+    UdpPacket,
+};
+
+use super::bridge::Verdict;
+use super::firewall::Packet;
+use super::nat::{self, NatTable, Protocol, RedirectTable};
+use super::policer::Direction;
+use super::tap::{self, Side};
+use crate::dhcp::packet::{CLIENT_PORT, SERVER_PORT};
+use crate::hotspot::policy::Access;
+use crate::wifi::access_point::ap_network;
+use crate::wifi::bridge;
+use crate::wifi::dhcp_relay;
+use crate::wifi::firewall;
+use crate::wifi::hotspot;
+use crate::wifi::http_server::HTTP_PORT;
+use crate::wifi::ipv6;
+use crate::wifi::port_forwards;
+use crate::wifi::traffic;
+use crate::wifi::uplink;
+
+const NAT_ENTRIES: usize = 64;
+const DNS_PORT: u16 = 53;
+const PLAIN_HTTP_PORT: u16 = 80;
+
+/// What to do with a frame that came in on one of the interfaces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Hand it to the interface's own stack.
+    Local,
+    /// Already queued on the other interface.
+    Forwarded,
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Uplink {
+    pub address: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub gateway_mac: Option<[u8; 6]>,
+    arp_sent_at: Option<u64>,
+}
+
+struct Flow {
+    protocol: Protocol,
+    src_port: u16,
+    dst_port: u16,
+}
+
+static MACS: Mutex<CriticalSectionRawMutex, Cell<[[u8; 6]; 2]>> =
+    Mutex::new(Cell::new([[0; 6]; 2]));
+static UPLINK: Mutex<CriticalSectionRawMutex, Cell<Option<Uplink>>> = Mutex::new(Cell::new(None));
+static NAT: Mutex<CriticalSectionRawMutex, RefCell<NatTable<NAT_ENTRIES>>> =
+    Mutex::new(RefCell::new(NatTable::new()));
+static REDIRECTS: Mutex<CriticalSectionRawMutex, RefCell<RedirectTable<16>>> =
+    Mutex::new(RefCell::new(RedirectTable::new()));
+
+pub fn set_mac(side: Side, mac: [u8; 6]) {
+    MACS.lock(|macs| {
+        let mut all = macs.get();
+        all[side as usize] = mac;
+        macs.set(all);
+    });
+}
+
+pub fn mac(side: Side) -> [u8; 6] {
+    MACS.lock(|macs| macs.get()[side as usize])
+}
+
+pub fn uplink() -> Option<Uplink> {
+    UPLINK.lock(|uplink| uplink.get())
+}
+
+/// Points forwarded traffic at the STA side's address and default gateway,
+/// or stops forwarding when `None`.
+pub fn set_uplink(uplink: Option<(Ipv4Addr, Ipv4Addr)>) {
+    UPLINK.lock(|current| {
+        current.set(uplink.map(|(address, gateway)| Uplink {
+            address,
+            gateway,
+            gateway_mac: None,
+            arp_sent_at: None,
+        }))
+    });
+}
+
+/// Drops the translations of a client, so its open connections stop working
+/// right away instead of idling out.
+pub fn forget_client(mac: &[u8; 6]) {
+    NAT.lock(|nat| nat.borrow_mut().remove_client(mac));
+}
+
+pub fn received(side: Side, frame: &mut [u8]) -> Action {
+    if side == Side::Sta {
+        watch_upstream(frame);
+    }
+    if is_own_neighbor_solicit(side, frame) {
+        return Action::Drop;
+    }
+    if bridge::bridge_mode() {
+        return bridged(side, frame);
+    }
+    match side {
+        Side::Ap => from_ap(frame),
+        Side::Sta => from_sta(frame),
+    }
+}
+
+/// Picks out what the station's own addressing needs, in either mode: DHCP
+/// replies for our client and Router Advertisements. IPv6 isn't routed.
+fn watch_upstream(frame: &[u8]) {
+    let Ok(eth) = EthernetFrame::new_checked(frame) else {
+        return;
+    };
+    match eth.ethertype() {
+        EthernetProtocol::Ipv4 => {
+            if let Some(message) = dhcp_client_message(eth.payload()) {
+                uplink::dhcp_reply(eth.src_addr().0, message);
+            }
+        }
+        EthernetProtocol::Ipv6 => ipv6::from_upstream(eth.payload()),
+        _ => {}
+    }
+}
+
+/// Neighbor Solicitations for our link-local addresses are answered here,
+/// the stacks don't know them.
+fn is_own_neighbor_solicit(side: Side, frame: &[u8]) -> bool {
+    let Ok(eth) = EthernetFrame::new_checked(frame) else {
+        return false;
+    };
+    eth.ethertype() == EthernetProtocol::Ipv6
+        && ipv6::answer_neighbor_solicit(side, eth.src_addr().0, eth.payload())
+}
+
+fn dhcp_client_message(payload: &[u8]) -> Option<&[u8]> {
+    let ip = Ipv4Packet::new_checked(payload).ok()?;
+    if ip.more_frags() || ip.frag_offset() != 0 || ip.next_header() != IpProtocol::Udp {
+        return None;
+    }
+    let udp = UdpPacket::new_checked(ip.payload()).ok()?;
+    (udp.src_port() == SERVER_PORT && udp.dst_port() == CLIENT_PORT).then(|| udp.payload())
+}
+
+/// Bridge mode skips routing altogether, along with the hotspot, firewall
+/// and bandwidth limits that hang off it.
+fn bridged(side: Side, frame: &mut [u8]) -> Action {
+    let sta_mac = mac(Side::Sta);
+    let (verdict, other) = match side {
+        Side::Ap => (bridge::from_ap(frame, sta_mac), Side::Sta),
+        Side::Sta => (bridge::from_sta(frame, sta_mac), Side::Ap),
+    };
+    match verdict {
+        Verdict::Local => Action::Local,
+        Verdict::Forward => {
+            tap::send(other, frame);
+            Action::Forwarded
+        }
+        Verdict::Both => {
+            tap::send(other, frame);
+            Action::Local
+        }
+        Verdict::Reply => {
+            tap::send(side, frame);
+            Action::Forwarded
+        }
+        Verdict::Drop => Action::Drop,
+    }
+}
+
+/// Called on every frame the AP stack sends, to undo the portal redirect on
+/// the reply path.
+pub fn transmitting(side: Side, frame: &mut [u8]) {
+    if side != Side::Ap || REDIRECTS.lock(|redirects| redirects.borrow().is_empty()) {
+        return;
+    }
+    let Ok(mut eth) = EthernetFrame::new_checked(frame) else {
+        return;
+    };
+    if eth.ethertype() != EthernetProtocol::Ipv4 {
+        return;
+    }
+    let Ok(mut ip) = Ipv4Packet::new_checked(eth.payload_mut()) else {
+        return;
+    };
+    let Some(flow) = flow(&ip, Icmpv4Message::EchoReply) else {
+        return;
+    };
+    if flow.protocol != Protocol::Tcp || flow.src_port != HTTP_PORT {
+        return;
+    }
+    let target =
+        REDIRECTS.lock(|redirects| redirects.borrow().target(ip.dst_addr(), flow.dst_port));
+    if let Some(target) = target {
+        rewrite(&mut ip, Some((target, PLAIN_HTTP_PORT)), None);
+    }
+}
+
+fn from_ap(frame: &mut [u8]) -> Action {
+    let Ok(mut eth) = EthernetFrame::new_checked(frame) else {
+        return Action::Local;
+    };
+    match eth.ethertype() {
+        EthernetProtocol::Ipv4 => {}
+        EthernetProtocol::Ipv6 => {
+            ipv6::from_client(eth.payload());
+            return Action::Local;
+        }
+        _ => return Action::Local,
+    }
+    let client_mac = eth.src_addr().0;
+    let network = ap_network();
+    let now = Instant::now().as_secs();
+
+    let (gateway_mac, len) = {
+        let Ok(mut ip) = Ipv4Packet::new_checked(eth.payload_mut()) else {
+            return Action::Local;
+        };
+        let (src, dst) = (ip.src_addr(), ip.dst_addr());
+        if network.contains(dst) || dst.is_broadcast() || dst.is_multicast() {
+            let service =
+                flow(&ip, Icmpv4Message::EchoRequest).map(|flow| (flow.protocol, flow.dst_port));
+            if !hotspot::local_access(&client_mac, src, service) {
+                return Action::Drop;
+            }
+            return Action::Local;
+        }
+        if !network.contains(src) {
+            return Action::Drop;
+        }
+        let Some(flow) = flow(&ip, Icmpv4Message::EchoRequest) else {
+            return Action::Drop;
+        };
+
+        // Replies from a forwarded service go out on its external port, and
+        // aren't held back by the hotspot
+        let forwarded_port = port_forwards::outbound(flow.protocol, src, flow.src_port);
+        if forwarded_port.is_none() {
+            match hotspot::access(&client_mac, src, dst, flow.protocol, flow.dst_port) {
+                Access::Allow => {}
+                Access::Redirect => {
+                    REDIRECTS.lock(|redirects| {
+                        redirects.borrow_mut().insert(src, flow.src_port, dst, now)
+                    });
+                    rewrite(&mut ip, None, Some((network.gateway, HTTP_PORT)));
+                    return Action::Local;
+                }
+                Access::Deny => return Action::Drop,
+            }
+        }
+        let packet = Packet {
+            direction: Direction::Up,
+            protocol: flow.protocol,
+            client: src,
+            client_port: flow.src_port,
+            remote: dst,
+            remote_port: flow.dst_port,
+        };
+        if !firewall::check(&packet) {
+            return Action::Drop;
+        }
+
+        let Some(uplink) = uplink() else {
+            return Action::Drop;
+        };
+        let Some(gateway_mac) = uplink.gateway_mac else {
+            request_gateway_mac(uplink, now);
+            return Action::Drop;
+        };
+        if ip.hop_limit() <= 1 {
+            return Action::Drop;
+        }
+        if !traffic::admit(&client_mac, Direction::Up, ip.total_len() as usize) {
+            return Action::Drop;
+        }
+        let outside_port = forwarded_port.or_else(|| {
+            NAT.lock(|nat| {
+                nat.borrow_mut()
+                    .outbound(flow.protocol, client_mac, src, flow.src_port, now)
+            })
+        });
+        let Some(outside_port) = outside_port else {
+            return Action::Drop;
+        };
+
+        ip.set_hop_limit(ip.hop_limit() - 1);
+        rewrite(&mut ip, Some((uplink.address, outside_port)), None);
+        (gateway_mac, ip.total_len() as u64)
+    };
+
+    hotspot::account(&client_mac, len, 0);
+    eth.set_src_addr(EthernetAddress(mac(Side::Sta)));
+    eth.set_dst_addr(EthernetAddress(gateway_mac));
+    tap::send(Side::Sta, eth.into_inner());
+    Action::Forwarded
+}
+
+fn from_sta(frame: &mut [u8]) -> Action {
+    let Ok(mut eth) = EthernetFrame::new_checked(frame) else {
+        return Action::Local;
+    };
+    match eth.ethertype() {
+        EthernetProtocol::Arp => {
+            learn_gateway_mac(eth.payload());
+            return Action::Local;
+        }
+        EthernetProtocol::Ipv4 => {}
+        _ => return Action::Local,
+    }
+    let Some(uplink) = uplink() else {
+        return Action::Local;
+    };
+    let now = Instant::now().as_secs();
+
+    let (client_mac, len) = {
+        let Ok(mut ip) = Ipv4Packet::new_checked(eth.payload_mut()) else {
+            return Action::Local;
+        };
+        // Relayed DHCP replies are sent to the AP gateway, the relay listens
+        // for them on the STA stack
+        if ip.dst_addr() == ap_network().gateway && dhcp_relay::relay_mode() {
+            let is_dhcp = flow(&ip, Icmpv4Message::EchoReply)
+                .is_some_and(|flow| flow.protocol == Protocol::Udp && flow.dst_port == SERVER_PORT);
+            if is_dhcp {
+                rewrite(&mut ip, None, Some((uplink.address, SERVER_PORT)));
+            }
+            return Action::Local;
+        }
+        if ip.dst_addr() != uplink.address {
+            return Action::Local;
+        }
+        let Some(flow) = flow(&ip, Icmpv4Message::EchoReply) else {
+            let Some(client_mac) = related_icmp_error(&mut ip, uplink.address, now) else {
+                return Action::Local;
+            };
+            let len = ip.total_len() as u64;
+            return send_to_client(eth, client_mac, len);
+        };
+        let (inside_mac, inside_ip, inside_port) = if nat::PORT_RANGE.contains(&flow.dst_port) {
+            let mapping =
+                NAT.lock(|nat| nat.borrow_mut().inbound(flow.protocol, flow.dst_port, now));
+            // The STA stack's own sockets may be on any port too
+            let Some(mapping) = mapping else {
+                return Action::Local;
+            };
+            (mapping.inside_mac, mapping.inside_ip, mapping.inside_port)
+        } else if let Some(target) = port_forwards::inbound(flow.protocol, flow.dst_port) {
+            let Some(mac) = target.mac else {
+                return Action::Drop;
+            };
+            (mac, target.ip, target.port)
+        } else {
+            return Action::Local;
+        };
+        let packet = Packet {
+            direction: Direction::Down,
+            protocol: flow.protocol,
+            client: inside_ip,
+            client_port: inside_port,
+            remote: ip.src_addr(),
+            remote_port: flow.src_port,
+        };
+        if !firewall::check(&packet) {
+            return Action::Drop;
+        }
+        if ip.hop_limit() <= 1 {
+            return Action::Drop;
+        }
+        if !traffic::admit(&inside_mac, Direction::Down, ip.total_len() as usize) {
+            return Action::Drop;
+        }
+
+        if flow.protocol == Protocol::Udp && flow.src_port == DNS_PORT {
+            if let Ok(udp) = UdpPacket::new_checked(ip.payload()) {
+                hotspot::learn_dns(udp.payload());
+            }
+        }
+
+        ip.set_hop_limit(ip.hop_limit() - 1);
+        rewrite(&mut ip, None, Some((inside_ip, inside_port)));
+        (inside_mac, ip.total_len() as u64)
+    };
+
+    send_to_client(eth, client_mac, len)
+}
+
+fn send_to_client(mut eth: EthernetFrame<&mut [u8]>, client_mac: [u8; 6], len: u64) -> Action {
+    hotspot::account(&client_mac, 0, len);
+    eth.set_src_addr(EthernetAddress(mac(Side::Ap)));
+    eth.set_dst_addr(EthernetAddress(client_mac));
+    tap::send(Side::Ap, eth.into_inner());
+    Action::Forwarded
+}
+
+/// Translates an ICMP error (unreachable, TTL exceeded) about a TCP/UDP flow
+/// a client opened through NAT back to that client, if the firewall still
+/// tracks the flow. Returns the client's MAC.
+fn related_icmp_error(
+    ip: &mut Ipv4Packet<&mut [u8]>,
+    uplink_address: Ipv4Addr,
+    now: u64,
+) -> Option<[u8; 6]> {
+    if ip.more_frags() || ip.frag_offset() != 0 || ip.next_header() != IpProtocol::Icmp {
+        return None;
+    }
+    let icmp = Icmpv4Packet::new_checked(ip.payload()).ok()?;
+    if !matches!(
+        icmp.msg_type(),
+        Icmpv4Message::DstUnreachable | Icmpv4Message::TimeExceeded
+    ) {
+        return None;
+    }
+    // The quoted packet is cut short after 8 bytes of its payload, so it
+    // can't go through `Ipv4Packet::new_checked`
+    let quoted = icmp.data();
+    let header_len = (*quoted.first()? & 0x0f) as usize * 4;
+    let ports = quoted.get(header_len..header_len + 4)?;
+    let inner = Ipv4Packet::new_unchecked(quoted);
+    if header_len < 20 || inner.src_addr() != uplink_address {
+        return None;
+    }
+    let protocol = match inner.next_header() {
+        IpProtocol::Tcp => Protocol::Tcp,
+        IpProtocol::Udp => Protocol::Udp,
+        _ => return None,
+    };
+    let outside_port = u16::from_be_bytes([ports[0], ports[1]]);
+    let remote_port = u16::from_be_bytes([ports[2], ports[3]]);
+    let remote = inner.dst_addr();
+
+    let mapping = NAT.lock(|nat| nat.borrow_mut().inbound(protocol, outside_port, now))?;
+    let packet = Packet {
+        direction: Direction::Up,
+        protocol,
+        client: mapping.inside_ip,
+        client_port: mapping.inside_port,
+        remote,
+        remote_port,
+    };
+    if !firewall::is_related(&packet) || ip.hop_limit() <= 1 {
+        return None;
+    }
+
+    let mut icmp = Icmpv4Packet::new_unchecked(ip.payload_mut());
+    let quoted = icmp.data_mut();
+    quoted[header_len..header_len + 2].copy_from_slice(&mapping.inside_port.to_be_bytes());
+    let mut inner = Ipv4Packet::new_unchecked(quoted);
+    inner.set_src_addr(mapping.inside_ip);
+    inner.fill_checksum();
+    icmp.fill_checksum();
+    ip.set_hop_limit(ip.hop_limit() - 1);
+    ip.set_dst_addr(mapping.inside_ip);
+    ip.fill_checksum();
+    Some(mapping.inside_mac)
+}
+
+/// The ports of a TCP/UDP packet, or the identifier of an ICMP echo of the
+/// given type. Fragments and anything else we can't translate give `None`.
+fn flow(ip: &Ipv4Packet<&mut [u8]>, echo: Icmpv4Message) -> Option<Flow> {
+    if ip.more_frags() || ip.frag_offset() != 0 {
+        return None;
+    }
+    match ip.next_header() {
+        IpProtocol::Tcp => {
+            let tcp = TcpPacket::new_checked(ip.payload()).ok()?;
+            Some(Flow {
+                protocol: Protocol::Tcp,
+                src_port: tcp.src_port(),
+                dst_port: tcp.dst_port(),
+            })
+        }
+        IpProtocol::Udp => {
+            let udp = UdpPacket::new_checked(ip.payload()).ok()?;
+            Some(Flow {
+                protocol: Protocol::Udp,
+                src_port: udp.src_port(),
+                dst_port: udp.dst_port(),
+            })
+        }
+        IpProtocol::Icmp => {
+            let icmp = Icmpv4Packet::new_checked(ip.payload()).ok()?;
+            (icmp.msg_type() == echo).then(|| Flow {
+                protocol: Protocol::Icmp,
+                src_port: icmp.echo_ident(),
+                dst_port: icmp.echo_ident(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Replaces the source and/or destination address and port (the echo
+/// identifier for ICMP) and fixes up the checksums.
+fn rewrite(
+    ip: &mut Ipv4Packet<&mut [u8]>,
+    src: Option<(Ipv4Addr, u16)>,
+    dst: Option<(Ipv4Addr, u16)>,
+) {
+    if let Some((addr, _)) = src {
+        ip.set_src_addr(addr);
+    }
+    if let Some((addr, _)) = dst {
+        ip.set_dst_addr(addr);
+    }
+    let src_addr = IpAddress::Ipv4(ip.src_addr());
+    let dst_addr = IpAddress::Ipv4(ip.dst_addr());
+
+    match ip.next_header() {
+        IpProtocol::Tcp => {
+            let mut tcp = TcpPacket::new_unchecked(ip.payload_mut());
+            if let Some((_, port)) = src {
+                tcp.set_src_port(port);
+            }
+            if let Some((_, port)) = dst {
+                tcp.set_dst_port(port);
+            }
+            tcp.fill_checksum(&src_addr, &dst_addr);
+        }
+        IpProtocol::Udp => {
+            let mut udp = UdpPacket::new_unchecked(ip.payload_mut());
+            if let Some((_, port)) = src {
+                udp.set_src_port(port);
+            }
+            if let Some((_, port)) = dst {
+                udp.set_dst_port(port);
+            }
+            // A zero checksum means the sender didn't compute one
+            if udp.checksum() != 0 {
+                udp.fill_checksum(&src_addr, &dst_addr);
+            }
+        }
+        IpProtocol::Icmp => {
+            let mut icmp = Icmpv4Packet::new_unchecked(ip.payload_mut());
+            if let Some((_, ident)) = src.or(dst) {
+                icmp.set_echo_ident(ident);
+            }
+            icmp.fill_checksum();
+        }
+        _ => {}
+    }
+    ip.fill_checksum();
+}
+
+fn learn_gateway_mac(payload: &[u8]) {
+    let Ok(packet) = ArpPacket::new_checked(payload) else {
+        return;
+    };
+    let Ok(ArpRepr::EthernetIpv4 {
+        source_hardware_addr,
+        source_protocol_addr,
+        ..
+    }) = ArpRepr::parse(&packet)
+    else {
+        return;
+    };
+    UPLINK.lock(|uplink| {
+        if let Some(mut current) = uplink.get() {
+            if current.gateway == source_protocol_addr {
+                current.gateway_mac = Some(source_hardware_addr.0);
+                uplink.set(Some(current));
+            }
+        }
+    });
+}
+
+/// Asks for the upstream gateway's MAC, at most once a second. The reply is
+/// picked up by `learn_gateway_mac` on its way to the STA stack.
+fn request_gateway_mac(uplink: Uplink, now: u64) {
+    if uplink.arp_sent_at == Some(now) {
+        return;
+    }
+    UPLINK.lock(|current| {
+        current.set(Some(Uplink {
+            arp_sent_at: Some(now),
+            ..uplink
+        }))
+    });
+
+    let sta_mac = EthernetAddress(mac(Side::Sta));
+    let arp = ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Request,
+        source_hardware_addr: sta_mac,
+        source_protocol_addr: uplink.address,
+        target_hardware_addr: EthernetAddress([0; 6]),
+        target_protocol_addr: uplink.gateway,
+    };
+    let mut buf = [0u8; 42];
+    let mut eth = EthernetFrame::new_unchecked(&mut buf[..]);
+    EthernetRepr {
+        src_addr: sta_mac,
+        dst_addr: EthernetAddress::BROADCAST,
+        ethertype: EthernetProtocol::Arp,
+    }
+    .emit(&mut eth);
+    arp.emit(&mut ArpPacket::new_unchecked(eth.payload_mut()));
+    tap::send(Side::Sta, &buf);
+}
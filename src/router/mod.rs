@@ -0,0 +1,3 @@
+pub mod forward;
+pub mod tap;
+pub use ap_core::router::{bridge, dhcpv6, firewall, ipv6, nat, ndp, policer, port_forward};
@@ -0,0 +1,178 @@
+use core::task::Context;
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::waitqueue::AtomicWaker;
+use heapless::Vec;
+
+use super::forward::{self, Action};
+
+pub const MAX_FRAME_LEN: usize = 1514;
+const QUEUE_LEN: usize = 4;
+
+pub type Frame = Vec<u8, MAX_FRAME_LEN>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Ap,
+    Sta,
+}
+
+static TO_AP: Channel<CriticalSectionRawMutex, Frame, QUEUE_LEN> = Channel::new();
+static TO_STA: Channel<CriticalSectionRawMutex, Frame, QUEUE_LEN> = Channel::new();
+static AP_WAKER: AtomicWaker = AtomicWaker::new();
+static STA_WAKER: AtomicWaker = AtomicWaker::new();
+
+fn queue(side: Side) -> &'static Channel<CriticalSectionRawMutex, Frame, QUEUE_LEN> {
+    match side {
+        Side::Ap => &TO_AP,
+        Side::Sta => &TO_STA,
+    }
+}
+
+fn waker(side: Side) -> &'static AtomicWaker {
+    match side {
+        Side::Ap => &AP_WAKER,
+        Side::Sta => &STA_WAKER,
+    }
+}
+
+/// Queues a frame to be transmitted on `side`. Frames are dropped when the
+/// queue is full, like a congested router would.
+pub fn send(side: Side, frame: &[u8]) -> bool {
+    let Ok(frame) = Vec::from_slice(frame) else {
+        return false;
+    };
+    let sent = queue(side).try_send(frame).is_ok();
+    waker(side).wake();
+    sent
+}
+
+/// Sits between a Wi-Fi interface and its `embassy_net` stack. Frames that
+/// are routed to the other interface are taken out here, the rest go up to
+/// the stack as usual.
+pub struct Tap<D> {
+    inner: D,
+    side: Side,
+    rx_buf: [u8; MAX_FRAME_LEN],
+    pending: Option<Frame>,
+}
+
+impl<D: Driver> Tap<D> {
+    pub fn new(inner: D, side: Side) -> Self {
+        if let HardwareAddress::Ethernet(mac) = inner.hardware_address() {
+            forward::set_mac(side, mac);
+        }
+        Self {
+            inner,
+            side,
+            rx_buf: [0; MAX_FRAME_LEN],
+            pending: None,
+        }
+    }
+
+    fn flush(&mut self, cx: &mut Context) {
+        loop {
+            if self.pending.is_none() {
+                self.pending = queue(self.side).try_receive().ok();
+            }
+            let Some(frame) = &self.pending else {
+                return;
+            };
+            let Some(tx) = self.inner.transmit(cx) else {
+                return;
+            };
+            tx.consume(frame.len(), |buf| buf.copy_from_slice(frame));
+            self.pending = None;
+        }
+    }
+}
+
+impl<D: Driver> Driver for Tap<D> {
+    type RxToken<'a>
+        = TapRx<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TapTx<D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        waker(self.side).register(cx.waker());
+        self.flush(cx);
+
+        let side = self.side;
+        let rx_buf = &mut self.rx_buf;
+        let (rx, tx) = self.inner.receive(cx)?;
+        let local_len = rx.consume(|frame| {
+            if frame.len() > rx_buf.len() {
+                return None;
+            }
+            match forward::received(side, frame) {
+                Action::Local => {
+                    rx_buf[..frame.len()].copy_from_slice(frame);
+                    Some(frame.len())
+                }
+                Action::Forwarded | Action::Drop => None,
+            }
+        });
+
+        match local_len {
+            Some(len) => Some((TapRx(&mut rx_buf[..len]), TapTx { inner: tx, side })),
+            None => {
+                // Nothing for the stack this time, have the runner poll again
+                cx.waker().wake_by_ref();
+                None
+            }
+        }
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.flush(cx);
+        let side = self.side;
+        self.inner.transmit(cx).map(|inner| TapTx { inner, side })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+pub struct TapRx<'a>(&'a mut [u8]);
+
+impl RxToken for TapRx<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(self.0)
+    }
+}
+
+pub struct TapTx<T> {
+    inner: T,
+    side: Side,
+}
+
+impl<T: TxToken> TxToken for TapTx<T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let side = self.side;
+        self.inner.consume(len, |buf| {
+            let result = f(buf);
+            forward::transmitting(side, buf);
+            result
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use esp_hal::efuse::Efuse;
+
+use crate::random;
+use crate::sealed::{SealingKey, NONCE_LEN};
+use crate::storage::{self, Secret};
+
+const DEVICE_KEY_LEN: usize = 32;
+
+static KEY: Mutex<CriticalSectionRawMutex, RefCell<Option<SealingKey>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Sets up the key that passwords and private keys are sealed with before
+/// they go to flash. Call once the radio is up, the RNG needs it.
+///
+/// The key comes from `STORAGE_KEY` when the firmware was built with one,
+/// so a flash dump holds nothing that opens the secrets. That only holds
+/// with flash encryption turned on, since the key is in the firmware image.
+/// Without it a random key is made on first boot and kept in the
+/// `sta_secrets` partition, which keeps secrets out of the settings but
+/// not from anyone who can read all of the flash.
+pub fn init() {
+    let mac = Efuse::read_base_mac_address();
+    let key = match option_env!("STORAGE_KEY").filter(|secret| !secret.is_empty()) {
+        Some(secret) => SealingKey::derive(secret.as_bytes(), &mac),
+        None => {
+            log::warn!("No STORAGE_KEY was built in, secrets are sealed with a key kept in flash");
+            SealingKey::derive(&device_key(), &mac)
+        }
+    };
+    KEY.lock(|current| current.replace(Some(key)));
+}
+
+fn device_key() -> [u8; DEVICE_KEY_LEN] {
+    let mut key = [0u8; DEVICE_KEY_LEN];
+    if storage::load_secret(Secret::DeviceKey, &mut key).map(<[u8]>::len) == Some(DEVICE_KEY_LEN) {
+        return key;
+    }
+    random::fill(&mut key);
+    if let Err(e) = storage::save_secret(Secret::DeviceKey, &key) {
+        // Whatever gets sealed this run can't be opened after a reboot
+        log::warn!("Failed to store the device key: {}", e.as_str());
+    }
+    key
+}
+
+fn key() -> SealingKey {
+    KEY.lock(|key| key.borrow().clone())
+        .expect("secrets::init was not called")
+}
+
+/// Encrypts `plaintext` for flash. `aad` names the record it goes to, and
+/// has to be given again to open it.
+pub fn seal(aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    random::fill(&mut nonce);
+    key().seal(nonce, aad, plaintext)
+}
+
+pub fn open(aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    key().open(aad, sealed)
+}
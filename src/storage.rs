@@ -0,0 +1,156 @@
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+
+use crate::uplink::profile::MAX_PROFILES;
+
+// `nvs_app` in partitions.csv. We don't speak the NVS format, the partition is
+// just split into one 4 KiB sector per record.
+const PARTITION_OFFSET: u32 = 0x390000;
+const PARTITION_SIZE: u32 = 0x20000;
+// `sta_secrets` in partitions.csv, laid out the same way
+const SECRETS_OFFSET: u32 = 0x290000;
+const SECRETS_SIZE: u32 = 0x10000;
+const SECTOR_SIZE: u32 = 4096;
+const MAGIC: [u8; 4] = *b"APS1";
+const HEADER_LEN: usize = 8;
+
+pub const MAX_RECORD_LEN: usize = SECTOR_SIZE as usize - HEADER_LEN;
+
+/// Settings persisted across reboots. The discriminant is the sector index
+/// inside the partition, so existing values must never be renumbered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    MacFilter = 0,
+    Hotspot = 1,
+    Radius = 2,
+    Vouchers = 3,
+    RateLimits = 4,
+    PortForwards = 5,
+    Firewall = 6,
+    Bridge = 7,
+    DhcpRelay = 8,
+    Ipv6 = 9,
+    StaProfiles = 10,
+    StaLeases = 11,
+    StaSecurity = 12,
+    // 13 held the station certificates, which are in `sta_secrets` now
+    Radio = 14,
+    Admin = 15,
+    ApNetwork = 16,
+    DhcpOptions = 17,
+}
+
+impl Record {
+    fn offset(self) -> u32 {
+        let offset = self as u32 * SECTOR_SIZE;
+        debug_assert!(offset + SECTOR_SIZE <= PARTITION_SIZE);
+        PARTITION_OFFSET + offset
+    }
+}
+
+/// Station network certificates get a slot each.
+pub const CERTIFICATE_SLOTS: usize = MAX_PROFILES;
+
+/// Records kept away from the settings, in their own partition. Their
+/// contents are sealed, see [`crate::secrets`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Secret {
+    /// The random key secrets are sealed with when the firmware was built
+    /// without `STORAGE_KEY`.
+    DeviceKey,
+    /// One station network's 802.1X certificates and key, `0..CERTIFICATE_SLOTS`.
+    Certificates(u8),
+}
+
+impl Secret {
+    fn offset(self) -> u32 {
+        let sector = match self {
+            Self::DeviceKey => 0,
+            Self::Certificates(slot) => 1 + slot as u32,
+        };
+        let offset = sector * SECTOR_SIZE;
+        debug_assert!(offset + SECTOR_SIZE <= SECRETS_SIZE);
+        SECRETS_OFFSET + offset
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageError {
+    TooLarge,
+    Flash,
+}
+
+impl StorageError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TooLarge => "setting is too large to store",
+            Self::Flash => "failed to write flash",
+        }
+    }
+}
+
+/// Reads `record` into `buf`, returning the stored bytes. A sector that was
+/// never written, or that fails its checksum, reads as `None`.
+pub fn load(record: Record, buf: &mut [u8]) -> Option<&[u8]> {
+    read(record.offset(), buf)
+}
+
+pub fn save(record: Record, data: &[u8]) -> Result<(), StorageError> {
+    write(record.offset(), data)
+}
+
+pub fn load_secret(secret: Secret, buf: &mut [u8]) -> Option<&[u8]> {
+    read(secret.offset(), buf)
+}
+
+pub fn save_secret(secret: Secret, data: &[u8]) -> Result<(), StorageError> {
+    write(secret.offset(), data)
+}
+
+fn read(offset: u32, buf: &mut [u8]) -> Option<&[u8]> {
+    let mut flash = FlashStorage::new();
+    let mut header = [0u8; HEADER_LEN];
+    flash.read(offset, &mut header).ok()?;
+    if header[..4] != MAGIC {
+        return None;
+    }
+
+    let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+    let checksum = u16::from_le_bytes([header[6], header[7]]);
+    if len > MAX_RECORD_LEN || len > buf.len() {
+        return None;
+    }
+    flash
+        .read(offset + HEADER_LEN as u32, &mut buf[..len])
+        .ok()?;
+    (fletcher16(&buf[..len]) == checksum).then_some(&buf[..len])
+}
+
+fn write(offset: u32, data: &[u8]) -> Result<(), StorageError> {
+    if data.len() > MAX_RECORD_LEN {
+        return Err(StorageError::TooLarge);
+    }
+
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&(data.len() as u16).to_le_bytes());
+    header[6..].copy_from_slice(&fletcher16(data).to_le_bytes());
+
+    // FlashStorage erases and rewrites whole sectors as needed
+    let mut flash = FlashStorage::new();
+    flash
+        .write(offset + HEADER_LEN as u32, data)
+        .map_err(|_| StorageError::Flash)?;
+    flash
+        .write(offset, &header)
+        .map_err(|_| StorageError::Flash)
+}
+
+fn fletcher16(data: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for byte in data {
+        a = (a + *byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
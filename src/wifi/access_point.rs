@@ -1,17 +1,34 @@
+use core::cell::{Cell, RefCell};
 use core::{net::Ipv4Addr, str::FromStr};
-use edge_dhcp::{
-    io::{self, DEFAULT_SERVER_PORT},
-    server::{Server, ServerOptions},
-};
-use edge_nal::UdpBind;
+use edge_nal::{UdpBind, UdpReceive, UdpSend};
 use edge_nal_embassy::{Udp, UdpBuffers};
 use embassy_executor::Spawner;
-use embassy_net::{Runner, Stack, StackResources, StaticConfigV4};
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select3, Either3};
+use embassy_net::{ConfigV4, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
 use esp_println::println;
 use esp_wifi::wifi::{WifiApDevice, WifiDevice};
 
+use super::ap_network::{avoid_upstream_conflict, ApNetworkConfig, ApNetworkError};
+use super::clients;
+use super::dhcp_relay;
+use super::hotspot::expire_sessions;
 use super::http_server::run_http_server;
+use super::icmp_probe::IcmpProbe;
+use super::ipv6;
+use super::port_forwards;
+use super::radio;
+use crate::dhcp::conflict::AddressProbe;
+use crate::dhcp::options::{DhcpOptions, OptionsConfig, OptionsError, MAX_DNS_SERVERS};
+use crate::dhcp::packet::{MessageType, Packet, CLIENT_PORT, MAX_MESSAGE_LEN, SERVER_PORT};
+use crate::dhcp::server::{Outcome, Server};
+use crate::dhcp::stats::LeaseEvent;
+use crate::mac::MacDisplay;
+use crate::router::tap::{Side, Tap};
+use crate::storage::{self, Record, StorageError};
 
 macro_rules! mk_static {
     ($t:ty, $val:expr) => {{
@@ -23,89 +40,401 @@ macro_rules! mk_static {
 }
 
 const GW_IP_ADDR_ENV: Option<&'static str> = option_env!("GATEWAY_IP");
+const DHCP_LEASE_SECS: u32 = 2 * 60 * 60;
+const DHCP_MAX_LEASES: usize = 64;
+// Addresses probed per DISCOVER before giving up and letting the client retry
+const DHCP_MAX_PROBES: usize = 3;
+const DHCP_EXPIRY_CHECK: Duration = Duration::from_secs(30);
 
-#[embassy_executor::task]
-pub async fn run_ap(spawner: Spawner, wifi_interface: WifiDevice<'static, WifiApDevice>) {
+static AP_NETWORK: Mutex<CriticalSectionRawMutex, RefCell<Option<ApNetworkConfig>>> =
+    Mutex::new(RefCell::new(None));
+static AP_NETWORK_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static DHCP_OPTIONS: Mutex<CriticalSectionRawMutex, RefCell<OptionsConfig>> =
+    Mutex::new(RefCell::new(OptionsConfig::new()));
+static DHCP_SERVER: Mutex<CriticalSectionRawMutex, RefCell<Option<Server<DHCP_MAX_LEASES>>>> =
+    Mutex::new(RefCell::new(None));
+/// Lease granted/renewed/expired/released events from the AP's DHCP server.
+pub static LEASE_EVENTS: PubSubChannel<CriticalSectionRawMutex, LeaseEvent, 8, 4, 1> =
+    PubSubChannel::new();
+static UPSTREAM_SUBNET: Mutex<CriticalSectionRawMutex, Cell<Option<(Ipv4Addr, u8)>>> =
+    Mutex::new(Cell::new(None));
+static UPSTREAM_DNS: Mutex<
+    CriticalSectionRawMutex,
+    RefCell<heapless::Vec<Ipv4Addr, MAX_DNS_SERVERS>>,
+> = Mutex::new(RefCell::new(heapless::Vec::new()));
+
+pub fn ap_network() -> ApNetworkConfig {
+    AP_NETWORK.lock(|network| {
+        network
+            .borrow_mut()
+            .get_or_insert_with(default_ap_network)
+            .clone()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetApNetworkError {
+    Invalid(ApNetworkError),
+    Storage(StorageError),
+}
+
+impl SetApNetworkError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_ap_network() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::ApNetwork, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(ApNetworkConfig::parse)
+        .filter(|network| network.validate().is_ok())
+    {
+        Some(network) => {
+            AP_NETWORK.lock(|current| current.replace(Some(network)));
+            port_forwards::warn_stale();
+        }
+        None => log::warn!("Stored AP network is invalid, ignoring it"),
+    }
+}
+
+/// Only the configured network is stored. A move out of the way of the
+/// upstream subnet is worked out again after every connection.
+pub fn set_ap_network(network: ApNetworkConfig) -> Result<(), SetApNetworkError> {
+    network
+        .validate_against(upstream_subnet())
+        .map_err(SetApNetworkError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = network.write_to(&mut text);
+    storage::save(Record::ApNetwork, text.as_bytes()).map_err(SetApNetworkError::Storage)?;
+    AP_NETWORK.lock(|current| current.replace(Some(network)));
+    AP_NETWORK_CHANGED.signal(());
+    port_forwards::warn_stale();
+    Ok(())
+}
+
+pub fn dhcp_options() -> OptionsConfig {
+    DHCP_OPTIONS.lock(|options| options.borrow().clone())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetDhcpOptionsError {
+    Invalid(OptionsError),
+    Storage(StorageError),
+}
+
+impl SetDhcpOptionsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_dhcp_options() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::DhcpOptions, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(OptionsConfig::parse)
+        .filter(|options| options.validate().is_ok())
+    {
+        Some(options) => {
+            DHCP_OPTIONS.lock(|current| current.replace(options));
+        }
+        None => log::warn!("Stored DHCP options are invalid, ignoring them"),
+    }
+}
+
+pub fn set_dhcp_options(options: OptionsConfig) -> Result<(), SetDhcpOptionsError> {
+    options.validate().map_err(SetDhcpOptionsError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = options.write_to(&mut text);
+    storage::save(Record::DhcpOptions, text.as_bytes()).map_err(SetDhcpOptionsError::Storage)?;
+    DHCP_OPTIONS.lock(|current| current.replace(options));
+    Ok(())
+}
+
+/// Runs `f` on the DHCP server, or returns `None` if it hasn't started yet.
+pub fn with_dhcp_server<R>(f: impl FnOnce(&mut Server<DHCP_MAX_LEASES>) -> R) -> Option<R> {
+    DHCP_SERVER.lock(|server| server.borrow_mut().as_mut().map(f))
+}
+
+fn dhcp_options_for(mac: &[u8; 6], vendor_class: Option<&[u8]>) -> DhcpOptions {
+    DHCP_OPTIONS.lock(|options| options.borrow().for_client(mac, vendor_class))
+}
+
+pub fn upstream_subnet() -> Option<(Ipv4Addr, u8)> {
+    UPSTREAM_SUBNET.lock(|subnet| subnet.get())
+}
+
+/// Records the subnet the station got from upstream and moves the AP out of
+/// the way if both ended up on the same network.
+pub fn set_upstream_subnet(address: Ipv4Addr, prefix_len: u8) {
+    UPSTREAM_SUBNET.lock(|subnet| subnet.set(Some((address, prefix_len))));
+
+    let current = ap_network();
+    if let Some(relocated) = avoid_upstream_conflict(&current, address, prefix_len) {
+        println!(
+            "AP subnet {}/{} overlaps upstream {}/{}, moving AP to {}/{}",
+            current.network(),
+            current.prefix_len,
+            address,
+            prefix_len,
+            relocated.network(),
+            relocated.prefix_len
+        );
+        AP_NETWORK.lock(|network| network.replace(Some(relocated)));
+        AP_NETWORK_CHANGED.signal(());
+        port_forwards::warn_stale();
+    } else if current.conflicts_with(address, prefix_len) {
+        println!("AP subnet overlaps upstream {address}/{prefix_len} and no free subnet was found");
+    }
+}
+
+/// DNS servers learned from upstream, handed to AP clients that have no DNS
+/// option configured so they can resolve through the NAT.
+pub fn set_upstream_dns(servers: &[Ipv4Addr]) {
+    UPSTREAM_DNS.lock(|dns| {
+        let mut dns = dns.borrow_mut();
+        dns.clear();
+        dns.extend(servers.iter().copied().take(MAX_DNS_SERVERS));
+    });
+}
+
+fn default_ap_network() -> ApNetworkConfig {
     let gw_ip_addr_str = GW_IP_ADDR_ENV.unwrap_or("192.168.2.1");
     let gw_ip_addr = Ipv4Addr::from_str(gw_ip_addr_str).expect("failed to parse gateway ip");
+    ApNetworkConfig::from_gateway(gw_ip_addr, 24)
+}
 
-    let config = embassy_net::Config::ipv4_static(StaticConfigV4 {
-        address: embassy_net::Ipv4Cidr::new(gw_ip_addr, 24),
-        gateway: Some(gw_ip_addr),
+fn static_config(network: &ApNetworkConfig) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: embassy_net::Ipv4Cidr::new(network.gateway, network.prefix_len),
+        gateway: Some(network.gateway),
         dns_servers: Default::default(),
-    });
+    }
+}
+
+#[embassy_executor::task]
+pub async fn run_ap(spawner: Spawner, wifi_interface: WifiDevice<'static, WifiApDevice>) {
+    load_dhcp_options();
+
+    let network = ap_network();
+    let mut config = embassy_net::Config::ipv4_static(static_config(&network));
+    config.ipv6 = ipv6::ap_config_v6();
 
     let seed = 0x87654321_u64;
 
     let (stack, runner) = embassy_net::new(
-        wifi_interface,
+        Tap::new(wifi_interface, Side::Ap),
         config,
         mk_static!(StackResources<6>, StackResources::<6>::new()),
         seed,
     );
 
     spawner.spawn(net_task(runner)).ok();
-    spawner.spawn(run_dhcp(stack, gw_ip_addr_str)).ok();
+    spawner.spawn(run_dhcp(stack)).ok();
+    spawner.spawn(apply_network_changes(stack)).ok();
+    spawner.spawn(expire_sessions()).ok();
+    spawner.spawn(ipv6::run_router_adverts(stack)).ok();
 
-    loop {
-        if stack.is_link_up() {
-            break;
-        }
-        Timer::after(Duration::from_millis(500)).await;
-    }
+    // The AP may not be up, depending on the operating mode
+    radio::while_ap_up(|| serve_http(stack)).await
+}
+
+async fn serve_http(stack: Stack<'static>) {
     println!(
-        "Connect to the AP `esp-wifi` and point your browser to http://{gw_ip_addr_str}:8080/"
+        "Connect to the AP `esp-wifi` and point your browser to http://{}:8080/",
+        ap_network().gateway
     );
     println!("DHCP is enabled so there's no need to configure a static IP, just in case:");
-    while !stack.is_config_up() {
-        Timer::after(Duration::from_millis(100)).await;
-    }
+    stack.wait_config_up().await;
     stack
         .config_v4()
         .inspect(|c| println!("ipv4 config: {c:?}"));
 
-    match run_http_server(&stack).await {
+    match run_http_server(&stack, Side::Ap).await {
         Ok(_) => println!("HTTP server completed successfully"),
-        Err(_) => println!("HTTP server failed, please restart the device"),
+        Err(_) => println!("HTTP server failed, it is started again with the AP"),
     }
+}
 
+#[embassy_executor::task]
+async fn apply_network_changes(stack: Stack<'static>) {
     loop {
-        Timer::after(Duration::from_secs(10)).await;
+        AP_NETWORK_CHANGED.wait().await;
+        let network = ap_network();
+        stack.set_config_v4(ConfigV4::Static(static_config(&network)));
+        println!(
+            "AP network is now {}/{}, pool {}-{}",
+            network.gateway, network.prefix_len, network.pool.start, network.pool.end
+        );
     }
 }
 
 #[embassy_executor::task]
-async fn run_dhcp(stack: Stack<'static>, gw_ip_addr: &'static str) {
-    use core::net::{Ipv4Addr, SocketAddrV4};
+async fn run_dhcp(stack: Stack<'static>) {
+    // Leases outlive the AP being taken down and brought back
+    let server = Server::new(ap_network(), DHCP_LEASE_SECS, true);
+    DHCP_SERVER.lock(|current| current.replace(Some(server)));
+    radio::while_ap_up(|| serve_dhcp(stack)).await
+}
 
-    let ip = Ipv4Addr::from_str(gw_ip_addr).expect("dhcp task failed to parse gw ip");
+async fn serve_dhcp(stack: Stack<'static>) {
+    use core::net::{SocketAddr, SocketAddrV4};
 
-    let mut buf = [0u8; 600];
-    let mut gw_buf = [Ipv4Addr::UNSPECIFIED];
-    let buffers = UdpBuffers::<2, 512, 512, 5>::new();
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    let mut reply_buf = [0u8; MAX_MESSAGE_LEN];
+    let buffers = UdpBuffers::<2, 1024, 1024, 5>::new();
     let unbound_socket = Udp::new(stack, &buffers);
-    let mut bound_socket = unbound_socket
-        .bind(core::net::SocketAddr::V4(SocketAddrV4::new(
+    let mut bound_socket = match unbound_socket
+        .bind(SocketAddr::V4(SocketAddrV4::new(
             Ipv4Addr::UNSPECIFIED,
-            DEFAULT_SERVER_PORT,
+            SERVER_PORT,
         )))
         .await
-        .unwrap();
+    {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::warn!("DHCP server can't bind its socket: {e:?}");
+            return;
+        }
+    };
+    let mut probe = IcmpProbe::new(stack);
 
     loop {
-        _ = io::server::run(
-            &mut Server::<_, 64>::new_with_et(ip),
-            &ServerOptions::new(ip, Some(&mut gw_buf)),
-            &mut bound_socket,
-            &mut buf,
+        let received = select3(
+            bound_socket.receive(&mut buf),
+            Timer::after(DHCP_EXPIRY_CHECK),
+            dhcp_relay::next_reply(),
         )
-        .await
-        .inspect_err(|e| log::warn!("DHCP server error: {e:?}"));
-        Timer::after(Duration::from_millis(500)).await;
+        .await;
+        let len = match received {
+            Either3::First(Ok((len, _))) => len,
+            Either3::First(Err(e)) => {
+                log::warn!("DHCP server error: {e:?}");
+                Timer::after(Duration::from_millis(500)).await;
+                continue;
+            }
+            Either3::Second(()) => {
+                let now = Instant::now().as_secs();
+                with_dhcp_server(|server| server.expire(now));
+                publish_lease_events();
+                continue;
+            }
+            Either3::Third((to, reply)) => {
+                let to = SocketAddr::V4(SocketAddrV4::new(to, CLIENT_PORT));
+                _ = bound_socket
+                    .send(to, &reply)
+                    .await
+                    .inspect_err(|e| log::warn!("DHCP relay error: {e:?}"));
+                continue;
+            }
+        };
+
+        let request = match Packet::decode(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!("Ignoring malformed DHCP packet: {e:?}");
+                with_dhcp_server(|server| server.record_malformed());
+                continue;
+            }
+        };
+
+        let verdict = clients::admits(&request.mac());
+        if !verdict.is_allowed() {
+            println!(
+                "DHCP: refusing {}, {}",
+                MacDisplay(&request.mac()),
+                verdict.as_str()
+            );
+            continue;
+        }
+
+        if dhcp_relay::relay_mode() {
+            if request.message_type() == Some(MessageType::Release) {
+                with_dhcp_server(|server| server.release(request.mac()));
+                publish_lease_events();
+            }
+            dhcp_relay::relay_request(&buf[..len]);
+            continue;
+        }
+
+        let network = ap_network();
+        with_dhcp_server(|server| {
+            if &network != server.network() {
+                server.reconfigure(network);
+            }
+        });
+
+        let mut options = dhcp_options_for(&request.mac(), request.vendor_class());
+        if options.dns.is_empty() {
+            options.dns = UPSTREAM_DNS.lock(|dns| dns.borrow().clone());
+        }
+        let mut probes = 0;
+        let outcome = loop {
+            let now = Instant::now().as_secs();
+            let outcome =
+                with_dhcp_server(|server| server.handle(now, &request, &options, &mut reply_buf))
+                    .unwrap_or(Outcome::Ignore);
+
+            match outcome {
+                Outcome::Probe(ip) if probes < DHCP_MAX_PROBES => {
+                    probes += 1;
+                    let in_use = probe.in_use(ip).await;
+                    if in_use {
+                        println!("DHCP: {ip} is already in use on the AP network, not offering it");
+                    }
+                    let now = Instant::now().as_secs();
+                    with_dhcp_server(|server| server.record_probe(ip, in_use, now));
+                }
+                outcome => break outcome,
+            }
+        };
+        publish_lease_events();
+
+        if let Outcome::PoolExhausted = outcome {
+            log::warn!(
+                "DHCP pool exhausted, no address left for {}",
+                MacDisplay(&request.mac())
+            );
+        }
+        if let Outcome::Reply(reply) = outcome {
+            let to = SocketAddr::V4(SocketAddrV4::new(reply.to, reply.port));
+            _ = bound_socket
+                .send(to, &reply_buf[..reply.len])
+                .await
+                .inspect_err(|e| log::warn!("DHCP server error: {e:?}"));
+        }
+    }
+}
+
+pub fn publish_lease_events() {
+    let publisher = LEASE_EVENTS.immediate_publisher();
+    while let Some(event) = with_dhcp_server(|server| server.take_event()).flatten() {
+        println!(
+            "DHCP lease {}: {} -> {} ({})",
+            event.kind.as_str(),
+            MacDisplay(&event.mac),
+            event.ip,
+            event.hostname
+        );
+        publisher.publish_immediate(event);
     }
 }
 
 #[embassy_executor::task]
-async fn net_task(mut runner: Runner<'static, WifiDevice<'static, WifiApDevice>>) {
+async fn net_task(mut runner: Runner<'static, Tap<WifiDevice<'static, WifiApDevice>>>) {
     runner.run().await
 }
@@ -0,0 +1,94 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use super::admin_auth::{AdminConfig, AdminError, PasswordHash, SALT_LEN};
+use crate::random;
+use crate::storage::{self, Record, StorageError};
+
+static CONFIG: Mutex<CriticalSectionRawMutex, Cell<Option<PasswordHash>>> =
+    Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetConfigError {
+    Invalid(AdminError),
+    Storage(StorageError),
+}
+
+impl SetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+/// Loads the stored admin password hash, falling back to the password the
+/// firmware was built with. Without either only the first `POST /api/admin`
+/// gets through, to set one.
+pub fn load_config() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let stored =
+        storage::load(Record::Admin, &mut buf).and_then(|stored| core::str::from_utf8(stored).ok());
+    let hash = stored.and_then(PasswordHash::parse).or_else(|| {
+        // Older firmware stored the password itself
+        let config = stored.and_then(AdminConfig::parse)?;
+        let hash = save_hash(&config.password);
+        if let Err(e) = hash {
+            log::warn!(
+                "Failed to replace the stored admin password: {}",
+                e.as_str()
+            );
+        }
+        hash.ok()
+    });
+    let hash = hash.or_else(|| {
+        let password: &str = option_env!("ADMIN_PASSWORD")?;
+        let config = AdminConfig {
+            password: password.try_into().ok()?,
+        };
+        config.validate().ok()?;
+        Some(PasswordHash::new(password, salt()))
+    });
+    if hash.is_none() {
+        log::warn!("No admin password is set, POST /api/admin to set one");
+    }
+    CONFIG.lock(|current| current.set(hash));
+}
+
+pub fn set_config(config: AdminConfig) -> Result<(), SetConfigError> {
+    config.validate().map_err(SetConfigError::Invalid)?;
+    let hash = save_hash(&config.password).map_err(SetConfigError::Storage)?;
+    CONFIG.lock(|current| current.set(Some(hash)));
+    Ok(())
+}
+
+/// Whether a password is set. Until then the admin pages are locked and
+/// anyone on the network may set the first one.
+pub fn is_configured() -> bool {
+    CONFIG.lock(|config| config.get().is_some())
+}
+
+/// Checks a request's `Authorization` header against the admin password.
+pub fn is_authorized(authorization: Option<&str>) -> bool {
+    let Some(authorization) = authorization else {
+        return false;
+    };
+    // Hashing takes a while, so not with the lock held
+    let hash = CONFIG.lock(|config| config.get());
+    hash.is_some_and(|hash| hash.authorizes(authorization))
+}
+
+fn save_hash(password: &str) -> Result<PasswordHash, StorageError> {
+    let hash = PasswordHash::new(password, salt());
+    let mut text = alloc::string::String::new();
+    _ = hash.write_to(&mut text);
+    storage::save(Record::Admin, text.as_bytes())?;
+    Ok(hash)
+}
+
+fn salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    random::fill(&mut salt);
+    salt
+}
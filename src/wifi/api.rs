@@ -0,0 +1,2207 @@
+use alloc::string::String;
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use edge_http::Method;
+use embassy_time::Instant;
+
+use super::access_point::{self, SetApNetworkError, SetDhcpOptionsError};
+use super::admin::{self, SetConfigError};
+use super::admin_auth::{AdminConfig, ADMIN_USER};
+use super::ap_network::{ApNetworkConfig, ApNetworkError, Ipv4Range};
+use super::bridge;
+use super::clients;
+use super::dhcp_relay;
+use super::enterprise::{self, SetCertificateError};
+use super::firewall;
+use super::form;
+use super::hotspot;
+use super::ipv6;
+use super::mac_filter::{FilterMode, MacPattern};
+use super::port_forwards;
+use super::radio;
+use super::radius_client;
+use super::traffic;
+use super::uplink;
+use crate::dhcp::client::ClientOptions;
+use crate::dhcp::conflict::DeclineReason;
+use crate::dhcp::options::{
+    ClientMatch, ClientOverride, CustomOption, DhcpOptions, OptionsConfig, StaticRoute,
+};
+use crate::dhcp::relay::RelayConfig;
+use crate::hotspot::policy::{HotspotPolicy, LocalUser};
+use crate::hotspot::voucher::{Voucher, VoucherTerms};
+use crate::mac::{self, MacDisplay};
+use crate::radio::channel::ChannelPolicy;
+use crate::radio::config::RadioConfig;
+use crate::radio::mode::{OperatingMode, DEFAULT_FALLBACK_AFTER_SECS, DEFAULT_FALLBACK_GRACE_SECS};
+use crate::radio::regulatory::{Country, PowerSave};
+use crate::radio::survey::AutoChannel;
+use crate::radius::config::{AuthMethod, RadiusConfig, Server};
+use crate::router::bridge::DISABLED_FEATURES;
+use crate::router::firewall::{block_private_preset, parse_policy, FirewallConfig, Rule};
+use crate::router::ipv6::{Ipv6Config, Prefix};
+use crate::router::nat::Protocol;
+use crate::router::policer::{Limit, RateLimits};
+use crate::router::port_forward::PortForward;
+use crate::storage::StorageError;
+use crate::uplink::enterprise::{CertificateKind, EapMethod, Enterprise, Phase2};
+use crate::uplink::profile::{parse_cidr, Addressing, NetworkProfile, StaticAddress};
+use crate::uplink::security::Security;
+
+pub struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn error(status: u16, reason: &'static str, message: &str) -> Self {
+        let mut body = String::new();
+        body.push_str("{\"error\":");
+        json_str(&mut body, message);
+        body.push('}');
+        Self {
+            status,
+            reason,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::error(404, "Not Found", "no such endpoint")
+    }
+}
+
+pub fn handle(method: Method, path: &str, body: &str) -> Response {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    match (method, path) {
+        (Method::Get, "/api/ap/network") => get_ap_network(),
+        (Method::Post, "/api/ap/network") => post_ap_network(body),
+        (Method::Get, "/api/dhcp/options") => get_dhcp_options(),
+        (Method::Post, "/api/dhcp/options") => post_dhcp_options(body),
+        (Method::Post, "/api/dhcp/overrides") => post_dhcp_override(body),
+        (Method::Delete, "/api/dhcp/overrides") => delete_dhcp_override(query),
+        (Method::Get, "/api/dhcp/stats") => get_dhcp_stats(),
+        (Method::Get, "/api/dhcp/leases") => get_dhcp_leases(),
+        (Method::Get, "/api/dhcp/declined") => get_dhcp_declined(),
+        (Method::Get, "/api/dhcp/relay") => get_dhcp_relay(),
+        (Method::Post, "/api/dhcp/relay") => post_dhcp_relay(body),
+        (Method::Delete, "/api/dhcp/declined") => delete_dhcp_declined(query),
+        (Method::Get, "/api/station/status") => get_station_status(),
+        (Method::Get, "/api/station/dhcp") => get_station_dhcp(),
+        (Method::Post, "/api/station/dhcp") => post_station_dhcp(body),
+        (Method::Get, "/api/station/certificates") => get_station_certificates(query),
+        (Method::Delete, "/api/station/certificates") => delete_station_certificate(query),
+        (Method::Get, "/api/station/networks") => get_station_networks(),
+        (Method::Post, "/api/station/networks") => post_station_network(body),
+        (Method::Delete, "/api/station/networks") => delete_station_network(query),
+        (Method::Get, "/api/ipv6") => get_ipv6(),
+        (Method::Post, "/api/ipv6") => post_ipv6(body),
+        (Method::Get, "/api/bridge") => get_bridge(),
+        (Method::Post, "/api/bridge") => post_bridge(body),
+        (Method::Get, "/api/radio") => get_radio(),
+        (Method::Post, "/api/radio") => post_radio(body),
+        (Method::Get, "/api/clients") => get_clients(),
+        (Method::Post, "/api/clients/settings") => post_client_settings(body),
+        (Method::Get, "/api/clients/filter") => get_mac_filter(),
+        (Method::Post, "/api/clients/filter") => post_mac_filter(body),
+        (Method::Delete, "/api/clients/bans") => delete_ban(query),
+        (Method::Get, "/api/hotspot") => get_hotspot(),
+        (Method::Post, "/api/hotspot") => post_hotspot(body),
+        (Method::Get, "/api/hotspot/sessions") => get_hotspot_sessions(),
+        (Method::Delete, "/api/hotspot/sessions") => delete_hotspot_session(query),
+        (Method::Get, "/api/hotspot/vouchers") => get_vouchers(query),
+        (Method::Post, "/api/hotspot/vouchers") => post_vouchers(body),
+        (Method::Delete, "/api/hotspot/vouchers") => delete_vouchers(query),
+        (Method::Get, "/api/traffic") => get_traffic(),
+        (Method::Post, "/api/traffic/limits") => post_traffic_limits(body),
+        (Method::Post, "/api/traffic/overrides") => post_traffic_override(body),
+        (Method::Delete, "/api/traffic/overrides") => delete_traffic_override(query),
+        (Method::Delete, "/api/traffic/counters") => delete_traffic_counters(query),
+        (Method::Get, "/api/port-forwards") => get_port_forwards(),
+        (Method::Post, "/api/port-forwards") => post_port_forward(body),
+        (Method::Delete, "/api/port-forwards") => delete_port_forward(query),
+        (Method::Get, "/api/firewall") => get_firewall(),
+        (Method::Post, "/api/firewall") => post_firewall(body),
+        (Method::Post, "/api/firewall/rules") => post_firewall_rule(body),
+        (Method::Delete, "/api/firewall/rules") => delete_firewall_rule(query),
+        (Method::Delete, "/api/firewall/counters") => delete_firewall_counters(),
+        (Method::Get, "/api/hotspot/radius") => get_radius(),
+        (Method::Post, "/api/hotspot/radius") => post_radius(body),
+        (Method::Post, "/api/admin") => post_admin(body),
+        (Method::Post, path) if path.starts_with("/api/clients/") && path.ends_with("/kick") => {
+            post_kick(
+                &path["/api/clients/".len()..path.len() - "/kick".len()],
+                body,
+            )
+        }
+        _ => Response::not_found(),
+    }
+}
+
+fn get_ap_network() -> Response {
+    let network = access_point::ap_network();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"gateway":"{}","prefix_len":{},"netmask":"{}","pool_start":"{}","pool_end":"{}","excluded":["#,
+        network.gateway,
+        network.prefix_len,
+        network.netmask(),
+        network.pool.start,
+        network.pool.end,
+    );
+    for (i, range) in network.excluded.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{}-{}""#, range.start, range.end);
+    }
+    body.push_str("],\"upstream\":");
+    match access_point::upstream_subnet() {
+        Some((address, prefix_len)) => _ = write!(body, r#""{address}/{prefix_len}""#),
+        None => body.push_str("null"),
+    }
+    body.push('}');
+
+    Response::json(body)
+}
+
+fn post_ap_network(body: &str) -> Response {
+    let network = match parse_ap_network(&access_point::ap_network(), body) {
+        Ok(network) => network,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match access_point::set_ap_network(network) {
+        Ok(()) => get_ap_network(),
+        Err(SetApNetworkError::Invalid(e)) => {
+            Response::error(422, "Unprocessable Entity", e.as_str())
+        }
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn parse_ap_network(
+    current: &ApNetworkConfig,
+    body: &str,
+) -> Result<ApNetworkConfig, &'static str> {
+    let gateway = match form::field(body, "gateway") {
+        Some(value) => value.parse().map_err(|_| "invalid gateway")?,
+        None => current.gateway,
+    };
+    let prefix_len = match form::field(body, "prefix_len") {
+        Some(value) => value.parse().map_err(|_| "invalid prefix_len")?,
+        None => current.prefix_len,
+    };
+    if !(8..=30).contains(&prefix_len) {
+        return Err(ApNetworkError::InvalidPrefix.as_str());
+    }
+
+    // A new subnet starts from its default pool unless one is given as well
+    let mut network = if gateway != current.gateway || prefix_len != current.prefix_len {
+        ApNetworkConfig::from_gateway(gateway, prefix_len)
+    } else {
+        current.clone()
+    };
+
+    if let Some(value) = form::field(body, "pool_start") {
+        network.pool.start = value.parse().map_err(|_| "invalid pool_start")?;
+    }
+    if let Some(value) = form::field(body, "pool_end") {
+        network.pool.end = value.parse().map_err(|_| "invalid pool_end")?;
+    }
+    if form::field(body, "excluded").is_some() {
+        let excluded = form::decoded_field::<128>(body, "excluded").ok_or("invalid excluded")?;
+        network.excluded.clear();
+        for range in excluded.split(',').filter(|range| !range.trim().is_empty()) {
+            let range = Ipv4Range::parse(range).ok_or("invalid excluded range")?;
+            network
+                .excluded
+                .push(range)
+                .map_err(|_| ApNetworkError::TooManyExclusions.as_str())?;
+        }
+    }
+
+    Ok(network)
+}
+
+fn get_dhcp_options() -> Response {
+    let config = access_point::dhcp_options();
+
+    let mut body = String::new();
+    body.push_str("{\"global\":");
+    write_dhcp_options(&mut body, &config.global);
+    body.push_str(",\"overrides\":[");
+    for (i, client_override) in config.overrides.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        match &client_override.client {
+            ClientMatch::Mac(mac) => {
+                _ = write!(body, r#"{{"mac":"{}","options":"#, MacDisplay(mac))
+            }
+            ClientMatch::VendorClass(class) => {
+                body.push_str("{\"vendor_class\":");
+                json_str(&mut body, class);
+                body.push_str(",\"options\":");
+            }
+        }
+        write_dhcp_options(&mut body, &client_override.options);
+        body.push('}');
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_dhcp_options(body: &str) -> Response {
+    let mut config = access_point::dhcp_options();
+    config.global = match parse_dhcp_options(body, config.global) {
+        Ok(options) => options,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    apply_dhcp_options(config)
+}
+
+fn post_dhcp_override(body: &str) -> Response {
+    let client = match parse_client_match(body) {
+        Ok(client) => client,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    let mut config = access_point::dhcp_options();
+    let existing = config.overrides.iter().position(|o| o.client == client);
+    let base = existing
+        .map(|index| config.overrides[index].options.clone())
+        .unwrap_or_default();
+    let options = match parse_dhcp_options(body, base) {
+        Ok(options) => options,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match existing {
+        Some(index) => config.overrides[index].options = options,
+        None => {
+            if config
+                .overrides
+                .push(ClientOverride { client, options })
+                .is_err()
+            {
+                return Response::error(422, "Unprocessable Entity", "too many client overrides");
+            }
+        }
+    }
+
+    apply_dhcp_options(config)
+}
+
+fn delete_dhcp_override(query: &str) -> Response {
+    let client = match parse_client_match(query) {
+        Ok(client) => client,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    let mut config = access_point::dhcp_options();
+    let before = config.overrides.len();
+    config.overrides.retain(|o| o.client != client);
+    if config.overrides.len() == before {
+        return Response::error(404, "Not Found", "no such client override");
+    }
+
+    apply_dhcp_options(config)
+}
+
+fn apply_dhcp_options(config: OptionsConfig) -> Response {
+    match access_point::set_dhcp_options(config) {
+        Ok(()) => get_dhcp_options(),
+        Err(SetDhcpOptionsError::Invalid(e)) => {
+            Response::error(422, "Unprocessable Entity", e.as_str())
+        }
+        Err(SetDhcpOptionsError::Storage(StorageError::TooLarge)) => {
+            Response::error(413, "Payload Too Large", StorageError::TooLarge.as_str())
+        }
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn get_dhcp_stats() -> Response {
+    let Some(stats) = access_point::with_dhcp_server(|server| *server.stats()) else {
+        return Response::error(503, "Service Unavailable", "DHCP server is not running");
+    };
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        concat!(
+            r#"{{"discover":{},"offer":{},"request":{},"ack":{},"nak":{},"release":{},"#,
+            r#""decline":{},"inform":{},"malformed":{},"pool_exhausted":{}}}"#
+        ),
+        stats.discover,
+        stats.offer,
+        stats.request,
+        stats.ack,
+        stats.nak,
+        stats.release,
+        stats.decline,
+        stats.inform,
+        stats.malformed,
+        stats.pool_exhausted,
+    );
+
+    Response::json(body)
+}
+
+fn get_dhcp_leases() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::from("[");
+    access_point::with_dhcp_server(|server| {
+        let bound = server.leases().iter().filter(|lease| lease.bound);
+        for (i, lease) in bound.enumerate() {
+            if i > 0 {
+                body.push(',');
+            }
+            _ = write!(
+                body,
+                r#"{{"mac":"{}","ip":"{}","expires_in":{},"hostname":"#,
+                MacDisplay(&lease.mac),
+                lease.ip,
+                lease.expires.saturating_sub(now)
+            );
+            json_str(&mut body, &lease.hostname);
+            body.push('}');
+        }
+    });
+    body.push(']');
+
+    Response::json(body)
+}
+
+fn get_dhcp_declined() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::from("[");
+    access_point::with_dhcp_server(|server| {
+        for (i, declined) in server.declined().iter().enumerate() {
+            if i > 0 {
+                body.push(',');
+            }
+            let reason = match declined.reason {
+                DeclineReason::Probe => "probe",
+                DeclineReason::Client => "client",
+            };
+            _ = write!(
+                body,
+                r#"{{"ip":"{}","reason":"{}","expires_in":{},"mac":"#,
+                declined.ip,
+                reason,
+                declined.until.saturating_sub(now)
+            );
+            match &declined.mac {
+                Some(mac) => _ = write!(body, r#""{}"}}"#, MacDisplay(mac)),
+                None => body.push_str("null}"),
+            }
+        }
+    });
+    body.push(']');
+
+    Response::json(body)
+}
+
+fn delete_dhcp_declined(query: &str) -> Response {
+    let Some(ip) = form::field(query, "ip").and_then(|ip| ip.parse().ok()) else {
+        return Response::error(400, "Bad Request", "invalid ip");
+    };
+
+    match access_point::with_dhcp_server(|server| server.forget_declined(ip)) {
+        Some(true) => get_dhcp_declined(),
+        _ => Response::error(404, "Not Found", "address is not declined"),
+    }
+}
+
+fn get_bridge() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"enabled":{},"disabled_features":["#,
+        bridge::bridge_mode()
+    );
+    for (i, feature) in DISABLED_FEATURES.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{feature}""#);
+    }
+    body.push_str(r#"],"hosts":["#);
+    for (i, host) in bridge::hosts().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"mac":"{}","ip":"{}","idle_secs":{}}}"#,
+            MacDisplay(&host.mac),
+            host.ip,
+            now.saturating_sub(host.last_seen)
+        );
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_bridge(body: &str) -> Response {
+    let Some(enabled) = form::field(body, "enabled").and_then(parse_bool) else {
+        return Response::error(400, "Bad Request", "invalid enabled");
+    };
+    match bridge::set_bridge_mode(enabled) {
+        Ok(()) => get_bridge(),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn post_admin(body: &str) -> Response {
+    let password = match decoded::<32>(body, "password") {
+        Ok(Some(password)) => password,
+        Ok(None) => return Response::error(400, "Bad Request", "missing password"),
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+    match admin::set_config(AdminConfig { password }) {
+        Ok(()) => {
+            let mut body = String::new();
+            _ = write!(body, r#"{{"user":"{ADMIN_USER}"}}"#);
+            Response::json(body)
+        }
+        Err(SetConfigError::Invalid(e)) => Response::error(422, "Unprocessable Entity", e.as_str()),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn get_radio() -> Response {
+    let now = Instant::now().as_secs();
+    let config = radio::config();
+
+    let mut body = String::new();
+    _ = write!(body, r#"{{"mode":"{}","#, config.mode.as_str());
+    if let OperatingMode::FallbackAp {
+        after_secs,
+        grace_secs,
+    } = config.mode
+    {
+        _ = write!(
+            body,
+            r#""fallback_after_secs":{after_secs},"fallback_grace_secs":{grace_secs},"#
+        );
+    }
+    body.push_str(r#""interfaces":"#);
+    match radio::interfaces() {
+        Some(interfaces) => {
+            _ = write!(
+                body,
+                r#"{{"ap":{},"sta":{},"ap_held":{}}}"#,
+                interfaces.ap,
+                interfaces.sta,
+                radio::ap_held()
+            );
+        }
+        None => body.push_str("null"),
+    }
+    body.push_str(r#","channel":"#);
+    write_channel(&mut body);
+    _ = write!(
+        body,
+        r#","channel_policy":"{}","pinned_channel":"#,
+        config.channel_policy.as_str()
+    );
+    match config.channel_policy.pinned() {
+        Some(channel) => _ = write!(body, "{channel}"),
+        None => body.push_str("null"),
+    }
+    _ = write!(
+        body,
+        r#","announce_switch":{},"auto_channel":"{}","recheck_mins":{},"survey":"#,
+        config.announce_switch,
+        config.auto_channel.as_str(),
+        config.recheck_mins
+    );
+    match radio::survey() {
+        Some(survey) => {
+            _ = write!(
+                body,
+                r#"{{"ago":{},"chosen":{},"scores":["#,
+                now.saturating_sub(survey.at),
+                survey.chosen
+            );
+            for (i, score) in survey.scores.iter().enumerate() {
+                if i > 0 {
+                    body.push(',');
+                }
+                _ = write!(body, "{score}");
+            }
+            body.push_str("]}");
+        }
+        None => body.push_str("null"),
+    }
+    _ = write!(
+        body,
+        r#","country":"{}","max_channel":{},"tx_power_limit":"#,
+        config.country.code, config.country.channels
+    );
+    match config.tx_power_limit {
+        Some(limit) => _ = write!(body, "{limit}"),
+        None => body.push_str("null"),
+    }
+    _ = write!(
+        body,
+        r#","power_save":"{}","applied":"#,
+        config.power_save.as_str()
+    );
+    match radio::applied() {
+        Some(applied) => {
+            _ = write!(
+                body,
+                r#"{{"country":"{}","tx_power":{},"power_save":"{}"}}"#,
+                applied.country,
+                applied.tx_power,
+                applied.power_save.as_str()
+            );
+        }
+        None => body.push_str("null"),
+    }
+    body.push_str(r#","countries":["#);
+    for (i, country) in Country::all().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{}""#, country.code);
+    }
+    body.push_str(r#"],"moves":["#);
+    for (i, channel_move) in radio::moves().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"from":{},"to":{},"ago":{},"announced":{}}}"#,
+            channel_move.from,
+            channel_move.to,
+            now.saturating_sub(channel_move.at),
+            channel_move.announced
+        );
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_radio(body: &str) -> Response {
+    let config = match parse_radio_config(body, radio::config()) {
+        Ok(config) => config,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match radio::set_config(config) {
+        Ok(()) => get_radio(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_radio_config(body: &str, mut config: RadioConfig) -> Result<RadioConfig, &'static str> {
+    let (mut after_secs, mut grace_secs) = match config.mode {
+        OperatingMode::FallbackAp {
+            after_secs,
+            grace_secs,
+        } => (after_secs, grace_secs),
+        _ => (DEFAULT_FALLBACK_AFTER_SECS, DEFAULT_FALLBACK_GRACE_SECS),
+    };
+    if let Some(value) = form::field(body, "fallback_after_secs") {
+        after_secs = value.parse().map_err(|_| "invalid fallback_after_secs")?;
+    }
+    if let Some(value) = form::field(body, "fallback_grace_secs") {
+        grace_secs = value.parse().map_err(|_| "invalid fallback_grace_secs")?;
+    }
+    if let Some(mode) = decoded::<8>(body, "mode")? {
+        config.mode = match mode.as_str() {
+            "ap" => OperatingMode::ApOnly,
+            "sta" => OperatingMode::StaOnly,
+            "ap+sta" => OperatingMode::ApSta,
+            "fallback" => OperatingMode::FallbackAp {
+                after_secs,
+                grace_secs,
+            },
+            _ => return Err("invalid mode"),
+        };
+    } else if let OperatingMode::FallbackAp { .. } = config.mode {
+        config.mode = OperatingMode::FallbackAp {
+            after_secs,
+            grace_secs,
+        };
+    }
+    let pinned_channel = match form::field(body, "pinned_channel") {
+        Some(value) => Some(value.parse().map_err(|_| "invalid pinned_channel")?),
+        None => config.channel_policy.pinned(),
+    };
+    if let Some(value) = form::field(body, "channel_policy") {
+        config.channel_policy = match value {
+            "follow" => ChannelPolicy::FollowStation,
+            "pinned" => ChannelPolicy::Pinned(pinned_channel.ok_or("pinned_channel is required")?),
+            _ => return Err("invalid channel_policy"),
+        };
+    } else if let (ChannelPolicy::Pinned(_), Some(channel)) =
+        (config.channel_policy, pinned_channel)
+    {
+        config.channel_policy = ChannelPolicy::Pinned(channel);
+    }
+    if let Some(value) = form::field(body, "announce_switch") {
+        config.announce_switch = parse_bool(value).ok_or("invalid announce_switch")?;
+    }
+    if let Some(value) = form::field(body, "auto_channel") {
+        config.auto_channel = AutoChannel::parse(value).ok_or("invalid auto_channel")?;
+    }
+    if let Some(value) = form::field(body, "recheck_mins") {
+        config.recheck_mins = value.parse().map_err(|_| "invalid recheck_mins")?;
+    }
+    if let Some(value) = form::field(body, "country") {
+        config.country = Country::find(value).ok_or("unknown country")?;
+    }
+    if let Some(value) = form::field(body, "tx_power_limit") {
+        config.tx_power_limit = match value {
+            "" | "auto" => None,
+            value => Some(value.parse().map_err(|_| "invalid tx_power_limit")?),
+        };
+    }
+    if let Some(value) = form::field(body, "power_save") {
+        config.power_save = PowerSave::parse(value).ok_or("invalid power_save")?;
+    }
+    Ok(config)
+}
+
+/// The channel both interfaces are on, `null` if the driver won't say.
+fn write_channel(body: &mut String) {
+    match radio::channel() {
+        Some(channel) => _ = write!(body, "{channel}"),
+        None => body.push_str("null"),
+    }
+}
+
+fn get_station_status() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::from(r#"{"joined":"#);
+    match uplink::joined() {
+        Some(joined) => {
+            body.push_str(r#"{"ssid":"#);
+            json_str(&mut body, &joined.ssid);
+            _ = write!(body, r#","bssid":"{}"}}"#, MacDisplay(&joined.bssid));
+        }
+        None => body.push_str("null"),
+    }
+    body.push_str(r#","channel":"#);
+    write_channel(&mut body);
+    body.push_str(r#","last_connect":"#);
+    match uplink::last_connect() {
+        Some(stats) => {
+            _ = write!(
+                body,
+                r#"{{"method":"{}","millis":{},"attempts":{}}}"#,
+                stats.method.as_str(),
+                stats.millis,
+                stats.attempts
+            );
+        }
+        None => body.push_str("null"),
+    }
+    body.push_str(r#","lease":"#);
+    match uplink::lease() {
+        Some(lease) => {
+            _ = write!(
+                body,
+                r#"{{"address":"{}/{}","server":"{}","gateway":"#,
+                lease.address, lease.prefix_len, lease.server
+            );
+            match lease.gateway {
+                Some(gateway) => _ = write!(body, r#""{gateway}""#),
+                None => body.push_str("null"),
+            }
+            body.push_str(r#","dns":"#);
+            write_ip_list(&mut body, &lease.dns_servers);
+            _ = write!(body, r#","lease_time":{}"#, lease.lease_secs);
+            // Infinite leases never renew, rebind or expire
+            for (name, at) in [
+                ("renew_in", lease.renew_at()),
+                ("rebind_in", lease.rebind_at()),
+                ("expires_in", lease.expires_at()),
+            ] {
+                if at == u64::MAX {
+                    _ = write!(body, r#","{name}":null"#);
+                } else {
+                    _ = write!(body, r#","{name}":{}"#, at.saturating_sub(now));
+                }
+            }
+            body.push('}');
+        }
+        None => body.push_str("null"),
+    }
+    body.push('}');
+
+    Response::json(body)
+}
+
+fn get_station_dhcp() -> Response {
+    let mut body = String::from("{");
+    write_client_options(&mut body, &uplink::profiles().defaults);
+    body.push('}');
+
+    Response::json(body)
+}
+
+fn post_station_dhcp(body: &str) -> Response {
+    let defaults = match parse_client_options(body) {
+        Ok(defaults) => defaults,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match uplink::set_defaults(defaults) {
+        Ok(()) => get_station_dhcp(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn get_station_networks() -> Response {
+    let mut body = String::from(r#"{"joined":"#);
+    match uplink::joined() {
+        Some(joined) => json_str(&mut body, &joined.ssid),
+        None => body.push_str("null"),
+    }
+    body.push_str(r#","networks":["#);
+    for (i, profile) in uplink::profiles().profiles.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        body.push_str(r#"{"ssid":"#);
+        json_str(&mut body, &profile.ssid);
+        _ = write!(
+            body,
+            r#","hidden":{},"auth":"{}","#,
+            profile.hidden,
+            profile.auth.map_or("auto", |auth| auth.as_str())
+        );
+        if let Some(enterprise) = &profile.enterprise {
+            write_enterprise(&mut body, enterprise);
+        }
+        match &profile.addressing {
+            Addressing::Dhcp(options) => {
+                body.push_str(r#""mode":"dhcp","#);
+                write_client_options(&mut body, options);
+            }
+            Addressing::Static(config) => {
+                _ = write!(
+                    body,
+                    r#""mode":"static","address":"{}/{}","gateway":"#,
+                    config.address, config.prefix_len
+                );
+                match config.gateway {
+                    Some(gateway) => _ = write!(body, r#""{gateway}""#),
+                    None => body.push_str("null"),
+                }
+                body.push_str(r#","dns":["#);
+                for (i, ip) in config.dns_servers.iter().enumerate() {
+                    if i > 0 {
+                        body.push(',');
+                    }
+                    _ = write!(body, r#""{ip}""#);
+                }
+                body.push(']');
+            }
+        }
+        body.push('}');
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_station_network(body: &str) -> Response {
+    let profile = match parse_network_profile(body) {
+        Ok(profile) => profile,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match uplink::set_profile(profile) {
+        Ok(()) => get_station_networks(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_network_profile(body: &str) -> Result<NetworkProfile, &'static str> {
+    let ssid = decoded::<32>(body, "ssid")?.ok_or("ssid is required")?;
+    let addressing = match form::field(body, "mode").unwrap_or("dhcp") {
+        "dhcp" => Addressing::Dhcp(parse_client_options(body)?),
+        "static" => {
+            let (address, prefix_len) = decoded::<18>(body, "address")?
+                .ok_or("address is required")
+                .and_then(|value| parse_cidr(&value).ok_or("invalid address"))?;
+            let gateway = match decoded::<15>(body, "gateway")? {
+                Some(value) if !value.is_empty() => {
+                    Some(value.parse().map_err(|_| "invalid gateway")?)
+                }
+                _ => None,
+            };
+            let dns_servers = match decoded::<64>(body, "dns")? {
+                Some(value) => parse_list(&value, |ip| ip.parse().ok()).ok_or("invalid dns")?,
+                None => heapless::Vec::new(),
+            };
+            Addressing::Static(StaticAddress {
+                address,
+                prefix_len,
+                gateway,
+                dns_servers,
+            })
+        }
+        _ => return Err("mode must be dhcp or static"),
+    };
+    let hidden = match form::field(body, "hidden") {
+        Some(value) => parse_bool(value).ok_or("invalid hidden")?,
+        None => false,
+    };
+    let auth = match form::field(body, "auth").unwrap_or("auto") {
+        "auto" => None,
+        value => Some(Security::parse(value).ok_or("invalid auth")?),
+    };
+    let enterprise = parse_enterprise(body, &ssid)?;
+    Ok(NetworkProfile {
+        ssid,
+        hidden,
+        auth,
+        enterprise,
+        addressing,
+    })
+}
+
+fn write_enterprise(body: &mut String, enterprise: &Enterprise) {
+    _ = write!(
+        body,
+        r#""eap":{{"method":"{}","phase2":"#,
+        enterprise.method.as_str()
+    );
+    match enterprise.method {
+        EapMethod::Ttls(phase2) => _ = write!(body, r#""{}""#, phase2.as_str()),
+        _ => body.push_str("null"),
+    }
+    body.push_str(r#","identity":"#);
+    json_str(body, &enterprise.identity);
+    body.push_str(r#","anonymous_identity":"#);
+    write_optional_str(body, enterprise.anonymous_identity.as_deref());
+    // The password is write-only
+    _ = write!(
+        body,
+        r#","password_set":{},"validate_server":{}}},"#,
+        enterprise.password.is_some(),
+        enterprise.validate_server
+    );
+}
+
+/// A missing password keeps the one already saved for the network.
+fn parse_enterprise(body: &str, ssid: &str) -> Result<Option<Enterprise>, &'static str> {
+    let method = match form::field(body, "eap").unwrap_or("none") {
+        "none" => return Ok(None),
+        "peap" => EapMethod::Peap,
+        "ttls" => EapMethod::Ttls(match form::field(body, "phase2") {
+            Some(value) => Phase2::parse(value).ok_or("invalid phase2")?,
+            None => Phase2::Mschapv2,
+        }),
+        "tls" => EapMethod::Tls,
+        _ => return Err("eap must be none, peap, ttls or tls"),
+    };
+    let identity = decoded::<64>(body, "identity")?.ok_or("identity is required")?;
+    let anonymous_identity =
+        decoded::<64>(body, "anonymous_identity")?.filter(|identity| !identity.is_empty());
+    let password = match decoded::<64>(body, "eap_password")? {
+        Some(password) => Some(password).filter(|password| !password.is_empty()),
+        None => uplink::profiles()
+            .network(ssid)
+            .and_then(|profile| profile.enterprise.as_ref()?.password.clone()),
+    };
+    let validate_server = match form::field(body, "validate_server") {
+        Some(value) => parse_bool(value).ok_or("invalid validate_server")?,
+        None => true,
+    };
+    Ok(Some(Enterprise {
+        method,
+        identity,
+        anonymous_identity,
+        password,
+        validate_server,
+    }))
+}
+
+fn get_station_certificates(query: &str) -> Response {
+    match certificate_ssid(query) {
+        Ok(ssid) => certificates_json(&ssid),
+        Err(response) => response,
+    }
+}
+
+fn certificates_json(ssid: &str) -> Response {
+    let certificates = enterprise::certificates(ssid);
+    let mut body = String::from("{");
+    for (i, kind) in [
+        CertificateKind::Ca,
+        CertificateKind::ClientCert,
+        CertificateKind::ClientKey,
+    ]
+    .iter()
+    .enumerate()
+    {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{}":"#, kind.as_str());
+        match certificates.get(*kind) {
+            Some(der) => _ = write!(body, r#"{{"size":{}}}"#, der.len()),
+            None => body.push_str("null"),
+        }
+    }
+    body.push('}');
+
+    Response::json(body)
+}
+
+/// Every network has its own certificates, picked with `ssid=`.
+fn certificate_ssid(query: &str) -> Result<heapless::String<32>, Response> {
+    match decoded::<32>(query, "ssid") {
+        Ok(Some(ssid)) => Ok(ssid),
+        Ok(None) => Err(Response::error(400, "Bad Request", "ssid is required")),
+        Err(message) => Err(Response::error(400, "Bad Request", message)),
+    }
+}
+
+fn certificate_kind(query: &str) -> Result<CertificateKind, Response> {
+    form::field(query, "kind")
+        .and_then(CertificateKind::parse)
+        .ok_or_else(|| {
+            Response::error(
+                400,
+                "Bad Request",
+                "kind must be ca, client_cert or client_key",
+            )
+        })
+}
+
+fn set_certificate_error(e: SetCertificateError) -> Response {
+    match e {
+        SetCertificateError::UnknownNetwork => Response::error(404, "Not Found", e.as_str()),
+        SetCertificateError::Pem(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+        SetCertificateError::Storage(StorageError::TooLarge) => {
+            Response::error(413, "Payload Too Large", "certificate is too large")
+        }
+        SetCertificateError::Storage(e) => {
+            Response::error(500, "Internal Server Error", e.as_str())
+        }
+    }
+}
+
+/// `POST /api/station/certificates?ssid=corp&kind=ca` with the PEM file as
+/// the body. Certificates don't fit the usual body buffer, so the HTTP
+/// server reads these separately.
+pub fn post_station_certificate(query: &str, pem: &str) -> Response {
+    let (ssid, kind) = match (certificate_ssid(query), certificate_kind(query)) {
+        (Ok(ssid), Ok(kind)) => (ssid, kind),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+
+    match enterprise::set_certificate(&ssid, kind, Some(pem)) {
+        Ok(()) => certificates_json(&ssid),
+        Err(e) => set_certificate_error(e),
+    }
+}
+
+fn delete_station_certificate(query: &str) -> Response {
+    let (ssid, kind) = match (certificate_ssid(query), certificate_kind(query)) {
+        (Ok(ssid), Ok(kind)) => (ssid, kind),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+
+    match enterprise::set_certificate(&ssid, kind, None) {
+        Ok(()) => certificates_json(&ssid),
+        Err(e) => set_certificate_error(e),
+    }
+}
+
+fn write_client_options(body: &mut String, options: &ClientOptions) {
+    body.push_str(r#""hostname":"#);
+    write_optional_str(body, options.hostname.as_deref());
+    body.push_str(r#","client_id":"#);
+    write_optional_str(body, options.client_id.as_deref());
+    body.push_str(r#","vendor_class":"#);
+    write_optional_str(body, options.vendor_class.as_deref());
+}
+
+/// Empty or missing fields leave the option unset.
+fn parse_client_options(body: &str) -> Result<ClientOptions, &'static str> {
+    let field = |name| -> Result<_, &'static str> {
+        Ok(decoded::<32>(body, name)?.filter(|value| !value.is_empty()))
+    };
+    Ok(ClientOptions {
+        hostname: field("hostname")?,
+        client_id: field("client_id")?,
+        vendor_class: field("vendor_class")?,
+    })
+}
+
+fn delete_station_network(query: &str) -> Response {
+    let ssid = match decoded::<32>(query, "ssid") {
+        Ok(Some(ssid)) => ssid,
+        Ok(None) => return Response::error(400, "Bad Request", "ssid is required"),
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match uplink::remove_profile(&ssid) {
+        Ok(true) => get_station_networks(),
+        Ok(false) => Response::error(404, "Not Found", "no such network"),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn get_ipv6() -> Response {
+    let config = ipv6::ipv6_config();
+    let now = Instant::now().as_secs();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"enabled":{},"prefix":"{}","address":"{}","dns":["#,
+        config.enabled,
+        config.prefix,
+        config.address()
+    );
+    for (i, ip) in config.dns_servers.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{ip}""#);
+    }
+    body.push_str(r#"],"upstream":"#);
+    match ipv6::upstream() {
+        Some(upstream) => {
+            _ = write!(
+                body,
+                r#"{{"address":"{}/{}","router":"#,
+                upstream.address, upstream.prefix_len
+            );
+            match upstream.router {
+                Some(router) => _ = write!(body, r#""{router}""#),
+                None => body.push_str("null"),
+            }
+            body.push_str(r#","expires_in":"#);
+            match upstream.expires_at {
+                Some(at) => _ = write!(body, "{}", at.saturating_sub(now)),
+                None => body.push_str("null"),
+            }
+            _ = write!(
+                body,
+                r#","dhcpv6":{},"delegated_prefix":"#,
+                upstream.managed
+            );
+            match upstream.delegated {
+                Some(prefix) => _ = write!(body, r#""{prefix}"}}"#),
+                None => body.push_str("null}"),
+            }
+        }
+        None => body.push_str("null"),
+    }
+    body.push('}');
+
+    Response::json(body)
+}
+
+fn post_ipv6(body: &str) -> Response {
+    let config = match parse_ipv6_config(body, ipv6::ipv6_config()) {
+        Ok(config) => config,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match ipv6::set_ipv6_config(config) {
+        Ok(()) => get_ipv6(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_ipv6_config(body: &str, mut config: Ipv6Config) -> Result<Ipv6Config, &'static str> {
+    if let Some(value) = form::field(body, "enabled") {
+        config.enabled = parse_bool(value).ok_or("invalid enabled")?;
+    }
+    if let Some(value) = decoded::<48>(body, "prefix")? {
+        config.prefix = Prefix::parse(&value).ok_or("invalid prefix")?;
+    }
+    if let Some(value) = decoded::<128>(body, "dns")? {
+        config.dns_servers = parse_list(&value, |ip| ip.parse().ok()).ok_or("invalid dns")?;
+    }
+    Ok(config)
+}
+
+fn get_dhcp_relay() -> Response {
+    let config = dhcp_relay::config();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"enabled":{},"server":"{}","circuit_id":"#,
+        config.enabled, config.server
+    );
+    json_str(&mut body, &config.circuit_id);
+    body.push_str(",\"remote_id\":");
+    json_str(&mut body, &config.remote_id);
+    body.push('}');
+
+    Response::json(body)
+}
+
+fn post_dhcp_relay(body: &str) -> Response {
+    let config = match parse_relay_config(body, dhcp_relay::config()) {
+        Ok(config) => config,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match dhcp_relay::set_config(config) {
+        Ok(()) => get_dhcp_relay(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_relay_config(body: &str, mut config: RelayConfig) -> Result<RelayConfig, &'static str> {
+    if let Some(value) = form::field(body, "enabled") {
+        config.enabled = parse_bool(value).ok_or("invalid enabled")?;
+    }
+    if let Some(value) = decoded::<15>(body, "server")? {
+        config.server = value.parse().map_err(|_| "invalid server")?;
+    }
+    if let Some(value) = decoded::<32>(body, "circuit_id")? {
+        config.circuit_id = value;
+    }
+    if let Some(value) = decoded::<32>(body, "remote_id")? {
+        config.remote_id = value;
+    }
+    Ok(config)
+}
+
+fn get_clients() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"release_on_disconnect":{},"clients":["#,
+        clients::release_lease_on_disconnect()
+    );
+    for (i, client) in clients::clients().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"mac":"{}","aid":{},"connected_for":{},"rssi":"#,
+            MacDisplay(&client.mac),
+            client.aid,
+            now.saturating_sub(client.connected_at)
+        );
+        match client.rssi {
+            Some(rssi) => _ = write!(body, "{rssi}"),
+            None => body.push_str("null"),
+        }
+
+        let lease = access_point::with_dhcp_server(|server| {
+            server
+                .leases()
+                .iter()
+                .find(|lease| lease.bound && lease.mac == client.mac)
+                .cloned()
+        })
+        .flatten();
+        match lease {
+            Some(lease) => {
+                _ = write!(
+                    body,
+                    r#","ip":"{}","expires_in":{},"hostname":"#,
+                    lease.ip,
+                    lease.expires.saturating_sub(now)
+                );
+                json_str(&mut body, &lease.hostname);
+            }
+            None => body.push_str(r#","ip":null,"expires_in":null,"hostname":null"#),
+        }
+        body.push('}');
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_client_settings(body: &str) -> Response {
+    if let Some(value) = form::field(body, "release_on_disconnect") {
+        let Some(enabled) = parse_bool(value) else {
+            return Response::error(400, "Bad Request", "invalid release_on_disconnect");
+        };
+        clients::set_release_lease_on_disconnect(enabled);
+    }
+    get_clients()
+}
+
+fn get_mac_filter() -> Response {
+    let filter = clients::mac_filter();
+    let now = Instant::now().as_secs();
+
+    let mut body = String::new();
+    _ = write!(body, r#"{{"mode":"{}","allow":["#, filter.mode.as_str());
+    write_pattern_list(&mut body, &filter.allow);
+    body.push_str("],\"deny\":[");
+    write_pattern_list(&mut body, &filter.deny);
+    body.push_str("],\"bans\":[");
+    let bans = filter.bans().iter().filter(|ban| ban.until > now);
+    for (i, ban) in bans.enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"mac":"{}","expires_in":{}}}"#,
+            MacDisplay(&ban.mac),
+            ban.until - now
+        );
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_mac_filter(body: &str) -> Response {
+    let mut filter = clients::mac_filter();
+    if let Some(value) = form::field(body, "mode") {
+        let Some(mode) = FilterMode::parse(value) else {
+            return Response::error(400, "Bad Request", "invalid mode");
+        };
+        filter.mode = mode;
+    }
+    match decoded::<400>(body, "allow") {
+        Ok(Some(value)) => match parse_list(&value, MacPattern::parse) {
+            Some(allow) => filter.allow = allow,
+            None => return Response::error(400, "Bad Request", "invalid allow list"),
+        },
+        Ok(None) => {}
+        Err(message) => return Response::error(400, "Bad Request", message),
+    }
+    match decoded::<400>(body, "deny") {
+        Ok(Some(value)) => match parse_list(&value, MacPattern::parse) {
+            Some(deny) => filter.deny = deny,
+            None => return Response::error(400, "Bad Request", "invalid deny list"),
+        },
+        Ok(None) => {}
+        Err(message) => return Response::error(400, "Bad Request", message),
+    }
+
+    match clients::set_mac_filter(filter) {
+        Ok(()) => get_mac_filter(),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn post_kick(mac: &str, body: &str) -> Response {
+    let Some(mac) = form::decode::<17>(mac).and_then(|mac| mac::parse(&mac)) else {
+        return Response::error(400, "Bad Request", "invalid mac");
+    };
+    let ban_secs = match form::field(body, "ban_secs").map(str::parse::<u64>) {
+        Some(Ok(0)) | None => None,
+        Some(Ok(secs)) => Some(secs),
+        Some(Err(_)) => return Response::error(400, "Bad Request", "invalid ban_secs"),
+    };
+
+    if !clients::kick(&mac, ban_secs) && ban_secs.is_none() {
+        return Response::error(404, "Not Found", "client is not connected");
+    }
+    get_mac_filter()
+}
+
+fn delete_ban(query: &str) -> Response {
+    let Some(mac) = form::decoded_field::<17>(query, "mac").and_then(|mac| mac::parse(&mac)) else {
+        return Response::error(400, "Bad Request", "invalid mac");
+    };
+
+    if clients::unban(&mac) {
+        get_mac_filter()
+    } else {
+        Response::error(404, "Not Found", "client is not banned")
+    }
+}
+
+fn get_hotspot() -> Response {
+    let policy = hotspot::policy();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"enabled":{},"session_secs":{},"quota_bytes":"#,
+        policy.enabled, policy.session_secs
+    );
+    match policy.quota_bytes {
+        Some(quota) => _ = write!(body, "{quota}"),
+        None => body.push_str("null"),
+    }
+    body.push_str(",\"walled_garden\":[");
+    for (i, domain) in policy.walled_garden.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        json_str(&mut body, domain);
+    }
+    // Passwords are write-only
+    body.push_str("],\"users\":[");
+    for (i, user) in policy.users.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        json_str(&mut body, &user.name);
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn post_hotspot(body: &str) -> Response {
+    let policy = match parse_hotspot_policy(body, hotspot::policy()) {
+        Ok(policy) => policy,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match hotspot::set_policy(policy) {
+        Ok(()) => get_hotspot(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_hotspot_policy(
+    body: &str,
+    mut policy: HotspotPolicy,
+) -> Result<HotspotPolicy, &'static str> {
+    if let Some(value) = form::field(body, "enabled") {
+        policy.enabled = parse_bool(value).ok_or("invalid enabled")?;
+    }
+    if let Some(value) = form::field(body, "session_secs") {
+        policy.session_secs = value.parse().map_err(|_| "invalid session_secs")?;
+    }
+    if let Some(value) = form::field(body, "quota_bytes") {
+        policy.quota_bytes = match non_empty(value) {
+            Some(quota) => Some(quota.parse().map_err(|_| "invalid quota_bytes")?),
+            None => None,
+        };
+    }
+    if let Some(value) = decoded::<400>(body, "walled_garden")? {
+        policy.walled_garden =
+            parse_list(&value, |domain| domain.try_into().ok()).ok_or("invalid walled_garden")?;
+    }
+    if let Some(value) = decoded::<600>(body, "users")? {
+        policy.users = parse_list(&value, LocalUser::parse).ok_or("invalid users")?;
+    }
+    Ok(policy)
+}
+
+fn get_hotspot_sessions() -> Response {
+    let now = Instant::now().as_secs();
+
+    let mut body = String::from("[");
+    for (i, session) in hotspot::sessions().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"id":"{:08X}","mac":"{}","ip":"{}","username":"#,
+            session.id,
+            MacDisplay(&session.mac),
+            session.ip
+        );
+        json_str(&mut body, &session.username);
+        _ = write!(
+            body,
+            r#","online_for":{},"expires_in":{},"bytes_up":{},"bytes_down":{},"quota_bytes":"#,
+            now.saturating_sub(session.started),
+            session.expires.saturating_sub(now),
+            session.bytes_up,
+            session.bytes_down
+        );
+        match session.quota_bytes {
+            Some(quota) => _ = write!(body, "{quota}}}"),
+            None => body.push_str("null}"),
+        }
+    }
+    body.push(']');
+
+    Response::json(body)
+}
+
+fn delete_hotspot_session(query: &str) -> Response {
+    let Some(mac) = form::decoded_field::<17>(query, "mac").and_then(|mac| mac::parse(&mac)) else {
+        return Response::error(400, "Bad Request", "invalid mac");
+    };
+
+    if hotspot::revoke(&mac) {
+        get_hotspot_sessions()
+    } else {
+        Response::error(404, "Not Found", "client has no session")
+    }
+}
+
+/// Lists vouchers, optionally only one `batch`, as JSON, or as CSV or a
+/// printable page with `format=csv`/`format=html`.
+fn get_vouchers(query: &str) -> Response {
+    let batch = match form::field(query, "batch").map(str::parse::<u16>) {
+        Some(Ok(batch)) => Some(batch),
+        Some(Err(_)) => return Response::error(400, "Bad Request", "invalid batch"),
+        None => None,
+    };
+    let in_batch = |voucher: &&Voucher| batch.is_none_or(|batch| voucher.batch == batch);
+
+    let mut body = String::new();
+    match form::field(query, "format").unwrap_or("json") {
+        "json" => {
+            body.push('[');
+            hotspot::with_vouchers(|book| {
+                for (i, voucher) in book.vouchers().iter().filter(in_batch).enumerate() {
+                    if i > 0 {
+                        body.push(',');
+                    }
+                    write_voucher_json(&mut body, voucher);
+                }
+            });
+            body.push(']');
+            Response::json(body)
+        }
+        "csv" => {
+            body.push_str(
+                "code,batch,duration_secs,quota_bytes,max_devices,state,used_secs,used_bytes,devices\r\n",
+            );
+            hotspot::with_vouchers(|book| {
+                for voucher in book.vouchers().iter().filter(in_batch) {
+                    _ = write!(
+                        body,
+                        "{},{},{},",
+                        voucher.code, voucher.batch, voucher.terms.duration_secs
+                    );
+                    if let Some(quota) = voucher.terms.quota_bytes {
+                        _ = write!(body, "{quota}");
+                    }
+                    _ = write!(
+                        body,
+                        ",{},{},{},{},",
+                        voucher.terms.max_devices,
+                        voucher.state().as_str(),
+                        voucher.used_secs,
+                        voucher.used_bytes
+                    );
+                    for (i, device) in voucher.devices.iter().enumerate() {
+                        if i > 0 {
+                            body.push(' ');
+                        }
+                        _ = write!(body, "{}", MacDisplay(device));
+                    }
+                    body.push_str("\r\n");
+                }
+            });
+            Response {
+                content_type: "text/csv",
+                ..Response::json(body)
+            }
+        }
+        "html" => {
+            body.push_str(
+                "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Vouchers</title><style>\
+                 body{font-family:sans-serif}\
+                 .voucher{display:inline-block;width:6cm;margin:4mm;padding:4mm;border:1px dashed #555;text-align:center;break-inside:avoid}\
+                 .code{font:bold 20pt monospace;letter-spacing:2px;margin:2mm 0}\
+                 </style></head><body>",
+            );
+            hotspot::with_vouchers(|book| {
+                for voucher in book.vouchers().iter().filter(in_batch) {
+                    _ = write!(
+                        body,
+                        "<div class=\"voucher\"><div>Wi-Fi access</div><div class=\"code\">{}</div><div>{} minutes",
+                        voucher.code,
+                        voucher.terms.duration_secs / 60
+                    );
+                    if let Some(quota) = voucher.terms.quota_bytes {
+                        _ = write!(body, ", {} MB", quota / 1_000_000);
+                    }
+                    if voucher.terms.max_devices > 1 {
+                        _ = write!(body, ", up to {} devices", voucher.terms.max_devices);
+                    }
+                    body.push_str("</div></div>");
+                }
+            });
+            body.push_str("</body></html>");
+            Response {
+                content_type: "text/html",
+                ..Response::json(body)
+            }
+        }
+        _ => Response::error(400, "Bad Request", "format must be json, csv or html"),
+    }
+}
+
+fn write_voucher_json(body: &mut String, voucher: &Voucher) {
+    _ = write!(
+        body,
+        r#"{{"code":"{}","batch":{},"duration_secs":{},"quota_bytes":"#,
+        voucher.code, voucher.batch, voucher.terms.duration_secs
+    );
+    match voucher.terms.quota_bytes {
+        Some(quota) => _ = write!(body, "{quota}"),
+        None => body.push_str("null"),
+    }
+    _ = write!(
+        body,
+        r#","max_devices":{},"state":"{}","remaining_secs":{},"used_bytes":{},"devices":["#,
+        voucher.terms.max_devices,
+        voucher.state().as_str(),
+        voucher.remaining_secs(),
+        voucher.used_bytes
+    );
+    for (i, device) in voucher.devices.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{}""#, MacDisplay(device));
+    }
+    body.push_str("]}");
+}
+
+fn post_vouchers(body: &str) -> Response {
+    let (count, terms) = match parse_voucher_batch(body) {
+        Ok(batch) => batch,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match hotspot::generate_vouchers(count, terms) {
+        Ok(batch) => {
+            let mut query = String::new();
+            _ = write!(query, "batch={batch}");
+            get_vouchers(&query)
+        }
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_voucher_batch(body: &str) -> Result<(usize, VoucherTerms), &'static str> {
+    let count = form::field(body, "count")
+        .ok_or("count is required")?
+        .parse()
+        .map_err(|_| "invalid count")?;
+    let duration_secs = form::field(body, "duration_secs")
+        .ok_or("duration_secs is required")?
+        .parse()
+        .map_err(|_| "invalid duration_secs")?;
+    let quota_bytes = match form::field(body, "quota_bytes").and_then(non_empty) {
+        Some(quota) => Some(quota.parse().map_err(|_| "invalid quota_bytes")?),
+        None => None,
+    };
+    let max_devices = match form::field(body, "max_devices") {
+        Some(value) => value.parse().map_err(|_| "invalid max_devices")?,
+        None => 1,
+    };
+    Ok((
+        count,
+        VoucherTerms {
+            duration_secs,
+            quota_bytes,
+            max_devices,
+        },
+    ))
+}
+
+fn delete_vouchers(query: &str) -> Response {
+    let result = if let Some(batch) = form::field(query, "batch") {
+        let Ok(batch) = batch.parse() else {
+            return Response::error(400, "Bad Request", "invalid batch");
+        };
+        hotspot::delete_voucher_batch(batch).map(|removed| removed > 0)
+    } else if let Some(code) = form::decoded_field::<16>(query, "code") {
+        hotspot::delete_voucher(&code)
+    } else {
+        return Response::error(400, "Bad Request", "batch or code is required");
+    };
+
+    match result {
+        Ok(true) => get_vouchers(""),
+        Ok(false) => Response::error(404, "Not Found", "no such vouchers"),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn get_radius() -> Response {
+    let config = radius_client::config();
+
+    let mut body = String::new();
+    _ = write!(body, r#"{{"enabled":{},"servers":["#, config.enabled);
+    for (i, server) in config.servers.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{server}""#);
+    }
+    // The secret is write-only
+    _ = write!(
+        body,
+        r#"],"secret_set":{},"nas_identifier":"#,
+        !config.secret.is_empty()
+    );
+    json_str(&mut body, &config.nas_identifier);
+    _ = write!(
+        body,
+        r#","method":"{}","timeout_secs":{},"retries":{},"accounting":{},"interim_secs":{}}}"#,
+        config.method.as_str(),
+        config.timeout_secs,
+        config.retries,
+        config.accounting,
+        config.interim_secs
+    );
+
+    Response::json(body)
+}
+
+fn post_radius(body: &str) -> Response {
+    let config = match parse_radius_config(body, radius_client::config()) {
+        Ok(config) => config,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match radius_client::set_config(config) {
+        Ok(()) => get_radius(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_radius_config(body: &str, mut config: RadiusConfig) -> Result<RadiusConfig, &'static str> {
+    if let Some(value) = form::field(body, "enabled") {
+        config.enabled = parse_bool(value).ok_or("invalid enabled")?;
+    }
+    if let Some(value) = decoded::<100>(body, "servers")? {
+        config.servers = parse_list(&value, Server::parse).ok_or("invalid servers")?;
+    }
+    if let Some(value) = decoded::<64>(body, "secret")? {
+        // Left empty by forms that don't show the current secret
+        if !value.is_empty() {
+            config.secret = value;
+        }
+    }
+    if let Some(value) = decoded::<32>(body, "nas_identifier")? {
+        config.nas_identifier = value;
+    }
+    if let Some(value) = form::field(body, "method") {
+        config.method = AuthMethod::parse(value).ok_or("invalid method")?;
+    }
+    if let Some(value) = form::field(body, "timeout_secs") {
+        config.timeout_secs = value.parse().map_err(|_| "invalid timeout_secs")?;
+    }
+    if let Some(value) = form::field(body, "retries") {
+        config.retries = value.parse().map_err(|_| "invalid retries")?;
+    }
+    if let Some(value) = form::field(body, "accounting") {
+        config.accounting = parse_bool(value).ok_or("invalid accounting")?;
+    }
+    if let Some(value) = form::field(body, "interim_secs") {
+        config.interim_secs = value.parse().map_err(|_| "invalid interim_secs")?;
+    }
+    Ok(config)
+}
+
+fn get_traffic() -> Response {
+    let config = traffic::rate_limits();
+
+    let mut body = String::from("{\"default\":");
+    write_limit(&mut body, &config.default);
+    body.push_str(",\"overrides\":[");
+    for (i, o) in config.overrides.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#"{{"mac":"{}","limit":"#, MacDisplay(&o.mac));
+        write_limit(&mut body, &o.limit);
+        body.push('}');
+    }
+    body.push_str("],\"clients\":[");
+    for (i, client) in traffic::clients().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        let counters = &client.counters;
+        _ = write!(body, r#"{{"mac":"{}","limit":"#, MacDisplay(&client.mac));
+        write_limit(&mut body, &client.limit);
+        _ = write!(
+            body,
+            r#","bytes_up":{},"bytes_down":{},"packets_up":{},"packets_down":{},"dropped_up":{},"dropped_down":{}}}"#,
+            counters.bytes_up,
+            counters.bytes_down,
+            counters.packets_up,
+            counters.packets_down,
+            counters.dropped_up,
+            counters.dropped_down
+        );
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn write_limit(body: &mut String, limit: &Limit) {
+    _ = write!(
+        body,
+        r#"{{"up_kbps":{},"down_kbps":{}}}"#,
+        limit.up_kbps, limit.down_kbps
+    );
+}
+
+fn parse_limit(body: &str, prefix: &str, mut limit: Limit) -> Result<Limit, &'static str> {
+    let mut name = heapless::String::<24>::new();
+    _ = write!(name, "{prefix}up_kbps");
+    if let Some(value) = form::field(body, &name) {
+        limit.up_kbps = value.parse().map_err(|_| "invalid up_kbps")?;
+    }
+    name.clear();
+    _ = write!(name, "{prefix}down_kbps");
+    if let Some(value) = form::field(body, &name) {
+        limit.down_kbps = value.parse().map_err(|_| "invalid down_kbps")?;
+    }
+    Ok(limit)
+}
+
+fn save_traffic(config: RateLimits) -> Response {
+    match traffic::set_rate_limits(config) {
+        Ok(()) => get_traffic(),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn post_traffic_limits(body: &str) -> Response {
+    let mut config = traffic::rate_limits();
+    config.default = match parse_limit(body, "default_", config.default) {
+        Ok(limit) => limit,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+    save_traffic(config)
+}
+
+fn post_traffic_override(body: &str) -> Response {
+    let Some(mac) = form::decoded_field::<17>(body, "mac").and_then(|mac| mac::parse(&mac)) else {
+        return Response::error(400, "Bad Request", "invalid mac");
+    };
+    let mut config = traffic::rate_limits();
+    let current = config.limit_for(&mac);
+    let limit = match parse_limit(body, "", current) {
+        Ok(limit) => limit,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+    if config.set_override(mac, limit).is_err() {
+        return Response::error(422, "Unprocessable Entity", "too many overrides");
+    }
+    save_traffic(config)
+}
+
+fn delete_traffic_override(query: &str) -> Response {
+    let Some(mac) = form::decoded_field::<17>(query, "mac").and_then(|mac| mac::parse(&mac)) else {
+        return Response::error(400, "Bad Request", "invalid mac");
+    };
+    let mut config = traffic::rate_limits();
+    if !config.remove_override(&mac) {
+        return Response::error(404, "Not Found", "no override for this client");
+    }
+    save_traffic(config)
+}
+
+fn delete_traffic_counters(query: &str) -> Response {
+    match form::decoded_field::<17>(query, "mac") {
+        Some(mac) => match mac::parse(&mac) {
+            Some(mac) => traffic::reset_counters(Some(&mac)),
+            None => return Response::error(400, "Bad Request", "invalid mac"),
+        },
+        None => traffic::reset_counters(None),
+    }
+    get_traffic()
+}
+
+fn get_port_forwards() -> Response {
+    let mut body = String::from("[");
+    let forwards = port_forwards::port_forwards();
+    let network = access_point::ap_network();
+    for (i, rule) in forwards.rules().iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        let stale = forwards.stale(&network).any(|stale| stale == rule);
+        _ = write!(
+            body,
+            r#"{{"protocol":"{}","external_port":{},"internal_ip":"{}","internal_port":{},"stale":{},"name":"#,
+            rule.protocol.as_str(),
+            rule.external_port,
+            rule.internal_ip,
+            rule.internal_port,
+            stale
+        );
+        json_str(&mut body, &rule.name);
+        body.push('}');
+    }
+    body.push(']');
+
+    Response::json(body)
+}
+
+fn post_port_forward(body: &str) -> Response {
+    let rule = match parse_port_forward(body) {
+        Ok(rule) => rule,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+
+    match port_forwards::add_port_forward(rule) {
+        Ok(()) => get_port_forwards(),
+        Err(e) => Response::error(422, "Unprocessable Entity", e.as_str()),
+    }
+}
+
+fn parse_port_forward(body: &str) -> Result<PortForward, &'static str> {
+    let protocol = parse_protocol(form::field(body, "protocol").unwrap_or("tcp"))?;
+    let external_port = form::field(body, "external_port")
+        .ok_or("external_port is required")?
+        .parse()
+        .map_err(|_| "invalid external_port")?;
+    let internal_ip = decoded::<15>(body, "internal_ip")?
+        .ok_or("internal_ip is required")?
+        .parse()
+        .map_err(|_| "invalid internal_ip")?;
+    let internal_port = match form::field(body, "internal_port") {
+        Some(port) => port.parse().map_err(|_| "invalid internal_port")?,
+        None => external_port,
+    };
+    let name = decoded::<24>(body, "name")?.unwrap_or_default();
+    Ok(PortForward {
+        protocol,
+        external_port,
+        internal_ip,
+        internal_port,
+        name,
+    })
+}
+
+fn delete_port_forward(query: &str) -> Response {
+    let protocol = match parse_protocol(form::field(query, "protocol").unwrap_or("tcp")) {
+        Ok(protocol) => protocol,
+        Err(message) => return Response::error(400, "Bad Request", message),
+    };
+    let Some(Ok(port)) = form::field(query, "external_port").map(str::parse) else {
+        return Response::error(400, "Bad Request", "invalid external_port");
+    };
+
+    match port_forwards::remove_port_forward(protocol, port) {
+        Ok(true) => get_port_forwards(),
+        Ok(false) => Response::error(404, "Not Found", "no such port forward"),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn get_firewall() -> Response {
+    let config = firewall::firewall();
+    let (hits, default_hits) = firewall::hits();
+
+    let mut body = String::new();
+    _ = write!(
+        body,
+        r#"{{"enabled":{},"default_out":"{}","default_in":"{}","default_hits":{{"out":{},"in":{}}},"rules":["#,
+        config.enabled,
+        config.default_out.as_str(),
+        config.default_in.as_str(),
+        default_hits[0],
+        default_hits[1]
+    );
+    for (i, rule) in config.rules.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(
+            body,
+            r#"{{"index":{},"rule":"{}","hits":{}}}"#,
+            i,
+            rule,
+            hits.get(i).copied().unwrap_or(0)
+        );
+    }
+    body.push_str("]}");
+
+    Response::json(body)
+}
+
+fn save_firewall(config: FirewallConfig) -> Response {
+    match firewall::set_firewall(config) {
+        Ok(()) => get_firewall(),
+        Err(e) => Response::error(500, "Internal Server Error", e.as_str()),
+    }
+}
+
+fn post_firewall(body: &str) -> Response {
+    let mut config = firewall::firewall();
+    if let Some(value) = form::field(body, "enabled") {
+        let Some(enabled) = parse_bool(value) else {
+            return Response::error(400, "Bad Request", "invalid enabled");
+        };
+        config.enabled = enabled;
+    }
+    for (name, policy) in [
+        ("default_out", &mut config.default_out),
+        ("default_in", &mut config.default_in),
+    ] {
+        if let Some(value) = form::field(body, name) {
+            let Some(value) = parse_policy(value) else {
+                return Response::error(
+                    400,
+                    "Bad Request",
+                    "default policies must be allow or deny",
+                );
+            };
+            *policy = value;
+        }
+    }
+    save_firewall(config)
+}
+
+/// Takes either `rule=<rule>` with an optional `position`, or
+/// `preset=block_private`, which puts the preset rules first.
+fn post_firewall_rule(body: &str) -> Response {
+    let mut config = firewall::firewall();
+    let position = match form::field(body, "position").map(str::parse) {
+        Some(Ok(position)) => Some(position),
+        Some(Err(_)) => return Response::error(400, "Bad Request", "invalid position"),
+        None => None,
+    };
+
+    let result = match form::field(body, "preset") {
+        Some("block_private") => block_private_preset()
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(i, rule)| config.insert(Some(i), rule)),
+        Some(_) => return Response::error(400, "Bad Request", "unknown preset"),
+        None => {
+            let text = match decoded::<128>(body, "rule") {
+                Ok(Some(text)) => text,
+                Ok(None) => return Response::error(400, "Bad Request", "rule is required"),
+                Err(message) => return Response::error(400, "Bad Request", message),
+            };
+            Rule::parse(&text).and_then(|rule| config.insert(position, rule))
+        }
+    };
+    if let Err(e) = result {
+        return Response::error(422, "Unprocessable Entity", e.as_str());
+    }
+    save_firewall(config)
+}
+
+fn delete_firewall_rule(query: &str) -> Response {
+    let Some(Ok(index)) = form::field(query, "index").map(str::parse::<usize>) else {
+        return Response::error(400, "Bad Request", "invalid index");
+    };
+    let mut config = firewall::firewall();
+    if index >= config.rules.len() {
+        return Response::error(404, "Not Found", "no such rule");
+    }
+    config.rules.remove(index);
+    save_firewall(config)
+}
+
+fn delete_firewall_counters() -> Response {
+    firewall::reset_hits();
+    get_firewall()
+}
+
+fn parse_protocol(value: &str) -> Result<Protocol, &'static str> {
+    match value {
+        "tcp" => Ok(Protocol::Tcp),
+        "udp" => Ok(Protocol::Udp),
+        _ => Err("protocol must be tcp or udp"),
+    }
+}
+
+fn write_pattern_list(body: &mut String, patterns: &[MacPattern]) {
+    for (i, pattern) in patterns.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{pattern}""#);
+    }
+}
+
+fn parse_client_match(body: &str) -> Result<ClientMatch, &'static str> {
+    if let Some(value) = decoded::<17>(body, "mac")? {
+        return mac::parse(&value)
+            .map(ClientMatch::Mac)
+            .ok_or("invalid mac");
+    }
+    match decoded::<32>(body, "vendor_class")? {
+        Some(class) if !class.is_empty() => Ok(ClientMatch::VendorClass(class)),
+        _ => Err("either mac or vendor_class is required"),
+    }
+}
+
+/// Applies the option fields present in `body` on top of `options`. An empty
+/// value clears the option.
+fn parse_dhcp_options(body: &str, mut options: DhcpOptions) -> Result<DhcpOptions, &'static str> {
+    if let Some(value) = decoded::<64>(body, "dns")? {
+        options.dns = parse_list(&value, |ip| ip.parse().ok()).ok_or("invalid dns")?;
+    }
+    if let Some(value) = decoded::<64>(body, "domain_name")? {
+        options.domain_name = non_empty(&value).map(|name| name.try_into().unwrap());
+    }
+    if let Some(value) = decoded::<64>(body, "ntp")? {
+        options.ntp = parse_list(&value, |ip| ip.parse().ok()).ok_or("invalid ntp")?;
+    }
+    if let Some(value) = decoded::<200>(body, "domain_search")? {
+        options.domain_search =
+            parse_list(&value, |name| name.try_into().ok()).ok_or("invalid domain_search")?;
+    }
+    if let Some(value) = decoded::<200>(body, "routes")? {
+        options.routes = parse_list(&value, StaticRoute::parse).ok_or("invalid routes")?;
+    }
+    if let Some(value) = decoded::<128>(body, "captive_portal")? {
+        options.captive_portal = non_empty(&value).map(|uri| uri.try_into().unwrap());
+    }
+    if let Some(value) = decoded::<8>(body, "mtu")? {
+        options.mtu = match non_empty(&value) {
+            Some(mtu) => Some(mtu.parse().map_err(|_| "invalid mtu")?),
+            None => None,
+        };
+    }
+    if let Some(value) = decoded::<400>(body, "custom")? {
+        options.custom = parse_list(&value, CustomOption::parse).ok_or("invalid custom options")?;
+    }
+    Ok(options)
+}
+
+fn write_dhcp_options(body: &mut String, options: &DhcpOptions) {
+    body.push_str("{\"dns\":");
+    write_ip_list(body, &options.dns);
+    body.push_str(",\"domain_name\":");
+    write_optional_str(body, options.domain_name.as_deref());
+    body.push_str(",\"ntp\":");
+    write_ip_list(body, &options.ntp);
+    body.push_str(",\"domain_search\":[");
+    for (i, name) in options.domain_search.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        json_str(body, name);
+    }
+    body.push_str("],\"routes\":[");
+    for (i, route) in options.routes.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{route}""#);
+    }
+    body.push_str("],\"captive_portal\":");
+    write_optional_str(body, options.captive_portal.as_deref());
+    body.push_str(",\"mtu\":");
+    match options.mtu {
+        Some(mtu) => _ = write!(body, "{mtu}"),
+        None => body.push_str("null"),
+    }
+    body.push_str(",\"custom\":[");
+    for (i, option) in options.custom.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{option}""#);
+    }
+    body.push_str("]}");
+}
+
+fn write_ip_list(body: &mut String, ips: &[Ipv4Addr]) {
+    body.push('[');
+    for (i, ip) in ips.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        _ = write!(body, r#""{ip}""#);
+    }
+    body.push(']');
+}
+
+fn write_optional_str(body: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => json_str(body, value),
+        None => body.push_str("null"),
+    }
+}
+
+/// Looks up and percent-decodes a form field. A field that is present but
+/// can't be decoded is an error rather than being treated as missing.
+fn decoded<const N: usize>(
+    body: &str,
+    name: &str,
+) -> Result<Option<heapless::String<N>>, &'static str> {
+    match form::field(body, name) {
+        Some(value) => form::decode(value)
+            .map(Some)
+            .ok_or("field too long or badly encoded"),
+        None => Ok(None),
+    }
+}
+
+fn parse_list<T, const N: usize>(
+    value: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<heapless::Vec<T, N>> {
+    let mut items = heapless::Vec::new();
+    for item in value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        items.push(parse(item)?).ok()?;
+    }
+    Some(items)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" | "on" => Some(true),
+        "false" | "0" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    Some(value.trim()).filter(|value| !value.is_empty())
+}
+
+pub fn json_str(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => _ = write!(out, "\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,65 @@
+use core::cell::{Cell, RefCell};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use esp_println::println;
+
+use super::access_point::ap_network;
+use super::clients;
+use crate::router::bridge::{Bridge, Host, Verdict};
+use crate::storage::{self, Record, StorageError};
+
+pub const MAX_HOSTS: usize = 32;
+
+static ENABLED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+static BRIDGE: Mutex<CriticalSectionRawMutex, RefCell<Bridge<MAX_HOSTS>>> =
+    Mutex::new(RefCell::new(Bridge::new()));
+
+pub fn load_bridge_mode() {
+    let mut buf = [0u8; 16];
+    let enabled = storage::load(Record::Bridge, &mut buf) == Some(b"enabled=1\n".as_slice());
+    ENABLED.lock(|current| current.set(enabled));
+    if enabled {
+        println!("Bridge mode: AP clients get their addresses from upstream");
+    }
+}
+
+/// Whether AP clients are bridged onto the upstream network instead of
+/// getting a private subnet, DHCP and NAT from us.
+pub fn bridge_mode() -> bool {
+    ENABLED.lock(|enabled| enabled.get())
+}
+
+/// Switches modes and disconnects the AP clients, so they come back and ask
+/// for an address on the new network.
+pub fn set_bridge_mode(enabled: bool) -> Result<(), StorageError> {
+    if enabled == bridge_mode() {
+        return Ok(());
+    }
+    let text: &[u8] = if enabled {
+        b"enabled=1\n"
+    } else {
+        b"enabled=0\n"
+    };
+    storage::save(Record::Bridge, text)?;
+    BRIDGE.lock(|bridge| bridge.borrow_mut().clear());
+    ENABLED.lock(|current| current.set(enabled));
+    for client in clients::clients() {
+        clients::kick(&client.mac, None);
+    }
+    Ok(())
+}
+
+pub fn hosts() -> heapless::Vec<Host, MAX_HOSTS> {
+    BRIDGE.lock(|bridge| bridge.borrow().hosts().iter().copied().collect())
+}
+
+pub fn from_ap(frame: &mut [u8], sta_mac: [u8; 6]) -> Verdict {
+    let network = ap_network();
+    let now = Instant::now().as_secs();
+    BRIDGE.lock(|bridge| bridge.borrow_mut().from_ap(frame, sta_mac, &network, now))
+}
+
+pub fn from_sta(frame: &mut [u8], sta_mac: [u8; 6]) -> Verdict {
+    let now = Instant::now().as_secs();
+    BRIDGE.lock(|bridge| bridge.borrow_mut().from_sta(frame, sta_mac, now))
+}
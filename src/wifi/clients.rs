@@ -0,0 +1,179 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+use esp_wifi::wifi::event::{self, EventExt};
+use esp_wifi_sys::include::{
+    esp_wifi_ap_get_sta_list, esp_wifi_deauth_sta, wifi_sta_list_t, ESP_OK,
+};
+
+use super::access_point::{publish_lease_events, with_dhcp_server};
+use super::client_table::{Client, ClientTable};
+use super::mac_filter::{MacFilter, Verdict};
+use crate::mac::MacDisplay;
+use crate::storage::{self, Record, StorageError};
+
+const MAX_CLIENTS: usize = 10;
+const RSSI_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+enum StationEvent {
+    Connected { mac: [u8; 6], aid: u8 },
+    Disconnected { mac: [u8; 6] },
+}
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<ClientTable<MAX_CLIENTS>>> =
+    Mutex::new(RefCell::new(ClientTable::new()));
+static STATION_EVENTS: Channel<CriticalSectionRawMutex, StationEvent, 8> = Channel::new();
+static RELEASE_LEASE_ON_DISCONNECT: AtomicBool = AtomicBool::new(false);
+static MAC_FILTER: Mutex<CriticalSectionRawMutex, RefCell<MacFilter>> =
+    Mutex::new(RefCell::new(MacFilter::new()));
+
+pub fn clients() -> heapless::Vec<Client, MAX_CLIENTS> {
+    CLIENTS.lock(|clients| clients.borrow().clients().iter().copied().collect())
+}
+
+pub fn release_lease_on_disconnect() -> bool {
+    RELEASE_LEASE_ON_DISCONNECT.load(Ordering::Relaxed)
+}
+
+pub fn set_release_lease_on_disconnect(enabled: bool) {
+    RELEASE_LEASE_ON_DISCONNECT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn load_mac_filter() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::MacFilter, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored).ok().and_then(MacFilter::parse) {
+        Some(filter) => {
+            MAC_FILTER.lock(|current| current.replace(filter));
+        }
+        None => log::warn!("Stored MAC filter is invalid, ignoring it"),
+    }
+}
+
+pub fn mac_filter() -> MacFilter {
+    MAC_FILTER.lock(|filter| filter.borrow().clone())
+}
+
+/// Persists `filter` and disconnects any station it no longer admits.
+pub fn set_mac_filter(filter: MacFilter) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = filter.write_to(&mut text);
+    storage::save(Record::MacFilter, text.as_bytes())?;
+
+    MAC_FILTER.lock(|current| current.replace(filter));
+    for client in clients() {
+        let verdict = admits(&client.mac);
+        if !verdict.is_allowed() {
+            println!(
+                "Disconnecting AP client {}: {}",
+                MacDisplay(&client.mac),
+                verdict.as_str()
+            );
+            deauth(client.aid);
+        }
+    }
+    Ok(())
+}
+
+pub fn admits(mac: &[u8; 6]) -> Verdict {
+    let now = Instant::now().as_secs();
+    MAC_FILTER.lock(|filter| {
+        let mut filter = filter.borrow_mut();
+        filter.expire_bans(now);
+        filter.evaluate(mac, now)
+    })
+}
+
+/// Disconnects `mac` and optionally keeps it out for `ban_secs`. Returns
+/// `false` if the station isn't connected, the ban is applied regardless.
+pub fn kick(mac: &[u8; 6], ban_secs: Option<u64>) -> bool {
+    if let Some(ban_secs) = ban_secs {
+        let until = Instant::now().as_secs() + ban_secs;
+        MAC_FILTER.lock(|filter| filter.borrow_mut().ban(*mac, until));
+    }
+    match CLIENTS.lock(|clients| clients.borrow().get(mac).copied()) {
+        Some(client) => {
+            println!("Kicking AP client {}", MacDisplay(mac));
+            deauth(client.aid);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn unban(mac: &[u8; 6]) -> bool {
+    MAC_FILTER.lock(|filter| filter.borrow_mut().unban(mac))
+}
+
+fn deauth(aid: u8) {
+    if unsafe { esp_wifi_deauth_sta(aid as u16) } != ESP_OK as i32 {
+        log::warn!("Failed to deauthenticate station with aid {aid}");
+    }
+}
+
+/// Hooks the AP station (dis)connect events. The handlers run in the Wi-Fi
+/// driver's context, so they only queue the event for [`track_clients`].
+pub fn register_event_handlers() {
+    event::ApStaconnected::update_handler(|event| {
+        _ = STATION_EVENTS.try_send(StationEvent::Connected {
+            mac: event.0.mac,
+            aid: event.0.aid,
+        });
+    });
+    event::ApStadisconnected::update_handler(|event| {
+        _ = STATION_EVENTS.try_send(StationEvent::Disconnected { mac: event.0.mac });
+    });
+}
+
+#[embassy_executor::task]
+pub async fn track_clients() {
+    loop {
+        match select(STATION_EVENTS.receive(), Timer::after(RSSI_POLL_INTERVAL)).await {
+            Either::First(StationEvent::Connected { mac, aid }) => {
+                let verdict = admits(&mac);
+                if !verdict.is_allowed() {
+                    println!(
+                        "Rejecting AP client {}: {}",
+                        MacDisplay(&mac),
+                        verdict.as_str()
+                    );
+                    deauth(aid);
+                    continue;
+                }
+                println!("AP client {} connected (aid {aid})", MacDisplay(&mac));
+                let now = Instant::now().as_secs();
+                CLIENTS.lock(|clients| clients.borrow_mut().connected(mac, aid, now));
+            }
+            Either::First(StationEvent::Disconnected { mac }) => {
+                println!("AP client {} disconnected", MacDisplay(&mac));
+                CLIENTS.lock(|clients| clients.borrow_mut().disconnected(&mac));
+                if release_lease_on_disconnect() {
+                    with_dhcp_server(|server| server.release(mac));
+                    publish_lease_events();
+                }
+            }
+            Either::Second(()) => refresh_rssi(),
+        }
+    }
+}
+
+fn refresh_rssi() {
+    let mut list: wifi_sta_list_t = unsafe { core::mem::zeroed() };
+    if unsafe { esp_wifi_ap_get_sta_list(&mut list) } != ESP_OK as i32 {
+        return;
+    }
+
+    let count = (list.num.max(0) as usize).min(list.sta.len());
+    CLIENTS.lock(|clients| {
+        let mut clients = clients.borrow_mut();
+        for station in &list.sta[..count] {
+            clients.update_rssi(&station.mac, station.rssi);
+        }
+    });
+}
@@ -0,0 +1,169 @@
+use core::cell::RefCell;
+use core::net::Ipv4Addr;
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+use super::access_point::{ap_network, publish_lease_events, with_dhcp_server};
+use crate::dhcp::packet::{MessageType, Packet, SERVER_PORT};
+use crate::dhcp::relay::{self, ConfigError, RelayConfig};
+use crate::storage::{self, Record, StorageError};
+
+const MAX_MESSAGE_LEN: usize = 600;
+const QUEUE_LEN: usize = 2;
+
+pub type Message = Vec<u8, MAX_MESSAGE_LEN>;
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<RelayConfig>> =
+    Mutex::new(RefCell::new(RelayConfig::new()));
+static TO_SERVER: Channel<CriticalSectionRawMutex, Message, QUEUE_LEN> = Channel::new();
+static TO_CLIENTS: Channel<CriticalSectionRawMutex, (Ipv4Addr, Message), QUEUE_LEN> =
+    Channel::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetConfigError {
+    Invalid(ConfigError),
+    Storage(StorageError),
+}
+
+impl SetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_config() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::DhcpRelay, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(RelayConfig::parse)
+    {
+        Some(config) => {
+            CONFIG.lock(|current| current.replace(config));
+        }
+        None => log::warn!("Stored DHCP relay configuration is invalid, ignoring it"),
+    }
+}
+
+pub fn config() -> RelayConfig {
+    CONFIG.lock(|config| config.borrow().clone())
+}
+
+pub fn set_config(config: RelayConfig) -> Result<(), SetConfigError> {
+    config.validate().map_err(SetConfigError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::DhcpRelay, text.as_bytes()).map_err(SetConfigError::Storage)?;
+    CONFIG.lock(|current| current.replace(config));
+    Ok(())
+}
+
+/// Whether AP clients' DHCP messages go to an upstream server instead of
+/// being answered by our own.
+pub fn relay_mode() -> bool {
+    CONFIG.lock(|config| config.borrow().enabled)
+}
+
+/// Queues a message from an AP client for the upstream server.
+pub fn relay_request(raw: &[u8]) {
+    let config = config();
+    let mut out = [0u8; MAX_MESSAGE_LEN];
+    let len = match relay::relay_request(raw, ap_network().gateway, &config, &mut out) {
+        Ok(len) => len,
+        Err(e) => {
+            log::warn!("DHCP relay: dropping client message, {}", e.as_str());
+            return;
+        }
+    };
+    let Ok(message) = Vec::from_slice(&out[..len]) else {
+        return;
+    };
+    if TO_SERVER.try_send(message).is_err() {
+        log::warn!("DHCP relay: queue full, dropping client message");
+    }
+}
+
+/// The next server reply to broadcast or send to its client, for `run_dhcp`
+/// to send from the AP stack.
+pub async fn next_reply() -> (Ipv4Addr, Message) {
+    TO_CLIENTS.receive().await
+}
+
+/// Talks to the upstream server from the STA stack. Replies are addressed to
+/// the AP gateway, so upstream needs a route for the AP network via our STA
+/// address; the forwarding path hands them to this socket.
+#[embassy_executor::task]
+pub async fn run_relay(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; QUEUE_LEN];
+    let mut rx_buffer = [0u8; MAX_MESSAGE_LEN * QUEUE_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; QUEUE_LEN];
+    let mut tx_buffer = [0u8; MAX_MESSAGE_LEN * QUEUE_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(SERVER_PORT) {
+        log::warn!("DHCP relay: failed to bind: {e:?}");
+        return;
+    }
+
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    let mut out = [0u8; MAX_MESSAGE_LEN];
+    loop {
+        match select(TO_SERVER.receive(), socket.recv_from(&mut buf)).await {
+            Either::First(message) => {
+                let server = IpEndpoint::new(IpAddress::Ipv4(config().server), SERVER_PORT);
+                if let Err(e) = socket.send_to(&message, server).await {
+                    log::warn!("DHCP relay: failed to reach {server}: {e:?}");
+                    Timer::after(Duration::from_millis(500)).await;
+                }
+            }
+            Either::Second(Ok((len, _))) => {
+                if !relay_mode() {
+                    continue;
+                }
+                let reply = match relay::relay_reply(&buf[..len], ap_network().gateway, &mut out) {
+                    Ok(reply) => reply,
+                    Err(e) => {
+                        log::warn!("DHCP relay: dropping server reply, {}", e.as_str());
+                        continue;
+                    }
+                };
+                record_lease(&out[..reply.len]);
+                let Ok(message) = Vec::from_slice(&out[..reply.len]) else {
+                    continue;
+                };
+                if TO_CLIENTS.try_send((reply.to, message)).is_err() {
+                    log::warn!("DHCP relay: queue full, dropping server reply");
+                }
+            }
+            Either::Second(Err(e)) => log::warn!("DHCP relay error: {e:?}"),
+        }
+    }
+}
+
+/// Keeps the lease table in step with what upstream hands out, for the
+/// features that look clients up by address.
+fn record_lease(reply: &[u8]) {
+    let Ok(ack) = Packet::decode(reply) else {
+        return;
+    };
+    if ack.message_type() == Some(MessageType::Ack) && !ack.yiaddr.is_unspecified() {
+        let now = Instant::now().as_secs();
+        with_dhcp_server(|server| server.record_relayed(&ack, now));
+        publish_lease_events();
+    }
+}
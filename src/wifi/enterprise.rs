@@ -0,0 +1,162 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use crate::secrets;
+use crate::storage::{self, Secret, StorageError, CERTIFICATE_SLOTS};
+use crate::uplink::enterprise::{CertificateKind, Certificates};
+use crate::uplink::pem::{self, PemError};
+use crate::wifi::uplink;
+
+const AAD_PREFIX: &[u8] = b"sta_certificates";
+
+static SLOTS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<Slot, CERTIFICATE_SLOTS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// One network's certificates and the `sta_secrets` sector they are in.
+#[derive(Clone, Copy)]
+struct Slot {
+    index: u8,
+    ssid: &'static str,
+    certificates: Certificates<'static>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetCertificateError {
+    /// Certificates only go with a saved station network.
+    UnknownNetwork,
+    Pem(PemError),
+    Storage(StorageError),
+}
+
+impl SetCertificateError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UnknownNetwork => "there is no profile for this network",
+            Self::Pem(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+/// Needs [`secrets::init`] first.
+pub fn load_certificates() {
+    let mut buf = alloc::vec![0u8; storage::MAX_RECORD_LEN];
+    for index in 0..CERTIFICATE_SLOTS as u8 {
+        let Some(stored) = storage::load_secret(Secret::Certificates(index), &mut buf) else {
+            continue;
+        };
+        // Forgotten slots are left empty
+        if stored.is_empty() {
+            continue;
+        }
+        let published = secrets::open(&aad(index), stored).is_some_and(|data| publish(index, data));
+        if !published {
+            log::warn!("Stored station certificates in slot {index} are invalid, ignoring them");
+        }
+    }
+}
+
+/// The certificates uploaded for `ssid`. They live for the rest of the run
+/// since the WiFi driver keeps pointers to them.
+pub fn certificates(ssid: &str) -> Certificates<'static> {
+    SLOTS.lock(|slots| {
+        slots
+            .borrow()
+            .iter()
+            .find(|slot| slot.ssid == ssid)
+            .map(|slot| slot.certificates)
+            .unwrap_or_default()
+    })
+}
+
+/// Stores a PEM certificate or key for `ssid`, or removes it with `None`.
+/// Keys are never handed back out, only whether one is set.
+pub fn set_certificate(
+    ssid: &str,
+    kind: CertificateKind,
+    pem: Option<&str>,
+) -> Result<(), SetCertificateError> {
+    let profiles = uplink::profiles();
+    if profiles.network(ssid).is_none() {
+        return Err(SetCertificateError::UnknownNetwork);
+    }
+    let der = match pem {
+        Some(pem) => Some(pem::decode(pem, kind.pem_labels()).map_err(SetCertificateError::Pem)?),
+        None => None,
+    };
+    let mut certificates = certificates(ssid);
+    certificates.set(kind, der.as_deref());
+
+    // The network's own slot, else a free one, else one left over from a
+    // network that was removed. There are as many slots as profiles.
+    let index = SLOTS.lock(|slots| {
+        let slots = slots.borrow();
+        slots
+            .iter()
+            .find(|slot| slot.ssid == ssid)
+            .map(|slot| slot.index)
+            .or_else(|| {
+                (0..CERTIFICATE_SLOTS as u8)
+                    .find(|index| slots.iter().all(|slot| slot.index != *index))
+            })
+            .or_else(|| {
+                slots
+                    .iter()
+                    .find(|slot| profiles.network(slot.ssid).is_none())
+                    .map(|slot| slot.index)
+            })
+            .expect("a profile without certificates leaves a slot free")
+    });
+    let data = certificates.encode_slot(ssid);
+    storage::save_secret(
+        Secret::Certificates(index),
+        &secrets::seal(&aad(index), &data),
+    )
+    .map_err(SetCertificateError::Storage)?;
+    publish(index, data);
+    Ok(())
+}
+
+/// Drops the certificates of a network whose profile is removed.
+pub fn forget_certificates(ssid: &str) -> Result<(), StorageError> {
+    let Some(index) = SLOTS.lock(|slots| {
+        let mut slots = slots.borrow_mut();
+        let position = slots.iter().position(|slot| slot.ssid == ssid)?;
+        Some(slots.swap_remove(position).index)
+    }) else {
+        return Ok(());
+    };
+    storage::save_secret(Secret::Certificates(index), &[])
+}
+
+/// Ties sealed data to its slot, so slots can't be swapped around.
+fn aad(index: u8) -> [u8; AAD_PREFIX.len() + 1] {
+    let mut aad = [index; AAD_PREFIX.len() + 1];
+    aad[..AAD_PREFIX.len()].copy_from_slice(AAD_PREFIX);
+    aad
+}
+
+/// Makes `data` the current certificates in slot `index`. The previous copy
+/// is leaked rather than freed, the driver may still be using it until the
+/// next connection; uploads are rare enough for that not to matter.
+fn publish(index: u8, data: Vec<u8>) -> bool {
+    let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+    let Some((ssid, certificates)) = Certificates::decode_slot(data) else {
+        return false;
+    };
+    SLOTS.lock(|slots| {
+        let mut slots = slots.borrow_mut();
+        slots.retain(|slot| slot.index != index && slot.ssid != ssid);
+        if certificates != Certificates::default() {
+            // Can't overflow, one slot per index
+            _ = slots.push(Slot {
+                index,
+                ssid,
+                certificates,
+            });
+        }
+    });
+    true
+}
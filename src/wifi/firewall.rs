@@ -0,0 +1,95 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+use crate::router::firewall::{Firewall, FirewallConfig, Packet, Verdict, MAX_RULES};
+use crate::router::policer::Direction;
+use crate::storage::{self, Record, StorageError};
+
+const MAX_CONNECTIONS: usize = 64;
+
+static FIREWALL: Mutex<CriticalSectionRawMutex, RefCell<Firewall<MAX_CONNECTIONS>>> =
+    Mutex::new(RefCell::new(Firewall::new()));
+
+pub fn load_firewall() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::Firewall, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(FirewallConfig::parse)
+    {
+        Some(config) => FIREWALL.lock(|firewall| firewall.borrow_mut().set_config(config)),
+        None => log::warn!("Stored firewall rules are invalid, ignoring them"),
+    }
+}
+
+pub fn firewall() -> FirewallConfig {
+    FIREWALL.lock(|firewall| firewall.borrow().config().clone())
+}
+
+pub fn set_firewall(config: FirewallConfig) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::Firewall, text.as_bytes())?;
+    FIREWALL.lock(|firewall| firewall.borrow_mut().set_config(config));
+    Ok(())
+}
+
+/// Hits per rule, and for the outbound and inbound default policies.
+pub fn hits() -> (heapless::Vec<u32, MAX_RULES>, [u32; 2]) {
+    FIREWALL.lock(|firewall| {
+        let firewall = firewall.borrow();
+        (
+            firewall.hits().iter().copied().collect(),
+            firewall.default_hits(),
+        )
+    })
+}
+
+pub fn reset_hits() {
+    FIREWALL.lock(|firewall| firewall.borrow_mut().reset_hits());
+}
+
+/// Called by the forwarding path for every packet between the AP and STA
+/// networks. Returns whether it may pass.
+pub fn check(packet: &Packet) -> bool {
+    let now = Instant::now().as_secs();
+    let decision = FIREWALL.lock(|firewall| firewall.borrow_mut().check(packet, now));
+    if decision.logged {
+        let arrow = match packet.direction {
+            Direction::Up => "->",
+            Direction::Down => "<-",
+        };
+        let verdict = match decision.verdict {
+            Verdict::Denied => "denied",
+            _ => "allowed",
+        };
+        let mut by = heapless::String::<16>::new();
+        _ = match decision.rule {
+            Some(index) => write!(by, "rule {index}"),
+            None => write!(by, "default policy"),
+        };
+        log::info!(
+            "Firewall: {} {}:{} {} {}:{} {} by {}",
+            packet.protocol.as_str(),
+            packet.client,
+            packet.client_port,
+            arrow,
+            packet.remote,
+            packet.remote_port,
+            verdict,
+            by
+        );
+    }
+    decision.verdict.is_allowed()
+}
+
+/// Whether an ICMP error about the connection `packet` describes may be
+/// passed on to the client.
+pub fn is_related(packet: &Packet) -> bool {
+    let now = Instant::now().as_secs();
+    FIREWALL.lock(|firewall| firewall.borrow().is_related(packet, now))
+}
@@ -0,0 +1,48 @@
+use heapless::String;
+
+/// Iterates the `key=value` pairs of an `application/x-www-form-urlencoded`
+/// body (or query string). Values are returned still encoded.
+pub fn fields(body: &str) -> impl Iterator<Item = (&str, &str)> {
+    body.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+}
+
+pub fn field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    fields(body)
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Percent-decodes a form value. Returns `None` if it is malformed or does
+/// not fit in `N` bytes.
+pub fn decode<const N: usize>(value: &str) -> Option<String<N>> {
+    let mut bytes = heapless::Vec::<u8, N>::new();
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = hex_value(input.next()?)?;
+                let low = hex_value(input.next()?)?;
+                high << 4 | low
+            }
+            byte => byte,
+        };
+        bytes.push(decoded).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+pub fn decoded_field<const N: usize>(body: &str, name: &str) -> Option<String<N>> {
+    field(body, name).and_then(decode)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
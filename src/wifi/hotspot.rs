@@ -0,0 +1,413 @@
+use core::cell::RefCell;
+use core::net::Ipv4Addr;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+
+use super::access_point::with_dhcp_server;
+use super::http_server::HTTP_PORT;
+use super::radius_client::{self, AcctStatus, AuthError};
+use crate::hotspot::policy::{Access, HotspotPolicy, PolicyError};
+use crate::hotspot::session::{EndReason, Session, SessionTable};
+use crate::hotspot::voucher::{self, VoucherBook, VoucherError, VoucherState, VoucherTerms};
+use crate::hotspot::walled_garden::{self, GardenCache};
+use crate::mac::MacDisplay;
+use crate::random;
+use crate::router::forward;
+use crate::router::nat::Protocol;
+use crate::storage::{self, Record, StorageError};
+
+pub const MAX_SESSIONS: usize = 16;
+// As many as are sure to fit in one storage record
+pub const MAX_VOUCHERS: usize = voucher::capacity(storage::MAX_RECORD_LEN);
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Voucher clocks only reach flash this often, to spare it
+const VOUCHER_SAVE_INTERVAL: u64 = 15 * 60;
+
+static POLICY: Mutex<CriticalSectionRawMutex, RefCell<HotspotPolicy>> =
+    Mutex::new(RefCell::new(HotspotPolicy::new()));
+static SESSIONS: Mutex<CriticalSectionRawMutex, RefCell<SessionTable<MAX_SESSIONS>>> =
+    Mutex::new(RefCell::new(SessionTable::new()));
+static GARDEN: Mutex<CriticalSectionRawMutex, RefCell<GardenCache<32>>> =
+    Mutex::new(RefCell::new(GardenCache::new()));
+static VOUCHERS: Mutex<CriticalSectionRawMutex, RefCell<VoucherBook<MAX_VOUCHERS>>> =
+    Mutex::new(RefCell::new(VoucherBook::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginError {
+    Disabled,
+    InvalidCredentials,
+    NoLease,
+    TooManySessions,
+    AuthServer(AuthError),
+    Voucher(VoucherError),
+}
+
+impl LoginError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disabled => "the hotspot is not enabled",
+            Self::InvalidCredentials => "invalid username or password",
+            Self::NoLease => "your device has no DHCP lease on this network",
+            Self::TooManySessions => "too many devices are logged in, try again later",
+            Self::AuthServer(e) => e.as_str(),
+            Self::Voucher(e) => e.as_str(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetPolicyError {
+    Invalid(PolicyError),
+    Storage(StorageError),
+}
+
+impl SetPolicyError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GenerateVouchersError {
+    Invalid(VoucherError),
+    Storage(StorageError),
+}
+
+impl GenerateVouchersError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_policy() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::Hotspot, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(HotspotPolicy::parse)
+    {
+        Some(policy) => {
+            POLICY.lock(|current| current.replace(policy));
+        }
+        None => log::warn!("Stored hotspot policy is invalid, ignoring it"),
+    }
+}
+
+pub fn policy() -> HotspotPolicy {
+    POLICY.lock(|policy| policy.borrow().clone())
+}
+
+pub fn set_policy(policy: HotspotPolicy) -> Result<(), SetPolicyError> {
+    policy.validate().map_err(SetPolicyError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = policy.write_to(&mut text);
+    storage::save(Record::Hotspot, text.as_bytes()).map_err(SetPolicyError::Storage)?;
+
+    POLICY.lock(|current| current.replace(policy));
+    // Addresses learned for domains that may no longer be in the garden
+    GARDEN.lock(|garden| garden.borrow_mut().clear());
+    Ok(())
+}
+
+pub fn load_vouchers() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::Vouchers, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(VoucherBook::parse)
+    {
+        Some(mut book) => {
+            book.tick(Instant::now().as_secs());
+            VOUCHERS.lock(|current| current.replace(book));
+        }
+        None => log::warn!("Stored vouchers are invalid, ignoring them"),
+    }
+}
+
+pub fn with_vouchers<R>(f: impl FnOnce(&VoucherBook<MAX_VOUCHERS>) -> R) -> R {
+    VOUCHERS.lock(|book| f(&book.borrow()))
+}
+
+/// Creates a batch of `count` vouchers and returns its number.
+pub fn generate_vouchers(count: usize, terms: VoucherTerms) -> Result<u16, GenerateVouchersError> {
+    let batch = VOUCHERS
+        .lock(|book| book.borrow_mut().generate(count, terms, random::u32))
+        .map_err(GenerateVouchersError::Invalid)?;
+    if let Err(e) = save_vouchers() {
+        // Codes that can't be kept across a reboot must not be handed out
+        VOUCHERS.lock(|book| book.borrow_mut().remove_batch(batch));
+        return Err(GenerateVouchersError::Storage(e));
+    }
+    println!("Hotspot: generated {} vouchers in batch {}", count, batch);
+    Ok(batch)
+}
+
+pub fn delete_voucher_batch(batch: u16) -> Result<usize, StorageError> {
+    let removed = VOUCHERS.lock(|book| book.borrow_mut().remove_batch(batch));
+    if removed > 0 {
+        save_vouchers()?;
+    }
+    Ok(removed)
+}
+
+pub fn delete_voucher(code: &str) -> Result<bool, StorageError> {
+    let removed = VOUCHERS.lock(|book| book.borrow_mut().remove(code));
+    if removed {
+        save_vouchers()?;
+    }
+    Ok(removed)
+}
+
+fn save_vouchers() -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    VOUCHERS.lock(|book| _ = book.borrow().write_to(&mut text));
+    storage::save(Record::Vouchers, text.as_bytes())
+}
+
+pub fn sessions() -> heapless::Vec<Session, MAX_SESSIONS> {
+    SESSIONS.lock(|sessions| sessions.borrow().sessions().iter().cloned().collect())
+}
+
+/// Decides whether an AP client's packet to `dst` may go upstream.
+pub fn access(
+    mac: &[u8; 6],
+    ip: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: Protocol,
+    dst_port: u16,
+) -> Access {
+    let now = Instant::now().as_secs();
+    POLICY.lock(|policy| {
+        let policy = policy.borrow();
+        if !policy.enabled {
+            return Access::Allow;
+        }
+        let authorized = SESSIONS.lock(|sessions| sessions.borrow().is_authorized(mac, ip, now));
+        let walled_garden = GARDEN.lock(|garden| garden.borrow().contains(dst, now));
+        policy.access(authorized, protocol, dst_port, walled_garden)
+    })
+}
+
+/// Decides whether an AP client's packet to the gateway itself may go to
+/// the AP stack.
+pub fn local_access(mac: &[u8; 6], ip: Ipv4Addr, service: Option<(Protocol, u16)>) -> bool {
+    let now = Instant::now().as_secs();
+    POLICY.lock(|policy| {
+        let policy = policy.borrow();
+        if !policy.enabled {
+            return true;
+        }
+        let authorized = SESSIONS.lock(|sessions| sessions.borrow().is_authorized(mac, ip, now));
+        policy.local_access(authorized, service, HTTP_PORT)
+    })
+}
+
+/// Counts a client's traffic against its session and, for a voucher, against
+/// the allowance all of the voucher's devices share.
+pub fn account(mac: &[u8; 6], bytes_up: u64, bytes_down: u64) {
+    let voucher = SESSIONS.lock(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        sessions.account(mac, bytes_up, bytes_down);
+        sessions
+            .get(mac)
+            .filter(|session| session.voucher)
+            .map(|session| session.username.clone())
+    });
+    let Some(code) = voucher else {
+        return;
+    };
+    let state = VOUCHERS.lock(|book| {
+        book.borrow_mut()
+            .account(&code, bytes_up.saturating_add(bytes_down))
+    });
+    if state == Some(VoucherState::Exhausted) {
+        // Saved once `expire_sessions` ends them, not from the packet path
+        SESSIONS.lock(|sessions| sessions.borrow_mut().cap_voucher(&code));
+    }
+}
+
+/// Looks at a DNS response on its way to a client and lets the addresses of
+/// walled-garden domains through.
+pub fn learn_dns(msg: &[u8]) {
+    let now = Instant::now().as_secs();
+    POLICY.lock(|policy| {
+        let policy = policy.borrow();
+        if !policy.enabled || policy.walled_garden.is_empty() {
+            return;
+        }
+        let Some((name, records)) = walled_garden::a_records(msg) else {
+            return;
+        };
+        if !policy.in_walled_garden(&name) {
+            return;
+        }
+        GARDEN.lock(|garden| {
+            let mut garden = garden.borrow_mut();
+            for (ip, ttl) in records {
+                garden.learn(ip, ttl, now);
+            }
+        });
+    });
+}
+
+/// Checks the credentials of the client at `ip`, against the RADIUS servers
+/// when they're configured, and authorizes it.
+pub async fn login(ip: Ipv4Addr, username: &str, password: &str) -> Result<Session, LoginError> {
+    let policy = policy();
+    if !policy.enabled {
+        return Err(LoginError::Disabled);
+    }
+    let mac = lease_mac(ip).ok_or(LoginError::NoLease)?;
+
+    let mut duration_secs = policy.session_secs as u64;
+    if radius_client::config().enabled {
+        match radius_client::authenticate(username, password, &mac, ip).await {
+            Ok(accept) => {
+                if let Some(timeout) = accept.session_timeout {
+                    duration_secs = timeout.into();
+                }
+            }
+            Err(AuthError::Rejected) => return Err(LoginError::InvalidCredentials),
+            Err(e) => return Err(LoginError::AuthServer(e)),
+        }
+    } else if !policy.check_local_user(username, password) {
+        return Err(LoginError::InvalidCredentials);
+    }
+    authorize(mac, ip, username, duration_secs, policy.quota_bytes, false)
+}
+
+/// Authorizes the client at `ip` with a voucher code.
+pub fn redeem_voucher(ip: Ipv4Addr, code: &str) -> Result<Session, LoginError> {
+    if !policy().enabled {
+        return Err(LoginError::Disabled);
+    }
+    let mac = lease_mac(ip).ok_or(LoginError::NoLease)?;
+    let grant = VOUCHERS
+        .lock(|book| book.borrow_mut().redeem(code, &mac))
+        .map_err(LoginError::Voucher)?;
+    let code = code.trim().to_ascii_uppercase();
+    let session = authorize(
+        mac,
+        ip,
+        &code,
+        grant.duration_secs.into(),
+        grant.quota_bytes,
+        true,
+    )?;
+    if let Err(e) = save_vouchers() {
+        log::warn!("Failed to save vouchers: {}", e.as_str());
+    }
+    Ok(session)
+}
+
+fn lease_mac(ip: Ipv4Addr) -> Option<[u8; 6]> {
+    with_dhcp_server(|server| {
+        server
+            .leases()
+            .iter()
+            .find(|lease| lease.bound && lease.ip == ip)
+            .map(|lease| lease.mac)
+    })
+    .flatten()
+}
+
+fn authorize(
+    mac: [u8; 6],
+    ip: Ipv4Addr,
+    username: &str,
+    duration_secs: u64,
+    quota_bytes: Option<u64>,
+    voucher: bool,
+) -> Result<Session, LoginError> {
+    let now = Instant::now().as_secs();
+    let mut session = Session::new(
+        random::u32(),
+        mac,
+        ip,
+        username,
+        now,
+        duration_secs,
+        quota_bytes,
+    );
+    session.voucher = voucher;
+    let replaced = SESSIONS
+        .lock(|sessions| sessions.borrow_mut().start(session.clone()))
+        .map_err(|_| LoginError::TooManySessions)?;
+    if let Some(replaced) = replaced {
+        end_session(replaced, EndReason::Revoked);
+    }
+    println!(
+        "Hotspot: {} ({}) logged in as {}",
+        MacDisplay(&mac),
+        ip,
+        session.username
+    );
+    radius_client::account(AcctStatus::Start, &session);
+    Ok(session)
+}
+
+pub fn revoke(mac: &[u8; 6]) -> bool {
+    match SESSIONS.lock(|sessions| sessions.borrow_mut().end(mac)) {
+        Some(session) => {
+            end_session(session, EndReason::Revoked);
+            true
+        }
+        None => false,
+    }
+}
+
+fn end_session(session: Session, reason: EndReason) {
+    println!(
+        "Hotspot: session of {} ({}) ended, {}",
+        MacDisplay(&session.mac),
+        session.username,
+        reason.as_str()
+    );
+    radius_client::account(AcctStatus::Stop(reason), &session);
+    // The voucher was charged as the traffic went, keep what it has left
+    if session.voucher {
+        if let Err(e) = save_vouchers() {
+            log::warn!("Failed to save vouchers: {}", e.as_str());
+        }
+    }
+    forward::forget_client(&session.mac);
+}
+
+#[embassy_executor::task]
+pub async fn expire_sessions() {
+    let mut vouchers_saved = Instant::now().as_secs();
+    loop {
+        Timer::after(SESSION_CHECK_INTERVAL).await;
+        let now = Instant::now().as_secs();
+        while let Some((session, reason)) =
+            SESSIONS.lock(|sessions| sessions.borrow_mut().take_ended(now))
+        {
+            end_session(session, reason);
+        }
+
+        let running = VOUCHERS.lock(|book| {
+            let mut book = book.borrow_mut();
+            book.tick(now);
+            book.vouchers()
+                .iter()
+                .any(|voucher| voucher.state() == VoucherState::Active)
+        });
+        if running && now - vouchers_saved >= VOUCHER_SAVE_INTERVAL {
+            vouchers_saved = now;
+            if let Err(e) = save_vouchers() {
+                log::warn!("Failed to save vouchers: {}", e.as_str());
+            }
+        }
+    }
+}
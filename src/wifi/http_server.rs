@@ -1,19 +1,41 @@
-use core::fmt::{Debug, Display};
-use edge_http::io::server::{Connection, DefaultServer, Handler};
+use alloc::string::String;
+use core::fmt::{Debug, Display, Write as _};
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use edge_http::io::server::{handle_connection, Connection, Handler};
 use edge_http::io::Error;
-use edge_http::Method;
-use edge_nal::TcpBind;
+use edge_http::{Headers, Method};
+use edge_nal::{TcpAccept, TcpBind};
 use edge_nal_embassy::{Tcp, TcpBuffers};
+use embassy_futures::join::join4;
 use embedded_io_async::{Read, Write};
 use esp_println::println;
 
-pub async fn run_http_server(stack: &embassy_net::Stack<'_>) -> Result<(), ()> {
-    let addr = "1.1.1.1:8080";
+use super::access_point::ap_network;
+use super::admin;
+use super::api;
+use super::form;
+use super::hotspot::{self, LoginError};
+use super::ipv6;
+use crate::router::tap::Side;
+use crate::storage;
+use crate::uplink::pem;
+
+pub const HTTP_PORT: u16 = 8080;
+const MAX_HEADERS: usize = 32;
+/// Uploaded certificates are read onto the heap. Anything longer than the
+/// PEM armor of a full record can't be stored, so it is refused unread.
+const MAX_PEM_LEN: usize = pem::encoded_len(storage::MAX_RECORD_LEN);
+
+/// Serves the AP's clients, or with `Side::Sta` the upstream network, which
+/// only gets the admin pages.
+pub async fn run_http_server(stack: &embassy_net::Stack<'_>, side: Side) -> Result<(), ()> {
+    // An unspecified address listens on both IPv4 and IPv6
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), HTTP_PORT);
     println!("Running HTTP server on {addr}");
 
     let buffers = TcpBuffers::<4, 2048, 2048>::new();
     let tcp = Tcp::new(*stack, &buffers);
-    let acceptor = match tcp.bind(addr.parse().unwrap()).await {
+    let acceptor = match tcp.bind(addr).await {
         Ok(a) => a,
         Err(e) => {
             println!("Failed to bind to {addr}: {:?}", e);
@@ -23,21 +45,41 @@ pub async fn run_http_server(stack: &embassy_net::Stack<'_>) -> Result<(), ()> {
 
     println!("HTTP server bound to {addr}, now accepting connections");
 
-    let result = DefaultServer::run(&mut DefaultServer::new(), None, acceptor, HttpHandler).await;
-
-    match result {
-        Ok(_) => {
-            println!("HTTP server has completed normally");
-            Ok(())
-        }
-        Err(e) => {
-            println!("HTTP server error: {:?}", e);
-            Err(())
+    // Same as DefaultServer, but keeping each connection's peer address so
+    // the login knows which client to authorize
+    let serve = |task_id: usize| {
+        let acceptor = &acceptor;
+        async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (peer, socket) = match acceptor.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        println!("HTTP server error: {:?}", e);
+                        return;
+                    }
+                };
+                handle_connection::<_, _, MAX_HEADERS>(
+                    socket,
+                    &mut buf,
+                    None,
+                    task_id,
+                    HttpHandler { peer, side },
+                )
+                .await;
+            }
         }
-    }
+    };
+    join4(serve(0), serve(1), serve(2), serve(3)).await;
+
+    println!("HTTP server stopped accepting connections");
+    Err(())
 }
 
-struct HttpHandler;
+struct HttpHandler {
+    peer: SocketAddr,
+    side: Side,
+}
 
 impl Handler for HttpHandler {
     type Error<E>
@@ -60,6 +102,10 @@ impl Handler for HttpHandler {
         );
 
         match (headers.method, headers.path) {
+            (_, path) if self.side == Side::Sta && !is_admin_path(path) => {
+                conn.initiate_response(404, Some("Not Found"), &[]).await?;
+                conn.write_all(b"Not Found").await?;
+            }
             (Method::Get, "/login") => {
                 conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/html")])
                     .await?;
@@ -67,96 +113,113 @@ impl Handler for HttpHandler {
                 conn.write_all(html_content.as_bytes()).await?;
             }
             (Method::Post, "/login") => {
-                // Handle login form submission
-                println!("Processing POST request to /login");
-
-                // Check Content-Length header to know how much data to read
-                let content_length = headers
-                    .headers
-                    .iter()
-                    .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
-                    .and_then(|(_, value)| value.parse::<usize>().ok())
-                    .unwrap_or(0);
-
-                println!("Content-Length: {}", content_length);
-
-                if content_length == 0 {
-                    println!("No content to read");
-                    conn.initiate_response(400, Some("Bad Request"), &[])
-                        .await?;
-                    conn.write_all(b"No form data provided").await?;
-                    return Ok(());
-                }
+                let content_length = content_length(&headers.headers);
+                let mut buffer = [0u8; 1024];
+                let len = read_body(conn, content_length, &mut buffer).await?;
+                let body = core::str::from_utf8(&buffer[..len]).unwrap_or("");
 
-                // Allocate buffer based on content length (with a reasonable maximum)
-                let max_size = core::cmp::min(content_length, 1024);
-                let mut buffer = [0u8; 1024]; // Fixed size buffer
-                let mut total_read = 0;
-
-                // Read the request body
-                println!("Reading request body...");
-                while total_read < max_size {
-                    match conn.read(&mut buffer[total_read..]).await {
-                        Ok(0) => break, // End of data
-                        Ok(n) => {
-                            total_read += n;
-                            println!("Read {} bytes, total: {}", n, total_read);
-                        }
-                        Err(e) => {
-                            println!("Error reading request body: {:?}", e);
-                            conn.initiate_response(500, Some("Internal Server Error"), &[])
-                                .await?;
-                            conn.write_all(b"Error reading form data").await?;
-                            return Ok(());
-                        }
+                let username = form::decoded_field::<32>(body, "username").unwrap_or_default();
+                let password = form::decoded_field::<32>(body, "password").unwrap_or_default();
+                let voucher = form::decoded_field::<16>(body, "voucher").unwrap_or_default();
+                let result = match self.peer.ip() {
+                    IpAddr::V4(ip) if !voucher.trim().is_empty() => {
+                        hotspot::redeem_voucher(ip, &voucher)
                     }
-                }
+                    IpAddr::V4(ip) => hotspot::login(ip, &username, &password).await,
+                    IpAddr::V6(_) => Err(LoginError::NoLease),
+                };
 
-                println!("Total bytes read: {}", total_read);
-
-                // Convert to string and parse
-                if let Ok(form_str) = core::str::from_utf8(&buffer[..total_read]) {
-                    println!("Received form data: {}", form_str);
-
-                    // Parse form data (application/x-www-form-urlencoded format)
-                    let mut username = "";
-                    let mut password = "";
-
-                    for pair in form_str.split('&') {
-                        println!("Processing pair: {}", pair);
-                        let mut parts = pair.split('=');
-                        if let Some(key) = parts.next() {
-                            if let Some(value) = parts.next() {
-                                println!("Found key: {}, value: {}", key, value);
-                                if key == "username" {
-                                    username = value;
-                                } else if key == "password" {
-                                    password = value;
-                                }
-                            }
-                        }
+                let mut page = String::new();
+                match result {
+                    Ok(session) => {
+                        conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/html")])
+                            .await?;
+                        _ = write!(
+                            page,
+                            "<html><body><h1>Login Successful</h1><p>You are online for {} minutes.</p></body></html>",
+                            (session.expires - session.started) / 60
+                        );
                     }
-
-                    println!("Parsed username: {}", username);
-                    println!("Parsed password: {}", password);
-
-                    // Send success response
-                    conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/html")])
+                    Err(e) => {
+                        println!("Hotspot login from {} failed: {}", self.peer, e.as_str());
+                        conn.initiate_response(
+                            403,
+                            Some("Forbidden"),
+                            &[("Content-Type", "text/html")],
+                        )
                         .await?;
-                    conn.write_all(b"<html><body><h1>Login Successful</h1><p>Username: ")
-                        .await?;
-                    conn.write_all(username.as_bytes()).await?;
-                    conn.write_all(b"</p><p>Password: ").await?;
-                    conn.write_all(password.as_bytes()).await?;
-                    conn.write_all(b"</p></body></html>").await?;
+                        _ = write!(
+                            page,
+                            "<html><body><h1>Login Failed</h1><p>{}</p><p><a href=\"/login\">Try again</a></p></body></html>",
+                            e.as_str()
+                        );
+                    }
+                }
+                conn.write_all(page.as_bytes()).await?;
+            }
+            _ if self.side == Side::Ap && is_portal_probe(&headers.headers) => {
+                // Requests for other hosts only get here through the hotspot
+                // redirect, send them to the login page
+                let mut location = String::new();
+                _ = write!(
+                    location,
+                    "http://{}:{HTTP_PORT}/login",
+                    ap_network().gateway
+                );
+                conn.initiate_response(302, Some("Found"), &[("Location", location.as_str())])
+                    .await?;
+            }
+            (method, path)
+                if is_admin_path(path)
+                    && !is_first_setup(self.side, method, path)
+                    && !admin::is_authorized(header(&headers.headers, "Authorization")) =>
+            {
+                conn.initiate_response(
+                    401,
+                    Some("Unauthorized"),
+                    &[
+                        ("WWW-Authenticate", "Basic realm=\"ap admin\""),
+                        ("Content-Type", "text/plain"),
+                    ],
+                )
+                .await?;
+                conn.write_all(b"Unauthorized").await?;
+            }
+            (Method::Get, "/clients") => {
+                conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/html")])
+                    .await?;
+                conn.write_all(include_str!("clients.html").as_bytes())
+                    .await?;
+            }
+            (Method::Get, "/station") => {
+                conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/html")])
+                    .await?;
+                conn.write_all(include_str!("station.html").as_bytes())
+                    .await?;
+            }
+            (Method::Post, path) if path.starts_with("/api/station/certificates?") => {
+                let content_length = content_length(&headers.headers);
+                if content_length > MAX_PEM_LEN {
+                    let response =
+                        api::Response::error(413, "Payload Too Large", "certificate is too large");
+                    write_response(conn, &response).await?;
                 } else {
-                    // Cannot parse as UTF-8
-                    println!("Failed to parse form data as UTF-8");
-                    conn.initiate_response(400, Some("Bad Request"), &[])
-                        .await?;
-                    conn.write_all(b"Invalid form data encoding").await?;
+                    let mut buffer = alloc::vec![0u8; content_length];
+                    let len = read_body(conn, content_length, &mut buffer).await?;
+                    let pem = core::str::from_utf8(&buffer[..len]).unwrap_or("");
+                    let query = path.split_once('?').map_or("", |(_, query)| query);
+                    write_response(conn, &api::post_station_certificate(query, pem)).await?;
                 }
             }
+            (method, path) if path.starts_with("/api/") => {
+                let content_length = content_length(&headers.headers);
+
+                let mut buffer = [0u8; 1024];
+                let len = read_body(conn, content_length, &mut buffer).await?;
+                let body = core::str::from_utf8(&buffer[..len]).unwrap_or("");
+
+                write_response(conn, &api::handle(method, path, body)).await?;
+            }
             (Method::Get, "/") => {
                 conn.initiate_response(200, Some("OK"), &[("Content-Type", "text/plain")])
                     .await?;
@@ -173,3 +236,83 @@ impl Handler for HttpHandler {
         Ok(())
     }
 }
+
+async fn write_response<T, const N: usize>(
+    conn: &mut Connection<'_, T, N>,
+    response: &api::Response,
+) -> Result<(), Error<T::Error>>
+where
+    T: Read + Write,
+{
+    conn.initiate_response(
+        response.status,
+        Some(response.reason),
+        &[("Content-Type", response.content_type)],
+    )
+    .await?;
+    conn.write_all(response.body.as_bytes()).await
+}
+
+fn header<'a, const N: usize>(headers: &'a Headers<'_, N>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+fn content_length<const N: usize>(headers: &Headers<'_, N>) -> usize {
+    header(headers, "Content-Length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+/// Everything but the hotspot login and the root page needs the admin
+/// password.
+fn is_admin_path(path: &str) -> bool {
+    path.starts_with("/api/") || path == "/clients" || path == "/station"
+}
+
+/// A device without an admin password lets anyone on the AP set the first
+/// one, or it could never be managed. Upstream has to wait for that.
+fn is_first_setup(side: Side, method: Method, path: &str) -> bool {
+    side == Side::Ap
+        && matches!(method, Method::Post)
+        && path == "/api/admin"
+        && !admin::is_configured()
+}
+
+fn is_portal_probe<const N: usize>(headers: &Headers<'_, N>) -> bool {
+    if !hotspot::policy().enabled {
+        return false;
+    }
+    header(headers, "Host").is_some_and(|host| !is_own_address(host))
+}
+
+fn is_own_address(host: &str) -> bool {
+    // IPv6 literals come bracketed, `[fd00::1]:8080`
+    if let Some(rest) = host.strip_prefix('[') {
+        let host = rest.split(']').next().unwrap_or(rest);
+        return host.parse::<Ipv6Addr>() == Ok(ipv6::ipv6_config().address());
+    }
+    let host = host.split(':').next().unwrap_or(host);
+    host.parse::<Ipv4Addr>() == Ok(ap_network().gateway)
+}
+
+async fn read_body<T, const N: usize>(
+    conn: &mut Connection<'_, T, N>,
+    content_length: usize,
+    buffer: &mut [u8],
+) -> Result<usize, Error<T::Error>>
+where
+    T: Read + Write,
+{
+    let max_size = core::cmp::min(content_length, buffer.len());
+    let mut total_read = 0;
+    while total_read < max_size {
+        match conn.read(&mut buffer[total_read..max_size]).await? {
+            0 => break,
+            n => total_read += n,
+        }
+    }
+    Ok(total_read)
+}
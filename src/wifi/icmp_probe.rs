@@ -0,0 +1,94 @@
+use core::net::Ipv4Addr;
+use embassy_net::icmp::{IcmpEndpoint, IcmpSocket, PacketMetadata};
+use embassy_net::{IpAddress, Stack};
+use embassy_time::{with_timeout, Duration};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{Icmpv4Packet, Icmpv4Repr};
+
+use crate::dhcp::conflict::AddressProbe;
+
+const PROBE_IDENT: u16 = 0xd4c9;
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+const PROBE_PAYLOAD: &[u8] = b"dhcp-probe";
+
+/// Probes addresses on the AP network with an ICMP echo request. A device
+/// that answers (or anything that makes the echo come back) means the
+/// address is taken.
+pub struct IcmpProbe {
+    stack: Stack<'static>,
+    seq_no: u16,
+}
+
+impl IcmpProbe {
+    pub fn new(stack: Stack<'static>) -> Self {
+        Self { stack, seq_no: 0 }
+    }
+}
+
+impl AddressProbe for IcmpProbe {
+    async fn in_use(&mut self, ip: Ipv4Addr) -> bool {
+        self.seq_no = self.seq_no.wrapping_add(1);
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0u8; 128];
+        let mut tx_meta = [PacketMetadata::EMPTY; 2];
+        let mut tx_buffer = [0u8; 128];
+        let mut socket = IcmpSocket::new(
+            self.stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        if socket.bind(IcmpEndpoint::Ident(PROBE_IDENT)).is_err() {
+            return false;
+        }
+
+        let request = Icmpv4Repr::EchoRequest {
+            ident: PROBE_IDENT,
+            seq_no: self.seq_no,
+            data: PROBE_PAYLOAD,
+        };
+        let mut buf = [0u8; 64];
+        let len = request.buffer_len();
+        request.emit(
+            &mut Icmpv4Packet::new_unchecked(&mut buf[..len]),
+            &ChecksumCapabilities::default(),
+        );
+        if socket
+            .send_to(&buf[..len], IpAddress::Ipv4(ip))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+
+        let seq_no = self.seq_no;
+        let reply = async {
+            let mut buf = [0u8; 64];
+            loop {
+                let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                    continue;
+                };
+                if from != IpAddress::Ipv4(ip) {
+                    continue;
+                }
+                let Ok(packet) = Icmpv4Packet::new_checked(&buf[..len]) else {
+                    continue;
+                };
+                if let Ok(Icmpv4Repr::EchoReply {
+                    ident: PROBE_IDENT,
+                    seq_no: reply_seq_no,
+                    ..
+                }) = Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default())
+                {
+                    if reply_seq_no == seq_no {
+                        return;
+                    }
+                }
+            }
+        };
+
+        with_timeout(PROBE_TIMEOUT, reply).await.is_ok()
+    }
+}
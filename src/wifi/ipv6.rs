@@ -0,0 +1,388 @@
+use core::cell::{Cell, RefCell};
+use core::net::Ipv6Addr;
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_net::{ConfigV6, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+
+use super::bridge;
+use crate::random;
+use crate::router::dhcpv6::{self, MAX_MESSAGE_LEN};
+use crate::router::forward;
+use crate::router::ipv6::{self, ConfigError, Ipv6Config, Prefix, ADVERT_INTERVAL_SECS};
+use crate::router::ndp::{self, RouterAdvert, MIN_ADVERT_DELAY_SECS};
+use crate::router::tap::{self, Side};
+use crate::storage::{self, Record, StorageError};
+
+// RFC 4861 section 10: solicit at most three times, four seconds apart
+const MAX_SOLICITS: u8 = 3;
+const SOLICIT_INTERVAL: Duration = Duration::from_secs(4);
+const MAX_FRAME_LEN: usize = 320;
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<Option<Ipv6Config>>> =
+    Mutex::new(RefCell::new(None));
+static CONFIG_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static SOLICITED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static ADVERTS: Signal<CriticalSectionRawMutex, (Ipv6Addr, RouterAdvert)> = Signal::new();
+/// Set when upstream adverts say addresses come from DHCPv6, with the router
+/// to use as gateway.
+static MANAGED: Signal<CriticalSectionRawMutex, Option<Ipv6Addr>> = Signal::new();
+static DHCPV6_REPLIES: Channel<CriticalSectionRawMutex, heapless::Vec<u8, MAX_MESSAGE_LEN>, 2> =
+    Channel::new();
+static UPSTREAM: Mutex<CriticalSectionRawMutex, Cell<Option<Upstream>>> =
+    Mutex::new(Cell::new(None));
+
+/// The address the STA side configured from upstream Router Advertisements
+/// or DHCPv6.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    pub address: Ipv6Addr,
+    pub prefix_len: u8,
+    pub router: Option<Ipv6Addr>,
+    /// Seconds since boot when the address runs out, `None` for never.
+    pub expires_at: Option<u64>,
+    /// The address came from DHCPv6.
+    pub managed: bool,
+    /// A prefix delegated to us by DHCPv6. The AP network doesn't use it yet.
+    pub delegated: Option<Prefix>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetConfigError {
+    Invalid(ConfigError),
+    Storage(StorageError),
+}
+
+impl SetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+/// Loads the AP's IPv6 settings, picking a random unique local prefix the
+/// first time so every device ends up with a different one.
+pub fn load_ipv6() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let stored = storage::load(Record::Ipv6, &mut buf)
+        .and_then(|stored| core::str::from_utf8(stored).ok())
+        .and_then(Ipv6Config::parse);
+    let config = match stored {
+        Some(config) => config,
+        None => {
+            let mut global_id = [0u8; 5];
+            random::fill(&mut global_id);
+            let config = Ipv6Config::new(Prefix::unique_local(global_id, 0));
+            if let Err(e) = save(&config) {
+                log::warn!("Failed to store the IPv6 prefix: {}", e.as_str());
+            }
+            config
+        }
+    };
+    if config.enabled {
+        println!("IPv6: AP network is {}", config.prefix);
+    }
+    CONFIG.lock(|current| current.replace(Some(config)));
+}
+
+pub fn ipv6_config() -> Ipv6Config {
+    CONFIG.lock(|config| config.borrow().clone().expect("load_ipv6 was not called"))
+}
+
+pub fn set_ipv6_config(config: Ipv6Config) -> Result<(), SetConfigError> {
+    config.validate().map_err(SetConfigError::Invalid)?;
+    save(&config).map_err(SetConfigError::Storage)?;
+    CONFIG.lock(|current| current.replace(Some(config)));
+    CONFIG_CHANGED.signal(());
+    Ok(())
+}
+
+fn save(config: &Ipv6Config) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::Ipv6, text.as_bytes())
+}
+
+/// The AP stack's IPv6 configuration: the first address of the prefix.
+/// embassy-net takes a single address, so the link-local one Router
+/// Advertisements are sent from is answered for in [`answer_neighbor_solicit`].
+pub fn ap_config_v6() -> ConfigV6 {
+    let config = ipv6_config();
+    if !config.enabled {
+        return ConfigV6::None;
+    }
+    ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(config.address(), config.prefix.prefix_len()),
+        gateway: None,
+        dns_servers: Default::default(),
+    })
+}
+
+pub fn upstream() -> Option<Upstream> {
+    UPSTREAM.lock(|upstream| upstream.get())
+}
+
+/// Answers Neighbor Solicitations for the link-local address of `side`,
+/// which the stacks don't own: AP clients resolve it to reach the router our
+/// adverts name, upstream routers to answer the station. Returns whether
+/// `packet` was one of them.
+pub fn answer_neighbor_solicit(side: Side, src_mac: [u8; 6], packet: &[u8]) -> bool {
+    let Some(solicit) = ndp::NeighborSolicit::parse(packet) else {
+        return false;
+    };
+    let mac = forward::mac(side);
+    if solicit.target != ipv6::link_local(&mac) {
+        return false;
+    }
+    let router = side == Side::Ap;
+    if router && (bridge::bridge_mode() || !ipv6_config().enabled) {
+        return false;
+    }
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    if let Some(len) = solicit.answer_frame(&mut frame, mac, src_mac, router) {
+        tap::send(side, &frame[..len]);
+    }
+    true
+}
+
+/// Called by the forwarding path for IPv6 packets from AP clients.
+pub fn from_client(packet: &[u8]) {
+    if ndp::is_router_solicit(packet) {
+        SOLICITED.signal(());
+    }
+}
+
+/// Called by the forwarding path for IPv6 packets from upstream.
+pub fn from_upstream(packet: &[u8]) {
+    if let Some(advert) = RouterAdvert::parse(packet) {
+        ADVERTS.signal(advert);
+    } else if let Some(message) = dhcpv6::client_message(packet) {
+        if let Ok(message) = heapless::Vec::from_slice(message) {
+            _ = DHCPV6_REPLIES.try_send(message);
+        }
+    }
+}
+
+/// Announces the AP prefix every few minutes, and right away (rate limited)
+/// when a client asks or the settings change.
+#[embassy_executor::task]
+pub async fn run_router_adverts(stack: Stack<'static>) {
+    let mut current = ipv6_config();
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        if current.enabled && !bridge::bridge_mode() && stack.is_link_up() {
+            if let Some(sent) = last_sent {
+                Timer::at(sent + Duration::from_secs(MIN_ADVERT_DELAY_SECS)).await;
+            }
+            send_advert(&current.advert());
+            last_sent = Some(Instant::now());
+        }
+
+        let woken = select3(
+            Timer::after(Duration::from_secs(ADVERT_INTERVAL_SECS as u64)),
+            SOLICITED.wait(),
+            CONFIG_CHANGED.wait(),
+        )
+        .await;
+        if let Either3::Third(()) = woken {
+            let config = ipv6_config();
+            // Tell clients to drop addresses from a prefix we no longer serve
+            if current.enabled && (!config.enabled || config.prefix != current.prefix) {
+                send_advert(&current.withdrawal());
+            }
+            stack.set_config_v6(ap_config_v6());
+            if config.enabled {
+                println!("IPv6: AP network is now {}", config.prefix);
+            }
+            current = config;
+        }
+    }
+}
+
+fn send_advert(advert: &RouterAdvert) {
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    if let Some(len) = advert.write_frame(&mut frame, forward::mac(Side::Ap)) {
+        tap::send(Side::Ap, &frame[..len]);
+    }
+}
+
+/// Stateless address autoconfiguration on the STA side. Networks that hand
+/// out addresses with DHCPv6 instead are left to [`run_dhcpv6`].
+#[embassy_executor::task]
+pub async fn run_slaac(stack: Stack<'static>) {
+    let mac = forward::mac(Side::Sta);
+    loop {
+        while !stack.is_link_up() {
+            Timer::after(Duration::from_millis(500)).await;
+        }
+        ADVERTS.reset();
+        let mut solicits = 0;
+        while stack.is_link_up() {
+            if upstream().is_none() && solicits < MAX_SOLICITS {
+                let mut frame = [0u8; MAX_FRAME_LEN];
+                if let Some(len) = ndp::router_solicit_frame(&mut frame, mac) {
+                    tap::send(Side::Sta, &frame[..len]);
+                }
+                solicits += 1;
+            }
+            match select(ADVERTS.wait(), Timer::after(SOLICIT_INTERVAL)).await {
+                Either::First((router, advert)) => apply_advert(stack, router, &advert, &mac),
+                Either::Second(()) => {
+                    let now = Instant::now().as_secs();
+                    if upstream().is_some_and(|up| up.expires_at.is_some_and(|at| at <= now)) {
+                        println!("IPv6: upstream address expired");
+                        forget_upstream(stack);
+                    }
+                }
+            }
+        }
+        forget_upstream(stack);
+    }
+}
+
+fn apply_advert(stack: Stack<'static>, router: Ipv6Addr, advert: &RouterAdvert, mac: &[u8; 6]) {
+    let Some((address, info)) = ipv6::slaac_address(advert, mac) else {
+        if advert.managed {
+            MANAGED.signal((advert.router_lifetime > 0).then_some(router));
+        }
+        return;
+    };
+    let gateway = (advert.router_lifetime > 0).then_some(router);
+    let mut dns_servers = heapless::Vec::new();
+    if advert.dns_lifetime > 0 {
+        dns_servers.extend(advert.dns_servers.iter().copied().take(3));
+    }
+    let config = StaticConfigV6 {
+        address: Ipv6Cidr::new(address, info.prefix.prefix_len()),
+        gateway,
+        dns_servers,
+    };
+    if stack.config_v6().as_ref() != Some(&config) {
+        println!(
+            "IPv6: got {}/{} from {}",
+            address,
+            info.prefix.prefix_len(),
+            router
+        );
+        stack.set_config_v6(ConfigV6::Static(config));
+    }
+    let expires_at = (info.valid_lifetime != u32::MAX)
+        .then(|| Instant::now().as_secs() + info.valid_lifetime as u64);
+    UPSTREAM.lock(|upstream| {
+        upstream.set(Some(Upstream {
+            address,
+            prefix_len: info.prefix.prefix_len(),
+            router: gateway,
+            expires_at,
+            managed: false,
+            delegated: None,
+        }))
+    });
+}
+
+/// Stateful address configuration on the STA side, for networks whose
+/// adverts set the managed flag and offer no prefix for SLAAC. Also asks for
+/// a delegated prefix. The gateway still comes from the adverts.
+#[embassy_executor::task]
+pub async fn run_dhcpv6(stack: Stack<'static>) {
+    let mut frame = [0u8; tap::MAX_FRAME_LEN];
+    loop {
+        let mut router = MANAGED.wait().await;
+        println!("IPv6: upstream hands out addresses with DHCPv6");
+        let mut client = dhcpv6::Client::new(forward::mac(Side::Sta), random::u32(), true);
+        DHCPV6_REPLIES.clear();
+
+        while stack.is_link_up() {
+            let now = Instant::now().as_secs();
+            match client.poll(now, &mut frame) {
+                Some(dhcpv6::Event::Send(len)) => _ = tap::send(Side::Sta, &frame[..len]),
+                Some(dhcpv6::Event::Lost) => {
+                    println!("IPv6: DHCPv6 lease expired");
+                    forget_upstream(stack);
+                }
+                _ => {}
+            }
+
+            // Wakes up now and then to notice the link going down
+            let sleep = client
+                .next_at()
+                .saturating_sub(now)
+                .clamp(1, SOLICIT_INTERVAL.as_secs());
+            let woken = select3(
+                Timer::after(Duration::from_secs(sleep)),
+                DHCPV6_REPLIES.receive(),
+                MANAGED.wait(),
+            )
+            .await;
+            match woken {
+                Either3::First(()) => {}
+                Either3::Second(message) => {
+                    match client.handle(&message, Instant::now().as_secs()) {
+                        Some(dhcpv6::Event::Bound) => {
+                            if let Some(lease) = client.lease() {
+                                apply_lease(stack, lease, router);
+                            }
+                        }
+                        Some(dhcpv6::Event::Lost) => {
+                            println!("IPv6: DHCPv6 server took the lease back");
+                            forget_upstream(stack);
+                        }
+                        _ => {}
+                    }
+                }
+                Either3::Third(gateway) => {
+                    router = gateway;
+                    if let Some(lease) = client.lease() {
+                        apply_lease(stack, lease, router);
+                    }
+                }
+            }
+        }
+        // `run_slaac` forgets the address, and a new network is asked afresh
+        MANAGED.reset();
+    }
+}
+
+/// DHCPv6 says nothing about which prefixes are on-link, so the address is
+/// a /128 and everything else goes through the router (RFC 5942).
+fn apply_lease(stack: Stack<'static>, lease: &dhcpv6::Lease, router: Option<Ipv6Addr>) {
+    if let Some(prefix) = lease.prefix {
+        if upstream().and_then(|up| up.delegated) != Some(prefix) {
+            println!("IPv6: delegated {prefix}");
+        }
+    }
+    let Some(address) = lease.address else {
+        return;
+    };
+    let config = StaticConfigV6 {
+        address: Ipv6Cidr::new(address, 128),
+        gateway: router,
+        dns_servers: lease.dns_servers.clone(),
+    };
+    if stack.config_v6().as_ref() != Some(&config) {
+        println!("IPv6: leased {address} with DHCPv6");
+        stack.set_config_v6(ConfigV6::Static(config));
+    }
+    let expires_at = (lease.valid_secs != u32::MAX).then(|| lease.expires_at());
+    UPSTREAM.lock(|upstream| {
+        upstream.set(Some(Upstream {
+            address,
+            prefix_len: 128,
+            router,
+            expires_at,
+            managed: true,
+            delegated: lease.prefix,
+        }))
+    });
+}
+
+fn forget_upstream(stack: Stack<'static>) {
+    if UPSTREAM.lock(|upstream| upstream.take()).is_some() {
+        stack.set_config_v6(ConfigV6::None);
+    }
+}
@@ -2,4 +2,21 @@ pub mod station;
 pub mod access_point;
 pub mod wifi_controller;
 pub mod http_server;
+pub mod api;
+pub mod form;
+pub mod icmp_probe;
+pub mod clients;
+pub mod hotspot;
+pub mod radius_client;
+pub mod traffic;
+pub mod port_forwards;
+pub mod firewall;
+pub mod bridge;
+pub mod dhcp_relay;
+pub mod ipv6;
+pub mod uplink;
+pub mod enterprise;
+pub mod radio;
+pub mod admin;
+pub use ap_core::wifi::{admin_auth, ap_network, client_table, mac_filter};
 // pub mod mqtt_client;
\ No newline at end of file
@@ -0,0 +1,122 @@
+use core::cell::RefCell;
+use core::net::Ipv4Addr;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use super::access_point::{ap_network, with_dhcp_server};
+use crate::router::nat::Protocol;
+use crate::router::port_forward::{PortForward, PortForwards, RuleError};
+use crate::storage::{self, Record, StorageError};
+
+static FORWARDS: Mutex<CriticalSectionRawMutex, RefCell<PortForwards>> =
+    Mutex::new(RefCell::new(PortForwards::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortForwardError {
+    Invalid(RuleError),
+    Storage(StorageError),
+}
+
+impl PortForwardError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_port_forwards() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::PortForwards, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(PortForwards::parse)
+    {
+        Some(forwards) => {
+            FORWARDS.lock(|current| current.replace(forwards));
+        }
+        None => log::warn!("Stored port forwards are invalid, ignoring them"),
+    }
+}
+
+pub fn port_forwards() -> PortForwards {
+    FORWARDS.lock(|forwards| forwards.borrow().clone())
+}
+
+pub fn add_port_forward(rule: PortForward) -> Result<(), PortForwardError> {
+    let mut forwards = port_forwards();
+    forwards
+        .add(rule, &ap_network())
+        .map_err(PortForwardError::Invalid)?;
+    save(forwards).map_err(PortForwardError::Storage)
+}
+
+pub fn remove_port_forward(protocol: Protocol, external_port: u16) -> Result<bool, StorageError> {
+    let mut forwards = port_forwards();
+    if !forwards.remove(protocol, external_port) {
+        return Ok(false);
+    }
+    save(forwards)?;
+    Ok(true)
+}
+
+/// Logs rules the AP network has moved away from. They are kept, so they
+/// can be fixed, but reach nobody until then.
+pub fn warn_stale() {
+    let network = ap_network();
+    for rule in port_forwards().stale(&network) {
+        log::warn!(
+            "Port forward {} points outside the AP network {}/{}",
+            rule,
+            network.network(),
+            network.prefix_len
+        );
+    }
+}
+
+fn save(forwards: PortForwards) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = forwards.write_to(&mut text);
+    storage::save(Record::PortForwards, text.as_bytes())?;
+    FORWARDS.lock(|current| current.replace(forwards));
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    /// Taken from the client's DHCP lease, so forwarding targets should have
+    /// a reserved address. `None` while the client is offline.
+    pub mac: Option<[u8; 6]>,
+}
+
+/// Where a packet arriving on `external_port` of the STA address goes.
+pub fn inbound(protocol: Protocol, external_port: u16) -> Option<Target> {
+    let (ip, port) = FORWARDS.lock(|forwards| {
+        forwards
+            .borrow()
+            .inbound(protocol, external_port)
+            .map(|rule| (rule.internal_ip, rule.internal_port))
+    })?;
+    let mac = with_dhcp_server(|server| {
+        server
+            .leases()
+            .iter()
+            .find(|lease| lease.bound && lease.ip == ip)
+            .map(|lease| lease.mac)
+    })
+    .flatten();
+    Some(Target { ip, port, mac })
+}
+
+/// The external port a reply from a forwarded service goes out on.
+pub fn outbound(protocol: Protocol, internal_ip: Ipv4Addr, internal_port: u16) -> Option<u16> {
+    FORWARDS.lock(|forwards| {
+        forwards
+            .borrow()
+            .outbound(protocol, internal_ip, internal_port)
+    })
+}
@@ -0,0 +1,271 @@
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+use esp_wifi::wifi::{Configuration, WifiController};
+use esp_wifi_sys::include::{
+    esp_wifi_80211_tx, esp_wifi_get_channel, esp_wifi_set_country, esp_wifi_set_max_tx_power,
+    esp_wifi_set_ps, wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_country_t,
+    wifi_interface_t_WIFI_IF_AP, wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+    wifi_ps_type_t_WIFI_PS_MIN_MODEM, wifi_ps_type_t_WIFI_PS_NONE, ESP_OK,
+};
+
+use crate::radio::channel::{self, ChannelMove, ChannelMoves, MAX_MOVES, SWITCH_COUNT};
+use crate::radio::config::{ConfigError, RadioConfig};
+use crate::radio::mode::Interfaces;
+use crate::radio::regulatory::PowerSave;
+use crate::radio::survey::Survey;
+use crate::router::forward;
+use crate::router::tap::Side;
+use crate::storage::{self, Record, StorageError};
+
+// One default beacon interval, 100 TU
+const BEACON_INTERVAL: Duration = Duration::from_micros(102_400);
+// Tasks that only run while the AP is up, see `while_ap_up`
+const AP_SERVICES: usize = 2;
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<RadioConfig>> =
+    Mutex::new(RefCell::new(RadioConfig::new()));
+static MOVES: Mutex<CriticalSectionRawMutex, RefCell<ChannelMoves>> =
+    Mutex::new(RefCell::new(ChannelMoves::new()));
+static SURVEY: Mutex<CriticalSectionRawMutex, Cell<Option<Survey>>> = Mutex::new(Cell::new(None));
+static INTERFACES: Mutex<CriticalSectionRawMutex, Cell<Option<Interfaces>>> =
+    Mutex::new(Cell::new(None));
+static AP_HELD: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+static APPLIED: Mutex<CriticalSectionRawMutex, Cell<Option<Applied>>> = Mutex::new(Cell::new(None));
+static AP_UP: Watch<CriticalSectionRawMutex, bool, AP_SERVICES> = Watch::new();
+
+/// The settings last handed to the driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Applied {
+    pub country: &'static str,
+    pub tx_power: u8,
+    pub power_save: PowerSave,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetConfigError {
+    Invalid(ConfigError),
+    Storage(StorageError),
+}
+
+impl SetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_config() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::Radio, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(RadioConfig::parse)
+    {
+        Some(config) => {
+            CONFIG.lock(|current| current.replace(config));
+        }
+        None => log::warn!("Stored radio configuration is invalid, ignoring it"),
+    }
+}
+
+pub fn config() -> RadioConfig {
+    CONFIG.lock(|config| *config.borrow())
+}
+
+/// The channel policy takes effect on the next connection attempt of the
+/// station, the rest within a couple of seconds.
+pub fn set_config(config: RadioConfig) -> Result<(), SetConfigError> {
+    config.validate().map_err(SetConfigError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::Radio, text.as_bytes()).map_err(SetConfigError::Storage)?;
+    CONFIG.lock(|current| current.replace(config));
+    Ok(())
+}
+
+/// The channel the radio, and so both interfaces, is on.
+pub fn channel() -> Option<u8> {
+    let mut primary = 0;
+    let mut second = 0;
+    if unsafe { esp_wifi_get_channel(&mut primary, &mut second) } != ESP_OK as i32 {
+        return None;
+    }
+    Some(primary)
+}
+
+/// Tells the AP's clients the AP is about to move to `to`, counting down
+/// over a few beacon intervals. Returns whether any announcement went out.
+pub async fn announce_switch(to: u8) -> bool {
+    let ap_mac = forward::mac(Side::Ap);
+    let mut sent = false;
+    for count in (1..=SWITCH_COUNT).rev() {
+        let frame = channel::csa_frame(ap_mac, to, count);
+        let result = unsafe {
+            esp_wifi_80211_tx(
+                wifi_interface_t_WIFI_IF_AP,
+                frame.as_ptr() as *const _,
+                frame.len() as i32,
+                true,
+            )
+        };
+        if result == ESP_OK as i32 {
+            sent = true;
+        } else {
+            log::warn!("Failed to send a channel switch announcement: {result}");
+        }
+        Timer::after(BEACON_INTERVAL).await;
+    }
+    sent
+}
+
+/// Logs the AP being moved by the station joining on another channel.
+pub fn record_move(from: u8, to: u8, announced: bool) {
+    println!(
+        "AP moved from channel {} to {} to follow the station{}",
+        from,
+        to,
+        if announced { ", clients were told" } else { "" }
+    );
+    let channel_move = ChannelMove {
+        from,
+        to,
+        at: Instant::now().as_secs(),
+        announced,
+    };
+    MOVES.lock(|moves| moves.borrow_mut().record(channel_move));
+}
+
+pub fn moves() -> heapless::Vec<ChannelMove, MAX_MOVES> {
+    MOVES.lock(|moves| moves.borrow().iter().copied().collect())
+}
+
+pub fn survey() -> Option<Survey> {
+    SURVEY.lock(|survey| survey.get())
+}
+
+pub fn set_survey(survey: Survey) {
+    SURVEY.lock(|current| current.set(Some(survey)));
+}
+
+pub fn applied() -> Option<Applied> {
+    APPLIED.lock(|applied| applied.get())
+}
+
+/// Hands the regulatory and power settings to the driver, if they changed
+/// since the last call. Called periodically by the connection task, since
+/// power saving also depends on whether the AP has clients.
+pub fn apply_settings(ap_clients: bool) {
+    let config = config();
+    let wanted = Applied {
+        country: config.country.code,
+        tx_power: config.tx_power(),
+        power_save: config.power_save.effective(ap_clients),
+    };
+    let applied = applied();
+    if applied == Some(wanted) {
+        return;
+    }
+
+    // Setting the country resets the transmit power, so it goes first
+    if applied.is_none_or(|applied| applied.country != wanted.country) {
+        let code = config.country.code.as_bytes();
+        let mut country: wifi_country_t = unsafe { core::mem::zeroed() };
+        country.cc = [code[0] as _, code[1] as _, 0];
+        country.schan = 1;
+        country.nchan = config.country.channels;
+        country.max_tx_power = config.country.max_power_dbm as i8;
+        // Manual, so joining an access point doesn't adopt its country
+        country.policy = wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL;
+        if unsafe { esp_wifi_set_country(&country) } != ESP_OK as i32 {
+            log::warn!("Failed to set the country to {}", config.country.code);
+        }
+    }
+    // In units of a quarter dBm
+    if unsafe { esp_wifi_set_max_tx_power(wanted.tx_power as i8 * 4) } != ESP_OK as i32 {
+        log::warn!(
+            "Failed to limit the transmit power to {} dBm",
+            wanted.tx_power
+        );
+    }
+    let power_save = match wanted.power_save {
+        PowerSave::None => wifi_ps_type_t_WIFI_PS_NONE,
+        PowerSave::Min => wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+        PowerSave::Max => wifi_ps_type_t_WIFI_PS_MAX_MODEM,
+    };
+    if unsafe { esp_wifi_set_ps(power_save) } != ESP_OK as i32 {
+        log::warn!(
+            "Failed to set power saving to {}",
+            wanted.power_save.as_str()
+        );
+    }
+    println!(
+        "Radio set to country {}, {} dBm, power saving {}",
+        wanted.country,
+        wanted.tx_power,
+        wanted.power_save.as_str()
+    );
+    APPLIED.lock(|current| current.set(Some(wanted)));
+}
+
+/// The interfaces the radio was last set to run.
+pub fn interfaces() -> Option<Interfaces> {
+    INTERFACES.lock(|interfaces| interfaces.get())
+}
+
+/// Whether the operating mode wants the AP up but it is held down, because
+/// the driver can't run it next to a station on an 802.1X network.
+pub fn ap_held() -> bool {
+    AP_HELD.lock(|held| held.get())
+}
+
+pub fn set_ap_held(held: bool) {
+    if AP_HELD.lock(|current| current.replace(held)) != held && held {
+        log::warn!("The AP stays down while the station is on an 802.1X network");
+    }
+}
+
+/// Starts and stops the AP and station. The driver picks the radio mode
+/// from the kind of `configuration`, so it has to match `wanted`.
+pub fn set_interfaces(
+    controller: &mut WifiController<'static>,
+    wanted: Interfaces,
+    configuration: &Configuration,
+) {
+    if interfaces() == Some(wanted) {
+        return;
+    }
+    if let Err(e) = controller.set_configuration(configuration) {
+        log::warn!("Failed to switch the radio to {wanted:?}: {e:?}");
+        return;
+    }
+    let state = |up: bool| if up { "up" } else { "down" };
+    println!(
+        "Access point {}, station {}",
+        state(wanted.ap),
+        state(wanted.sta)
+    );
+    INTERFACES.lock(|current| current.set(Some(wanted)));
+    AP_UP.sender().send(wanted.ap);
+}
+
+/// Runs `serve` whenever the AP is up. Taking the AP down drops it, and
+/// with it its sockets; it starts over when the AP comes back. A `serve`
+/// that gives up isn't retried until then.
+pub async fn while_ap_up<F: Future>(mut serve: impl FnMut() -> F) -> ! {
+    let mut ap_up = AP_UP.receiver().expect("more AP services than AP_SERVICES");
+    loop {
+        ap_up.get_and(|up| *up).await;
+        if let Either::First(_) = select(serve(), ap_up.changed_and(|up| !*up)).await {
+            ap_up.changed_and(|up| !*up).await;
+        }
+    }
+}
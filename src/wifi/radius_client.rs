@@ -0,0 +1,270 @@
+use core::cell::{Cell, RefCell};
+use core::net::{Ipv4Addr, SocketAddrV4};
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+use esp_println::println;
+
+use super::hotspot;
+use crate::hotspot::session::Session;
+use crate::mac::MacDisplay;
+use crate::radius::client::{self, Event, Login, Nonce, Transport, MAX_PACKET_LEN};
+use crate::radius::config::{ConfigError, RadiusConfig, ACCT_SOURCE_PORT, AUTH_SOURCE_PORT};
+use crate::random;
+use crate::storage::{self, Record, StorageError};
+
+pub use crate::radius::client::{Accept, AcctStatus, AuthError};
+
+const ACCOUNTING_QUEUE_LEN: usize = 8;
+
+static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<RadiusConfig>> =
+    Mutex::new(RefCell::new(RadiusConfig::new()));
+static STACK: Mutex<CriticalSectionRawMutex, Cell<Option<Stack<'static>>>> =
+    Mutex::new(Cell::new(None));
+// Logins share the one authentication port, so they go out one at a time
+static AUTH_EXCHANGE: AsyncMutex<CriticalSectionRawMutex, ()> = AsyncMutex::new(());
+static ACCOUNTING: Channel<CriticalSectionRawMutex, (AcctStatus, Session), ACCOUNTING_QUEUE_LEN> =
+    Channel::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetConfigError {
+    Invalid(ConfigError),
+    Storage(StorageError),
+}
+
+impl SetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_config() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::Radius, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(RadiusConfig::parse)
+    {
+        Some(config) => {
+            CONFIG.lock(|current| current.replace(config));
+        }
+        None => log::warn!("Stored RADIUS configuration is invalid, ignoring it"),
+    }
+}
+
+pub fn config() -> RadiusConfig {
+    CONFIG.lock(|config| config.borrow().clone())
+}
+
+pub fn set_config(config: RadiusConfig) -> Result<(), SetConfigError> {
+    config.validate().map_err(SetConfigError::Invalid)?;
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::Radius, text.as_bytes()).map_err(SetConfigError::Storage)?;
+    CONFIG.lock(|current| current.replace(config));
+    Ok(())
+}
+
+/// RADIUS servers are reached through the upstream network, so requests go
+/// out of the STA stack.
+pub fn set_stack(stack: Stack<'static>) {
+    STACK.lock(|current| current.set(Some(stack)));
+}
+
+/// Queues an accounting record for `session`, if accounting is on.
+pub fn account(status: AcctStatus, session: &Session) {
+    let enabled = CONFIG.lock(|config| {
+        let config = config.borrow();
+        config.enabled && config.accounting
+    });
+    if enabled && ACCOUNTING.try_send((status, session.clone())).is_err() {
+        log::warn!("RADIUS accounting queue is full, dropping a record");
+    }
+}
+
+/// Sends an Access-Request for the client `mac`/`ip` logging in with
+/// `username` and `password`.
+pub async fn authenticate(
+    username: &str,
+    password: &str,
+    mac: &[u8; 6],
+    ip: Ipv4Addr,
+) -> Result<Accept, AuthError> {
+    let config = config();
+    let (stack, nas_ip) = uplink().ok_or(AuthError::NoUplink)?;
+
+    let mut nonce = Nonce {
+        identifier: random::u32() as u8,
+        authenticator: [0; 16],
+        challenge: [0; 16],
+    };
+    random::fill(&mut nonce.authenticator);
+    random::fill(&mut nonce.challenge);
+    let login = Login {
+        username,
+        password,
+        mac: *mac,
+        ip,
+    };
+
+    let _exchange = AUTH_EXCHANGE.lock().await;
+    let mut buffers = SocketBuffers::new();
+    let mut transport =
+        UdpTransport::bind(stack, &mut buffers, AUTH_SOURCE_PORT).ok_or(AuthError::Unreachable)?;
+    client::authenticate(&mut transport, &config, nas_ip, &login, &nonce).await
+}
+
+fn uplink() -> Option<(Stack<'static>, Ipv4Addr)> {
+    let stack = STACK.lock(|stack| stack.get())?;
+    let address = stack.config_v4()?.address.address();
+    Some((stack, address))
+}
+
+struct SocketBuffers {
+    rx_meta: [PacketMetadata; 2],
+    rx: [u8; MAX_PACKET_LEN],
+    tx_meta: [PacketMetadata; 2],
+    tx: [u8; MAX_PACKET_LEN],
+}
+
+impl SocketBuffers {
+    fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx: [0; MAX_PACKET_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 2],
+            tx: [0; MAX_PACKET_LEN],
+        }
+    }
+}
+
+/// Requests go out of a UDP socket on the STA stack.
+struct UdpTransport<'a> {
+    socket: UdpSocket<'a>,
+}
+
+impl<'a> UdpTransport<'a> {
+    fn bind(stack: Stack<'a>, buffers: &'a mut SocketBuffers, port: u16) -> Option<Self> {
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut buffers.rx_meta,
+            &mut buffers.rx,
+            &mut buffers.tx_meta,
+            &mut buffers.tx,
+        );
+        socket.bind(port).ok()?;
+        Some(Self { socket })
+    }
+}
+
+impl Transport for UdpTransport<'_> {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    async fn send(&mut self, to: SocketAddrV4, packet: &[u8]) -> bool {
+        let endpoint = IpEndpoint::new(IpAddress::Ipv4(*to.ip()), to.port());
+        self.socket.send_to(packet, endpoint).await.is_ok()
+    }
+
+    async fn receive(&mut self, buf: &mut [u8], deadline_ms: u64) -> Option<(usize, SocketAddrV4)> {
+        let datagram = async {
+            loop {
+                let Ok((len, meta)) = self.socket.recv_from(buf).await else {
+                    continue;
+                };
+                if let IpAddress::Ipv4(address) = meta.endpoint.addr {
+                    return (len, SocketAddrV4::new(address, meta.endpoint.port));
+                }
+            }
+        };
+        with_deadline(Instant::from_millis(deadline_ms), datagram)
+            .await
+            .ok()
+    }
+
+    fn report(&mut self, event: Event<'_>) {
+        match event {
+            Event::NoAnswer(server) => log::warn!("RADIUS server {} did not answer", server),
+            Event::FailedVerification(server) => log::warn!(
+                "RADIUS reply from {} failed verification, check the shared secret",
+                server
+            ),
+            Event::NoMessageAuthenticator => {
+                log::warn!("RADIUS reply without Message-Authenticator, ignoring it")
+            }
+            Event::Rejected { username, message } => {
+                println!("RADIUS: {} rejected: {}", username, message)
+            }
+            Event::Challenge => log::warn!("RADIUS Access-Challenge is not supported"),
+        }
+    }
+}
+
+async fn send_accounting(status: AcctStatus, session: &Session) {
+    let config = config();
+    let Some((stack, nas_ip)) = uplink() else {
+        log::warn!("No uplink, dropping RADIUS accounting record");
+        return;
+    };
+    let mut buffers = SocketBuffers::new();
+    let now = Instant::now().as_secs();
+    let identifier = random::u32() as u8;
+    let acknowledged = match UdpTransport::bind(stack, &mut buffers, ACCT_SOURCE_PORT) {
+        Some(mut transport) => {
+            client::account(
+                &mut transport,
+                &config,
+                nas_ip,
+                identifier,
+                status,
+                session,
+                now,
+            )
+            .await
+        }
+        None => false,
+    };
+    if !acknowledged {
+        println!(
+            "RADIUS: accounting for {} was not acknowledged",
+            MacDisplay(&session.mac)
+        );
+    }
+}
+
+#[embassy_executor::task]
+pub async fn run_accounting() {
+    let mut last_interim = Instant::now();
+    loop {
+        let interim_secs = CONFIG.lock(|config| config.borrow().interim_secs);
+        let wait = if interim_secs == 0 {
+            Duration::from_secs(60)
+        } else {
+            (last_interim + Duration::from_secs(interim_secs.into()))
+                .saturating_duration_since(Instant::now())
+        };
+        match select(ACCOUNTING.receive(), Timer::after(wait)).await {
+            Either::First((status, session)) => send_accounting(status, &session).await,
+            Either::Second(()) => {
+                last_interim = Instant::now();
+                let config = config();
+                if !config.enabled || !config.accounting || config.interim_secs == 0 {
+                    continue;
+                }
+                for session in hotspot::sessions() {
+                    send_accounting(AcctStatus::Interim, &session).await;
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,18 @@
 use embassy_executor::Spawner;
-use embassy_net::{Runner, StackResources};
+use embassy_net::{Runner, Stack, StackResources};
 use embassy_time::{Duration, Timer};
 use esp_println::println;
 use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
 
+use super::access_point::{set_upstream_dns, set_upstream_subnet};
+use super::dhcp_relay;
+use super::http_server::run_http_server;
+use super::ipv6;
+use super::radius_client;
+use super::uplink;
+use crate::router::forward::set_uplink;
+use crate::router::tap::{Side, Tap};
+
 macro_rules! mk_static {
     ($t:ty, $val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -15,18 +24,27 @@ macro_rules! mk_static {
 
 #[embassy_executor::task]
 pub async fn run_station(spawner: Spawner, wifi_interface: WifiDevice<'static, WifiStaDevice>) {
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    // Addressing is applied per network by `uplink::run_addressing`
+    let config = embassy_net::Config::default();
 
     let seed = 0x12345678_u64;
 
     let (stack, runner) = embassy_net::new(
-        wifi_interface.into(),
+        Tap::new(wifi_interface, Side::Sta),
         config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        // Room for the admin pages next to the relay, RADIUS and probes
+        mk_static!(StackResources<10>, StackResources::<10>::new()),
         seed,
     );
 
     spawner.spawn(net_task(runner)).ok();
+    radius_client::set_stack(stack);
+    spawner.spawn(radius_client::run_accounting()).ok();
+    spawner.spawn(dhcp_relay::run_relay(stack)).ok();
+    spawner.spawn(ipv6::run_slaac(stack)).ok();
+    spawner.spawn(ipv6::run_dhcpv6(stack)).ok();
+    spawner.spawn(uplink::run_addressing(stack)).ok();
+    spawner.spawn(serve_admin(stack)).ok();
 
     loop {
         if stack.is_link_up() {
@@ -36,20 +54,41 @@ pub async fn run_station(spawner: Spawner, wifi_interface: WifiDevice<'static, W
     }
 
     println!("Waiting to get IP address...");
+    let mut current = None;
     loop {
-        if let Some(cfg) = stack.config_v4() {
-            println!("Got IP: {}", cfg.address);
-            break;
+        let config = stack.config_v4();
+        if config != current {
+            match &config {
+                Some(cfg) => {
+                    println!("Got IP: {}", cfg.address);
+                    set_upstream_subnet(cfg.address.address(), cfg.address.prefix_len());
+                    set_upstream_dns(&cfg.dns_servers);
+                    set_uplink(cfg.gateway.map(|gateway| (cfg.address.address(), gateway)));
+                }
+                None => {
+                    println!("Lost IP address");
+                    set_uplink(None);
+                }
+            }
+            current = config;
         }
         Timer::after(Duration::from_millis(500)).await;
     }
+}
 
+/// The admin pages are served upstream too, in `sta` mode they are the
+/// only way in.
+#[embassy_executor::task]
+async fn serve_admin(stack: Stack<'static>) {
     loop {
-        Timer::after(Duration::from_millis(1_000)).await;
+        stack.wait_config_up().await;
+        if run_http_server(&stack, Side::Sta).await.is_err() {
+            Timer::after(Duration::from_secs(5)).await;
+        }
     }
 }
 
 #[embassy_executor::task]
-async fn net_task(mut runner: Runner<'static, WifiDevice<'static, WifiStaDevice>>) {
+async fn net_task(mut runner: Runner<'static, Tap<WifiDevice<'static, WifiStaDevice>>>) {
     runner.run().await
 }
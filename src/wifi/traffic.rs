@@ -0,0 +1,52 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+use crate::router::policer::{ClientTraffic, Direction, Policer, RateLimits};
+use crate::storage::{self, Record, StorageError};
+
+pub const MAX_TRACKED_CLIENTS: usize = 16;
+
+static POLICER: Mutex<CriticalSectionRawMutex, RefCell<Policer<MAX_TRACKED_CLIENTS>>> =
+    Mutex::new(RefCell::new(Policer::new()));
+
+pub fn load_rate_limits() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::RateLimits, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(RateLimits::parse)
+    {
+        Some(config) => POLICER.lock(|policer| policer.borrow_mut().set_config(config)),
+        None => log::warn!("Stored bandwidth limits are invalid, ignoring them"),
+    }
+}
+
+pub fn rate_limits() -> RateLimits {
+    POLICER.lock(|policer| policer.borrow().config().clone())
+}
+
+pub fn set_rate_limits(config: RateLimits) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = config.write_to(&mut text);
+    storage::save(Record::RateLimits, text.as_bytes())?;
+    POLICER.lock(|policer| policer.borrow_mut().set_config(config));
+    Ok(())
+}
+
+pub fn clients() -> heapless::Vec<ClientTraffic, MAX_TRACKED_CLIENTS> {
+    POLICER.lock(|policer| policer.borrow().clients().iter().copied().collect())
+}
+
+pub fn reset_counters(mac: Option<&[u8; 6]>) {
+    POLICER.lock(|policer| policer.borrow_mut().reset(mac));
+}
+
+/// Called by the forwarding path for every packet of an AP client, which
+/// drops the ones over the client's limit.
+pub fn admit(mac: &[u8; 6], direction: Direction, len: usize) -> bool {
+    let now = Instant::now().as_millis();
+    POLICER.lock(|policer| policer.borrow_mut().admit(mac, direction, len, now))
+}
@@ -0,0 +1,430 @@
+use core::cell::{Cell, RefCell};
+use core::net::Ipv4Addr;
+use embassy_futures::select::{select, Either};
+use embassy_net::{ConfigV4, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+use heapless::{String, Vec};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{
+    EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, IpAddress, IpProtocol,
+    Ipv4Packet, Ipv4Repr, UdpPacket,
+};
+
+use crate::dhcp::client::{Client, ClientOptions, Event, Lease, Outgoing};
+use crate::dhcp::packet::{CLIENT_PORT, SERVER_PORT};
+use crate::random;
+use crate::router::forward;
+use crate::router::tap::{self, Side};
+use crate::secrets;
+use crate::storage::{self, Record, StorageError};
+use crate::uplink::lease_cache::LeaseCache;
+use crate::uplink::profile::{Addressing, NetworkProfile, ProfileError, Profiles, StaticAddress};
+use crate::uplink::reconnect::ConnectStats;
+use crate::uplink::security::{self, Security, SecurityError, SeenSecurity};
+use crate::wifi::enterprise;
+
+const MAX_MESSAGE_LEN: usize = 600;
+const HEADERS_LEN: usize = 14 + 20 + 8;
+// Wake up now and then even with a long lease, the clock only counts up
+const MAX_SLEEP_SECS: u64 = 3600;
+const PROFILES_AAD: &[u8] = b"sta_profiles";
+
+type Message = Vec<u8, MAX_MESSAGE_LEN>;
+
+static PROFILES: Mutex<CriticalSectionRawMutex, RefCell<Profiles>> =
+    Mutex::new(RefCell::new(Profiles::new()));
+static JOINED: Mutex<CriticalSectionRawMutex, RefCell<Option<Joined>>> =
+    Mutex::new(RefCell::new(None));
+static SEEN_SECURITY: Mutex<CriticalSectionRawMutex, RefCell<SeenSecurity>> =
+    Mutex::new(RefCell::new(SeenSecurity::new()));
+static LAST_CONNECT: Mutex<CriticalSectionRawMutex, Cell<Option<ConnectStats>>> =
+    Mutex::new(Cell::new(None));
+static LEASE_CACHE: Mutex<CriticalSectionRawMutex, RefCell<LeaseCache>> =
+    Mutex::new(RefCell::new(LeaseCache::new()));
+static LEASE: Mutex<CriticalSectionRawMutex, RefCell<Option<Lease>>> =
+    Mutex::new(RefCell::new(None));
+static ADDRESSED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static REPLIES: Channel<CriticalSectionRawMutex, ([u8; 6], Message), 2> = Channel::new();
+
+/// The upstream access point the station is connected to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Joined {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetProfileError {
+    Invalid(ProfileError),
+    Storage(StorageError),
+}
+
+impl SetProfileError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Invalid(e) => e.as_str(),
+            Self::Storage(e) => e.as_str(),
+        }
+    }
+}
+
+pub fn load_profiles() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::StaProfiles, &mut buf) else {
+        return;
+    };
+    if let Some(text) = secrets::open(PROFILES_AAD, stored) {
+        match core::str::from_utf8(&text).ok().and_then(Profiles::parse) {
+            Some(profiles) => {
+                PROFILES.lock(|current| current.replace(profiles));
+            }
+            None => log::warn!("Stored station network profiles are invalid, ignoring them"),
+        }
+        return;
+    }
+    // Profiles saved before they were sealed are plain text; seal them now
+    match core::str::from_utf8(stored).ok().and_then(Profiles::parse) {
+        Some(profiles) => {
+            PROFILES.lock(|current| current.replace(profiles.clone()));
+            if let Err(e) = save(profiles) {
+                log::warn!(
+                    "Failed to seal the station network profiles: {}",
+                    e.as_str()
+                );
+            }
+        }
+        None => log::warn!("Stored station network profiles can't be opened, ignoring them"),
+    }
+}
+
+pub fn load_lease_cache() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::StaLeases, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(LeaseCache::parse)
+    {
+        Some(cache) => {
+            LEASE_CACHE.lock(|current| current.replace(cache));
+        }
+        None => log::warn!("Stored station leases are invalid, ignoring them"),
+    }
+}
+
+pub fn load_seen_security() {
+    let mut buf = [0u8; storage::MAX_RECORD_LEN];
+    let Some(stored) = storage::load(Record::StaSecurity, &mut buf) else {
+        return;
+    };
+    match core::str::from_utf8(stored)
+        .ok()
+        .and_then(SeenSecurity::parse)
+    {
+        Some(seen) => {
+            SEEN_SECURITY.lock(|current| current.replace(seen));
+        }
+        None => log::warn!("Stored station network security is invalid, ignoring it"),
+    }
+}
+
+pub fn profiles() -> Profiles {
+    PROFILES.lock(|profiles| profiles.borrow().clone())
+}
+
+pub fn set_profile(profile: NetworkProfile) -> Result<(), SetProfileError> {
+    let mut profiles = profiles();
+    let ssid = profile.ssid.clone();
+    profiles.set(profile).map_err(SetProfileError::Invalid)?;
+    save(profiles).map_err(SetProfileError::Storage)?;
+    // Setting a network up by hand overrides what we saw of it before
+    update_seen_security(|seen| seen.forget(&ssid));
+    reapply_if_joined(&ssid);
+    Ok(())
+}
+
+/// DHCP options for networks whose profile doesn't set its own.
+pub fn set_defaults(defaults: ClientOptions) -> Result<(), SetProfileError> {
+    let mut profiles = profiles();
+    profiles
+        .set_defaults(defaults)
+        .map_err(SetProfileError::Invalid)?;
+    save(profiles).map_err(SetProfileError::Storage)?;
+    if joined().is_some() {
+        CHANGED.signal(());
+    }
+    Ok(())
+}
+
+/// Returns whether there was a profile for `ssid`.
+pub fn remove_profile(ssid: &str) -> Result<bool, StorageError> {
+    let mut profiles = profiles();
+    if !profiles.remove(ssid) {
+        return Ok(false);
+    }
+    save(profiles)?;
+    update_seen_security(|seen| seen.forget(ssid));
+    enterprise::forget_certificates(ssid)?;
+    reapply_if_joined(ssid);
+    Ok(true)
+}
+
+/// Sealed as a whole, since enterprise logins carry passwords.
+fn save(profiles: Profiles) -> Result<(), StorageError> {
+    let mut text = alloc::string::String::new();
+    _ = profiles.write_to(&mut text);
+    storage::save(
+        Record::StaProfiles,
+        &secrets::seal(PROFILES_AAD, text.as_bytes()),
+    )?;
+    PROFILES.lock(|current| current.replace(profiles));
+    Ok(())
+}
+
+fn reapply_if_joined(ssid: &str) {
+    if joined().is_some_and(|joined| joined.ssid == ssid) {
+        CHANGED.signal(());
+    }
+}
+
+pub fn joined() -> Option<Joined> {
+    JOINED.lock(|joined| joined.borrow().clone())
+}
+
+/// Called by the connection task when the station joins or leaves a
+/// network, so the matching addressing gets applied.
+pub fn set_joined(network: Option<(&str, [u8; 6])>) {
+    let network = network.and_then(|(ssid, bssid)| {
+        Some(Joined {
+            ssid: ssid.try_into().ok()?,
+            bssid,
+        })
+    });
+    JOINED.lock(|joined| joined.replace(network));
+    CHANGED.signal(());
+}
+
+/// The authentication to join `ssid` with when it advertises `offered`,
+/// see [`security::choose`].
+pub fn choose_security(
+    ssid: &str,
+    wanted: Option<Security>,
+    offered: Security,
+) -> Result<Security, SecurityError> {
+    let seen = SEEN_SECURITY.lock(|seen| seen.borrow().get(ssid));
+    security::choose(wanted, offered, seen)
+}
+
+/// Called by the connection task after joining `ssid`.
+pub fn joined_with(ssid: &str, offered: Security) {
+    update_seen_security(|seen| seen.record(ssid, offered));
+}
+
+fn update_seen_security(update: impl FnOnce(&mut SeenSecurity) -> bool) {
+    let mut seen = SEEN_SECURITY.lock(|seen| seen.borrow().clone());
+    if !update(&mut seen) {
+        return;
+    }
+    let mut text = alloc::string::String::new();
+    _ = seen.write_to(&mut text);
+    if let Err(e) = storage::save(Record::StaSecurity, text.as_bytes()) {
+        log::warn!("Failed to store station network security: {}", e.as_str());
+    }
+    SEEN_SECURITY.lock(|current| current.replace(seen));
+}
+
+/// How the connection task made the current (or last) connection.
+pub fn last_connect() -> Option<ConnectStats> {
+    LAST_CONNECT.lock(|stats| stats.get())
+}
+
+pub fn set_last_connect(stats: ConnectStats) {
+    LAST_CONNECT.lock(|current| current.set(Some(stats)));
+}
+
+/// The lease our DHCP client currently holds on the joined network.
+pub fn lease() -> Option<Lease> {
+    LEASE.lock(|lease| lease.borrow().clone())
+}
+
+/// Whether the station has an address on the joined network, static or
+/// leased.
+pub fn addressed() -> bool {
+    ADDRESSED.lock(|addressed| addressed.get())
+}
+
+fn set_address(stack: Stack<'static>, config: ConfigV4) {
+    ADDRESSED.lock(|addressed| addressed.set(matches!(config, ConfigV4::Static(_))));
+    stack.set_config_v4(config);
+}
+
+fn set_lease(lease: Option<Lease>) {
+    LEASE.lock(|current| current.replace(lease));
+}
+
+/// Remembers the leased address for the next connection to `network`. The
+/// flash is only written when the address or access point changed, not on
+/// every renewal.
+fn remember_lease(network: &Joined, address: Ipv4Addr) {
+    let mut cache = LEASE_CACHE.lock(|cache| cache.borrow().clone());
+    if !cache.remember(&network.ssid, network.bssid, address) {
+        return;
+    }
+    let mut text = alloc::string::String::new();
+    _ = cache.write_to(&mut text);
+    if let Err(e) = storage::save(Record::StaLeases, text.as_bytes()) {
+        log::warn!("Failed to store the station lease: {}", e.as_str());
+    }
+    LEASE_CACHE.lock(|current| current.replace(cache));
+}
+
+/// Called by the forwarding path for DHCP messages to the station.
+pub fn dhcp_reply(src_mac: [u8; 6], message: &[u8]) {
+    if let Ok(message) = Vec::from_slice(message) {
+        _ = REPLIES.try_send((src_mac, message));
+    }
+}
+
+/// Configures the STA stack for the joined network: a static address, or a
+/// lease from our own DHCP client, which sends the configured hostname,
+/// client ID and vendor class.
+#[embassy_executor::task]
+pub async fn run_addressing(stack: Stack<'static>) {
+    loop {
+        set_lease(None);
+        let Some(joined) = joined() else {
+            set_address(stack, ConfigV4::None);
+            CHANGED.wait().await;
+            continue;
+        };
+        match profiles().addressing(&joined.ssid) {
+            Addressing::Static(config) => {
+                println!(
+                    "Station: static address {}/{}",
+                    config.address, config.prefix_len
+                );
+                set_address(stack, ConfigV4::Static(static_config(&config)));
+            }
+            Addressing::Dhcp(options) => {
+                set_address(stack, ConfigV4::None);
+                select(run_dhcp_client(stack, &joined, options), CHANGED.wait()).await;
+                continue;
+            }
+        }
+        CHANGED.wait().await;
+    }
+}
+
+fn static_config(config: &StaticAddress) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(config.address, config.prefix_len),
+        gateway: config.gateway,
+        dns_servers: config.dns_servers.clone(),
+    }
+}
+
+async fn run_dhcp_client(stack: Stack<'static>, network: &Joined, options: ClientOptions) {
+    let previous = LEASE_CACHE.lock(|cache| cache.borrow().address(&network.ssid, &network.bssid));
+    if let Some(address) = previous {
+        println!("Station DHCP: asking for {address} again");
+    }
+    let mut client = Client::new(forward::mac(Side::Sta), options, random::u32(), previous);
+    // Unicast renewals go to whoever delivered the ACK, the server or a relay
+    let mut server_mac = [0xff; 6];
+    let mut buf = [0u8; HEADERS_LEN + MAX_MESSAGE_LEN];
+    REPLIES.clear();
+
+    loop {
+        let now = Instant::now().as_secs();
+        match client.poll(now, &mut buf[HEADERS_LEN..]) {
+            Some(Event::Send(out)) => {
+                let dst_mac = if out.dst.is_broadcast() {
+                    [0xff; 6]
+                } else {
+                    server_mac
+                };
+                send(&mut buf, out, dst_mac);
+            }
+            Some(Event::Lost) => {
+                println!("Station DHCP: lease lost");
+                set_lease(None);
+                set_address(stack, ConfigV4::None);
+            }
+            _ => {}
+        }
+
+        let sleep = client
+            .next_at()
+            .saturating_sub(now)
+            .clamp(1, MAX_SLEEP_SECS);
+        let (src_mac, message) =
+            match select(Timer::after(Duration::from_secs(sleep)), REPLIES.receive()).await {
+                Either::First(()) => continue,
+                Either::Second(reply) => reply,
+            };
+        match client.handle(&message, Instant::now().as_secs()) {
+            Some(Event::Bound) => {
+                let Some(lease) = client.lease() else {
+                    continue;
+                };
+                server_mac = src_mac;
+                let config = StaticConfigV4 {
+                    address: Ipv4Cidr::new(lease.address, lease.prefix_len),
+                    gateway: lease.gateway,
+                    dns_servers: lease.dns_servers.clone(),
+                };
+                if stack.config_v4().as_ref() != Some(&config) {
+                    println!(
+                        "Station DHCP: leased {}/{} from {} for {} s",
+                        lease.address, lease.prefix_len, lease.server, lease.lease_secs
+                    );
+                    set_address(stack, ConfigV4::Static(config));
+                }
+                remember_lease(network, lease.address);
+                set_lease(Some(lease.clone()));
+            }
+            Some(Event::Lost) => {
+                println!("Station DHCP: server took the lease back");
+                set_lease(None);
+                set_address(stack, ConfigV4::None);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends the message at `buf[HEADERS_LEN..]` as a raw frame, the STA stack
+/// can't send from 0.0.0.0.
+fn send(buf: &mut [u8], out: Outgoing, dst_mac: [u8; 6]) {
+    let caps = ChecksumCapabilities::default();
+    let udp_len = 8 + out.len;
+    let mut eth = EthernetFrame::new_unchecked(&mut buf[..HEADERS_LEN + out.len]);
+    EthernetRepr {
+        src_addr: EthernetAddress(forward::mac(Side::Sta)),
+        dst_addr: EthernetAddress(dst_mac),
+        ethertype: EthernetProtocol::Ipv4,
+    }
+    .emit(&mut eth);
+    let mut ip = Ipv4Packet::new_unchecked(eth.payload_mut());
+    Ipv4Repr {
+        src_addr: out.src,
+        dst_addr: out.dst,
+        next_header: IpProtocol::Udp,
+        payload_len: udp_len,
+        hop_limit: 64,
+    }
+    .emit(&mut ip, &caps);
+    let mut udp = UdpPacket::new_unchecked(ip.payload_mut());
+    // The message is already in place right behind the headers
+    udp.set_src_port(CLIENT_PORT);
+    udp.set_dst_port(SERVER_PORT);
+    udp.set_len(udp_len as u16);
+    udp.fill_checksum(&IpAddress::Ipv4(out.src), &IpAddress::Ipv4(out.dst));
+    tap::send(Side::Sta, eth.into_inner());
+}
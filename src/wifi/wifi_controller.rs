@@ -1,14 +1,44 @@
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::timer::timg::TimerGroup;
 use esp_println::println;
 use esp_wifi::wifi::{
-    AccessPointConfiguration, ClientConfiguration, Configuration, WifiController, WifiEvent,
-    WifiState,
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
+    EapClientConfiguration, ScanConfig, ScanTypeConfig, TtlsPhase2Method, WifiController,
+    WifiEvent, WifiState,
 };
 use esp_wifi::{init, EspWifiController};
+use esp_wifi_sys::include::{esp_wifi_sta_get_ap_info, wifi_ap_record_t, ESP_OK};
 
-use super::access_point::run_ap;
+use super::access_point::{load_ap_network, run_ap};
+use super::admin;
+use super::bridge::load_bridge_mode;
+use super::clients::{clients, load_mac_filter, register_event_handlers, track_clients};
+use super::dhcp_relay;
+use super::enterprise::{self, load_certificates};
+use super::firewall::load_firewall;
+use super::hotspot::{load_policy, load_vouchers};
+use super::ipv6::load_ipv6;
+use super::port_forwards::load_port_forwards;
+use super::radio;
+use super::radius_client;
 use super::station::run_station;
+use super::traffic::load_rate_limits;
+use super::uplink;
+use crate::mac::MacDisplay;
+use crate::radio::channel::{self, ChannelChange};
+use crate::radio::mode::{Interfaces, ModeMachine};
+use crate::radio::survey::{self, AutoChannel, Scores, Survey};
+use crate::secrets;
+use crate::uplink::enterprise::{EapMethod, Enterprise, Phase2};
+use crate::uplink::reconnect::{Method, Plan, Reconnect, SCAN_RETRY_SECS};
+use crate::uplink::roaming::{RoamConfig, Roaming};
+use crate::uplink::security::{Security, SecurityError};
+
+const RSSI_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MODE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_AP_CHANNEL: u8 = 1;
+const MAX_SURVEY_RESULTS: usize = 32;
 
 macro_rules! mk_static {
     ($t:ty, $val:expr) => {{
@@ -39,7 +69,28 @@ pub async fn init_wifi(
     let (ap_interface, sta_interface, ap_sta_controller) =
         esp_wifi::wifi::new_ap_sta(&init, wifi).expect("Failed to init AP/STA mode");
 
+    load_mac_filter();
+    load_policy();
+    load_vouchers();
+    load_rate_limits();
+    load_port_forwards();
+    load_ap_network();
+    load_firewall();
+    load_bridge_mode();
+    radius_client::load_config();
+    dhcp_relay::load_config();
+    load_ipv6();
+    // The radio is up, so the RNG can make a device key if one is needed
+    secrets::init();
+    uplink::load_profiles();
+    uplink::load_lease_cache();
+    uplink::load_seen_security();
+    load_certificates();
+    radio::load_config();
+    admin::load_config();
+    register_event_handlers();
     spawner.spawn(connection(ap_sta_controller)).unwrap();
+    spawner.spawn(track_clients()).unwrap();
 
     spawner.spawn(run_station(spawner, sta_interface)).unwrap();
     spawner.spawn(run_ap(spawner, ap_interface)).unwrap();
@@ -50,6 +101,16 @@ pub async fn init_wifi(
     }
 }
 
+/// Where the last successful connection went, enough to connect again
+/// without scanning.
+#[derive(Clone, Copy, Debug)]
+struct CachedAp {
+    bssid: [u8; 6],
+    channel: u8,
+    /// What the access point advertised.
+    security: Security,
+}
+
 #[embassy_executor::task]
 async fn connection(mut controller: WifiController<'static>) {
     println!("Start wifi connection task");
@@ -57,82 +118,551 @@ async fn connection(mut controller: WifiController<'static>) {
         "Device capabilities: {:?}",
         controller.capabilities().unwrap()
     );
+    let mut reconnect = Reconnect::<CachedAp>::new();
+    let mut roaming = Roaming::new(RoamConfig::default());
+    let mut surveyed_at = None;
+    let mut modes = ModeMachine::new(radio::config().mode, Instant::now().as_secs());
+    // What the station joins next, or last joined
+    let mut station = Configuration::Client(Default::default());
     loop {
         match esp_wifi::wifi::wifi_state() {
             WifiState::StaConnected => {
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_millis(5000)).await;
+                let ssid = get_wifi_credentials().0;
+                if let Some(ap) =
+                    stay_connected(&mut controller, &mut roaming, &mut modes, &station, ssid).await
+                {
+                    reconnect.roam_to(ap);
+                    if let Err(e) = controller.disconnect_async().await {
+                        println!("Failed to leave the access point: {e:?}");
+                    }
+                }
+                uplink::set_joined(None);
+                reconnect.disconnected(Instant::now().as_millis());
             }
             WifiState::ApStarted => {
-                controller.wait_for_event(WifiEvent::ApStop).await;
-                Timer::after(Duration::from_millis(5000)).await;
+                // Wake up now and then to notice operating mode changes
+                if let Either::First(()) = select(
+                    controller.wait_for_event(WifiEvent::ApStop),
+                    Timer::after(MODE_POLL_INTERVAL),
+                )
+                .await
+                {
+                    Timer::after(Duration::from_millis(5000)).await;
+                }
             }
             _ => {}
         }
+        let radio_config = radio::config();
+        let pinned = radio_config.channel_policy.pinned();
+        let sta_connected = matches!(controller.is_connected(), Ok(true));
+        // Configured before starting, so the AP never comes up unconfigured
+        let interfaces = update_interfaces(&mut controller, &mut modes, &station, sta_connected);
         if !matches!(controller.is_started(), Ok(true)) {
-            let ap_config = Configuration::AccessPoint(AccessPointConfiguration {
-                ssid: "esp-wifi".try_into().unwrap(),
-                password: "12345678".try_into().unwrap(),
-                auth_method: esp_wifi::wifi::AuthMethod::WPA2Personal,
-                ..Default::default()
-            });
-            controller.set_configuration(&ap_config).unwrap();
-            println!("Access Point configuration set!");
             controller.start_async().await.unwrap();
             println!("WiFi started!");
         }
-        if !matches!(controller.is_connected(), Ok(true)) {
-            Timer::after(Duration::from_millis(5000)).await;
-            let (desired_ssid, desired_password) = get_wifi_credentials();
-
-            let mut found_ap = None;
-            match controller.scan_n_async::<8>().await {
-                Ok(scan_result) => {
-                    println!("Available networks:");
-                    for ap in scan_result.0 {
-                        println!(
-                            "SSID: {}, AuthMethod: {:#?}, SignalStrength: {}",
-                            ap.ssid,
-                            ap.auth_method.unwrap(),
-                            gui_signal_strength(ap.signal_strength),
-                        );
-                        if ap.ssid == desired_ssid {
-                            found_ap = Some(ap);
-                        }
-                    }
-                }
-                Err(e) => println!("Failed to scan for networks: {e:?}"),
+        radio::apply_settings(!clients().is_empty());
+        if sta_connected {
+            Timer::after(Duration::from_millis(1000)).await;
+            continue;
+        }
+        // With the station down the AP is free to go back to its own channel
+        if let Some(pinned) =
+            pinned.filter(|pinned| interfaces.ap && radio::channel() != Some(*pinned))
+        {
+            println!("Moving the AP to pinned channel {pinned}");
+            controller
+                .set_configuration(&configuration(interfaces, &station, pinned))
+                .unwrap();
+        }
+        let now = Instant::now().as_secs();
+        // Moving the AP drops its clients, leave it be while there are any
+        if interfaces.ap
+            && pinned.is_none()
+            && radio_config.auto_channel != AutoChannel::Off
+            && survey::survey_due(radio_config.recheck_mins, surveyed_at, now)
+            && clients().is_empty()
+        {
+            surveyed_at = Some(now);
+            let current = radio::channel();
+            let channel = current.unwrap_or(DEFAULT_AP_CHANNEL);
+            // Scans need the station interface, without one it comes up
+            // unconnected for the survey
+            if !interfaces.sta {
+                let scanning = Interfaces {
+                    sta: true,
+                    ..interfaces
+                };
+                let idle = Configuration::Client(Default::default());
+                controller
+                    .set_configuration(&configuration(scanning, &idle, channel))
+                    .unwrap();
+            }
+            let chosen = survey_channels(&mut controller).await.and_then(|scores| {
+                let chosen = survey::choose(
+                    &scores,
+                    radio_config.auto_channel,
+                    radio_config.country.channels,
+                    current,
+                )?;
+                radio::set_survey(Survey {
+                    at: now,
+                    chosen,
+                    scores,
+                });
+                Some(chosen)
+            });
+            let moved = chosen.filter(|chosen| current != Some(*chosen));
+            if let Some(chosen) = moved {
+                println!("Moving the AP to the least congested channel {chosen}");
+            }
+            if moved.is_some() || !interfaces.sta {
+                controller
+                    .set_configuration(&configuration(
+                        interfaces,
+                        &station,
+                        moved.unwrap_or(channel),
+                    ))
+                    .unwrap();
+            }
+        }
+        if !interfaces.sta {
+            Timer::after(MODE_POLL_INTERVAL).await;
+            continue;
+        }
+
+        let (desired_ssid, desired_password) = get_wifi_credentials();
+        let profile = uplink::profiles().network(desired_ssid).cloned();
+        let hidden = profile.as_ref().is_some_and(|profile| profile.hidden);
+        let wanted = profile.as_ref().and_then(|profile| profile.wanted_auth());
+        let enterprise = profile.and_then(|profile| profile.enterprise);
+        let (method, target) = match reconnect.attempt(Instant::now().as_millis()) {
+            Plan::Direct(ap) => {
+                println!(
+                    "Connecting straight to {} on channel {}...",
+                    MacDisplay(&ap.bssid),
+                    ap.channel
+                );
+                (Method::Direct, ap)
+            }
+            Plan::Scan { wait_secs } => {
+                Timer::after(Duration::from_secs(wait_secs)).await;
+                let Some(ap) = scan_for(&mut controller, desired_ssid, hidden, pinned).await else {
+                    println!("Desired network '{}' not found in scan list.", desired_ssid);
+                    reconnect.failed(Method::Scan);
+                    continue;
+                };
+                (Method::Scan, ap)
             }
-            if let Some(ap_info) = found_ap {
+        };
+        let security = match uplink::choose_security(desired_ssid, wanted, target.security) {
+            Ok(security) => security,
+            Err(SecurityError::Downgrade { seen, offered }) => {
                 println!(
-                    "Desired network '{}' found with signal strength: {}",
+                    "Refusing '{}': it offers {} but used {} before",
                     desired_ssid,
-                    gui_signal_strength(ap_info.signal_strength)
+                    offered.as_str(),
+                    seen.as_str()
                 );
-                let client_config = Configuration::Client(ClientConfiguration {
-                    ssid: desired_ssid.try_into().unwrap(),
-                    password: desired_password.try_into().unwrap(),
-                    ..Default::default()
-                });
-                controller.set_configuration(&client_config).unwrap();
-                println!("Connecting to WiFi...");
-            } else {
-                println!("Desired network '{}' not found in scan list.", desired_ssid);
+                reconnect.failed(method);
+                continue;
+            }
+            Err(SecurityError::Mismatch { wanted, offered }) => {
+                println!(
+                    "Refusing '{}': it offers {} but is set up for {}",
+                    desired_ssid,
+                    offered.as_str(),
+                    wanted.as_str()
+                );
+                reconnect.failed(method);
+                continue;
+            }
+        };
+        station = match &enterprise {
+            Some(enterprise) => match eap_config(desired_ssid, &target, enterprise) {
+                Ok(config) => Configuration::EapClient(config),
+                Err(message) => {
+                    println!("Can't join '{}': {}", desired_ssid, message);
+                    reconnect.failed(method);
+                    Timer::after(Duration::from_secs(SCAN_RETRY_SECS)).await;
+                    continue;
+                }
+            },
+            None => Configuration::Client(ClientConfiguration {
+                ssid: desired_ssid.try_into().unwrap(),
+                password: match security {
+                    Security::Open => Default::default(),
+                    _ => desired_password.try_into().unwrap(),
+                },
+                bssid: Some(target.bssid),
+                channel: Some(target.channel),
+                auth_method: auth_method(security),
+                ..Default::default()
+            }),
+        };
+        // Moving between 802.1X and other networks holds the AP down or
+        // lets it back up
+        let interfaces = update_interfaces(&mut controller, &mut modes, &station, false);
+        let ap_channel = radio::channel();
+        let mut announced = false;
+        match channel::plan(
+            radio_config.channel_policy,
+            ap_channel.unwrap_or(target.channel),
+            target.channel,
+        ) {
+            ChannelChange::Refuse { pinned } => {
+                println!(
+                    "Skipping {} on channel {}, the AP is pinned to channel {}",
+                    MacDisplay(&target.bssid),
+                    target.channel,
+                    pinned
+                );
+                reconnect.failed(method);
                 continue;
             }
+            ChannelChange::Move { to, .. }
+                if radio_config.announce_switch && !clients().is_empty() =>
+            {
+                announced = radio::announce_switch(to).await;
+            }
+            _ => {}
         }
+        controller
+            .set_configuration(&configuration(interfaces, &station, target.channel))
+            .unwrap();
+
         println!("About to connect...");
         match controller.connect_async().await {
             Ok(_) => {
-                println!("WiFi connected!");
-
-                Timer::after(Duration::from_millis(1000)).await;
+                let stats = reconnect.connected(target, method, Instant::now().as_millis());
+                println!(
+                    "WiFi connected in {} ms ({}, {} attempts)",
+                    stats.millis,
+                    stats.method.as_str(),
+                    stats.attempts
+                );
+                uplink::joined_with(desired_ssid, target.security);
+                uplink::set_joined(Some((desired_ssid, target.bssid)));
+                uplink::set_last_connect(stats);
+                roaming.joined(Instant::now().as_secs());
+                if let (Some(from), Some(to)) = (ap_channel, radio::channel()) {
+                    if from != to {
+                        radio::record_move(from, to, announced);
+                    }
+                }
             }
             Err(e) => {
                 println!("Failed to connect to WiFi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await;
+                reconnect.failed(method);
+            }
+        }
+    }
+}
+
+/// Waits for the station to disconnect, meanwhile watching the signal.
+/// Returns a clearly better access point of the same network to move to.
+async fn stay_connected(
+    controller: &mut WifiController<'static>,
+    roaming: &mut Roaming,
+    modes: &mut ModeMachine,
+    station: &Configuration,
+    ssid: &str,
+) -> Option<CachedAp> {
+    let pinned = radio::config().channel_policy.pinned();
+    loop {
+        let disconnected = select(
+            controller.wait_for_event(WifiEvent::StaDisconnected),
+            Timer::after(RSSI_POLL_INTERVAL),
+        )
+        .await;
+        // Events during a scan go unnoticed, so check the state as well
+        if matches!(disconnected, Either::First(()))
+            || !matches!(esp_wifi::wifi::wifi_state(), WifiState::StaConnected)
+        {
+            return None;
+        }
+        update_interfaces(controller, modes, station, true);
+        radio::apply_settings(!clients().is_empty());
+        let Some((current, rssi)) = current_ap() else {
+            continue;
+        };
+        if !roaming.sample(Instant::now().as_secs(), rssi) {
+            continue;
+        }
+
+        let found = light_scan(controller, ssid, pinned).await;
+        let seen = found.iter().map(|(ap, rssi)| (ap.bssid, *rssi));
+        let Some((bssid, best)) = roaming.choose(&current, seen) else {
+            continue;
+        };
+        let Some((ap, _)) = found.into_iter().find(|(ap, _)| ap.bssid == bssid) else {
+            continue;
+        };
+        println!(
+            "Roaming from {} ({} dBm) to {} ({} dBm) on channel {}",
+            MacDisplay(&current),
+            roaming.rssi().unwrap_or(rssi),
+            MacDisplay(&bssid),
+            best,
+            ap.channel
+        );
+        return Some(ap);
+    }
+}
+
+/// Brings the AP and station in line with the operating mode, returning
+/// which of them should be up.
+fn update_interfaces(
+    controller: &mut WifiController<'static>,
+    modes: &mut ModeMachine,
+    station: &Configuration,
+    sta_connected: bool,
+) -> Interfaces {
+    let now = Instant::now().as_secs();
+    modes.set_mode(radio::config().mode);
+    modes.set_provisioned(sta_connected && uplink::addressed(), now);
+    let wanted = modes.interfaces(now);
+    // The driver has no AP+STA configuration with 802.1X, the AP waits
+    // while the station uses it
+    let held = wanted.ap && wanted.sta && matches!(station, Configuration::EapClient(_));
+    radio::set_ap_held(held);
+    let interfaces = Interfaces {
+        ap: wanted.ap && !held,
+        ..wanted
+    };
+    // The radio has one channel, wherever the station or AP already is
+    let channel = radio::channel()
+        .filter(|channel| *channel != 0)
+        .or(radio::config().channel_policy.pinned())
+        .unwrap_or(DEFAULT_AP_CHANNEL);
+    radio::set_interfaces(
+        controller,
+        interfaces,
+        &configuration(interfaces, station, channel),
+    );
+    interfaces
+}
+
+/// The driver picks the radio mode from the kind of configuration, so the
+/// AP and station settings always go in together, shaped by which of them
+/// should be up.
+fn configuration(interfaces: Interfaces, station: &Configuration, channel: u8) -> Configuration {
+    let ap = ap_configuration(channel);
+    match (interfaces.ap, interfaces.sta, station) {
+        (true, false, _) => Configuration::AccessPoint(ap),
+        (true, true, Configuration::Client(client)) => Configuration::Mixed(client.clone(), ap),
+        // An 802.1X station never comes with the AP, `update_interfaces`
+        // holds it down
+        (_, _, station) => station.clone(),
+    }
+}
+
+/// The BSSID and signal of the access point the station is on.
+fn current_ap() -> Option<([u8; 6], i8)> {
+    let mut record: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    if unsafe { esp_wifi_sta_get_ap_info(&mut record) } != ESP_OK as i32 {
+        return None;
+    }
+    Some((record.bssid, record.rssi))
+}
+
+/// A quick probe for other access points of `ssid`, short enough on each
+/// channel not to upset the AP's own clients much.
+async fn light_scan(
+    controller: &mut WifiController<'static>,
+    ssid: &str,
+    channel: Option<u8>,
+) -> heapless::Vec<(CachedAp, i8), 8> {
+    let config = ScanConfig {
+        ssid: Some(ssid),
+        channel,
+        show_hidden: true,
+        scan_type: ScanTypeConfig::Active {
+            min: core::time::Duration::from_millis(20),
+            max: core::time::Duration::from_millis(60),
+        },
+        ..Default::default()
+    };
+    let mut found = heapless::Vec::new();
+    match controller.scan_with_config_async::<8>(config).await {
+        Ok(scan_result) => {
+            for ap in scan_result.0 {
+                let Some(security) = ap.auth_method.and_then(security) else {
+                    continue;
+                };
+                let cached = CachedAp {
+                    bssid: ap.bssid,
+                    channel: ap.channel,
+                    security,
+                };
+                _ = found.push((cached, ap.signal_strength));
             }
         }
+        Err(e) => println!("Roaming scan failed: {e:?}"),
+    }
+    found
+}
+
+/// 802.1X settings for `target`, with the uploaded certificates.
+fn eap_config(
+    ssid: &str,
+    target: &CachedAp,
+    login: &Enterprise,
+) -> Result<EapClientConfiguration, &'static str> {
+    let certificates = enterprise::certificates(ssid);
+    let ca_cert = match (login.validate_server, certificates.ca) {
+        (true, None) => return Err("no CA certificate to validate the server with"),
+        (true, ca) => ca,
+        (false, _) => {
+            log::warn!("Not validating the certificate of '{ssid}'");
+            None
+        }
+    };
+    let certificate_and_key = match (certificates.client_cert, certificates.client_key) {
+        (Some(cert), Some(key)) => Some((cert, key, None)),
+        _ if login.method == EapMethod::Tls => {
+            return Err("EAP-TLS needs a client certificate and key")
+        }
+        _ => None,
+    };
+    let ttls_phase2_method = match login.method {
+        EapMethod::Ttls(Phase2::Mschapv2) => Some(TtlsPhase2Method::Mschapv2),
+        EapMethod::Ttls(Phase2::Mschap) => Some(TtlsPhase2Method::Mschap),
+        EapMethod::Ttls(Phase2::Pap) => Some(TtlsPhase2Method::Pap),
+        EapMethod::Ttls(Phase2::Chap) => Some(TtlsPhase2Method::Chap),
+        EapMethod::Peap | EapMethod::Tls => None,
+    };
+    Ok(EapClientConfiguration {
+        ssid: ssid.try_into().unwrap(),
+        bssid: Some(target.bssid),
+        channel: Some(target.channel),
+        auth_method: AuthMethod::WPA2Enterprise,
+        identity: login.outer_identity().try_into().ok(),
+        username: login
+            .method
+            .uses_password()
+            .then(|| login.identity.as_str().try_into().ok())
+            .flatten(),
+        password: login
+            .password
+            .as_deref()
+            .and_then(|password| password.try_into().ok()),
+        ca_cert,
+        certificate_and_key,
+        ttls_phase2_method,
+        // There's no real-time clock to check certificate validity with
+        time_check: false,
+        ..Default::default()
+    })
+}
+
+/// Scores every channel by the access points on and around it.
+async fn survey_channels(controller: &mut WifiController<'static>) -> Option<Scores> {
+    let config = ScanConfig {
+        show_hidden: true,
+        ..Default::default()
+    };
+    match controller
+        .scan_with_config_async::<MAX_SURVEY_RESULTS>(config)
+        .await
+    {
+        Ok(scan_result) => Some(survey::scores(
+            scan_result
+                .0
+                .iter()
+                .map(|ap| (ap.channel, ap.signal_strength)),
+        )),
+        Err(e) => {
+            println!("Channel survey failed: {e:?}");
+            None
+        }
+    }
+}
+
+/// Hidden networks don't show up in a normal scan, they are probed for by
+/// name instead. With a `channel`, only that one is scanned.
+async fn scan_for(
+    controller: &mut WifiController<'static>,
+    ssid: &str,
+    hidden: bool,
+    channel: Option<u8>,
+) -> Option<CachedAp> {
+    let config = ScanConfig {
+        ssid: hidden.then_some(ssid),
+        channel,
+        show_hidden: hidden,
+        ..Default::default()
+    };
+    let mut found_ap = None;
+    match controller.scan_with_config_async::<8>(config).await {
+        Ok(scan_result) => {
+            println!("Available networks:");
+            for ap in scan_result.0 {
+                println!(
+                    "SSID: {}, AuthMethod: {:#?}, SignalStrength: {}",
+                    ap.ssid,
+                    ap.auth_method.unwrap(),
+                    gui_signal_strength(ap.signal_strength),
+                );
+                // Scans come sorted by signal, keep the strongest usable one
+                if ap.ssid == ssid && found_ap.is_none() {
+                    found_ap = ap
+                        .auth_method
+                        .and_then(security)
+                        .map(|security| (ap, security));
+                }
+            }
+        }
+        Err(e) => println!("Failed to scan for networks: {e:?}"),
+    }
+    let (ap, security) = found_ap?;
+    println!(
+        "Desired network '{}' found with signal strength: {}",
+        ssid,
+        gui_signal_strength(ap.signal_strength)
+    );
+    Some(CachedAp {
+        bssid: ap.bssid,
+        channel: ap.channel,
+        security,
+    })
+}
+
+fn ap_configuration(channel: u8) -> AccessPointConfiguration {
+    AccessPointConfiguration {
+        ssid: "esp-wifi".try_into().unwrap(),
+        password: "12345678".try_into().unwrap(),
+        auth_method: esp_wifi::wifi::AuthMethod::WPA2Personal,
+        channel,
+        ..Default::default()
+    }
+}
+
+/// `None` for authentication the station can't do.
+fn security(auth_method: AuthMethod) -> Option<Security> {
+    Some(match auth_method {
+        AuthMethod::None => Security::Open,
+        AuthMethod::WEP => Security::Wep,
+        AuthMethod::WPA => Security::Wpa,
+        AuthMethod::WPAWPA2Personal => Security::WpaWpa2,
+        AuthMethod::WPA2Personal => Security::Wpa2,
+        AuthMethod::WPA2Enterprise => Security::Wpa2Enterprise,
+        AuthMethod::WPA2WPA3Personal => Security::Wpa2Wpa3,
+        AuthMethod::WPA3Personal => Security::Wpa3,
+        _ => return None,
+    })
+}
+
+fn auth_method(security: Security) -> AuthMethod {
+    match security {
+        Security::Open => AuthMethod::None,
+        Security::Wep => AuthMethod::WEP,
+        Security::Wpa => AuthMethod::WPA,
+        Security::WpaWpa2 => AuthMethod::WPAWPA2Personal,
+        Security::Wpa2 => AuthMethod::WPA2Personal,
+        Security::Wpa2Enterprise => AuthMethod::WPA2Enterprise,
+        Security::Wpa2Wpa3 => AuthMethod::WPA2WPA3Personal,
+        Security::Wpa3 => AuthMethod::WPA3Personal,
     }
 }
 